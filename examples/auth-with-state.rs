@@ -51,20 +51,20 @@ async fn hello(
     authstate: AuthedState,
 ) -> HTTPResult<AuthedState> {
     let name = params.get("name").unwrap();
-    let bytes = Body::from(format!("hello, {}!\n", name));
+    let greeting = format!("hello, {}!\n", name);
 
     if let Some(authed) = authstate.authed {
         if authed {
             return Ok((
                 req,
-                Some(Response::builder().status(200).body(bytes).unwrap()),
+                Some(respond::text(StatusCode::OK, greeting)),
                 authstate,
             ));
         }
     } else if authstate.authed.is_none() {
         return Ok((
             req,
-            Some(Response::builder().status(200).body(bytes).unwrap()),
+            Some(respond::text(StatusCode::OK, greeting)),
             authstate,
         ));
     }
@@ -75,8 +75,8 @@ async fn hello(
     ))
 }
 
-// Our global application state; must be `Clone`.
-#[derive(Clone)]
+// Our global application state; it is shared behind an `Arc<Mutex<_>>`, so
+// it does not need to be `Clone`.
 struct State {
     authtoken: &'static str,
 }
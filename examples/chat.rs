@@ -0,0 +1,72 @@
+use ratpack::{
+    body,
+    handler::Handler,
+    hub::{Hub, Overflow},
+    prelude::*,
+};
+
+// a chat room over plain HTTP: each client holds a streamed response open
+// and is sent every line posted to the room, along with a welcome of its own.
+//
+//   curl -N http://127.0.0.1:3000/room
+//   curl -d 'hi there' http://127.0.0.1:3000/room/erik
+//
+// ratpack has no WebSocket upgrades yet; with them, the task forwarding from
+// the subscription would write to the socket instead.
+#[tokio::main]
+async fn main() -> Result<(), ServerError> {
+    // clients that fall 64 lines behind are disconnected rather than letting
+    // the room's messages pile up for them.
+    let hub: Hub<String> = Hub::new(64).overflow(Overflow::Disconnect);
+
+    let mut app: App<(), NoState> = App::new();
+
+    let room = hub.clone();
+    app.get(
+        "/room",
+        Handler::from_fn(move |req, _resp, _params, _app, state| {
+            let room = room.clone();
+            async move {
+                let mut subscription = room.subscribe();
+                let welcome = format!(
+                    "welcome, you are #{}; {} here\n",
+                    subscription.id(),
+                    room.len()
+                );
+                room.send_to(subscription.id(), welcome);
+
+                // the subscription is dropped, leaving the room, once the
+                // client goes away and writing to it fails.
+                let body = body::stream(move |mut writer| async move {
+                    while let Some(line) = subscription.recv().await {
+                        writer.write_chunk(line).await?;
+                    }
+                    Ok(())
+                });
+
+                Ok((req, Some(Response::new(body)), state))
+            }
+        }),
+    );
+
+    let room = hub.clone();
+    app.post(
+        "/room/:name",
+        Handler::from_fn(move |mut req, _resp, params, _app, state| {
+            let room = room.clone();
+            async move {
+                let text = body::read_to_string_limited(&mut req, 1024).await?;
+                let line = format!("{}: {}\n", params["name"], text.trim_end());
+                let sent = room.broadcast(line);
+
+                let reply = format!("sent to {}\n", sent);
+                Ok((req, Some(respond::text(StatusCode::OK, reply)), state))
+            }
+        }),
+    );
+
+    eprintln!("Chatting on 127.0.0.1:3000/room");
+    app.serve("127.0.0.1:3000").await?;
+
+    Ok(())
+}
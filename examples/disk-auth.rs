@@ -3,6 +3,7 @@ use ratpack::prelude::*;
 const DEFAULT_AUTHTOKEN: &str = "867-5309";
 const AUTHTOKEN_FILENAME: &str = "authtoken.secret";
 
+#[allow(clippy::needless_return)]
 async fn validate_authtoken(
     req: Request<Body>,
     resp: Option<Response<Body>>,
@@ -32,6 +33,7 @@ async fn validate_authtoken(
     return Ok((req, resp, NoState {}));
 }
 
+#[allow(clippy::needless_return)]
 async fn hello(
     req: Request<Body>,
     _resp: Option<Response<Body>>,
@@ -40,11 +42,11 @@ async fn hello(
     _state: NoState,
 ) -> HTTPResult<NoState> {
     let name = params.get("name").unwrap();
-    let bytes = Body::from(format!("hello, {}!\n", name));
+    let greeting = format!("hello, {}!\n", name);
 
     return Ok((
         req,
-        Some(Response::builder().status(200).body(bytes).unwrap()),
+        Some(respond::text(StatusCode::OK, greeting)),
         NoState {},
     ));
 }
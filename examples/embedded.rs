@@ -0,0 +1,26 @@
+use ratpack::{cache::CacheControl, files::Embedded, prelude::*};
+use std::time::Duration;
+
+// the files beneath examples/assets, compiled into the binary; it can be run
+// from anywhere, without them alongside.
+static ASSETS: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/examples/assets");
+
+#[tokio::main]
+async fn main() -> Result<(), ServerError> {
+    let mut app: App<(), NoState> = App::new();
+    app.get_prefix(
+        "/static",
+        Embedded::new(&ASSETS)
+            .cache_control(
+                CacheControl::new()
+                    .public()
+                    .max_age(Duration::from_secs(3600)),
+            )
+            .handler(),
+    );
+
+    eprintln!("Serving http://127.0.0.1:3000/static/ from the binary");
+    app.serve("127.0.0.1:3000").await?;
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use ratpack::prelude::*;
+
+// The user our authentication middleware found. It is passed to later handlers
+// in the request's extensions, so no TransientState is needed to carry it.
+struct User {
+    name: String,
+}
+
+// looks the user up by the `X-AuthToken` header, and stores them in the
+// request's extensions. Requests without a valid token are refused with
+// `401 Unauthorized`.
+async fn authenticate(
+    mut req: Request<Body>,
+    resp: Option<Response<Body>>,
+    _params: Params,
+    _app: App<(), NoState>,
+    state: NoState,
+) -> HTTPResult<NoState> {
+    let name = match req.headers().get("X-AuthToken").map(|token| token.to_str()) {
+        Some(Ok("867-5309")) => "jenny",
+        _ => {
+            return Err(Error::StatusCode(
+                StatusCode::UNAUTHORIZED,
+                String::default(),
+            ))
+        }
+    };
+
+    ext::insert(
+        &mut req,
+        User {
+            name: name.to_string(),
+        },
+    );
+    Ok((req, resp, state))
+}
+
+// greets the user the middleware found.
+async fn hello(
+    req: Request<Body>,
+    _resp: Option<Response<Body>>,
+    _params: Params,
+    _app: App<(), NoState>,
+    state: NoState,
+) -> HTTPResult<NoState> {
+    let user = ext::get::<User>(&req).unwrap();
+    let bytes = Body::from(format!("hello, {}!\n", user.name));
+
+    Ok((
+        req,
+        Some(Response::builder().status(200).body(bytes).unwrap()),
+        state,
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), ServerError> {
+    let mut app = App::new();
+    app.get("/hello", compose_handler!(authenticate, hello));
+
+    app.serve("127.0.0.1:3000").await?;
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use ratpack::{
+    app::TestApp,
+    flash::{self, Level},
+    prelude::*,
+};
+
+// saves a post, then redirects to the list of posts with a notice saying so.
+// the notice travels in a cookie set on the redirect.
+async fn save(
+    req: Request<Body>,
+    _resp: Option<Response<Body>>,
+    _params: Params,
+    _app: App<(), NoState>,
+    state: NoState,
+) -> HTTPResult<NoState> {
+    let mut resp = respond::see_other("/posts")?;
+    flash::set(&mut resp, Level::Success, "Saved!")?;
+    Ok((req, Some(resp), state))
+}
+
+// renders the list of posts, with any notices above it. taking the notices
+// has `flash::handler()` remove their cookie with this response, so they are
+// shown only once.
+async fn posts(
+    req: Request<Body>,
+    _resp: Option<Response<Body>>,
+    _params: Params,
+    _app: App<(), NoState>,
+    state: NoState,
+) -> HTTPResult<NoState> {
+    let mut page = String::new();
+    for message in flash::take(&req) {
+        page += &format!("[{}] {}\n", message.level, message.text);
+    }
+    page += "(no posts yet)\n";
+
+    Ok((req, Some(respond::text(StatusCode::OK, page)), state))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), ServerError> {
+    let mut app = App::new();
+    app.post("/posts", compose_handler!(save));
+    app.get("/posts", compose_handler!(posts));
+    app.wrap(flash::handler());
+
+    // play the part of a browser: submit the form, then follow the redirect
+    // with the cookie it set.
+    let app = TestApp::new(app);
+    let res = app.post("/posts", Body::empty()).await;
+    println!("POST /posts: {}", res.status());
+
+    let mut headers = http::HeaderMap::new();
+    for cookie in res.headers().get_all("set-cookie") {
+        let pair = cookie.to_str()?.split(';').next().unwrap_or_default();
+        headers.append("cookie", pair.parse()?);
+    }
+
+    let mut res = app.with_headers(headers).get("/posts").await;
+    let page = hyper::body::to_bytes(res.body_mut()).await?;
+    println!("GET /posts:\n{}", String::from_utf8_lossy(&page));
+    for cookie in res.headers().get_all("set-cookie") {
+        println!("clearing with: {}", cookie.to_str()?);
+    }
+
+    // loaded again, the page has no notices.
+    let mut res = app.get("/posts").await;
+    let page = hyper::body::to_bytes(res.body_mut()).await?;
+    println!("GET /posts again:\n{}", String::from_utf8_lossy(&page));
+
+    Ok(())
+}
@@ -2,6 +2,7 @@ use ratpack::prelude::*;
 #[cfg(feature = "unix")]
 use std::path::PathBuf;
 
+#[allow(clippy::needless_return)]
 async fn hello(
     req: Request<Body>,
     _resp: Option<Response<Body>>,
@@ -10,11 +11,11 @@ async fn hello(
     _state: NoState,
 ) -> HTTPResult<NoState> {
     let name = params.get("name").unwrap();
-    let bytes = Body::from(format!("hello, {}!\n", name));
+    let greeting = format!("hello, {}!\n", name);
 
     return Ok((
         req,
-        Some(Response::builder().status(200).body(bytes).unwrap()),
+        Some(respond::text(StatusCode::OK, greeting)),
         NoState {},
     ));
 }
@@ -21,6 +21,7 @@ async fn log(
     Ok((req, resp, NoState {}))
 }
 
+#[allow(clippy::needless_return)]
 async fn hello(
     req: Request<Body>,
     _resp: Option<Response<Body>>,
@@ -0,0 +1,51 @@
+use ratpack::{multipart, prelude::*};
+
+// accepts a form uploading files, and responds with the size of each. Try it
+// with: curl -F file=@Cargo.toml http://127.0.0.1:3000/upload
+async fn upload(
+    mut req: Request<Body>,
+    _resp: Option<Response<Body>>,
+    _params: Params,
+    _app: App<(), NoState>,
+    state: NoState,
+) -> HTTPResult<NoState> {
+    let mut multipart = multipart::parse(&mut req)?;
+    let mut summary = String::new();
+
+    // parts stream from the request body one after the other; each must be
+    // read before moving to the next.
+    while let Some(mut part) = multipart.next_part().await? {
+        let mut size = 0;
+        while let Some(chunk) = part.chunk().await? {
+            size += chunk.len();
+        }
+
+        summary += &format!(
+            "{} ({}): {} bytes\n",
+            part.name(),
+            part.filename().unwrap_or("no filename"),
+            size
+        );
+    }
+
+    Ok((
+        req,
+        Some(
+            Response::builder()
+                .status(200)
+                .body(Body::from(summary))
+                .unwrap(),
+        ),
+        state,
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), ServerError> {
+    let mut app = App::new();
+    app.post("/upload", compose_handler!(upload));
+
+    app.serve("127.0.0.1:3000").await?;
+
+    Ok(())
+}
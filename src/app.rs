@@ -1,15 +1,38 @@
-use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
-use http::{HeaderMap, Method, Request, Response, StatusCode};
-use hyper::{server::conn::Http, service::service_fn, Body};
-use tokio::{net::TcpListener, sync::Mutex};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use hyper::{body::Bytes, server::conn::Http, service::service_fn, Body};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, ToSocketAddrs},
+    sync::{mpsc, watch, Mutex, Notify, OwnedSemaphorePermit, Semaphore},
+};
 
 #[cfg(feature = "unix")]
 use std::path::PathBuf;
 #[cfg(feature = "unix")]
 use tokio::net::UnixListener;
 
-use crate::{handler::Handler, router::Router, Error, ServerError, TransientState};
+use crate::{
+    handler::Handler,
+    host::HostPattern,
+    path::Path,
+    router::{Methods, Router},
+    weighted::Weighted,
+    Disconnected, Error, Params, RequestStart, ServerError, TransientState,
+};
 
 /// App is used to define application-level functionality and initialize the server. Routes are
 /// typically programmed here.
@@ -49,373 +72,2524 @@ use crate::{handler::Handler, router::Router, Error, ServerError, TransientState
 /// with `:`. For example, `/a/b/c` will always only match one route, while `/a/:b/c` will match
 /// any route with `/a/<anything>/c`.
 ///
+/// Dynamic components may be constrained with a regular expression in braces, which must match
+/// the whole component for the route to match: `/users/:id{[0-9]+}` will match `/users/42` but not
+/// `/users/erik`, which is left to other routes. Constraints may not contain `/`.
+///
+/// Dynamic components suffixed with `?` are optional, and may only be followed by other optional
+/// components: `/api/items/:id?` matches both `/api/items` and `/api/items/42`. When absent, the
+/// parameter is not present in [crate::Params].
+///
+/// A final component starting with `*` is a wildcard, and captures the remainder of the path
+/// (slashes included) under its name. For example, `/static/*rest` will match `/static/css/a.css`
+/// with `rest` set to `css/a.css`, and `/static/` with `rest` set to an empty string. Wildcards may
+/// only appear as the last component of a path.
+///
 /// Variadic path components are accessible through the [crate::Params] implementation. Paths are
 /// typically used through [crate::app::App] methods that use a string form of the Path.
 ///
+/// Paths must begin with `/` and may contain neither whitespace nor empty segments (`//`).
+/// Registering a route with an invalid path panics, so that misconfigurations fail at startup.
+///
 /// Requests are routed through paths to [crate::handler::HandlerFunc]s.
-#[derive(Clone)]
-pub struct App<S: Clone + Send, T: TransientState + 'static + Clone + Send> {
-    router: Router<S, T>,
+///
+/// Requests whose target is in absolute-form (`GET http://example.com/a HTTP/1.1`), as sent to
+/// proxies, are routed on the path of the target, and handlers see them as though they had been
+/// made in the usual form: the request URI holds just the path and query, and the Host header
+/// holds the authority of the target. The target of a CONNECT request is the authority to tunnel
+/// to, which is left as the request URI; such requests are routed as though their path were `/`.
+///
+/// Cloning an App copies its routes, settings and maintenance mode, so clones may be given routes
+/// of their own; they share its state and [App::stats]. Routes may still be added and removed
+/// (see [App::remove]) while the App is serving, through a [RouteHandle] taken before
+/// [App::serve] or through the App handlers are given; each change takes effect for requests that
+/// arrive after it, while requests already being dispatched finish with the routes they started
+/// with.
+///
+/// The settings of the server, such as [App::set_idle_timeout] and [App::set_max_connections],
+/// are read once, as a server starts serving: changing them affects servers started afterwards,
+/// not those already running.
+pub struct App<S: Send, T: TransientState + 'static + Clone + Send> {
+    router: Arc<RwLock<Arc<Router<S, T>>>>,
     global_state: Option<Arc<Mutex<S>>>,
+    counters: Arc<Counters>,
+    maintenance: Arc<Maintenance>,
+    server: Arc<RwLock<ServerOptions>>,
 }
 
-impl<S: 'static + Clone + Send, T: TransientState + 'static + Clone + Send> App<S, T> {
-    /// Construct a new App with no state; it will be passed to handlers as `App<()>`.
-    pub fn new() -> Self {
+/// AppStats is a point-in-time snapshot of the connection and request gauges for an
+/// [crate::app::App]. See [crate::app::App::stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppStats {
+    /// The number of connections currently open against the server.
+    pub connections: usize,
+    /// The number of requests currently being dispatched.
+    pub in_flight: usize,
+    /// The number of requests that have finished dispatching since the App was created.
+    pub total_served: u64,
+    /// The number of connections that were rejected, or left waiting to be accepted, because as
+    /// many as [crate::app::App::set_max_connections] allows were open.
+    pub limited_connections: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    connections: AtomicUsize,
+    in_flight: AtomicUsize,
+    total_served: AtomicU64,
+    limited: AtomicU64,
+}
+
+// Whether the App is in maintenance mode, and how it answers requests while it is.
+struct Maintenance {
+    enabled: AtomicBool,
+    settings: RwLock<MaintenanceSettings>,
+}
+
+#[derive(Clone)]
+struct MaintenanceSettings {
+    allowed: Vec<String>,
+    retry_after: Duration,
+    body: Bytes,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
         Self {
-            router: Router::new(),
-            global_state: None,
+            enabled: AtomicBool::new(false),
+            settings: RwLock::new(MaintenanceSettings {
+                allowed: Vec::new(),
+                retry_after: Duration::from_secs(60),
+                body: Bytes::from_static(b"down for maintenance"),
+            }),
         }
     }
+}
 
-    /// Construct an App with state.
-    ///
-    /// This has the type `App<S>` where S is `+ 'static + Clone + Send` and will be passed to
-    /// handlers with the appropriate concrete type.
-    ///
-    pub fn with_state(state: S) -> Self {
+impl Maintenance {
+    // an independent copy of the mode and its settings as they are now.
+    fn copy(&self) -> Self {
         Self {
-            router: Router::new(),
-            global_state: Some(Arc::new(Mutex::new(state))),
+            enabled: AtomicBool::new(self.enabled.load(Ordering::SeqCst)),
+            settings: RwLock::new(
+                self.settings
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone(),
+            ),
         }
     }
 
-    // FIXME Currently you must await this, seems pointless.
-    /// Return the state of the App. This is returned as `Arc<Mutex<S>>` and must be acquired under
-    /// lock. In situations where there is no state, [std::option::Option::None] is returned.
-    pub async fn state(&self) -> Option<Arc<Mutex<S>>> {
-        self.global_state.clone()
-    }
+    // the response to a request, if the App is in maintenance mode and the path it is routed on
+    // is not allowed through.
+    fn response<S: Send, T: TransientState + 'static + Clone + Send>(
+        &self,
+        req: &Request<Body>,
+        router: &Router<S, T>,
+    ) -> Option<Response<Body>> {
+        if !self.enabled.load(Ordering::SeqCst) {
+            return None;
+        }
 
-    /// Create a route for a GET request. See App's docs and [crate::handler::Handler] for
-    /// more information.
-    pub fn get(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::GET, path.to_string(), ch);
-    }
+        let settings = self.settings.read().unwrap_or_else(PoisonError::into_inner);
+        if settings
+            .allowed
+            .iter()
+            .any(|allowed| router.requests_path(req, allowed))
+        {
+            return None;
+        }
 
-    /// Create a route for a POST request. See App's docs and [crate::handler::Handler] for
-    /// more information.
-    pub fn post(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::POST, path.to_string(), ch);
+        let retry_after = (settings.retry_after.as_secs_f64().ceil() as u64).max(1);
+        let mut res = Response::new(Body::from(settings.body.clone()));
+        *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        res.headers_mut()
+            .insert(http::header::RETRY_AFTER, retry_after.into());
+        Some(res)
     }
+}
 
-    /// Create a route for a DELETE request. See App's docs and [crate::handler::Handler] for
-    /// more information.
-    pub fn delete(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::DELETE, path.to_string(), ch);
-    }
+/// MaintenanceHandle switches an [crate::app::App] in and out of maintenance mode, in which every
+/// request is answered with a 503 Service Unavailable rather than routed. See
+/// [crate::app::App::maintenance_handle].
+#[derive(Clone)]
+pub struct MaintenanceHandle(Arc<Maintenance>);
 
-    /// Create a route for a PUT request. See App's docs and [crate::handler::Handler] for
-    /// more information.
-    pub fn put(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::PUT, path.to_string(), ch);
+impl MaintenanceHandle {
+    /// Answer requests with a 503 from now on.
+    pub fn enable(&self) {
+        self.0.enabled.store(true, Ordering::SeqCst);
     }
 
-    /// Create a route for an OPTIONS request. See App's docs and
-    /// [crate::handler::Handler] for more information.
-    pub fn options(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::OPTIONS, path.to_string(), ch);
+    /// Route requests as usual from now on.
+    pub fn disable(&self) {
+        self.0.enabled.store(false, Ordering::SeqCst);
     }
 
-    /// Create a route for a PATCH request. See App's docs and
-    /// [crate::handler::Handler] for more information.
-    pub fn patch(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::PATCH, path.to_string(), ch);
+    /// Whether the App is in maintenance mode.
+    pub fn is_enabled(&self) -> bool {
+        self.0.enabled.load(Ordering::SeqCst)
     }
 
-    /// Create a route for a HEAD request. See App's docs and
-    /// [crate::handler::Handler] for more information.
-    pub fn head(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::HEAD, path.to_string(), ch);
+    /// Route requests for these paths as usual even in maintenance mode, such as health checks
+    /// that should keep passing. Request paths are compared with these as they are with routes,
+    /// trailing slashes and dot segments included; these replace the paths given before.
+    pub fn allow(&self, paths: &[&str]) {
+        self.settings().allowed = paths.iter().map(ToString::to_string).collect();
     }
 
-    /// Create a route for a CONNECT request. See App's docs and
-    /// [crate::handler::Handler] for more information.
-    pub fn connect(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::CONNECT, path.to_string(), ch);
+    /// Tell clients to try again after this long, rounded up to the second; a minute unless
+    /// changed.
+    pub fn set_retry_after(&self, retry_after: Duration) {
+        self.settings().retry_after = retry_after;
     }
 
-    /// Create a route for a TRACE request. See App's docs and
-    /// [crate::handler::Handler] for more information.
-    pub fn trace(&mut self, path: &str, ch: Handler<S, T>) {
-        self.router.add(Method::TRACE, path.to_string(), ch);
+    /// Answer with this body, rather than `down for maintenance`.
+    pub fn set_body(&self, body: impl Into<Bytes>) {
+        self.settings().body = body.into();
     }
 
-    /// Dispatch a route based on the request. Returns a response based on the error status of the
-    /// handler chain following the normal chain of responsibility rules described elsewhere. Only
-    /// needed by server implementors.
-    pub async fn dispatch(&self, req: Request<Body>) -> Result<Response<Body>, Infallible> {
-        let _uri = req.uri().clone();
-        let _method = req.method().clone();
+    fn settings(&self) -> std::sync::RwLockWriteGuard<'_, MaintenanceSettings> {
+        self.0
+            .settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
 
-        #[cfg(all(feature = "logging", not(feature = "trace")))]
-        log::info!("{} request to {}", _method, _uri);
+// Held for the life of a connection; decrements the gauge however the connection ends, and makes
+// room for another under the App's limit.
+struct ConnectionGuard {
+    counters: Arc<Counters>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
 
-        #[cfg(feature = "trace")]
-        tracing::info!("{} request to {}", _method, _uri);
+impl ConnectionGuard {
+    fn new(counters: Arc<Counters>, permit: Option<OwnedSemaphorePermit>) -> Self {
+        counters.connections.fetch_add(1, Ordering::SeqCst);
+        Self {
+            counters,
+            _permit: permit,
+        }
+    }
+}
 
-        match self.router.dispatch(req, self.clone()).await {
-            Ok(resp) => {
-                let _status = resp.status().clone();
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.counters.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-                #[cfg(all(feature = "logging", not(feature = "trace")))]
-                log::info!(
-                    "{} request to {}: responding with status {}",
-                    _method,
-                    _uri,
-                    _status,
-                );
+/// ConnectionOverflow is what an [crate::app::App] does with connections beyond its limit. See
+/// [crate::app::App::set_max_connections].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionOverflow {
+    /// Leave them in the system's backlog until a connection closes. Clients see a slow connect,
+    /// and once the backlog is full, a refusal.
+    Wait,
+    /// Accept them, answer with a 503 Service Unavailable, and close them. Connections served
+    /// over TLS are closed without an answer.
+    Reject,
+}
 
-                #[cfg(feature = "trace")]
-                tracing::info!(
-                    "{} request to {}: responding with status {}",
-                    _method,
-                    _uri,
-                    _status,
-                );
+// How long to wait before accepting again after failing to accept a connection.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
-                Ok(resp)
-            }
-            Err(e) => {
-                #[cfg(all(feature = "logging", not(feature = "trace")))]
-                log::error!(
-                    "{} request to {}: responding with error {:?}",
-                    _method,
-                    _uri,
-                    e,
-                );
+// Acceptor is what a server accepts connections from: a TCP listener, serving TLS or not, or a
+// Unix domain socket.
+trait Acceptor {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
 
-                #[cfg(feature = "trace")]
-                tracing::error!(
-                    "{} request to {}: responding with error {:?}",
-                    _method,
-                    _uri,
-                    e,
-                );
-                match e.clone() {
-                    Error::StatusCode(sc, msg) => Ok(Response::builder()
-                        .status(sc)
-                        .body(Body::from(msg))
-                        .unwrap()),
-                    Error::InternalServerError(e) => Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from(e.to_string()))
-                        .unwrap()),
-                }
-            }
-        }
-    }
+    // Whether requests arrive over TLS.
+    const SECURE: bool = false;
 
-    #[cfg(feature = "unix")]
-    pub async fn serve_unix(self, filename: PathBuf) -> Result<(), ServerError> {
-        let unix_listener = UnixListener::bind(filename)?;
-        loop {
-            let (stream, _) = unix_listener.accept().await?;
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<(Self::Stream, Option<SocketAddr>)>>;
 
-            let s = self.clone();
-            let sfn = service_fn(move |req: Request<Body>| {
-                let s = s.clone();
-                async move { s.clone().dispatch(req).await }
-            });
+    // Make an accepted connection ready for HTTP to be served over it.
+    fn establish(
+        &self,
+        stream: Self::Stream,
+    ) -> impl Future<Output = std::io::Result<Self::Io>> + Send + 'static;
 
-            tokio::task::spawn(async move {
-                if let Err(http_err) = Http::new()
-                    .http1_keep_alive(true)
-                    .serve_connection(stream, sfn)
-                    .await
-                {
-                    #[cfg(feature = "logging")]
-                    log::error!("Error while serving HTTP connection: {}", http_err);
-                    #[cfg(feature = "trace")]
-                    tracing::error!("Error while serving HTTP connection: {}", http_err);
-                    #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
-                    eprintln!("Error while serving HTTP connection: {}", http_err);
-                }
-            });
-        }
+    // Turn away a connection beyond the limit.
+    fn reject(&self, stream: Self::Stream) {
+        tokio::task::spawn(reject(stream));
     }
+}
 
-    /// Start a TCP/HTTP server with tokio. Performs dispatch on an as-needed basis. This is a more
-    /// common path for users to start a server.
-    pub async fn serve(self, addr: &str) -> Result<(), ServerError> {
-        let socketaddr: SocketAddr = addr.parse()?;
+impl Acceptor for TcpListener {
+    type Stream = tokio::net::TcpStream;
+    type Io = tokio::net::TcpStream;
 
-        let tcp_listener = TcpListener::bind(socketaddr).await?;
-        loop {
-            let (tcp_stream, sa) = tcp_listener.accept().await?;
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<(Self::Stream, Option<SocketAddr>)>> {
+        TcpListener::poll_accept(self, cx).map_ok(|(stream, sa)| (stream, Some(sa)))
+    }
 
-            let s = self.clone();
-            let sfn = service_fn(move |mut req: Request<Body>| {
-                let ip = sa.ip();
-                req.extensions_mut().insert(ip);
-                let s = s.clone();
-                async move { s.clone().dispatch(req).await }
-            });
+    fn establish(
+        &self,
+        stream: Self::Stream,
+    ) -> impl Future<Output = std::io::Result<Self::Io>> + Send + 'static {
+        std::future::ready(Ok(stream))
+    }
+}
 
-            #[cfg(all(feature = "logging", not(feature = "trace")))]
-            log::trace!("Request from {}", sa);
+#[cfg(feature = "unix")]
+impl Acceptor for UnixListener {
+    type Stream = tokio::net::UnixStream;
+    type Io = tokio::net::UnixStream;
 
-            #[cfg(feature = "trace")]
-            tracing::trace!("Request from {}", sa);
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<(Self::Stream, Option<SocketAddr>)>> {
+        UnixListener::poll_accept(self, cx).map_ok(|(stream, _)| (stream, None))
+    }
 
-            tokio::task::spawn(async move {
-                if let Err(http_err) = Http::new()
-                    .http1_keep_alive(true)
-                    .serve_connection(tcp_stream, sfn)
-                    .await
-                {
-                    #[cfg(feature = "logging")]
-                    log::error!("Error while serving HTTP connection: {}", http_err);
-                    #[cfg(feature = "trace")]
-                    tracing::error!("Error while serving HTTP connection: {}", http_err);
-                    #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
-                    eprintln!("Error while serving HTTP connection: {}", http_err);
-                }
-            });
-        }
+    fn establish(
+        &self,
+        stream: Self::Stream,
+    ) -> impl Future<Output = std::io::Result<Self::Io>> + Send + 'static {
+        std::future::ready(Ok(stream))
     }
+}
 
-    /// Start a TLS-backed TCP/HTTP server with tokio. Performs dispatch on an as-needed basis. This is a more
-    /// common path for users to start a server.
-    #[cfg(feature = "tls")]
-    pub async fn serve_tls(
-        self,
-        addr: &str,
-        config: tokio_rustls::rustls::ServerConfig,
-    ) -> Result<(), ServerError> {
-        let socketaddr: SocketAddr = addr.parse()?;
+// A TCP listener whose connections are served over TLS.
+#[cfg(feature = "tls")]
+struct TlsListener {
+    listener: TcpListener,
+    tls: tokio_rustls::TlsAcceptor,
+}
 
-        let config = tokio_rustls::TlsAcceptor::from(Arc::new(config));
-        let tcp_listener = TcpListener::bind(socketaddr).await?;
-        loop {
-            let (tcp_stream, sa) = tcp_listener.accept().await?;
+#[cfg(feature = "tls")]
+impl Acceptor for TlsListener {
+    type Stream = tokio::net::TcpStream;
+    type Io = tokio_rustls::server::TlsStream<tokio::net::TcpStream>;
 
-            let s = self.clone();
-            let sfn = service_fn(move |mut req: Request<Body>| {
-                let ip = sa.ip();
-                req.extensions_mut().insert(ip);
-                let s = s.clone();
-                async move { s.clone().dispatch(req).await }
-            });
+    const SECURE: bool = true;
 
-            #[cfg(all(feature = "logging", not(feature = "trace")))]
-            log::trace!("Request from {}", sa);
+    fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<(Self::Stream, Option<SocketAddr>)>> {
+        Acceptor::poll_accept(&self.listener, cx)
+    }
 
-            #[cfg(feature = "trace")]
-            tracing::trace!("Request from {}", sa);
+    fn establish(
+        &self,
+        stream: Self::Stream,
+    ) -> impl Future<Output = std::io::Result<Self::Io>> + Send + 'static {
+        self.tls.accept(stream)
+    }
 
-            let config = config.clone();
-            tokio::task::spawn(async move {
-                match config.accept(tcp_stream).await {
-                    Ok(tcp_stream) => {
-                        if let Err(http_err) = Http::new()
-                            .http1_keep_alive(true)
-                            .serve_connection(tcp_stream, sfn)
-                            .await
-                        {
-                            #[cfg(feature = "logging")]
-                            log::error!("Error while serving HTTP connection: {}", http_err);
-                            #[cfg(feature = "trace")]
-                            tracing::error!("Error while serving HTTP connection: {}", http_err);
-                            #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
-                            eprintln!("Error while serving HTTP connection: {}", http_err);
-                        }
-                    }
-                    Err(e) => {
-                        #[cfg(feature = "logging")]
-                        log::error!("Error while serving TLS: {:?}", e);
-                        #[cfg(feature = "trace")]
-                        tracing::error!("Error while serving TLS: {:?}", e);
-                        #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
-                        eprintln!("Error while serving TLS: {:?}", e);
-                    }
-                }
-            });
-        }
+    fn reject(&self, stream: Self::Stream) {
+        // there is no answering without a TLS handshake.
+        drop(stream);
     }
 }
 
-/// TestApp is a testing framework for ratpack applications. Given an App, it can issue mock
-/// requests to it without standing up a typical web server.
-#[derive(Clone)]
-pub struct TestApp<S: Clone + Send + 'static, T: TransientState + 'static + Clone + Send> {
-    app: App<S, T>,
-    headers: Option<HeaderMap>,
+// Limiter keeps the connections a server has open within the App's limit.
+struct Limiter {
+    limit: Option<(Arc<Semaphore>, usize, ConnectionOverflow)>,
+    counters: Arc<Counters>,
+    at_limit: bool,
 }
 
-impl<S: Clone + Send + 'static, T: TransientState + 'static + Clone + Send> TestApp<S, T> {
-    /// Construct a new tested application.
-    pub fn new(app: App<S, T>) -> Self {
-        Self { app, headers: None }
-    }
+// Whether a connection may be served, holding its place under the limit if so.
+enum Admission {
+    Serve(Option<OwnedSemaphorePermit>),
+    Reject,
+}
 
-    /// with_headers applies the headers to any following request, and acts as an alternative
-    /// constructor.
-    pub fn with_headers(&self, headers: http::HeaderMap) -> Self {
+impl Limiter {
+    fn new(options: ServerOptions, counters: Arc<Counters>) -> Self {
         Self {
-            app: self.app.clone(),
-            headers: Some(headers),
+            limit: options
+                .max_connections
+                .map(|(max, overflow)| (Arc::new(Semaphore::new(max)), max, overflow)),
+            counters,
+            at_limit: false,
         }
     }
 
-    /// dispatch a request to the application, this allows for maximum flexibility.
-    pub async fn dispatch(&self, req: Request<Body>) -> Response<Body> {
-        self.app.dispatch(req).await.unwrap()
-    }
+    // Wait for room for another connection, before accepting it, if connections beyond the limit
+    // are left waiting.
+    async fn reserve(&mut self) -> Option<OwnedSemaphorePermit> {
+        let permits = match &self.limit {
+            Some((permits, _, ConnectionOverflow::Wait)) => permits.clone(),
+            _ => return None,
+        };
 
-    fn populate_headers(&self, mut req: http::request::Builder) -> http::request::Builder {
-        if let Some(include_headers) = self.headers.clone() {
-            for (header, value) in include_headers.clone() {
-                if let Some(header) = header {
-                    req = req.header(header, value.clone());
-                }
+        match permits.clone().try_acquire_owned() {
+            Ok(permit) => {
+                self.at_limit = false;
+                Some(permit)
+            }
+            Err(_) => {
+                self.reached();
+                permits.acquire_owned().await.ok()
             }
         }
-
-        req
     }
 
-    /// Perform a GET request against the path.
-    pub async fn get(&self, path: &str) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
+    // Decide whether an accepted connection may be served; `reserved` is what reserve returned.
+    fn admit(&mut self, reserved: Option<OwnedSemaphorePermit>) -> Admission {
+        let permits = match &self.limit {
+            Some((permits, _, ConnectionOverflow::Reject)) => permits.clone(),
+            _ => return Admission::Serve(reserved),
+        };
 
-        self.app
-            .dispatch(req.uri(path).body(Body::default()).unwrap())
-            .await
-            .unwrap()
+        match permits.try_acquire_owned() {
+            Ok(permit) => {
+                self.at_limit = false;
+                Admission::Serve(Some(permit))
+            }
+            Err(_) => {
+                self.reached();
+                Admission::Reject
+            }
+        }
     }
 
-    /// Perform a POST request against the path.
-    pub async fn post(&self, path: &str, body: Body) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
+    // Count a connection the limit held back, logging when the limit is first reached.
+    fn reached(&mut self) {
+        self.counters.limited.fetch_add(1, Ordering::SeqCst);
+        if self.at_limit {
+            return;
+        }
+        self.at_limit = true;
 
-        self.app
-            .dispatch(req.method(Method::POST).uri(path).body(body).unwrap())
-            .await
-            .unwrap()
+        let _max = self.limit.as_ref().map_or(0, |(_, max, _)| *max);
+
+        #[cfg(all(feature = "logging", not(feature = "trace")))]
+        log::warn!("Connection limit of {} reached", _max);
+        #[cfg(feature = "trace")]
+        tracing::warn!("Connection limit of {} reached", _max);
     }
+}
 
-    /// Perform a DELETE request against the path.
-    pub async fn delete(&self, path: &str) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
-        self.app
-            .dispatch(
-                req.method(Method::DELETE)
-                    .uri(path)
-                    .body(Body::default())
-                    .unwrap(),
-            )
-            .await
-            .unwrap()
+// Answer a connection beyond the limit with a 503 and close it. What the client sent is read for
+// a moment, so that closing with it unread does not reset the connection before the answer
+// arrives.
+async fn reject<I: AsyncRead + AsyncWrite + Unpin>(mut io: I) {
+    let answer =
+        b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+    if io.write_all(answer).await.is_err() || io.shutdown().await.is_err() {
+        return;
     }
 
-    /// Perform a PUT request against the path.
-    pub async fn put(&self, path: &str, body: Body) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
-        self.app
-            .dispatch(req.method(Method::PUT).uri(path).body(body).unwrap())
-            .await
-            .unwrap()
+    let mut buf = [0; 1024];
+    let draining = async { while let Ok(1..) = io.read(&mut buf).await {} };
+    tokio::time::timeout(Duration::from_secs(1), draining)
+        .await
+        .unwrap_or_default();
+}
+
+// Held for the life of a dispatch; also covers dispatches abandoned by a departing client.
+struct InFlightGuard(Arc<Counters>);
+
+impl InFlightGuard {
+    fn new(counters: Arc<Counters>) -> Self {
+        counters.in_flight.fetch_add(1, Ordering::SeqCst);
+        Self(counters)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.total_served.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Held for the life of a dispatch, signalling [Disconnected] if dropped before it is defused: that
+// is, if the dispatch was abandoned before producing a response.
+struct AbandonGuard(Option<watch::Sender<bool>>);
+
+impl AbandonGuard {
+    fn new() -> (Self, Disconnected) {
+        let (sender, receiver) = watch::channel(false);
+        (Self(Some(sender)), Disconnected(receiver))
+    }
+
+    fn defuse(mut self) {
+        self.0.take();
+    }
+}
+
+impl Drop for AbandonGuard {
+    fn drop(&mut self) {
+        if let Some(sender) = self.0.take() {
+            sender.send_replace(true);
+        }
+    }
+}
+
+// The parameter holding the remainder of the path of a request to a prefix route.
+const SUFFIX: &str = "suffix";
+
+/// Guard is a predicate over the incoming request, attached to a route with
+/// [crate::app::RouteBuilder::guard]. A route with a guard only matches requests the guard accepts;
+/// rejected requests continue on to the routes registered after it, as if the route were not
+/// there. Several routes may therefore share a method and path, discriminated by headers:
+///
+/// ```ignore
+///     app.route_builder(Method::POST, "/hook", compose_handler!(github))
+///         .guard(|req| req.headers().contains_key("x-github-event"))
+///         .register();
+///     app.post("/hook", compose_handler!(generic));
+/// ```
+///
+/// A route whose guard rejects the request does not contribute to the Allow header of a 405.
+pub type Guard = Arc<dyn Fn(&Request<Body>) -> bool + Send + Sync>;
+
+/// RouteOptions are the per-route settings configured through [crate::app::RouteBuilder]. The
+/// defaults are those used by [crate::app::App::get] and friends: priority 0, no metadata, no
+/// timeout, no guard, any host, encoded slashes decoded like any other character, and the App's
+/// limit on the size of request bodies.
+#[derive(Clone, Default)]
+pub struct RouteOptions {
+    pub(crate) name: Option<String>,
+    pub(crate) priority: i32,
+    pub(crate) meta: Arc<BTreeMap<String, String>>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) guard: Option<Guard>,
+    pub(crate) host: Option<HostPattern>,
+    pub(crate) reject_encoded_slashes: bool,
+    pub(crate) max_body_size: Option<u64>,
+}
+
+impl RouteOptions {
+    /// The name of the route, used to generate URLs with [crate::app::App::url_for].
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The priority of the route. Higher priorities are tried first.
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    /// The metadata attached to the route.
+    pub fn meta(&self) -> &BTreeMap<String, String> {
+        &self.meta
+    }
+
+    /// The maximum time the route's handler chain may run for, if any.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The host pattern the route is restricted to, if any.
+    pub fn host(&self) -> Option<String> {
+        self.host.as_ref().map(ToString::to_string)
+    }
+
+    /// Whether requests with an encoded slash in a parameter are refused.
+    pub fn rejects_encoded_slashes(&self) -> bool {
+        self.reject_encoded_slashes
+    }
+
+    /// The largest request body the route accepts, if it differs from the App's.
+    pub fn max_body_size(&self) -> Option<u64> {
+        self.max_body_size
+    }
+}
+
+/// RouteMeta is the metadata of the route a request was dispatched to. It is inserted into the
+/// request's extensions before the route's handlers run; see [crate::route_meta].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteMeta(pub(crate) Arc<BTreeMap<String, String>>);
+
+impl RouteMeta {
+    /// Return the value for the key, if it was set with [crate::app::RouteBuilder::meta].
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Iterate over the metadata in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+}
+
+/// PathLimits bound the size of request paths, which are checked against them before any routing
+/// work is done; see [crate::app::App::set_path_limits]. Paths longer than `max_bytes` are
+/// answered with a 414 URI Too Long, and those with too many or too long segments with a 400 Bad
+/// Request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathLimits {
+    /// The maximum length of the path in bytes, as sent. Defaults to 8192.
+    pub max_bytes: usize,
+    /// The maximum number of segments in the path. Defaults to 64.
+    pub max_segments: usize,
+    /// The maximum length of any one segment in bytes, as sent. Defaults to 1024.
+    pub max_segment_bytes: usize,
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 8192,
+            max_segments: 64,
+            max_segment_bytes: 1024,
+        }
+    }
+}
+
+impl PathLimits {
+    pub(crate) fn check(&self, path: &str) -> Result<(), Error> {
+        if path.len() > self.max_bytes {
+            return Err(Error::new_status(
+                StatusCode::URI_TOO_LONG,
+                format!("path exceeds limit of {} bytes", self.max_bytes),
+            ));
+        }
+
+        let mut segments = 0;
+        for segment in path.split('/').skip(1) {
+            segments += 1;
+            if segments > self.max_segments {
+                return Err(Error::new_status(
+                    StatusCode::BAD_REQUEST,
+                    format!("path exceeds limit of {} segments", self.max_segments),
+                ));
+            }
+
+            if segment.len() > self.max_segment_bytes {
+                return Err(Error::new_status(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "path segment exceeds limit of {} bytes",
+                        self.max_segment_bytes
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// RouteInfo describes a registered route; see [crate::app::App::routes].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// The methods the route answers, comma-separated, or `*` for a route answering any method.
+    pub method: String,
+    /// The path template of the route, such as `/users/:id`.
+    pub path: String,
+    /// The number of handlers in the route's chain.
+    pub handlers: usize,
+    /// The name of the route, if it has one.
+    pub name: Option<String>,
+    /// The host pattern the route is restricted to, if any.
+    pub host: Option<String>,
+}
+
+/// RouteBuilder registers a route with [crate::app::RouteOptions]. Construct one with
+/// [crate::app::App::route_builder], configure it, and finish with
+/// [crate::app::RouteBuilder::register]:
+///
+/// ```ignore
+///     app.route_builder(Method::GET, "/admin", compose_handler!(admin))
+///         .priority(5)
+///         .meta("tag", "admin")
+///         .timeout(Duration::from_secs(2))
+///         .guard(|req| req.headers().contains_key("x-admin"))
+///         .host("admin.example.com")
+///         .register();
+/// ```
+pub struct RouteBuilder<'a, S: Send, T: TransientState + 'static + Clone + Send> {
+    app: &'a mut App<S, T>,
+    methods: Methods,
+    path: String,
+    handler: Handler<S, T>,
+    options: RouteOptions,
+}
+
+impl<'a, S: 'static + Send, T: TransientState + 'static + Clone + Send> RouteBuilder<'a, S, T> {
+    /// Name the route, so its URL can be generated with [crate::app::App::url_for]. Names must be
+    /// unique within an App; registering a second route with the same name panics.
+    pub fn name(mut self, name: &str) -> Self {
+        self.options.name = Some(name.to_string());
+        self
+    }
+
+    /// Set the priority of the route. Routes with a higher priority are tried before those with a
+    /// lower one; routes of the same priority are tried in registration order. Defaults to 0.
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.options.priority = priority;
+        self
+    }
+
+    /// Attach a piece of metadata to the route. Handlers can read it with [crate::route_meta].
+    pub fn meta(mut self, key: &str, value: &str) -> Self {
+        Arc::make_mut(&mut self.options.meta).insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Limit how long the route's handler chain may run for. If the limit is exceeded, the chain
+    /// is dropped and a 503 Service Unavailable is returned.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Only match requests for which the guard returns true. See [crate::app::Guard].
+    pub fn guard<F>(mut self, guard: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> bool + Send + Sync + 'static,
+    {
+        self.options.guard = Some(Arc::new(guard));
+        self
+    }
+
+    /// Only match requests made to hosts matching the pattern, such as `api.example.com`. The host
+    /// is taken from the authority of absolute-form requests and from the Host header otherwise,
+    /// without its port. Labels may be parameters, which are made available in [crate::Params]
+    /// alongside those of the path: `:tenant.example.com` captures the first label as `tenant`,
+    /// and `*.example.com` captures it as `subdomain`. Panics if the pattern has an empty label;
+    /// registration panics if a parameter of the host shares its name with one of the path.
+    pub fn host(mut self, pattern: &str) -> Self {
+        self.options.host = Some(HostPattern::new(pattern));
+        self
+    }
+
+    /// Answer requests with a 400 Bad Request if a parameter of the path contains an encoded
+    /// slash (`%2F`). By default the path is split into segments before it is decoded, so
+    /// `/files/:name` receives `a%2Fb.txt` as a `name` of `a/b.txt`; for routes that go on to use
+    /// the value as a file name or similar, that is better refused outright.
+    pub fn reject_encoded_slashes(mut self) -> Self {
+        self.options.reject_encoded_slashes = true;
+        self
+    }
+
+    /// Accept request bodies of up to `max` bytes, whether above or below the App's limit (see
+    /// [crate::app::App::set_max_body_size]), such as for an upload endpoint.
+    pub fn max_body_size(mut self, max: u64) -> Self {
+        self.options.max_body_size = Some(max);
+        self
+    }
+
+    /// Add the route to the App. Panics if the path is not a valid route path.
+    pub fn register(self) {
+        self.app.update(|router| {
+            router.add_with_options(self.methods, self.path, self.handler, self.options);
+        });
+    }
+}
+
+/// Group registers routes under a shared path prefix and handler chain. Construct one with
+/// [crate::app::App::group]:
+///
+/// ```ignore
+///     let mut api = app.group("/api/v1", compose_handler!(validate_authtoken));
+///     api.get("/users", compose_handler!(list_users));
+///     api.post("/users", compose_handler!(create_user));
+/// ```
+///
+/// Here `GET /api/v1/users` is served by `validate_authtoken` followed by `list_users`. Groups may
+/// be nested with [crate::app::Group::group], in which case prefixes and chains are combined
+/// outermost first.
+pub struct Group<'a, S: Send, T: TransientState + 'static + Clone + Send> {
+    app: &'a mut App<S, T>,
+    prefix: String,
+    chain: Handler<S, T>,
+}
+
+impl<'a, S: 'static + Send, T: TransientState + 'static + Clone + Send> Group<'a, S, T> {
+    fn path(&self, path: &str) -> String {
+        format!("{}{}", self.prefix.trim_end_matches('/'), path)
+    }
+
+    fn add(&mut self, methods: impl Into<Methods>, path: &str, ch: Handler<S, T>) {
+        let path = self.path(path);
+        let ch = self.chain.clone().then(ch);
+        self.app.update(|router| {
+            router.add(methods, path, ch);
+        });
+    }
+
+    /// Create a nested group; its prefix is appended to this group's, and its chain performed
+    /// after this group's.
+    pub fn group(&mut self, prefix: &str, ch: Handler<S, T>) -> Group<'_, S, T> {
+        Group {
+            prefix: self.path(prefix),
+            chain: self.chain.clone().then(ch),
+            app: self.app,
+        }
+    }
+
+    /// Begin registering a route with options under the group's prefix and chain; see
+    /// [crate::app::RouteBuilder].
+    pub fn route_builder(
+        &mut self,
+        method: Method,
+        path: &str,
+        ch: Handler<S, T>,
+    ) -> RouteBuilder<'_, S, T> {
+        let path = self.path(path);
+        let ch = self.chain.clone().then(ch);
+        self.app.route_builder(method, &path, ch)
+    }
+
+    /// Create a route for requests of any method under the group's prefix and chain.
+    pub fn any(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Methods::Any, path, ch);
+    }
+
+    /// Create a route for each of the given methods under the group's prefix and chain.
+    pub fn route(&mut self, methods: &[Method], path: &str, ch: Handler<S, T>) {
+        self.add(Methods::set(methods), path, ch);
+    }
+
+    /// Create a route for a GET request under the group's prefix and chain.
+    pub fn get(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::GET, path, ch);
+    }
+
+    /// Create a route for a POST request under the group's prefix and chain.
+    pub fn post(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::POST, path, ch);
+    }
+
+    /// Create a route for a DELETE request under the group's prefix and chain.
+    pub fn delete(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::DELETE, path, ch);
+    }
+
+    /// Create a route for a PUT request under the group's prefix and chain.
+    pub fn put(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::PUT, path, ch);
+    }
+
+    /// Create a route for an OPTIONS request under the group's prefix and chain.
+    pub fn options(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::OPTIONS, path, ch);
+    }
+
+    /// Create a route for a PATCH request under the group's prefix and chain.
+    pub fn patch(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::PATCH, path, ch);
+    }
+
+    /// Create a route for a HEAD request under the group's prefix and chain.
+    pub fn head(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::HEAD, path, ch);
+    }
+
+    /// Create a route for a CONNECT request under the group's prefix and chain.
+    pub fn connect(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::CONNECT, path, ch);
+    }
+
+    /// Create a route for a TRACE request under the group's prefix and chain.
+    pub fn trace(&mut self, path: &str, ch: Handler<S, T>) {
+        self.add(Method::TRACE, path, ch);
+    }
+}
+
+// Clone is implemented by hand so that S does not need to be Clone; the state lives behind an Arc.
+// The routes are copied lazily: the table is shared until either App changes it.
+impl<S: Send, T: TransientState + 'static + Clone + Send> Clone for App<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            router: Arc::new(RwLock::new(self.table())),
+            global_state: self.global_state.clone(),
+            counters: self.counters.clone(),
+            maintenance: Arc::new(self.maintenance.copy()),
+            server: Arc::new(RwLock::new(self.server_options())),
+        }
+    }
+}
+
+impl<S: Send, T: TransientState + 'static + Clone + Send> App<S, T> {
+    // another App on the same routes, settings and maintenance mode, as servers and handlers are
+    // given, so that changes made through any of them apply to all.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            router: self.router.clone(),
+            global_state: self.global_state.clone(),
+            counters: self.counters.clone(),
+            maintenance: self.maintenance.clone(),
+            server: self.server.clone(),
+        }
+    }
+
+    // the routes as they are now.
+    fn table(&self) -> Arc<Router<S, T>> {
+        self.router
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // the settings a server starting now is to serve with.
+    fn server_options(&self) -> ServerOptions {
+        *self.server.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// RouteHandle changes the routes of the [App] it was taken from, including while the App is
+/// serving; it offers all of the App's methods. Clones of a handle change the same routes. See
+/// [App::routes_handle].
+pub struct RouteHandle<S: Send, T: TransientState + 'static + Clone + Send>(App<S, T>);
+
+impl<S: Send, T: TransientState + 'static + Clone + Send> Clone for RouteHandle<S, T> {
+    fn clone(&self) -> Self {
+        Self(self.0.share())
+    }
+}
+
+impl<S: Send, T: TransientState + 'static + Clone + Send> std::ops::Deref for RouteHandle<S, T> {
+    type Target = App<S, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S: Send, T: TransientState + 'static + Clone + Send> std::ops::DerefMut for RouteHandle<S, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<S: 'static + Send, T: TransientState + 'static + Clone + Send> App<S, T> {
+    /// Construct a new App with no state; it will be passed to handlers as `App<()>`.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            router: Arc::new(RwLock::new(Arc::new(Router::new()))),
+            global_state: None,
+            counters: Arc::new(Counters::default()),
+            maintenance: Arc::new(Maintenance::default()),
+            server: Arc::new(RwLock::new(ServerOptions::default())),
+        }
+    }
+
+    /// Construct an App with state.
+    ///
+    /// This has the type `App<S>` where S is `+ 'static + Send` and will be passed to handlers
+    /// with the appropriate concrete type. S does not need to be Clone, as it is stored behind an
+    /// `Arc<Mutex<S>>`.
+    ///
+    pub fn with_state(state: S) -> Self {
+        Self {
+            router: Arc::new(RwLock::new(Arc::new(Router::new()))),
+            global_state: Some(Arc::new(Mutex::new(state))),
+            counters: Arc::new(Counters::default()),
+            maintenance: Arc::new(Maintenance::default()),
+            server: Arc::new(RwLock::new(ServerOptions::default())),
+        }
+    }
+
+    /// Return a snapshot of the connection and request gauges. The gauges are shared by all
+    /// clones of the App, so this may be called from handlers or from outside the server while
+    /// it is running.
+    pub fn stats(&self) -> AppStats {
+        AppStats {
+            connections: self.counters.connections.load(Ordering::SeqCst),
+            in_flight: self.counters.in_flight.load(Ordering::SeqCst),
+            total_served: self.counters.total_served.load(Ordering::SeqCst),
+            limited_connections: self.counters.limited.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Return a handle for changing the routes of the App once it is serving, as clones of it have
+    /// routes of their own. Changes take effect for requests that arrive after them.
+    ///
+    /// ```ignore
+    ///     let mut routes = app.routes_handle();
+    ///     tokio::spawn(app.serve("0.0.0.0:8080"));
+    ///
+    ///     routes.get("/beta", compose_handler!(beta));
+    ///     routes.remove(Method::GET, "/legacy");
+    /// ```
+    pub fn routes_handle(&self) -> RouteHandle<S, T> {
+        RouteHandle(self.share())
+    }
+
+    /// Return a handle for switching the App in and out of maintenance mode at runtime. While in
+    /// it, every request is answered with a 503 Service Unavailable carrying a `Retry-After`
+    /// header, without being routed, except those for the paths given to
+    /// [MaintenanceHandle::allow]. The chain given to [App::wrap_after] still sees the responses.
+    ///
+    /// ```ignore
+    ///     let maintenance = app.maintenance_handle();
+    ///     maintenance.allow(&["/healthz"]);
+    ///     tokio::spawn(app.serve("0.0.0.0:8080"));
+    ///
+    ///     maintenance.enable();
+    ///     migrate().await?;
+    ///     maintenance.disable();
+    /// ```
+    ///
+    /// Take the handle from the App before serving it, or from the App handlers are given; clones
+    /// of an App have a mode of their own. Requests already being dispatched when it is enabled
+    /// are finished as usual.
+    pub fn maintenance_handle(&self) -> MaintenanceHandle {
+        MaintenanceHandle(self.maintenance.clone())
+    }
+
+    // FIXME Currently you must await this, seems pointless.
+    /// Return the state of the App. This is returned as `Arc<Mutex<S>>` and must be acquired under
+    /// lock. In situations where there is no state, [std::option::Option::None] is returned.
+    pub async fn state(&self) -> Option<Arc<Mutex<S>>> {
+        self.global_state.clone()
+    }
+
+    /// Create a route for a GET request. See App's docs and [crate::handler::Handler] for
+    /// more information.
+    pub fn get(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::GET, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for GET requests to `prefix` and every path beneath it, such as for serving
+    /// static files. The remainder of the path after the prefix is given to the handler as the
+    /// `suffix` parameter, percent-decoded and without a leading slash:
+    ///
+    /// ```ignore
+    ///     app.get_prefix("/assets", compose_handler!(assets));
+    /// ```
+    ///
+    /// Here a request for `/assets/css/site.css` has a `suffix` of `css/site.css`, and one for
+    /// `/assets` an empty `suffix`. More specific routes are tried first, whatever order they were
+    /// registered in: `/assets/logo.png` or a prefix route for `/assets/img` win over this one for
+    /// the requests they match. The prefix may not itself have a parameter named `suffix`.
+    pub fn get_prefix(&mut self, prefix: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(
+                Method::GET,
+                format!("{}/*{}", prefix.trim_end_matches('/'), SUFFIX),
+                ch,
+            );
+        });
+    }
+
+    /// Create a named route for a GET request; see [crate::app::App::url_for]. Routes for other
+    /// methods may be named with [crate::app::RouteBuilder::name].
+    pub fn get_named(&mut self, name: &str, path: &str, ch: Handler<S, T>) {
+        self.route_builder(Method::GET, path, ch)
+            .name(name)
+            .register();
+    }
+
+    /// Create a route for a GET request that performs one of several chains, chosen per request
+    /// in proportion to their weights: `&[(90, current), (10, candidate)]` sends about one request
+    /// in ten to `candidate`. See [crate::weighted::Weighted] for seeding and sticky assignment.
+    pub fn get_weighted(&mut self, path: &str, variants: &[(u32, Handler<S, T>)]) {
+        self.get(path, Weighted::new(variants).handler());
+    }
+
+    /// Stop answering `method` requests at `path`, returning whether a route did. Paths are
+    /// compared as registered, except that parameter names do not matter: `/users/:id` removes a
+    /// route added as `/users/:user`, but not one added as `/users/new`, nor the reverse. A route
+    /// added for several methods keeps answering the others, and routes added with [App::any] are
+    /// not affected.
+    pub fn remove(&mut self, method: Method, path: &str) -> bool {
+        self.update(|router| router.remove(&method, path.to_string()))
+    }
+
+    // change the routes. the table is copied if a request is being dispatched with it, so that
+    // the request is not affected.
+    fn update<R>(&self, f: impl FnOnce(&mut Router<S, T>) -> R) -> R {
+        let mut router = self.router.write().unwrap_or_else(PoisonError::into_inner);
+        f(Arc::make_mut(&mut router))
+    }
+
+    /// List the registered routes, ordered by method and then path. This is useful for printing a
+    /// route table at startup:
+    ///
+    /// ```ignore
+    ///     for route in app.routes() {
+    ///         println!("{:8} {} ({} handlers)", route.method, route.path, route.handlers);
+    ///     }
+    /// ```
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.table().routes()
+    }
+
+    /// Generate the path of the route registered under `name`, substituting the given values for
+    /// its parameters:
+    ///
+    /// ```ignore
+    ///     app.get_named("user_show", "/users/:id", compose_handler!(show_user));
+    ///     assert_eq!(app.url_for("user_show", &[("id", "42")])?, "/users/42");
+    /// ```
+    ///
+    /// Handlers may call this on the App they are given, e.g. to build a `Location` header. An
+    /// error is returned if there is no such route, a parameter is missing or unknown, or a value
+    /// contains characters that would need encoding.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, Error> {
+        self.table().url_for(name, params)
+    }
+
+    /// Create a route that answers requests of any method. Handlers can tell the method of the
+    /// request from [http::Request::method]. Routes for specific methods on the same path are
+    /// preferred only if they are registered first (or with a higher priority).
+    pub fn any(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Methods::Any, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a request of the given method, which may be an extension method such
+    /// as WebDAV's `PROPFIND`:
+    ///
+    /// ```ignore
+    ///     app.method(Method::from_bytes(b"PROPFIND")?, "/files/*path", compose_handler!(propfind));
+    /// ```
+    pub fn method(&mut self, method: Method, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(method, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route that answers each of the given methods with the same handler, such as a form
+    /// and its submission:
+    ///
+    /// ```ignore
+    ///     app.route(&[Method::GET, Method::POST], "/form", compose_handler!(form));
+    /// ```
+    ///
+    /// Panics if no methods are given.
+    pub fn route(&mut self, methods: &[Method], path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Methods::set(methods), path.to_string(), ch);
+        });
+    }
+
+    /// Register a route redirecting `from` to `to` with the given status, which must be a 3xx.
+    /// Parameters captured by `from` may be used in `to`, and the query string of the request is
+    /// carried over to the redirect:
+    ///
+    /// ```ignore
+    ///     app.redirect("/old/:id", "/new/:id", StatusCode::MOVED_PERMANENTLY);
+    /// ```
+    ///
+    /// Here `GET /old/1?page=2` is answered with a 301 to `/new/1?page=2`. The target may also be
+    /// absolute, such as `https://example.com/new/:id`. The route answers GET and HEAD. Panics if
+    /// the status is not a redirection, or if `to` uses a parameter `from` does not capture.
+    pub fn redirect(&mut self, from: &str, to: &str, status: StatusCode) {
+        if !status.is_redirection() {
+            panic!("redirect status {} is not a redirection", status);
+        }
+
+        // an absolute target keeps its scheme and authority; only its path is a template.
+        let (origin, to) = match to.find("://") {
+            Some(i) => match to[i + 3..].find('/') {
+                Some(j) => to.split_at(i + 3 + j),
+                None => (to, "/"),
+            },
+            None => ("", to),
+        };
+
+        let source = Path::new(from.to_string()).params();
+        let target = Path::new(to.to_string());
+        if let Some(param) = target.params().into_iter().find(|p| !source.contains(p)) {
+            panic!(
+                "redirect target `{}` uses parameter `{}`, which `{}` does not capture",
+                to, param, from
+            );
+        }
+
+        let origin = origin.to_string();
+        let handler = Handler::around(
+            Arc::new(move |req, _resp, params, _app, state, _next| {
+                let location =
+                    target
+                        .url_from_params(&params)
+                        .map(|path| match req.uri().query() {
+                            Some(query) => format!("{}{}?{}", origin, path, query),
+                            None => format!("{}{}", origin, path),
+                        });
+
+                Box::pin(async move {
+                    let response = Response::builder()
+                        .status(status)
+                        .header(http::header::LOCATION, location.map_err(Error::new)?)
+                        .body(Body::empty())?;
+                    Ok((req, Some(response), state))
+                })
+            }),
+            None,
+        )
+        .named("redirect");
+
+        self.update(|router| {
+            router.add(
+                Methods::set(&[Method::GET, Method::HEAD]),
+                from.to_string(),
+                handler,
+            );
+        });
+    }
+
+    /// Create a route for a POST request. See App's docs and [crate::handler::Handler] for
+    /// more information.
+    pub fn post(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::POST, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a DELETE request. See App's docs and [crate::handler::Handler] for
+    /// more information.
+    pub fn delete(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::DELETE, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a PUT request. See App's docs and [crate::handler::Handler] for
+    /// more information.
+    pub fn put(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::PUT, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for an OPTIONS request. See App's docs and
+    /// [crate::handler::Handler] for more information.
+    pub fn options(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::OPTIONS, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a PATCH request. See App's docs and
+    /// [crate::handler::Handler] for more information.
+    pub fn patch(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::PATCH, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a HEAD request. See App's docs and
+    /// [crate::handler::Handler] for more information.
+    pub fn head(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::HEAD, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a CONNECT request. See App's docs and
+    /// [crate::handler::Handler] for more information.
+    pub fn connect(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::CONNECT, path.to_string(), ch);
+        });
+    }
+
+    /// Create a route for a TRACE request. See App's docs and
+    /// [crate::handler::Handler] for more information.
+    pub fn trace(&mut self, path: &str, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.add(Method::TRACE, path.to_string(), ch);
+        });
+    }
+
+    /// Match slashes in request paths exactly: a route of `/account` no longer serves
+    /// `/account/`, one of `/account/` serves only that, and paths with empty segments such as
+    /// `//account` match no route at all. Requests that no longer match are answered with a 404.
+    /// By default, trailing and duplicate slashes are ignored.
+    pub fn set_strict_slashes(&mut self, strict_slashes: bool) {
+        self.update(|router| {
+            router.set_strict_slashes(strict_slashes);
+        });
+    }
+
+    /// Match the literal components of route paths without regard to case, so a route of
+    /// `/api/users` also serves `/API/Users`. Parameters are passed through in their original
+    /// case. Matching is case-sensitive unless this is set.
+    pub fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.update(|router| {
+            router.set_case_insensitive(case_insensitive);
+        });
+    }
+
+    /// Bound the size of request paths; see [crate::app::PathLimits] for the defaults.
+    ///
+    /// ```ignore
+    ///     app.set_path_limits(PathLimits {
+    ///         max_segments: 16,
+    ///         ..Default::default()
+    ///     });
+    /// ```
+    pub fn set_path_limits(&mut self, limits: PathLimits) {
+        self.update(|router| {
+            router.set_path_limits(limits);
+        });
+    }
+
+    /// Refuse request bodies larger than `max` bytes with a 413 Payload Too Large. Requests whose
+    /// `Content-Length` exceeds it are refused before any handler runs; the bodies of those sent
+    /// without one fail once more than `max` bytes have arrived, so that handlers reading them,
+    /// with [crate::body::read_limited] or otherwise, fail with the 413. Routes may raise or lower
+    /// the limit with [crate::app::RouteBuilder::max_body_size]. There is no limit by default.
+    ///
+    /// The limit is on the bytes sent by the client, before any decompression or parsing, such as
+    /// that of [crate::body::read_limited] or multipart bodies, which have limits of their own.
+    pub fn set_max_body_size(&mut self, max: u64) {
+        self.update(|router| {
+            router.set_max_body_size(max);
+        });
+    }
+
+    /// Resolve `.` and `..` segments of request paths before routing them, so that a request for
+    /// `/static/../admin` is routed, and seen by handlers, as `/admin`. Requests whose path would
+    /// resolve to above the root are answered with a 400 Bad Request. This is on by default;
+    /// turn it off for apps, such as proxies, that need the path exactly as it was sent.
+    pub fn set_normalize_paths(&mut self, normalize: bool) {
+        self.update(|router| {
+            router.set_normalize_paths(normalize);
+        });
+    }
+
+    /// Answer OPTIONS requests for paths that have routes, but no OPTIONS route, with a 204 No
+    /// Content listing the registered methods in its `Allow` header. Registered OPTIONS routes
+    /// always take precedence. Without this, such requests receive a 405 Method Not Allowed.
+    pub fn enable_auto_options(&mut self) {
+        self.update(|router| {
+            router.enable_auto_options();
+        });
+    }
+
+    /// Let HTML forms, which can only be sent with GET or POST, reach PUT, PATCH and DELETE routes:
+    /// a POST request naming one of those in its `X-HTTP-Method-Override` header, or failing that
+    /// in the `_method` field of its `application/x-www-form-urlencoded` body, is routed and
+    /// handled as if it had been sent with that method. Other requests, and other methods, are
+    /// left alone. The method the request arrived with is available through
+    /// [crate::original_method].
+    ///
+    /// To find the field, form bodies are read before routing, within the limit given to
+    /// [App::set_max_body_size] or 1MiB without one, and put back for the handlers.
+    pub fn enable_method_override(&mut self) {
+        self.update(|router| {
+            router.enable_method_override();
+        });
+    }
+
+    /// Render errors no handler turned into a response as `application/problem+json` problems
+    /// (see [crate::problem::Problem]) rather than plain text, for clients whose `Accept` header
+    /// prefers `application/problem+json` or `application/json` to `text/plain`. The problem has
+    /// the status of the error, and its message as the detail. Others, including those sending no
+    /// `Accept` header, are answered in plain text as before.
+    ///
+    /// A [crate::problem::Problem] returned by a handler is rendered as a problem whether this is
+    /// enabled or not.
+    pub fn enable_problem_json(&mut self) {
+        self.update(|router| {
+            router.enable_problem_json();
+        });
+    }
+
+    /// Answer requests whose chain failed with an `X-Ratpack-Stage` header naming the stage the
+    /// error came from, as named by [crate::compose_handler!] or [Handler::named]. This is meant
+    /// for debugging, as it tells clients how the App is put together; the stage is always logged
+    /// along with the error.
+    pub fn enable_stage_header(&mut self) {
+        self.update(|router| {
+            router.enable_stage_header();
+        });
+    }
+
+    // the settings of the servers the App starts from now on.
+    fn server_options_mut(&self) -> std::sync::RwLockWriteGuard<'_, ServerOptions> {
+        self.server.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stop waiting for open connections to finish this long after a server started with
+    /// [App::serve_with_shutdown], or its TLS and Unix socket counterparts, is asked to stop,
+    /// closing those still open. By default it waits for as long as they take. Applies to servers
+    /// started from then on.
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.server_options_mut().shutdown_timeout = Some(timeout);
+    }
+
+    /// Close HTTP/1 connections whose client has not sent the whole head of a request, its request
+    /// line and headers, within `timeout`, so that clients sending them a byte at a time cannot
+    /// hold connections open forever. For a connection's first request, the time counts from the
+    /// connection being accepted. Connections are closed without a response, as hyper gives none.
+    /// There is no limit by default. Applies to servers started from then on.
+    pub fn set_header_read_timeout(&mut self, timeout: Duration) {
+        self.server_options_mut().header_read_timeout = Some(timeout);
+    }
+
+    /// Keep HTTP/1 connections open for further requests once a response has been sent, as is
+    /// done by default, or close them after each. Applies to servers started from then on.
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.server_options_mut().keep_alive = keep_alive;
+    }
+
+    /// Close connections over which nothing has been sent or received for `timeout`, so that
+    /// clients keeping connections open without using them do not exhaust the server's file
+    /// descriptors. Requests still in progress, such as those whose handlers take longer than
+    /// `timeout`, are answered before the connection is closed. Connections are kept open for as
+    /// long as clients like by default. Applies to servers started from then on.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.server_options_mut().idle_timeout = Some(timeout);
+    }
+
+    /// Close connections once they have made `max` requests, answering those in progress first.
+    /// There is no limit by default. Applies to servers started from then on. Panics if `max` is
+    /// zero.
+    pub fn set_max_requests_per_connection(&mut self, max: usize) {
+        if max == 0 {
+            panic!("no request could be served: max requests per connection must be above zero");
+        }
+
+        self.server_options_mut().max_requests = Some(max);
+    }
+
+    /// Serve at most `max` connections at once, so that a flood of connections cannot exhaust the
+    /// server's memory; those beyond it are dealt with as `overflow` says. Reaching the limit is
+    /// logged, and the connections held back are counted in [App::stats]. The limit is for each
+    /// server the App is serving; there is none by default. Applies to servers started from then
+    /// on. Panics if `max` is zero.
+    ///
+    /// ```ignore
+    ///     app.set_max_connections(10_000, ConnectionOverflow::Reject);
+    /// ```
+    pub fn set_max_connections(&mut self, max: usize, overflow: ConnectionOverflow) {
+        if max == 0 {
+            panic!("no connection could be served: max connections must be above zero");
+        }
+
+        self.server_options_mut().max_connections = Some((max, overflow));
+    }
+
+    /// Perform `ch` for requests whose path matches a route, but whose method does not, instead of
+    /// responding with a plain 405 Method Not Allowed. The handler receives the original request
+    /// and no parameters; the methods that would have been answered are available through
+    /// [crate::allowed_methods]. If the response has no `Allow` header, one is added.
+    pub fn method_not_allowed(&mut self, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.set_method_not_allowed(ch);
+        });
+    }
+
+    /// Perform `ch` before the chain of every route, such as for logging or authentication:
+    ///
+    /// ```ignore
+    ///     app.wrap(compose_handler!(log_request, request_id));
+    /// ```
+    ///
+    /// This applies to all routes, whether they are added before or after the call, as the chains
+    /// are joined when a request is dispatched. The wrapping chain sees the parameters of the
+    /// matched route, and is within its timeout; an error from it ends the request as an error from
+    /// the route's own chain would. Requests that match no route are not wrapped. Calling this
+    /// again appends to the wrapping chain.
+    pub fn wrap(&mut self, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.wrap(ch);
+        });
+    }
+
+    /// Perform `ch` after every request, once its response has been produced, such as to set
+    /// headers on every response or to record latency:
+    ///
+    /// ```ignore
+    ///     app.wrap_after(compose_handler!(security_headers, record_latency));
+    /// ```
+    ///
+    /// The chain is given the response, which may be modified or replaced, and always present:
+    /// when a route returns an error, or no route matches, it is given the response rendering the
+    /// error. As the request has been consumed by then, the chain is given a copy of its method,
+    /// URI, version and headers, with an empty body and no parameters; [crate::elapsed] still
+    /// works on it. An error from the chain is rendered as the response. Calling this again
+    /// appends to the chain.
+    pub fn wrap_after(&mut self, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.wrap_after(ch);
+        });
+    }
+
+    /// Perform `ch` for requests sending `Expect: 100-continue` before they are routed, so that
+    /// they can be refused before their client sends the body, such as an upload by a client that
+    /// is not signed in:
+    ///
+    /// ```ignore
+    ///     app.on_expect_continue(compose_handler!(authenticate_upload));
+    /// ```
+    ///
+    /// Clients sending the header wait to be told to continue before sending the body, and are
+    /// told so once a handler first reads it. A response or error from the chain answers the
+    /// request without reading its body, so the client is never told to continue; the connection
+    /// is closed after the response rather than reading the body that was not asked for. Requests
+    /// the chain passes on without a response are routed as usual, and their body is read, and so
+    /// continued, by whichever handler reads it first. The chain is given no parameters, as the
+    /// request has not yet been routed. Calling this again appends to the chain.
+    ///
+    /// Without it, route handlers answering without reading the body spare the client sending it
+    /// all the same; this lets the decision be made before anything else reads it, such as
+    /// [App::enable_method_override] reading forms.
+    pub fn on_expect_continue(&mut self, ch: Handler<S, T>) {
+        self.update(|router| {
+            router.on_expect_continue(ch);
+        });
+    }
+
+    /// Mount the routes of another App under `prefix`: a route of `/users/:id` mounted at
+    /// `/admin` is served at `/admin/users/:id`, and a route of `/` at both `/admin` and
+    /// `/admin/`. Parameters in the prefix, such as `/tenants/:tenant`, are merged into the
+    /// [crate::Params] of the mounted handlers.
+    ///
+    /// Mounted routes are dispatched by this App, so their handlers receive this App and its
+    /// state; the state of the mounted App, if any, is not used. Routes added to the mounted App
+    /// after it is mounted are not seen.
+    pub fn mount(&mut self, prefix: &str, app: App<S, T>) {
+        let other = app.table();
+        self.update(|router| {
+            router.mount(prefix.to_string(), &other);
+        });
+    }
+
+    /// Add the routes of another App to this one, at the paths they were registered with. This
+    /// is useful for building features as separate Apps:
+    ///
+    /// ```ignore
+    ///     let mut app = App::with_state(state);
+    ///     app.merge(users::routes())?;
+    ///     app.merge(billing::routes())?;
+    /// ```
+    ///
+    /// An error is returned, and no routes are added, if a route of `app` would answer the same
+    /// requests as one of this App (the same method, host and path, whatever its parameters are
+    /// named), or has the name of one of its routes.
+    ///
+    /// As with [App::mount], merged routes are dispatched by this App, so their handlers receive
+    /// this App and its state; the state of `app`, if any, is not used, and neither are its
+    /// settings, such as [App::set_strict_slashes].
+    pub fn merge(&mut self, app: App<S, T>) -> Result<(), Error> {
+        let other = app.table();
+        self.update(|router| router.merge(&other))
+    }
+
+    /// Create a group of routes sharing a path prefix and a handler chain, which is performed
+    /// before the chain of each route in the group. See [crate::app::Group].
+    pub fn group(&mut self, prefix: &str, ch: Handler<S, T>) -> Group<'_, S, T> {
+        Group {
+            app: self,
+            prefix: prefix.to_string(),
+            chain: ch,
+        }
+    }
+
+    /// Begin registering a route with options; see [crate::app::RouteBuilder]. The route is not
+    /// added until [crate::app::RouteBuilder::register] is called.
+    pub fn route_builder(
+        &mut self,
+        method: Method,
+        path: &str,
+        ch: Handler<S, T>,
+    ) -> RouteBuilder<'_, S, T> {
+        RouteBuilder {
+            app: self,
+            methods: method.into(),
+            path: path.to_string(),
+            handler: ch,
+            options: RouteOptions::default(),
+        }
+    }
+
+    /// Dispatch a route based on the request. Returns a response based on the error status of the
+    /// handler chain following the normal chain of responsibility rules described elsewhere. Only
+    /// needed by server implementors.
+    pub async fn dispatch(&self, mut req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let _in_flight = InFlightGuard::new(self.counters.clone());
+        let (abandon, disconnected) = AbandonGuard::new();
+        req.extensions_mut().insert(disconnected);
+        let _start = RequestStart::now();
+        req.extensions_mut().insert(_start);
+        req.extensions_mut()
+            .insert(crate::cookies::ParsedCookies::default());
+        let failed = crate::handler::FailedStage::default();
+        req.extensions_mut().insert(failed.clone());
+
+        #[cfg(debug_assertions)]
+        crate::body::track(&mut req);
+
+        let _uri = req.uri().clone();
+        let _method = req.method().clone();
+
+        #[cfg(all(feature = "logging", not(feature = "trace")))]
+        log::info!("{} request to {}", _method, _uri);
+
+        #[cfg(feature = "trace")]
+        tracing::info!("{} request to {}", _method, _uri);
+
+        // the table is held for the whole request, so requests in flight are unaffected by
+        // routes added or removed meanwhile.
+        let router = self.table();
+
+        // the request itself is consumed by its route, so the after chain is given its head.
+        let head = router.after().map(|_| crate::request_head(&req));
+        let problem_json = router.problem_json() && crate::problem::accepted(req.headers());
+
+        // in maintenance mode, requests are answered without being routed.
+        let dispatched = match self.maintenance.response(&req, &router) {
+            Some(resp) => Ok(resp),
+            None => match router.expect_continue() {
+                // requests expecting a 100 Continue may be refused before their body is read.
+                Some(check) if crate::body::expects_continue(&req) => match check
+                    .perform(req, None, Params::default(), self.share(), T::initial())
+                    .await
+                {
+                    Ok((_, Some(resp), _)) => Ok(resp),
+                    Ok((req, None, _)) => router.dispatch(req, self.share()).await,
+                    Err(e) => Err(e),
+                },
+                _ => router.dispatch(req, self.share()).await,
+            },
+        };
+
+        let resp = match dispatched {
+            Ok(resp) => {
+                #[allow(clippy::clone_on_copy)]
+                let _status = resp.status().clone();
+
+                #[cfg(all(feature = "logging", not(feature = "trace")))]
+                log::info!(
+                    "{} request to {}: responding with status {} after {:?}",
+                    _method,
+                    _uri,
+                    _status,
+                    _start.0.elapsed(),
+                );
+
+                #[cfg(feature = "trace")]
+                tracing::info!(
+                    "{} request to {}: responding with status {} after {:?}",
+                    _method,
+                    _uri,
+                    _status,
+                    _start.0.elapsed(),
+                );
+
+                resp
+            }
+            Err(e) => {
+                let stage = failed.get();
+                let _from = stage
+                    .map(|stage| format!(" from stage `{}`", stage))
+                    .unwrap_or_default();
+
+                #[cfg(all(feature = "logging", not(feature = "trace")))]
+                log::error!(
+                    "{} request to {}: responding with error {:?}{} after {:?}",
+                    _method,
+                    _uri,
+                    e,
+                    _from,
+                    _start.0.elapsed(),
+                );
+
+                #[cfg(feature = "trace")]
+                tracing::error!(
+                    stage = stage.unwrap_or_default(),
+                    "{} request to {}: responding with error {:?}{} after {:?}",
+                    _method,
+                    _uri,
+                    e,
+                    _from,
+                    _start.0.elapsed(),
+                );
+
+                let mut resp = error_response(e, problem_json);
+                if let (true, Some(stage)) = (router.stage_header(), stage) {
+                    if let Ok(stage) = HeaderValue::from_str(stage) {
+                        resp.headers_mut().insert("x-ratpack-stage", stage);
+                    }
+                }
+                resp
+            }
+        };
+
+        let mut resp = match (router.after(), head) {
+            (Some(after), Some(head)) => {
+                match after
+                    .perform(
+                        head,
+                        Some(resp),
+                        Params::default(),
+                        self.share(),
+                        T::initial(),
+                    )
+                    .await
+                {
+                    Ok((_, Some(resp), _)) => resp,
+                    Ok((_, None, _)) => error_response(
+                        Error::StatusCode(StatusCode::INTERNAL_SERVER_ERROR, String::new()),
+                        problem_json,
+                    ),
+                    Err(e) => error_response(e, problem_json),
+                }
+            }
+            _ => resp,
+        };
+
+        // whatever the handlers did, responses to HEAD requests have no body.
+        if _method == Method::HEAD {
+            crate::body::strip(&mut resp);
+        }
+
+        abandon.defuse();
+        Ok(resp)
+    }
+
+    /// Bind a TCP/HTTP server to `addr`, resolved as with [App::serve], without serving yet, so
+    /// that the address it was bound to can be learned first; see [Server].
+    pub async fn listen(self, addr: impl ToSocketAddrs) -> Result<Server<S, T>, ServerError> {
+        Server::new(self, bind(addr).await?)
+    }
+
+    /// Bind a TLS-backed TCP/HTTP server to `addr` without serving yet, as [App::listen] does.
+    #[cfg(feature = "tls")]
+    pub async fn listen_tls(
+        self,
+        addr: impl ToSocketAddrs,
+        config: tokio_rustls::rustls::ServerConfig,
+    ) -> Result<Server<S, T>, ServerError> {
+        let mut server = Server::new(self, bind(addr).await?)?;
+        server.tls = Some(config);
+        Ok(server)
+    }
+
+    /// Bind a HTTP server to a Unix domain socket without serving yet, as [App::listen] does.
+    #[cfg(feature = "unix")]
+    pub fn listen_unix(self, filename: PathBuf) -> Result<UnixServer<S, T>, ServerError> {
+        let listener = UnixListener::bind(filename)?;
+        Ok(UnixServer {
+            app: self,
+            local_addr: listener.local_addr()?,
+            listener,
+        })
+    }
+
+    /// Start a HTTP server on a Unix domain socket with tokio. Performs dispatch on an as-needed
+    /// basis.
+    #[cfg(feature = "unix")]
+    pub async fn serve_unix(self, filename: PathBuf) -> Result<(), ServerError> {
+        self.serve_unix_with_shutdown(filename, std::future::pending())
+            .await
+    }
+
+    /// Start a HTTP server on a Unix domain socket as [App::serve_unix] does, stopping once
+    /// `signal` resolves as with [App::serve_with_shutdown].
+    #[cfg(feature = "unix")]
+    pub async fn serve_unix_with_shutdown(
+        self,
+        filename: PathBuf,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        let unix_listener = UnixListener::bind(filename)?;
+        self.serve_unix_listener_with_shutdown(unix_listener, signal)
+            .await
+    }
+
+    /// Start a HTTP server on a Unix domain socket bound beforehand, stopping once `signal`
+    /// resolves as with [App::serve_with_shutdown].
+    #[cfg(feature = "unix")]
+    pub async fn serve_unix_listener_with_shutdown(
+        self,
+        unix_listener: UnixListener,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        self.accept_connections(unix_listener, signal).await;
+        Ok(())
+    }
+
+    /// Start a TCP/HTTP server with tokio. Performs dispatch on an as-needed basis. This is a more
+    /// common path for users to start a server.
+    ///
+    /// `addr` may name a host, such as `localhost:8080`, rather than an IP address. The addresses
+    /// it resolves to are tried in the order the resolver returns them, and the server listens on
+    /// the first that can be bound alone: a name resolving to both IPv4 and IPv6 addresses, as
+    /// `localhost` often does, is served on whichever of them is listed first only. Listen on
+    /// `[::]` or `0.0.0.0` to be reached over every interface. If no address can be bound, the
+    /// error lists each one tried and why it failed.
+    pub async fn serve(self, addr: impl ToSocketAddrs) -> Result<(), ServerError> {
+        self.serve_with_shutdown(addr, std::future::pending()).await
+    }
+
+    /// Start a TCP/HTTP server as [App::serve] does, stopping once `signal` resolves: no more
+    /// connections are accepted, and those open are closed once the requests in progress on them
+    /// have been answered. Returns once they all are, or once the timeout set with
+    /// [App::set_shutdown_timeout] has passed, closing those still open.
+    ///
+    /// ```ignore
+    ///     app.serve_with_shutdown("0.0.0.0:8080", async {
+    ///         tokio::signal::ctrl_c().await.unwrap_or_default();
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn serve_with_shutdown(
+        self,
+        addr: impl ToSocketAddrs,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        let tcp_listener = bind(addr).await?;
+        self.serve_listener_with_shutdown(tcp_listener, signal)
+            .await
+    }
+
+    /// Start a TCP/HTTP server as [App::serve] does, on a listener bound beforehand, such as by a
+    /// supervisor or before dropping privileges. Binding port 0 this way tells tests which port
+    /// the server is on before it starts.
+    ///
+    /// ```ignore
+    ///     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    ///     let addr = listener.local_addr()?;
+    ///     tokio::spawn(app.serve_listener(listener));
+    /// ```
+    pub async fn serve_listener(self, listener: TcpListener) -> Result<(), ServerError> {
+        self.serve_listener_with_shutdown(listener, std::future::pending())
+            .await
+    }
+
+    /// Start a TCP/HTTP server as [App::serve_listener] does, on a listener from the standard
+    /// library; it is made nonblocking, as tokio requires. Must be called from within a tokio
+    /// runtime.
+    pub async fn serve_std_listener(
+        self,
+        listener: std::net::TcpListener,
+    ) -> Result<(), ServerError> {
+        listener.set_nonblocking(true)?;
+        self.serve_listener(TcpListener::from_std(listener)?).await
+    }
+
+    /// Start a TCP/HTTP server on a listener bound beforehand, stopping once `signal` resolves as
+    /// with [App::serve_with_shutdown].
+    pub async fn serve_listener_with_shutdown(
+        self,
+        tcp_listener: TcpListener,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        self.accept_connections(tcp_listener, signal).await;
+        Ok(())
+    }
+
+    /// Start a TLS-backed TCP/HTTP server with tokio. Performs dispatch on an as-needed basis. This is a more
+    /// common path for users to start a server. `addr` is resolved and bound as with [App::serve].
+    #[cfg(feature = "tls")]
+    pub async fn serve_tls(
+        self,
+        addr: impl ToSocketAddrs,
+        config: tokio_rustls::rustls::ServerConfig,
+    ) -> Result<(), ServerError> {
+        self.serve_tls_with_shutdown(addr, config, std::future::pending())
+            .await
+    }
+
+    /// Start a TLS-backed TCP/HTTP server as [App::serve_tls] does, stopping once `signal`
+    /// resolves as with [App::serve_with_shutdown].
+    #[cfg(feature = "tls")]
+    pub async fn serve_tls_with_shutdown(
+        self,
+        addr: impl ToSocketAddrs,
+        config: tokio_rustls::rustls::ServerConfig,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        let tcp_listener = bind(addr).await?;
+        self.serve_tls_listener_with_shutdown(tcp_listener, config, signal)
+            .await
+    }
+
+    /// Start a TLS-backed TCP/HTTP server as [App::serve_tls] does, on a listener bound
+    /// beforehand as with [App::serve_listener].
+    #[cfg(feature = "tls")]
+    pub async fn serve_tls_listener(
+        self,
+        listener: TcpListener,
+        config: tokio_rustls::rustls::ServerConfig,
+    ) -> Result<(), ServerError> {
+        self.serve_tls_listener_with_shutdown(listener, config, std::future::pending())
+            .await
+    }
+
+    /// Start a TLS-backed TCP/HTTP server on a listener bound beforehand, stopping once `signal`
+    /// resolves as with [App::serve_with_shutdown].
+    #[cfg(feature = "tls")]
+    pub async fn serve_tls_listener_with_shutdown(
+        self,
+        tcp_listener: TcpListener,
+        config: tokio_rustls::rustls::ServerConfig,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        let tls_listener = TlsListener {
+            listener: tcp_listener,
+            tls: tokio_rustls::TlsAcceptor::from(Arc::new(config)),
+        };
+        self.accept_connections(tls_listener, signal).await;
+        Ok(())
+    }
+
+    // Accept connections and serve them until `signal` resolves, then wait for those open to
+    // finish. Failing to accept a connection, as when the process is out of file descriptors, is
+    // logged and accepting resumes after a moment; the server only stops for `signal`.
+    async fn accept_connections<A: Acceptor>(self, acceptor: A, signal: impl Future<Output = ()>) {
+        let options = self.server_options();
+        let shutdown = Shutdown::new();
+        let mut limiter = Limiter::new(options, self.counters.clone());
+        tokio::pin!(signal);
+        loop {
+            let reserved = tokio::select! {
+                reserved = limiter.reserve() => reserved,
+                _ = &mut signal => break,
+            };
+            let accepted = tokio::select! {
+                accepted = std::future::poll_fn(|cx| acceptor.poll_accept(cx)) => accepted,
+                _ = &mut signal => break,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    #[cfg(feature = "logging")]
+                    log::warn!("Error while accepting connection: {}", e);
+                    #[cfg(feature = "trace")]
+                    tracing::warn!("Error while accepting connection: {}", e);
+                    #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
+                    eprintln!("Error while accepting connection: {}", e);
+
+                    // a client giving up on its connection says nothing about the next one.
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::ConnectionAborted
+                            | std::io::ErrorKind::ConnectionReset
+                            | std::io::ErrorKind::ConnectionRefused
+                    ) {
+                        continue;
+                    }
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(ACCEPT_BACKOFF) => continue,
+                        _ = &mut signal => break,
+                    }
+                }
+            };
+            let permit = match limiter.admit(reserved) {
+                Admission::Serve(permit) => permit,
+                Admission::Reject => {
+                    acceptor.reject(stream);
+                    continue;
+                }
+            };
+            let connection = ConnectionGuard::new(self.counters.clone(), permit);
+            let stopping = shutdown.connection();
+
+            let s = self.share();
+            let sfn = service_fn(move |mut req: Request<Body>| {
+                if let Some(peer) = peer {
+                    req.extensions_mut().insert(peer.ip());
+                }
+                if A::SECURE {
+                    req.extensions_mut().insert(http::uri::Scheme::HTTPS);
+                }
+                let s = s.share();
+                async move { s.dispatch(req).await }
+            });
+
+            if let Some(_peer) = peer {
+                #[cfg(all(feature = "logging", not(feature = "trace")))]
+                log::trace!("Request from {}", _peer);
+
+                #[cfg(feature = "trace")]
+                tracing::trace!("Request from {}", _peer);
+            }
+
+            let establishing = acceptor.establish(stream);
+            tokio::task::spawn(async move {
+                let _connection = connection;
+                let io = match establishing.await {
+                    Ok(io) => io,
+                    Err(e) => {
+                        #[cfg(feature = "logging")]
+                        log::error!("Error while establishing connection: {:?}", e);
+                        #[cfg(feature = "trace")]
+                        tracing::error!("Error while establishing connection: {:?}", e);
+                        #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
+                        eprintln!("Error while establishing connection: {:?}", e);
+                        return;
+                    }
+                };
+
+                if let Err(http_err) = serve_connection(io, sfn, options, stopping).await {
+                    #[cfg(feature = "logging")]
+                    log::error!("Error while serving HTTP connection: {}", http_err);
+                    #[cfg(feature = "trace")]
+                    tracing::error!("Error while serving HTTP connection: {}", http_err);
+                    #[cfg(all(not(feature = "trace"), not(feature = "logging")))]
+                    eprintln!("Error while serving HTTP connection: {}", http_err);
+                }
+            });
+        }
+
+        drop(acceptor);
+        self.drain(shutdown, options.shutdown_timeout).await;
+    }
+
+    // Wait for the connections of a server that has stopped accepting them to finish, closing
+    // those still open once the shutdown timeout has passed.
+    async fn drain(&self, shutdown: Shutdown, timeout: Option<Duration>) {
+        #[cfg(all(feature = "logging", not(feature = "trace")))]
+        log::info!("Shutting down: waiting for open connections to finish");
+        #[cfg(feature = "trace")]
+        tracing::info!("Shutting down: waiting for open connections to finish");
+
+        if !shutdown.drain(timeout).await {
+            #[cfg(all(feature = "logging", not(feature = "trace")))]
+            log::warn!("Shutdown timeout passed: closed the connections still open");
+            #[cfg(feature = "trace")]
+            tracing::warn!("Shutdown timeout passed: closed the connections still open");
+        }
+    }
+}
+
+/// Server is an [App] bound to an address but not yet serving it, as returned by [App::listen] and
+/// [App::listen_tls], so that the address can be learned before serving starts. This tells tests
+/// which port the system chose when binding port 0:
+///
+/// ```ignore
+///     let server = app.listen("127.0.0.1:0").await?;
+///     let addr = server.local_addr();
+///     tokio::spawn(server.run());
+///     client.get(format!("http://{}/", addr).parse()?).await?;
+/// ```
+///
+/// Connections are queued by the system from the moment the address is bound, and answered once
+/// the server runs.
+pub struct Server<S: 'static + Send, T: TransientState + 'static + Clone + Send> {
+    app: App<S, T>,
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    #[cfg(feature = "tls")]
+    tls: Option<tokio_rustls::rustls::ServerConfig>,
+}
+
+impl<S: 'static + Send, T: TransientState + 'static + Clone + Send> Server<S, T> {
+    fn new(app: App<S, T>, listener: TcpListener) -> Result<Self, ServerError> {
+        Ok(Self {
+            app,
+            local_addr: listener.local_addr()?,
+            listener,
+            #[cfg(feature = "tls")]
+            tls: None,
+        })
+    }
+
+    /// The address the server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Serve, as [App::serve] or [App::serve_tls] would.
+    pub async fn run(self) -> Result<(), ServerError> {
+        self.run_with_shutdown(std::future::pending()).await
+    }
+
+    /// Serve until `signal` resolves, as [App::serve_with_shutdown] or
+    /// [App::serve_tls_with_shutdown] would.
+    pub async fn run_with_shutdown(
+        self,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        #[cfg(feature = "tls")]
+        if let Some(config) = self.tls {
+            return self
+                .app
+                .serve_tls_listener_with_shutdown(self.listener, config, signal)
+                .await;
+        }
+
+        self.app
+            .serve_listener_with_shutdown(self.listener, signal)
+            .await
+    }
+}
+
+/// UnixServer is an [App] bound to a Unix domain socket but not yet serving it, as returned by
+/// [App::listen_unix]; the counterpart of [Server].
+#[cfg(feature = "unix")]
+pub struct UnixServer<S: 'static + Send, T: TransientState + 'static + Clone + Send> {
+    app: App<S, T>,
+    listener: UnixListener,
+    local_addr: tokio::net::unix::SocketAddr,
+}
+
+#[cfg(feature = "unix")]
+impl<S: 'static + Send, T: TransientState + 'static + Clone + Send> UnixServer<S, T> {
+    /// The address the server is bound to.
+    pub fn local_addr(&self) -> &tokio::net::unix::SocketAddr {
+        &self.local_addr
+    }
+
+    /// Serve, as [App::serve_unix] would.
+    pub async fn run(self) -> Result<(), ServerError> {
+        self.run_with_shutdown(std::future::pending()).await
+    }
+
+    /// Serve until `signal` resolves, as [App::serve_unix_with_shutdown] would.
+    pub async fn run_with_shutdown(
+        self,
+        signal: impl Future<Output = ()>,
+    ) -> Result<(), ServerError> {
+        self.app
+            .serve_unix_listener_with_shutdown(self.listener, signal)
+            .await
+    }
+}
+
+// ServerOptions are the settings of the servers an [App] starts, read as each starts.
+#[derive(Debug, Clone, Copy)]
+struct ServerOptions {
+    shutdown_timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    keep_alive: bool,
+    idle_timeout: Option<Duration>,
+    max_requests: Option<usize>,
+    max_connections: Option<(usize, ConnectionOverflow)>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            shutdown_timeout: None,
+            header_read_timeout: None,
+            keep_alive: true,
+            idle_timeout: None,
+            max_requests: None,
+            max_connections: None,
+        }
+    }
+}
+
+// Bind the first of the addresses `addr` resolves to that can be bound, failing with the reasons
+// each could not.
+async fn bind(addr: impl ToSocketAddrs) -> Result<TcpListener, ServerError> {
+    let mut failures = Vec::new();
+    for socketaddr in tokio::net::lookup_host(addr).await? {
+        match TcpListener::bind(socketaddr).await {
+            Ok(listener) => return Ok(listener),
+            Err(e) => failures.push(format!("{} ({})", socketaddr, e)),
+        }
+    }
+
+    if failures.is_empty() {
+        return Err(ServerError::from("address resolved to nothing to bind"));
+    }
+
+    Err(ServerError::from(format!(
+        "could not bind any address: {}",
+        failures.join(", ")
+    )))
+}
+
+// How far a server started with one of the serve_with_shutdown methods has got in stopping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ServerPhase {
+    Serving,
+    Draining,
+    Closing,
+}
+
+// Shutdown tells the connections of a server to stop once it does, and lets it wait for them to.
+struct Shutdown {
+    phase: watch::Sender<ServerPhase>,
+    // held by every open connection, so that receiving from drained ends once they are all closed.
+    open: mpsc::Sender<()>,
+    drained: mpsc::Receiver<()>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        let (phase, _) = watch::channel(ServerPhase::Serving);
+        let (open, drained) = mpsc::channel(1);
+        Self {
+            phase,
+            open,
+            drained,
+        }
+    }
+
+    fn connection(&self) -> ConnectionShutdown {
+        ConnectionShutdown {
+            phase: self.phase.subscribe(),
+            _open: self.open.clone(),
+        }
+    }
+
+    // Ask connections to close once their requests in progress are answered, returning whether
+    // they all did within the timeout; those that did not are closed.
+    async fn drain(self, timeout: Option<Duration>) -> bool {
+        let Self {
+            phase,
+            open,
+            mut drained,
+        } = self;
+        drop(open);
+
+        phase.send_replace(ServerPhase::Draining);
+        let finished = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, drained.recv()).await.is_ok(),
+            None => {
+                drained.recv().await;
+                true
+            }
+        };
+
+        if !finished {
+            phase.send_replace(ServerPhase::Closing);
+            drained.recv().await;
+        }
+
+        finished
+    }
+}
+
+// A connection's view of its server's [Shutdown].
+struct ConnectionShutdown {
+    phase: watch::Receiver<ServerPhase>,
+    _open: mpsc::Sender<()>,
+}
+
+impl ConnectionShutdown {
+    // Wait until the server has reached `phase` in stopping; forever if it went away without, as
+    // the connections of a server whose future was dropped are left to run.
+    async fn reached(&mut self, phase: ServerPhase) {
+        if self
+            .phase
+            .wait_for(|current| *current >= phase)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// Activity is when a connection last sent or received anything, for closing it once idle.
+#[derive(Clone)]
+struct Activity {
+    since: Instant,
+    // milliseconds after `since`.
+    last: Arc<AtomicU64>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            last: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.since.elapsed().as_millis() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> Instant {
+        self.since + Duration::from_millis(self.last.load(Ordering::Relaxed))
+    }
+
+    // Wait until nothing has been sent or received for `timeout`; forever if there is none.
+    async fn idle(&self, timeout: Option<Duration>) {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return std::future::pending().await,
+        };
+
+        loop {
+            let last = self.last();
+            tokio::time::sleep_until((last + timeout).into()).await;
+            if self.last() == last {
+                return;
+            }
+        }
+    }
+}
+
+// Tracked notes the [Activity] of a connection as its bytes are read and written.
+struct Tracked<I> {
+    io: I,
+    activity: Activity,
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Tracked<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.io).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            self.activity.touch();
+        }
+        result
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for Tracked<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.io).poll_write(cx, buf);
+        if let Poll::Ready(Ok(1..)) = result {
+            self.activity.touch();
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.io).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(1..)) = result {
+            self.activity.touch();
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+// Counted counts the requests made on a connection, notifying `exhausted` once it has taken as
+// many as it may.
+struct Counted<S> {
+    service: S,
+    remaining: Option<usize>,
+    exhausted: Arc<Notify>,
+}
+
+impl<S> hyper::service::Service<Request<Body>> for Counted<S>
+where
+    S: hyper::service::Service<Request<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(1);
+            if *remaining == 0 {
+                self.exhausted.notify_one();
+            }
+        }
+
+        self.service.call(req)
+    }
+}
+
+// Serve HTTP on a connection until it ends. Once the server is stopping, or the connection has
+// been idle or made as many requests as it may, the requests in progress are answered before the
+// connection is closed, unless the server stops waiting for them first.
+async fn serve_connection<I, S>(
+    io: I,
+    service: S,
+    options: ServerOptions,
+    mut shutdown: ConnectionShutdown,
+) -> Result<(), hyper::Error>
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: hyper::service::Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let mut http = Http::new();
+    http.http1_keep_alive(options.keep_alive);
+    if let Some(timeout) = options.header_read_timeout {
+        http.http1_header_read_timeout(timeout);
+    }
+
+    let activity = Activity::new();
+    let io = Tracked {
+        io,
+        activity: activity.clone(),
+    };
+    let exhausted = Arc::new(Notify::new());
+    let service = Counted {
+        service,
+        remaining: options.max_requests,
+        exhausted: exhausted.clone(),
+    };
+
+    let connection = http.serve_connection(io, service);
+    tokio::pin!(connection);
+
+    tokio::select! {
+        result = connection.as_mut() => return result,
+        _ = shutdown.reached(ServerPhase::Draining) => {}
+        _ = activity.idle(options.idle_timeout) => {}
+        _ = exhausted.notified() => {}
+    }
+
+    connection.as_mut().graceful_shutdown();
+    tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.reached(ServerPhase::Closing) => Ok(()),
+    }
+}
+
+// The response rendering an error that no handler turned into a response; as a problem if it is
+// one, or if the client would rather have one.
+fn error_response(e: Error, problem_json: bool) -> Response<Body> {
+    match e {
+        Error::Problem(problem) => problem.to_response(),
+        e if problem_json => crate::problem::from_error(e).to_response(),
+        Error::StatusCode(sc, msg) => Response::builder()
+            .status(sc)
+            .body(Body::from(msg))
+            .unwrap(),
+        Error::InternalServerError(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(e))
+            .unwrap(),
+    }
+}
+
+/// TestApp is a testing framework for ratpack applications. Given an App, it can issue mock
+/// requests to it without standing up a typical web server.
+pub struct TestApp<S: Send + 'static, T: TransientState + 'static + Clone + Send> {
+    app: App<S, T>,
+    headers: Option<HeaderMap>,
+}
+
+impl<S: Send + 'static, T: TransientState + 'static + Clone + Send> Clone for TestApp<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            app: self.app.share(),
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+impl<S: Send + 'static, T: TransientState + 'static + Clone + Send> TestApp<S, T> {
+    /// Construct a new tested application.
+    pub fn new(app: App<S, T>) -> Self {
+        Self { app, headers: None }
+    }
+
+    /// with_headers applies the headers to any following request, and acts as an alternative
+    /// constructor.
+    pub fn with_headers(&self, headers: http::HeaderMap) -> Self {
+        Self {
+            app: self.app.share(),
+            headers: Some(headers),
+        }
+    }
+
+    /// dispatch a request to the application, this allows for maximum flexibility.
+    pub async fn dispatch(&self, req: Request<Body>) -> Response<Body> {
+        self.app.dispatch(req).await.unwrap()
+    }
+
+    fn populate_headers(&self, mut req: http::request::Builder) -> http::request::Builder {
+        if let Some(include_headers) = self.headers.clone() {
+            for (header, value) in include_headers.clone() {
+                if let Some(header) = header {
+                    req = req.header(header, value.clone());
+                }
+            }
+        }
+
+        req
+    }
+
+    /// Perform a request of any method, including extension methods, against the path.
+    pub async fn request(&self, method: Method, path: &str, body: Body) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(req.method(method).uri(path).body(body).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Perform a GET request against the path.
+    pub async fn get(&self, path: &str) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+
+        self.app
+            .dispatch(req.uri(path).body(Body::default()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Perform a GET request against the path with the headers, which are sent along with any
+    /// given to [TestApp::with_headers].
+    pub async fn get_with(&self, path: &str, headers: &[(&str, &str)]) -> Response<Body> {
+        let mut req = self.populate_headers(Request::builder());
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+
+        self.app
+            .dispatch(req.uri(path).body(Body::default()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Perform a POST request against the path.
+    pub async fn post(&self, path: &str, body: Body) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+
+        self.app
+            .dispatch(req.method(Method::POST).uri(path).body(body).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Perform a DELETE request against the path.
+    pub async fn delete(&self, path: &str) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(
+                req.method(Method::DELETE)
+                    .uri(path)
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Perform a PUT request against the path.
+    pub async fn put(&self, path: &str, body: Body) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(req.method(Method::PUT).uri(path).body(body).unwrap())
+            .await
+            .unwrap()
     }
 
     /// Perform an OPTIONS request against the path.
@@ -428,58 +2602,3068 @@ impl<S: Clone + Send + 'static, T: TransientState + 'static + Clone + Send> Test
                     .body(Body::default())
                     .unwrap(),
             )
-            .await
-            .unwrap()
+            .await
+            .unwrap()
+    }
+
+    /// Perform a PATCH request against the path.
+    pub async fn patch(&self, path: &str, body: Body) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(req.method(Method::PATCH).uri(path).body(body).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Perform a HEAD request against the path.
+    pub async fn head(&self, path: &str) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(
+                req.method(Method::HEAD)
+                    .uri(path)
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Perform a TRACE request against the path.
+    pub async fn trace(&self, path: &str) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(
+                req.method(Method::TRACE)
+                    .uri(path)
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Perform a CONNECT request against the path.
+    pub async fn connect(&self, path: &str) -> Response<Body> {
+        let req = self.populate_headers(Request::builder());
+        self.app
+            .dispatch(
+                req.method(Method::CONNECT)
+                    .uri(path)
+                    .body(Body::default())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_app_non_clone_state() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        // deliberately not Clone; a stand-in for file handles, clients, etc.
+        struct Counter {
+            count: usize,
+        }
+
+        async fn count(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            app: App<Counter, NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            let state = app.state().await.unwrap();
+            let mut lock = state.lock().await;
+            lock.count += 1;
+
+            Ok((
+                req,
+                Some(
+                    Response::builder()
+                        .status(StatusCode::OK)
+                        .body(Body::from(lock.count.to_string()))?,
+                ),
+                NoState {},
+            ))
+        }
+
+        let mut app = App::with_state(Counter { count: 0 });
+        app.get("/count", compose_handler!(count));
+
+        let app = TestApp::new(app);
+
+        for i in 1..=3 {
+            let mut res = app.get("/count").await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, i.to_string().as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_stats() {
+        use super::{App, AppStats};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::{sync::Arc, time::Duration};
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+            sync::Notify,
+        };
+
+        async fn wait(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            app: App<Arc<Notify>, NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            let notify = app.state().await.unwrap().lock().await.clone();
+            notify.notified().await;
+            Ok((req, Some(Response::new(Body::from("done"))), NoState {}))
+        }
+
+        async fn wait_for(app: &App<Arc<Notify>, NoState>, want: AppStats) {
+            for _ in 0..100 {
+                if app.stats() == want {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            assert_eq!(app.stats(), want);
+        }
+
+        let notify = Arc::new(Notify::new());
+        let mut app = App::with_state(notify.clone());
+        app.get("/wait", compose_handler!(wait));
+
+        let server = app.clone().listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        tokio::spawn(server.run());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream
+            .write_all(b"GET /wait HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        wait_for(
+            &app,
+            AppStats {
+                connections: 1,
+                in_flight: 1,
+                total_served: 0,
+                limited_connections: 0,
+            },
+        )
+        .await;
+
+        notify.notify_one();
+
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert!(String::from_utf8_lossy(&buf).ends_with("done"));
+        drop(stream);
+
+        wait_for(
+            &app,
+            AppStats {
+                connections: 0,
+                in_flight: 0,
+                total_served: 1,
+                limited_connections: 0,
+            },
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_app_elapsed() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, elapsed, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::time::Duration;
+
+        async fn sleep(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok((req, None, state))
+        }
+
+        async fn report(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let elapsed = elapsed(&req).unwrap();
+            Ok((
+                req,
+                Some(Response::new(Body::from(elapsed.as_millis().to_string()))),
+                state,
+            ))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(sleep, report));
+
+        let mut res = TestApp::new(app).get("/").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        let millis: u128 = String::from_utf8(body.to_vec()).unwrap().parse().unwrap();
+        assert!(millis >= 50);
+
+        assert!(elapsed(&Request::new(Body::empty())).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_app_query() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, query, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn search(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let query = query(&req).unwrap();
+            let body = format!(
+                "q={:?} tags={:?} exact={}",
+                query.get("q"),
+                query.get_all("tag"),
+                query.contains_key("exact")
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/search", compose_handler!(search));
+        let app = TestApp::new(app);
+
+        for (uri, expected) in [
+            (
+                "/search?q=hello+w%C3%B6rld&tag=a&tag=b&exact",
+                "q=Some(\"hello wörld\") tags=[\"a\", \"b\"] exact=true",
+            ),
+            ("/search", "q=None tags=[] exact=false"),
+        ] {
+            let mut res = app.get(uri).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_route_builder() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, route_meta, HTTPResult, NoState, Params};
+        use http::{HeaderMap, Method, Request, Response, StatusCode};
+        use hyper::Body;
+        use std::time::Duration;
+
+        async fn param(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("param"))), state))
+        }
+
+        async fn fixed(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("fixed"))), state))
+        }
+
+        async fn tag(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let tag = format!("{:?}", route_meta(&req).unwrap().get("tag"));
+            Ok((req, Some(Response::new(Body::from(tag))), state))
+        }
+
+        async fn slow(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok((req, Some(Response::new(Body::from("slow"))), state))
+        }
+
+        let mut app = App::new();
+        // priority comes before specificity; here they agree on trying the static route first.
+        app.get("/items/:id", compose_handler!(param));
+        app.route_builder(Method::GET, "/items/fixed", compose_handler!(fixed))
+            .priority(5)
+            .register();
+
+        app.route_builder(Method::GET, "/admin", compose_handler!(tag))
+            .meta("tag", "admin")
+            .register();
+        app.get("/plain", compose_handler!(tag));
+
+        app.route_builder(Method::GET, "/slow", compose_handler!(slow))
+            .timeout(Duration::from_millis(10))
+            .register();
+        app.get("/patient", compose_handler!(slow));
+
+        app.route_builder(Method::GET, "/guarded", compose_handler!(fixed))
+            .guard(|req| req.headers().contains_key("x-admin"))
+            .register();
+        app.get("/guarded", compose_handler!(param));
+
+        let app = TestApp::new(app);
+
+        for (uri, status, expected) in [
+            ("/items/fixed", StatusCode::OK, "fixed"),
+            ("/items/other", StatusCode::OK, "param"),
+            ("/admin", StatusCode::OK, "Some(\"admin\")"),
+            ("/plain", StatusCode::OK, "None"),
+            ("/slow", StatusCode::SERVICE_UNAVAILABLE, "route timed out"),
+            ("/patient", StatusCode::OK, "slow"),
+            ("/guarded", StatusCode::OK, "param"),
+        ] {
+            let mut res = app.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes(), "{}", uri);
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-admin", "1".parse().unwrap());
+        let mut res = app.with_headers(headers).get("/guarded").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "fixed".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_app_mount() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn index(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            app: App<String, NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let name = app.state().await.unwrap().lock().await.clone();
+            let body = format!("{} index for {}", name, params.get("tenant").unwrap());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn user(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<String, NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!(
+                "user {} of {}",
+                params.get("id").unwrap(),
+                params.get("tenant").unwrap()
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut admin = App::new();
+        admin.get("/", compose_handler!(index));
+        admin.get("/users/:id", compose_handler!(user));
+
+        let mut app = App::with_state("parent".to_string());
+        app.mount("/tenants/:tenant/admin/", admin);
+        let app = TestApp::new(app);
+
+        for (uri, expected) in [
+            ("/tenants/acme/admin", "parent index for acme"),
+            ("/tenants/acme/admin/", "parent index for acme"),
+            ("/tenants/acme/admin/users/7", "user 7 of acme"),
+        ] {
+            let mut res = app.get(uri).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+
+        for uri in ["/", "/users/7", "/tenants/acme/users/7"] {
+            assert_eq!(
+                app.get(uri).await.status(),
+                StatusCode::NOT_FOUND,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_group() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, Params, TransientState};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        #[derive(Clone)]
+        struct Stages(Vec<&'static str>);
+
+        impl TransientState for Stages {
+            fn initial() -> Self {
+                Self(Vec::new())
+            }
+        }
+
+        async fn authenticate(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Stages>,
+            mut state: Stages,
+        ) -> HTTPResult<Stages> {
+            state.0.push("authenticate");
+            Ok((req, None, state))
+        }
+
+        async fn admin_only(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Stages>,
+            mut state: Stages,
+        ) -> HTTPResult<Stages> {
+            state.0.push("admin_only");
+            Ok((req, None, state))
+        }
+
+        async fn report(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), Stages>,
+            state: Stages,
+        ) -> HTTPResult<Stages> {
+            let body = format!(
+                "{} {}",
+                state.0.join(","),
+                params.get("id").map(String::as_str).unwrap_or("-")
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        let mut api = app.group("/api/v1/", compose_handler!(authenticate));
+        api.get("/users/:id", compose_handler!(report));
+        api.post("/users", compose_handler!(report));
+
+        let mut admin = api.group("/admin", compose_handler!(admin_only));
+        admin.get("/", compose_handler!(report));
+        admin.delete("/users/:id", compose_handler!(report));
+
+        app.get("/users/:id", compose_handler!(report));
+        let app = TestApp::new(app);
+
+        for (res, expected) in [
+            (app.get("/api/v1/users/7").await, "authenticate 7"),
+            (
+                app.post("/api/v1/users", Body::empty()).await,
+                "authenticate -",
+            ),
+            (app.get("/api/v1/admin").await, "authenticate,admin_only -"),
+            (
+                app.delete("/api/v1/admin/users/7").await,
+                "authenticate,admin_only 7",
+            ),
+            (app.get("/users/7").await, " 7"),
+        ] {
+            let mut res = res;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_url_for() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn create(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let location = app.url_for("user_show", &[("id", "42")])?;
+            Ok((
+                req,
+                Some(
+                    Response::builder()
+                        .status(StatusCode::SEE_OTHER)
+                        .header(header::LOCATION, location)
+                        .body(Body::empty())?,
+                ),
+                state,
+            ))
+        }
+
+        async fn broken(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            app.url_for("user_show", &[])?;
+            Ok((req, None, state))
+        }
+
+        let mut app = App::new();
+        app.get_named("user_show", "/users/:id", compose_handler!(create));
+        app.route_builder(Method::POST, "/users", compose_handler!(create))
+            .name("user_create")
+            .register();
+        app.get("/broken", compose_handler!(broken));
+
+        assert_eq!(app.url_for("user_create", &[]).unwrap(), "/users");
+        assert!(app.url_for("user_delete", &[]).is_err());
+
+        let app = TestApp::new(app);
+        let res = app.post("/users", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/users/42");
+
+        let mut res = app.get("/broken").await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(
+            body,
+            "cannot generate URL for route `user_show`: missing value for parameter `id`"
+                .as_bytes()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "route name `home` is already registered")]
+    fn test_app_rejects_duplicate_route_names() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn home(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, None, state))
+        }
+
+        let mut app = App::new();
+        app.get_named("home", "/", compose_handler!(home));
+        app.get_named("home", "/home", compose_handler!(home));
+    }
+
+    #[tokio::test]
+    async fn test_app_decoded_params() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, raw_params, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!(
+                "{} {}",
+                params.get("name").unwrap(),
+                raw_params(&req).unwrap().get("name").unwrap()
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/hello/:name", compose_handler!(hello));
+        let app = TestApp::new(app);
+
+        for (uri, expected) in [
+            ("/hello/J%C3%BCrgen", "Jürgen J%C3%BCrgen"),
+            ("/hello/erik%20h", "erik h erik%20h"),
+            ("/h%65llo/erik", "erik erik"),
+        ] {
+            let mut res = app.get(uri).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+
+        for uri in ["/hello/%ZZ", "/hello/%C3%28"] {
+            assert_eq!(
+                app.get(uri).await.status(),
+                StatusCode::BAD_REQUEST,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_case_insensitive() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn user(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = params.get("name").unwrap().clone();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/api/users/:name", compose_handler!(user));
+
+        let test = TestApp::new(app.clone());
+        assert_eq!(
+            test.get("/API/Users/Erik").await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        app.set_case_insensitive(true);
+        let test = TestApp::new(app);
+        for uri in ["/API/Users/Erik", "/api/users/Erik"] {
+            let mut res = test.get(uri).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, "Erik".as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_any() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response};
+        use hyper::Body;
+
+        async fn diagnostics(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("{} diagnostics", req.method());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.any("/diagnostics", compose_handler!(diagnostics));
+        let app = TestApp::new(app);
+
+        for method in [
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::PATCH,
+            Method::HEAD,
+            Method::OPTIONS,
+            Method::TRACE,
+            Method::CONNECT,
+        ] {
+            let mut res = app
+                .dispatch(
+                    Request::builder()
+                        .method(method.clone())
+                        .uri("/diagnostics")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            if method == Method::HEAD {
+                assert!(body.is_empty());
+            } else {
+                assert_eq!(body, format!("{} diagnostics", method).as_bytes());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_matched_path() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, matched_path, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn report(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = matched_path(&req).unwrap().to_string();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut admin = App::new();
+        admin.get("/users/:id{[0-9]+}", compose_handler!(report));
+
+        let mut app = App::new();
+        app.get("/static/*rest", compose_handler!(report));
+        app.mount("/tenants/:tenant", admin);
+        let app = TestApp::new(app);
+
+        for (uri, expected) in [
+            (
+                "/tenants/acme/users/12345",
+                "/tenants/:tenant/users/:id{[0-9]+}",
+            ),
+            ("/static/css/site.css", "/static/*rest"),
+        ] {
+            let mut res = app.get(uri).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+
+        assert!(matched_path(&Request::new(Body::empty())).is_none());
+    }
+
+    #[test]
+    fn test_app_routes() {
+        use super::{App, RouteInfo};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response};
+        use hyper::Body;
+
+        async fn stage(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, None, state))
+        }
+
+        let mut app = App::new();
+        app.post("/users", compose_handler!(stage, stage));
+        app.get_named("user_show", "/users/:id", compose_handler!(stage));
+        app.any("/diagnostics", compose_handler!(stage));
+        app.route(
+            &[Method::POST, Method::GET],
+            "/form",
+            compose_handler!(stage, stage, stage),
+        );
+        app.get("/", compose_handler!(stage));
+
+        let route = |method: &str, path: &str, handlers, name: Option<&str>| RouteInfo {
+            method: method.to_string(),
+            path: path.to_string(),
+            handlers,
+            name: name.map(str::to_string),
+            host: None,
+        };
+
+        assert_eq!(
+            app.routes(),
+            vec![
+                route("*", "/diagnostics", 1, None),
+                route("GET", "/", 1, None),
+                route("GET", "/users/:id", 1, Some("user_show")),
+                route("GET,POST", "/form", 3, None),
+                route("POST", "/users", 2, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_app_method_not_allowed() {
+        use super::{App, TestApp};
+        use crate::{allowed_methods, compose_handler, HTTPResult, NoState, Params};
+        use http::{header, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn item(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("item"))), state))
+        }
+
+        async fn not_allowed(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let allowed = allowed_methods(&req)
+                .unwrap()
+                .iter()
+                .map(|method| format!("\"{}\"", method))
+                .collect::<Vec<_>>()
+                .join(",");
+            let body = format!(
+                "{{\"error\":\"{} not allowed\",\"allowed\":[{}]}}",
+                req.method(),
+                allowed
+            );
+
+            Ok((
+                req,
+                Some(
+                    Response::builder()
+                        .status(StatusCode::METHOD_NOT_ALLOWED)
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body))?,
+                ),
+                state,
+            ))
+        }
+
+        let mut app = App::new();
+        app.get("/items/:id", compose_handler!(item));
+        app.delete("/items/:id", compose_handler!(item));
+
+        let res = TestApp::new(app.clone())
+            .put("/items/1", Body::empty())
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            res.headers().get(header::ALLOW).unwrap(),
+            "GET, DELETE, HEAD"
+        );
+
+        app.method_not_allowed(compose_handler!(not_allowed));
+        let app = TestApp::new(app);
+
+        let mut res = app.put("/items/1", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            res.headers().get(header::ALLOW).unwrap(),
+            "GET, DELETE, HEAD"
+        );
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(
+            body,
+            "{\"error\":\"PUT not allowed\",\"allowed\":[\"GET\",\"DELETE\",\"HEAD\"]}".as_bytes()
+        );
+
+        // unmatched paths are still a 404.
+        assert_eq!(
+            app.put("/nope", Body::empty()).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_app_host() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn site(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = match params.get("subdomain") {
+                Some(subdomain) => format!("{} {}", subdomain, params.get("id").unwrap()),
+                None => "site".to_string(),
+            };
+
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn api(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("api"))), state))
+        }
+
+        let mut app = App::new();
+        app.route_builder(Method::GET, "/", compose_handler!(api))
+            .host("api.example.com")
+            .register();
+        app.route_builder(Method::GET, "/items/:id", compose_handler!(site))
+            .host("*.example.com")
+            .register();
+        app.get("/", compose_handler!(site));
+
+        assert_eq!(app.routes()[0].host.as_deref(), Some("api.example.com"));
+
+        let app = TestApp::new(app);
+        for (uri, host, body) in [
+            ("/", "api.example.com", "api"),
+            ("/", "api.example.com:8443", "api"),
+            ("/", "www.example.com", "site"),
+            ("http://api.example.com/", "www.example.com", "api"),
+            ("/items/1", "acme.example.com", "acme 1"),
+        ] {
+            let mut res = app.get_with(uri, &[("host", host)]).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{} {}", uri, host);
+        }
+
+        // routes for other hosts are not considered at all.
+        let res = app.get_with("/items/1", &[("host", "example.org")]).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_app_redirect() {
+        use super::{App, TestApp};
+        use crate::NoState;
+        use http::{header, StatusCode};
+        use hyper::Body;
+
+        let mut app: App<(), NoState> = App::new();
+        app.redirect("/old", "/new", StatusCode::MOVED_PERMANENTLY);
+        app.redirect(
+            "/users/:id/profile",
+            "/profiles/:id",
+            StatusCode::PERMANENT_REDIRECT,
+        );
+        app.redirect(
+            "/docs/*rest",
+            "https://docs.example.com/*rest",
+            StatusCode::FOUND,
+        );
+        let app = TestApp::new(app);
+
+        for (uri, status, location) in [
+            ("/old", StatusCode::MOVED_PERMANENTLY, "/new"),
+            (
+                "/old?page=2&q=a",
+                StatusCode::MOVED_PERMANENTLY,
+                "/new?page=2&q=a",
+            ),
+            (
+                "/users/42/profile",
+                StatusCode::PERMANENT_REDIRECT,
+                "/profiles/42",
+            ),
+            (
+                "/users/a%2Fb/profile",
+                StatusCode::PERMANENT_REDIRECT,
+                "/profiles/a%2Fb",
+            ),
+            (
+                "/docs/guide/intro?v=1",
+                StatusCode::FOUND,
+                "https://docs.example.com/guide/intro?v=1",
+            ),
+        ] {
+            let res = app.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            assert_eq!(res.headers().get(header::LOCATION).unwrap(), location);
+        }
+
+        let res = app.head("/old").await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+
+        let res = app.post("/old", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "redirect target `/new/:slug` uses parameter `slug`, which `/old/:id` does not capture"
+    )]
+    fn test_app_redirect_rejects_unknown_params() {
+        use super::App;
+        use crate::NoState;
+        use http::StatusCode;
+
+        let mut app: App<(), NoState> = App::new();
+        app.redirect("/old/:id", "/new/:slug", StatusCode::FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_app_guard() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{header, HeaderMap, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn github(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("github"))), state))
+        }
+
+        async fn json(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("json"))), state))
+        }
+
+        let mut app = App::new();
+        app.route_builder(Method::POST, "/hook", compose_handler!(github))
+            .guard(|req| req.headers().contains_key("x-github-event"))
+            .register();
+        app.route_builder(Method::POST, "/hook", compose_handler!(json))
+            .guard(|req| {
+                req.headers()
+                    .get(header::CONTENT_TYPE)
+                    .is_some_and(|value| value == "application/json")
+            })
+            .register();
+        let app = TestApp::new(app);
+
+        let with = |name: &str, value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+            app.with_headers(headers)
+        };
+
+        for (app, expected) in [
+            (with("x-github-event", "push"), "github"),
+            (with("content-type", "application/json"), "json"),
+        ] {
+            let mut res = app.post("/hook", Body::empty()).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+
+        // neither guard accepts, so as far as the request is concerned there is no route.
+        let res = with("content-type", "text/plain")
+            .post("/hook", Body::empty())
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(app.get("/hook").await.status(), StatusCode::NOT_FOUND);
+
+        // only routes whose guard accepts the request are listed as allowed.
+        let res = with("x-github-event", "push").get("/hook").await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "POST");
+    }
+
+    #[tokio::test]
+    async fn test_app_get_prefix() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, matched_path, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn suffix(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!(
+                "{} {}",
+                matched_path(&req).unwrap(),
+                params.get("suffix").cloned().unwrap_or_default()
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get_prefix("/assets", compose_handler!(suffix));
+        app.get_prefix("/assets/img/", compose_handler!(suffix));
+        app.get("/assets/special.css", compose_handler!(suffix));
+        let app = TestApp::new(app);
+
+        for (uri, expected) in [
+            ("/assets", "/assets/*suffix "),
+            ("/assets/", "/assets/*suffix "),
+            ("/assets/css/site.css", "/assets/*suffix css/site.css"),
+            ("/assets/a%20b.txt", "/assets/*suffix a b.txt"),
+            ("/assets/special.css", "/assets/special.css "),
+            ("/assets/img/logo.png", "/assets/img/*suffix logo.png"),
+            ("/assets/img", "/assets/img/*suffix "),
+        ] {
+            let mut res = app.get(uri).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes(), "{}", uri);
+        }
+
+        assert_eq!(app.get("/assetsfoo").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_app_strict_slashes() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/account", compose_handler!(ok));
+        app.get("/docs/", compose_handler!(ok));
+
+        let lenient = TestApp::new(app.clone());
+        for uri in ["/account", "/account/", "//account", "/docs", "/docs/"] {
+            assert_eq!(lenient.get(uri).await.status(), StatusCode::OK, "{}", uri);
+        }
+
+        app.set_strict_slashes(true);
+        let strict = TestApp::new(app);
+        for (uri, status) in [
+            ("/account", StatusCode::OK),
+            ("/account/", StatusCode::NOT_FOUND),
+            ("//account", StatusCode::NOT_FOUND),
+            ("/docs/", StatusCode::OK),
+            ("/docs", StatusCode::NOT_FOUND),
+        ] {
+            assert_eq!(strict.get(uri).await.status(), status, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_encoded_slashes() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn echo(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = params.values().cloned().collect::<Vec<_>>().join(" ");
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/files/:name", compose_handler!(echo));
+        app.get("/tree/*rest", compose_handler!(echo));
+        app.route_builder(Method::GET, "/strict/:name", compose_handler!(echo))
+            .reject_encoded_slashes()
+            .register();
+        app.route_builder(Method::GET, "/strict_tree/*rest", compose_handler!(echo))
+            .reject_encoded_slashes()
+            .register();
+        let app = TestApp::new(app);
+
+        for (uri, status, body) in [
+            ("/files/a%2Fb.txt", StatusCode::OK, "a/b.txt"),
+            ("/files/a%2fb.txt", StatusCode::OK, "a/b.txt"),
+            ("/files/a/b.txt", StatusCode::NOT_FOUND, ""),
+            ("/tree/a/b%2Fc/d", StatusCode::OK, "a/b/c/d"),
+            ("/strict/a.txt", StatusCode::OK, "a.txt"),
+            (
+                "/strict/a%2Fb.txt",
+                StatusCode::BAD_REQUEST,
+                "parameter `name` contains an encoded slash",
+            ),
+            ("/strict_tree/a/b/c", StatusCode::OK, "a/b/c"),
+            (
+                "/strict_tree/a/b%2Fc",
+                StatusCode::BAD_REQUEST,
+                "parameter `rest` contains an encoded slash",
+            ),
+        ] {
+            let mut res = app.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_normalize_paths() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, matched_path, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn show(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("{} {}", matched_path(&req).unwrap(), req.uri());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/admin/secret", compose_handler!(show));
+        app.get("/static/*rest", compose_handler!(show));
+
+        let normalized = TestApp::new(app.clone());
+        for (uri, status, body) in [
+            (
+                "/static/../admin/secret",
+                StatusCode::OK,
+                "/admin/secret /admin/secret",
+            ),
+            (
+                "/static/./css/../site.css?v=1",
+                StatusCode::OK,
+                "/static/*rest /static/site.css?v=1",
+            ),
+            (
+                "/static/../../etc/passwd",
+                StatusCode::BAD_REQUEST,
+                "path escapes the root",
+            ),
+        ] {
+            let mut res = normalized.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{}", uri);
+        }
+
+        app.set_normalize_paths(false);
+        let raw = TestApp::new(app);
+        let mut res = raw.get("/static/../admin/secret").await;
+        let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(bytes, "/static/*rest /static/../admin/secret".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_app_path_limits() {
+        use super::{App, PathLimits, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/*rest", compose_handler!(ok));
+
+        let defaults = TestApp::new(app.clone());
+        let deep = "/a".repeat(64);
+        let long = format!("/{}", "a".repeat(1024));
+        for (uri, status) in [
+            (deep.clone(), StatusCode::OK),
+            (format!("{}/a", deep), StatusCode::BAD_REQUEST),
+            (long.clone(), StatusCode::OK),
+            (format!("{}a", long), StatusCode::BAD_REQUEST),
+            (format!("/{}", "a/".repeat(5000)), StatusCode::URI_TOO_LONG),
+        ] {
+            assert_eq!(defaults.get(&uri).await.status(), status, "{}", uri.len());
+        }
+
+        app.set_path_limits(PathLimits {
+            max_bytes: 16,
+            max_segments: 3,
+            max_segment_bytes: 4,
+        });
+        let limited = TestApp::new(app);
+        for (uri, status) in [
+            ("/a/b/c", StatusCode::OK),
+            ("/a/b/c/d", StatusCode::BAD_REQUEST),
+            ("/abcd", StatusCode::OK),
+            ("/abcde", StatusCode::BAD_REQUEST),
+            ("/abcd/abcd/abcd/a", StatusCode::URI_TOO_LONG),
+        ] {
+            assert_eq!(limited.get(uri).await.status(), status, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_dynamic_routes() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        // removes its own route, but still completes.
+        async fn once(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            mut app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            assert!(app.remove(Method::GET, "/once"));
+            Ok((req, Some(Response::new(Body::from("once"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/items/:id", compose_handler!(ok));
+        app.route(
+            &[Method::GET, Method::DELETE],
+            "/both",
+            compose_handler!(ok),
+        );
+        app.get("/once", compose_handler!(once));
+        // clones have routes of their own, and the handle changes those being served.
+        let mut template = app.clone();
+        template.get("/template", compose_handler!(ok));
+        let mut routes = app.routes_handle();
+        let test = TestApp::new(app);
+        assert_eq!(test.get("/template").await.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(test.get("/live").await.status(), StatusCode::NOT_FOUND);
+        routes.get("/live", compose_handler!(ok));
+        assert_eq!(test.get("/live").await.status(), StatusCode::OK);
+
+        // parameter names do not matter when removing.
+        assert_eq!(test.get("/items/1").await.status(), StatusCode::OK);
+        assert!(routes.remove(Method::GET, "/items/:name"));
+        assert!(!routes.remove(Method::GET, "/items/:name"));
+        assert_eq!(test.get("/items/1").await.status(), StatusCode::NOT_FOUND);
+
+        // nor do literals remove parameters in their place.
+        routes.get("/users/:id", compose_handler!(ok));
+        routes.get("/users/new", compose_handler!(ok));
+        assert!(routes.remove(Method::GET, "/users/new"));
+        let paths: Vec<String> = routes.routes().into_iter().map(|r| r.path).collect();
+        assert!(paths.contains(&"/users/:id".to_string()), "{:?}", paths);
+        assert!(!paths.contains(&"/users/new".to_string()), "{:?}", paths);
+        assert_eq!(test.get("/users/1").await.status(), StatusCode::OK);
+        assert!(!routes.remove(Method::GET, "/users/new"));
+
+        assert!(routes.remove(Method::GET, "/both"));
+        assert_eq!(
+            test.get("/both").await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(test.delete("/both").await.status(), StatusCode::OK);
+
+        let mut res = test.get("/once").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "once".as_bytes());
+        assert_eq!(test.get("/once").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_app_merge() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, Error, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn users(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("users"))), state))
+        }
+
+        async fn billing(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("billing"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/users/:id", compose_handler!(users));
+
+        let mut other = App::new();
+        other.get("/invoices/:id", compose_handler!(billing));
+        other.post("/users/:id", compose_handler!(billing));
+        app.merge(other).unwrap();
+
+        let mut routes = app.routes_handle();
+        let test = TestApp::new(app);
+        for (method, uri, body) in [
+            (Method::GET, "/users/1", "users"),
+            (Method::POST, "/users/1", "billing"),
+            (Method::GET, "/invoices/1", "billing"),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            let mut res = test.dispatch(req).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{}", uri);
+        }
+
+        // a conflict, whatever its parameter is named, adds none of the routes.
+        let mut other = App::new();
+        other.get("/reports", compose_handler!(billing));
+        other.route(
+            &[Method::GET, Method::PUT],
+            "/users/:user",
+            compose_handler!(billing),
+        );
+        assert!(matches!(
+            routes.merge(other),
+            Err(Error::InternalServerError(msg))
+                if msg == "route `GET,PUT /users/:user` conflicts with `GET /users/:id`"
+        ));
+        assert_eq!(test.get("/reports").await.status(), StatusCode::NOT_FOUND);
+
+        // literals and constrained parameters may sit alongside a parameter.
+        let mut other = App::new();
+        other.get("/users/new", compose_handler!(billing));
+        other.get("/users/:slug{[a-z]+}", compose_handler!(billing));
+        routes.merge(other).unwrap();
+        let mut res = test.get("/users/new").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "billing".as_bytes());
+        let mut res = test.get("/users/1").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "users".as_bytes());
+
+        // but not alongside one of the same constraint.
+        let mut other = App::new();
+        other.get("/users/:name{[a-z]+}", compose_handler!(billing));
+        assert!(matches!(
+            routes.merge(other),
+            Err(Error::InternalServerError(msg))
+                if msg == "route `GET /users/:name{[a-z]+}` conflicts with `GET /users/:slug{[a-z]+}`"
+        ));
+
+        let mut other = App::new();
+        other.get_named("home", "/home", compose_handler!(billing));
+        routes.get_named("home", "/", compose_handler!(users));
+        assert!(matches!(
+            routes.merge(other),
+            Err(Error::InternalServerError(msg)) if msg == "route name `home` is already registered"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_app_extension_methods() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn echo(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("{} {}", req.method(), req.uri().path());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        let mkcol = Method::from_bytes(b"MKCOL").unwrap();
+
+        let mut app = App::new();
+        app.method(propfind.clone(), "/files/*path", compose_handler!(echo));
+        app.method(mkcol.clone(), "/files/*path", compose_handler!(echo));
+        let app = TestApp::new(app);
+
+        let mut res = app
+            .request(propfind, "/files/docs/a.txt", Body::empty())
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "PROPFIND /files/docs/a.txt".as_bytes());
+
+        let res = app
+            .request(
+                Method::from_bytes(b"REPORT").unwrap(),
+                "/files/docs",
+                Body::empty(),
+            )
+            .await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), "PROPFIND, MKCOL");
+    }
+
+    #[tokio::test]
+    async fn test_app_request_target_forms() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn echo(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!(
+                "{} {} {}",
+                req.method(),
+                req.uri(),
+                req.headers().get(header::HOST).unwrap().to_str().unwrap()
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/a/:b", compose_handler!(echo));
+        app.route_builder(Method::GET, "/only", compose_handler!(echo))
+            .host("api.example.com")
+            .register();
+        app.connect("/", compose_handler!(echo));
+        let app = TestApp::new(app);
+
+        let request = |method: Method, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::HOST, "ignored.example.com")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for (req, status, body) in [
+            (
+                request(Method::GET, "http://user@example.com:8080/a/b?c=d"),
+                StatusCode::OK,
+                "GET /a/b?c=d example.com:8080",
+            ),
+            (
+                request(Method::GET, "http://api.example.com/only"),
+                StatusCode::OK,
+                "GET /only api.example.com",
+            ),
+            (
+                request(Method::CONNECT, "example.com:443"),
+                StatusCode::OK,
+                "CONNECT example.com:443 example.com:443",
+            ),
+            (
+                request(Method::GET, "example.com:443"),
+                StatusCode::BAD_REQUEST,
+                "authority-form request target is only allowed for CONNECT",
+            ),
+        ] {
+            let mut res = app.dispatch(req).await;
+            assert_eq!(res.status(), status, "{}", body);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes());
+        }
+
+        assert_eq!(
+            app.dispatch(request(Method::GET, "http://www.example.com/only"))
+                .await
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_app_wrap() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, Error, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn tag(
+            mut req: Request<Body>,
+            resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let tag = format!("tagged {}", params.get("id").map_or("-", String::as_str));
+            req.extensions_mut().insert(tag);
+            Ok((req, resp, state))
+        }
+
+        async fn deny(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            if req.uri().path() == "/denied" {
+                return Err(Error::new_status(StatusCode::FORBIDDEN, "denied"));
+            }
+            Ok((req, resp, state))
+        }
+
+        async fn show(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = req.extensions().get::<String>().unwrap().clone();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/items/:id", compose_handler!(show));
+        app.wrap(compose_handler!(tag));
+        app.wrap(compose_handler!(deny));
+        // added after wrapping, but wrapped all the same.
+        app.get("/denied", compose_handler!(show));
+        app.get("/later", compose_handler!(show));
+        let app = TestApp::new(app);
+
+        for (uri, status, body) in [
+            ("/items/1", StatusCode::OK, "tagged 1"),
+            ("/later", StatusCode::OK, "tagged -"),
+            ("/denied", StatusCode::FORBIDDEN, "denied"),
+            ("/missing", StatusCode::NOT_FOUND, ""),
+        ] {
+            let mut res = app.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_wrap_after() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, elapsed, Error, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        async fn fail(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            Err(Error::new_status(StatusCode::CONFLICT, "conflict"))
+        }
+
+        async fn stamp(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            assert!(elapsed(&req).is_some());
+            let mut resp = resp.unwrap();
+            let path = req.uri().path().to_string();
+            resp.headers_mut().insert("x-path", path.parse().unwrap());
+            Ok((req, Some(resp), state))
+        }
+
+        async fn replace_errors(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let resp = resp.unwrap();
+            if resp.status() != StatusCode::CONFLICT {
+                return Ok((req, Some(resp), state));
+            }
+
+            let mut replaced = Response::new(Body::from("replaced"));
+            *replaced.status_mut() = resp.status();
+            replaced
+                .headers_mut()
+                .extend(resp.headers().clone().into_iter());
+            Ok((req, Some(replaced), state))
+        }
+
+        let mut app = App::new();
+        app.get("/ok", compose_handler!(ok));
+        app.get("/fail", compose_handler!(fail));
+        app.wrap_after(compose_handler!(stamp));
+        app.wrap_after(compose_handler!(replace_errors));
+        let app = TestApp::new(app);
+
+        for (uri, status, body) in [
+            ("/ok", StatusCode::OK, "ok"),
+            ("/fail", StatusCode::CONFLICT, "replaced"),
+            ("/missing", StatusCode::NOT_FOUND, ""),
+        ] {
+            let mut res = app.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            assert_eq!(res.headers().get("x-path").unwrap(), uri);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_max_body_size() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::{body::Bytes, Body};
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        async fn echo(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = crate::body::read_limited(&mut req, 1 << 20).await?;
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let performed = Arc::new(AtomicUsize::new(0));
+        let counter = performed.clone();
+        let count = crate::handler::Handler::from_fn(move |req, resp, _params, _app, state| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move { Ok((req, resp, state)) }
+        });
+
+        let mut app: App<(), NoState> = App::new();
+        app.set_max_body_size(8);
+        app.post("/small", count.then(compose_handler!(echo)));
+        app.route_builder(Method::POST, "/upload", compose_handler!(echo))
+            .max_body_size(64)
+            .register();
+        app.route_builder(Method::POST, "/tiny", compose_handler!(echo))
+            .max_body_size(4)
+            .register();
+        let app = TestApp::new(app);
+
+        // with a Content-Length, or sent in chunks without one.
+        let post = |path: &str, body: &'static str, sized: bool| {
+            let req = Request::builder().method(Method::POST).uri(path);
+            if sized {
+                req.header("content-length", body.len())
+                    .body(Body::from(body))
+                    .unwrap()
+            } else {
+                let chunks: Vec<Result<Bytes, std::io::Error>> = body
+                    .as_bytes()
+                    .chunks(3)
+                    .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                    .collect();
+                req.body(Body::wrap_stream(TestStream(chunks.into_iter())))
+                    .unwrap()
+            }
+        };
+
+        for sized in [true, false] {
+            for (path, body, status) in [
+                ("/small", "12345678", StatusCode::OK),
+                ("/small", "123456789", StatusCode::PAYLOAD_TOO_LARGE),
+                ("/upload", "123456789", StatusCode::OK),
+                ("/tiny", "12345", StatusCode::PAYLOAD_TOO_LARGE),
+            ] {
+                let mut res = app.dispatch(post(path, body, sized)).await;
+                assert_eq!(res.status(), status, "{} {} {}", path, body, sized);
+                if status == StatusCode::OK {
+                    let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+                    assert_eq!(bytes, body);
+                }
+            }
+        }
+
+        // oversized bodies with a Content-Length are refused before any handler runs.
+        assert_eq!(performed.load(Ordering::SeqCst), 3);
+
+        struct TestStream(std::vec::IntoIter<Result<Bytes, std::io::Error>>);
+
+        impl futures_core::Stream for TestStream {
+            type Item = Result<Bytes, std::io::Error>;
+
+            fn poll_next(
+                mut self: std::pin::Pin<&mut Self>,
+                _cx: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Option<Self::Item>> {
+                std::task::Poll::Ready(self.0.next())
+            }
+        }
     }
 
-    /// Perform a PATCH request against the path.
-    pub async fn patch(&self, path: &str, body: Body) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
-        self.app
-            .dispatch(req.method(Method::PATCH).uri(path).body(body).unwrap())
+    #[tokio::test]
+    async fn test_app_head_strips_body() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn page(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let res = Response::builder()
+                .header("content-type", "text/html")
+                .body(Body::from("<p>page</p>"))
+                .unwrap();
+            Ok((req, Some(res), state))
+        }
+
+        async fn stream(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body =
+                crate::body::stream(
+                    |mut writer| async move { writer.write_chunk("streamed").await },
+                );
+            Ok((req, Some(Response::new(body)), state))
+        }
+
+        let mut app = App::new();
+        app.head("/explicit", compose_handler!(page));
+        app.get("/fallback", compose_handler!(page));
+        app.head("/stream", compose_handler!(stream));
+        let app = TestApp::new(app);
+
+        for path in ["/explicit", "/fallback"] {
+            let mut res = app.head(path).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers().get("content-length").unwrap(), "11");
+            assert_eq!(res.headers().get("content-type").unwrap(), "text/html");
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert!(body.is_empty(), "{}", path);
+        }
+
+        // bodies of unknown length have none advertised.
+        let mut res = app.head("/stream").await;
+        assert!(res.headers().get("content-length").is_none());
+        assert!(hyper::body::to_bytes(res.body_mut())
             .await
             .unwrap()
+            .is_empty());
     }
 
-    /// Perform a HEAD request against the path.
-    pub async fn head(&self, path: &str) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
-        self.app
-            .dispatch(
-                req.method(Method::HEAD)
-                    .uri(path)
-                    .body(Body::default())
-                    .unwrap(),
+    #[tokio::test]
+    async fn test_app_problem_json() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, problem::Problem, Error, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn conflict(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            Err(Error::new_status(
+                StatusCode::CONFLICT,
+                "name \"bob\" is taken",
+            ))
+        }
+
+        async fn problem(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            Err(Problem::new(StatusCode::FORBIDDEN)
+                .type_uri("https://example.com/probs/out-of-credit")
+                .instance("/problem")
+                .into())
+        }
+
+        let app = |problem_json: bool| {
+            let mut app = App::new();
+            app.get("/conflict", compose_handler!(conflict));
+            app.get("/problem", compose_handler!(problem));
+            if problem_json {
+                app.enable_problem_json();
+            }
+            TestApp::new(app)
+        };
+
+        async fn get(
+            app: &TestApp<(), NoState>,
+            path: &str,
+            headers: &[(&str, &str)],
+        ) -> (StatusCode, Option<String>, String) {
+            let mut res = app.get_with(path, headers).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            (
+                res.status(),
+                res.headers()
+                    .get("content-type")
+                    .map(|value| value.to_str().unwrap().to_string()),
+                String::from_utf8(body.to_vec()).unwrap(),
             )
-            .await
-            .unwrap()
+        }
+
+        let problem_json = Some("application/problem+json".to_string());
+        let plain = app(false);
+        let app = app(true);
+
+        // problems are always rendered as such.
+        for app in [&plain, &app] {
+            assert_eq!(
+                get(app, "/problem", &[]).await,
+                (
+                    StatusCode::FORBIDDEN,
+                    problem_json.clone(),
+                    r#"{"type":"https://example.com/probs/out-of-credit","title":"Forbidden","status":403,"instance":"/problem"}"#
+                        .to_string()
+                )
+            );
+        }
+
+        // other errors are, for clients that prefer them.
+        let as_problem = (
+            StatusCode::CONFLICT,
+            problem_json.clone(),
+            r#"{"type":"about:blank","title":"Conflict","status":409,"detail":"name \"bob\" is taken"}"#
+                .to_string(),
+        );
+        let as_text = (
+            StatusCode::CONFLICT,
+            None,
+            "name \"bob\" is taken".to_string(),
+        );
+        for (headers, expected) in [
+            (vec![("accept", "application/problem+json")], &as_problem),
+            (
+                vec![("accept", "application/json, text/plain;q=0.5")],
+                &as_problem,
+            ),
+            (vec![("accept", "text/html, */*;q=0.1")], &as_text),
+            (vec![("accept", "*/*")], &as_text),
+            (vec![], &as_text),
+        ] {
+            assert_eq!(
+                &get(&app, "/conflict", &headers).await,
+                expected,
+                "{:?}",
+                headers
+            );
+        }
+        assert_eq!(
+            get(
+                &plain,
+                "/conflict",
+                &[("accept", "application/problem+json")]
+            )
+            .await,
+            as_text
+        );
+
+        // as are requests no route matched.
+        let (status, content_type, _) =
+            get(&app, "/missing", &[("accept", "application/json")]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(content_type, problem_json);
     }
 
-    /// Perform a TRACE request against the path.
-    pub async fn trace(&self, path: &str) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
-        self.app
-            .dispatch(
-                req.method(Method::TRACE)
-                    .uri(path)
-                    .body(Body::default())
+    #[tokio::test]
+    async fn test_app_method_override() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, original_method, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn echo(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = hyper::body::to_bytes(req.body_mut()).await?;
+            let body = format!(
+                "{} {} {}",
+                req.method(),
+                original_method(&req),
+                String::from_utf8_lossy(&body)
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let app = |method_override: bool| {
+            let mut app = App::new();
+            app.route(
+                &[Method::POST, Method::PUT, Method::PATCH, Method::DELETE],
+                "/posts/:id",
+                compose_handler!(echo),
+            );
+            if method_override {
+                app.enable_method_override();
+            }
+            TestApp::new(app)
+        };
+        let plain = app(false);
+        let app = app(true);
+
+        let req = |method: &str, header: Option<&str>, form: Option<&str>| {
+            let mut req = Request::builder().method(method).uri("/posts/7");
+            if let Some(header) = header {
+                req = req.header("x-http-method-override", header);
+            }
+            match form {
+                Some(form) => req
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Body::from(form.to_string()))
                     .unwrap(),
+                None => req.body(Body::empty()).unwrap(),
+            }
+        };
+
+        for (req, expected) in [
+            (req("POST", Some("delete"), None), "DELETE POST "),
+            (
+                req("POST", Some("PUT"), Some("_method=PATCH")),
+                "PUT POST _method=PATCH",
+            ),
+            (
+                req("POST", None, Some("title=a&_method=patch")),
+                "PATCH POST title=a&_method=patch",
+            ),
+            // only POST requests are overridden, and only to PUT, PATCH or DELETE.
+            (req("PUT", Some("DELETE"), None), "PUT PUT "),
+            (req("POST", Some("GET"), None), "POST POST "),
+            (
+                req("POST", None, Some("_method=CONNECT")),
+                "POST POST _method=CONNECT",
+            ),
+            (req("POST", None, None), "POST POST "),
+        ] {
+            let mut res = app.dispatch(req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+
+        // without enabling it, the header is ignored.
+        let mut res = plain.dispatch(req("POST", Some("DELETE"), None)).await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "POST POST ".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_app_disconnected() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, disconnected, Disconnected, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::{sync::Mutex, time::Duration};
+
+        static SIGNAL: Mutex<Option<Disconnected>> = Mutex::new(None);
+
+        async fn work(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let signal = disconnected(&req).unwrap();
+            assert!(!signal.is_disconnected());
+            *SIGNAL.lock().unwrap() = Some(signal);
+            if req.uri().path() == "/slow" {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            Ok((req, Some(Response::new(Body::from("done"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/slow", compose_handler!(work));
+        app.get("/fast", compose_handler!(work));
+        let app = TestApp::new(app);
+
+        // the client going away drops the dispatch before it answers.
+        let abandoned = tokio::time::timeout(Duration::from_millis(10), app.get("/slow")).await;
+        assert!(abandoned.is_err());
+        let signal = SIGNAL.lock().unwrap().take().unwrap();
+        assert!(signal.is_disconnected());
+        tokio::time::timeout(Duration::from_secs(1), signal.wait())
+            .await
+            .unwrap();
+
+        // answered requests are never disconnected.
+        app.get("/fast").await;
+        let signal = SIGNAL.lock().unwrap().take().unwrap();
+        assert!(!signal.is_disconnected());
+        let waited = tokio::time::timeout(Duration::from_millis(10), signal.wait()).await;
+        assert!(waited.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_app_maintenance() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use std::time::Duration;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        async fn stamp(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let mut resp = resp.unwrap();
+            resp.headers_mut().insert("x-stamp", "1".parse().unwrap());
+            Ok((req, Some(resp), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(ok));
+        app.get("/healthz", compose_handler!(ok));
+        app.wrap_after(compose_handler!(stamp));
+        let maintenance = app.maintenance_handle();
+        let app = TestApp::new(app);
+
+        assert!(!maintenance.is_enabled());
+        assert_eq!(app.get("/").await.status(), StatusCode::OK);
+
+        maintenance.allow(&["/healthz"]);
+        maintenance.enable();
+        assert!(maintenance.is_enabled());
+        for path in ["/", "/missing"] {
+            let mut res = app.get(path).await;
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", path);
+            assert_eq!(res.headers().get("retry-after").unwrap(), "60");
+            assert_eq!(res.headers().get("x-stamp").unwrap(), "1");
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, "down for maintenance".as_bytes());
+        }
+        assert_eq!(app.get("/healthz").await.status(), StatusCode::OK);
+
+        // allowed paths are compared with the path requests are routed on.
+        for path in ["/healthz/", "//healthz", "/status/../healthz", "/heal%74hz"] {
+            assert_eq!(app.get(path).await.status(), StatusCode::OK, "{}", path);
+        }
+        let res = app.get("/healthz/..").await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        maintenance.set_retry_after(Duration::from_millis(1500));
+        maintenance.set_body("back soon");
+        let mut res = app.get("/").await;
+        assert_eq!(res.headers().get("retry-after").unwrap(), "2");
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "back soon".as_bytes());
+
+        maintenance.disable();
+        assert_eq!(app.get("/").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_app_stage_header() {
+        use super::{App, TestApp};
+        use crate::{compose_handler, flag, Error, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn authenticate(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, None, state))
+        }
+
+        async fn load_user(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            Err(Error::new("database went away"))
+        }
+
+        let app = |enabled: bool| {
+            let mut app = App::new();
+            if enabled {
+                app.enable_stage_header();
+            }
+            app.get("/user", compose_handler!(authenticate, load_user));
+            app.get(
+                "/beta",
+                flag::gate("beta", |_: &str, _: &Request<Body>| false)
+                    .then(compose_handler!(authenticate)),
+            );
+            app.get(
+                "/wrapped",
+                flag::gate("on", |_: &str, _: &Request<Body>| true)
+                    .then(compose_handler!(load_user)),
+            );
+            app.get(
+                "/mapped",
+                compose_handler!(authenticate, load_user)
+                    .on_error(|_, _| Ok(Response::new(Body::from("sorry")))),
+            );
+            TestApp::new(app)
+        };
+
+        let stage = |res: &Response<Body>| {
+            res.headers()
+                .get("x-ratpack-stage")
+                .map(|stage| stage.to_str().unwrap().to_string())
+        };
+
+        let plain = app(false);
+        let res = plain.get("/user").await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(stage(&res), None);
+
+        // the stage that failed is named, rather than the stages wrapping it.
+        let debug = app(true);
+        let res = debug.get("/user").await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(stage(&res).as_deref(), Some("load_user"));
+        let res = debug.get("/beta").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(stage(&res).as_deref(), Some("flag_gate"));
+        assert_eq!(
+            stage(&debug.get("/wrapped").await).as_deref(),
+            Some("load_user")
+        );
+
+        // errors turned into responses, and those from no stage, name none.
+        let res = debug.get("/mapped").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(stage(&res), None);
+        let res = debug.get("/missing").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(stage(&res), None);
+    }
+
+    #[tokio::test]
+    async fn test_app_expect_continue() {
+        use super::App;
+        use crate::{body, compose_handler, Error, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use std::time::Duration;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+        };
+
+        // refuses uploads without a token, before their body is sent.
+        async fn authenticate(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            assert!(body::expects_continue(&req));
+            match req.headers().contains_key("x-token") {
+                true => Ok((req, None, state)),
+                false => Err(Error::new_status(StatusCode::UNAUTHORIZED, "sign in")),
+            }
+        }
+
+        async fn upload(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = body::read_to_string_limited(&mut req, 1024).await?;
+            let reply = format!("got {}", body);
+            Ok((req, Some(Response::new(Body::from(reply))), state))
+        }
+
+        let mut app = App::new();
+        app.on_expect_continue(compose_handler!(authenticate));
+        app.post("/upload", compose_handler!(upload));
+
+        let server = app.listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        tokio::spawn(server.run());
+
+        let connect = || async move { TcpStream::connect(addr).await.unwrap() };
+
+        // read what the server sends until it pauses, as a client waiting to continue would.
+        async fn read_some(stream: &mut TcpStream) -> String {
+            let mut buf = vec![0; 1024];
+            let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        }
+
+        // a client with a token is told to continue, and its body is read.
+        let mut stream = connect().await;
+        stream
+            .write_all(
+                b"POST /upload HTTP/1.1\r\nhost: localhost\r\nx-token: yes\r\n\
+                  content-length: 5\r\nexpect: 100-continue\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        assert!(read_some(&mut stream)
+            .await
+            .starts_with("HTTP/1.1 100 Continue\r\n"));
+        stream.write_all(b"hello").await.unwrap();
+        let res = read_some(&mut stream).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("got hello"), "{}", res);
+
+        // one without is refused without being told to continue, and the connection closed.
+        let mut stream = connect().await;
+        stream
+            .write_all(
+                b"POST /upload HTTP/1.1\r\nhost: localhost\r\n\
+                  content-length: 5\r\nexpect: 100-continue\r\n\r\n",
             )
+            .await
+            .unwrap();
+        let mut res = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut res))
             .await
             .unwrap()
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", res);
+        assert!(res.ends_with("sign in"), "{}", res);
     }
 
-    /// Perform a CONNECT request against the path.
-    pub async fn connect(&self, path: &str) -> Response<Body> {
-        let req = self.populate_headers(Request::builder());
-        self.app
-            .dispatch(
-                req.method(Method::CONNECT)
-                    .uri(path)
-                    .body(Body::default())
-                    .unwrap(),
-            )
+    #[tokio::test]
+    async fn test_app_serve_with_shutdown() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params, ServerError};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::{net::SocketAddr, time::Duration};
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+            sync::oneshot,
+            task::JoinHandle,
+        };
+
+        async fn slow(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let wait = req.uri().query().unwrap().parse().unwrap();
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+            Ok((req, Some(Response::new(Body::from("done"))), state))
+        }
+
+        // serve the app, with a request in progress when it is asked to stop.
+        async fn shut_down_during(
+            mut app: App<(), NoState>,
+            wait: u64,
+        ) -> (TcpStream, SocketAddr, JoinHandle<Result<(), ServerError>>) {
+            app.get("/slow", compose_handler!(slow));
+
+            let server = app.listen("127.0.0.1:0").await.unwrap();
+            let addr = server.local_addr();
+            let (stop, stopped) = oneshot::channel();
+            let server =
+                tokio::spawn(server.run_with_shutdown(async { stopped.await.unwrap_or_default() }));
+
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let req = format!("GET /slow?{} HTTP/1.1\r\nHost: localhost\r\n\r\n", wait);
+            stream.write_all(req.as_bytes()).await.unwrap();
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            stop.send(()).unwrap();
+            (stream, addr, server)
+        }
+
+        // the request in progress is answered, then the connection closed; new ones are refused.
+        let (mut stream, addr, server) = shut_down_during(App::new(), 300).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(TcpStream::connect(addr).await.is_err());
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("done"), "{}", res);
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        // connections still open once the timeout has passed are closed.
+        let mut app = App::new();
+        app.set_shutdown_timeout(Duration::from_millis(100));
+        let (mut stream, _, server) = shut_down_during(app, 60_000).await;
+        tokio::time::timeout(Duration::from_secs(5), server)
             .await
             .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap_or_default();
+        assert!(res.is_empty(), "{}", res);
+    }
+
+    #[tokio::test]
+    async fn test_app_serve_resolves() {
+        use super::App;
+        use crate::NoState;
+        use std::{
+            net::{SocketAddr, TcpListener},
+            time::Duration,
+        };
+        use tokio::net::TcpStream;
+
+        async fn connect(addr: String) -> TcpStream {
+            for _ in 0..100 {
+                if let Ok(stream) = TcpStream::connect(&addr).await {
+                    return stream;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            panic!("server did not start");
+        }
+
+        let app: App<(), NoState> = App::new();
+
+        // names are resolved.
+        let server = app.clone().listen("localhost:0").await.unwrap();
+        let addr = server.local_addr();
+        assert!(addr.ip().is_loopback(), "{}", addr);
+        tokio::spawn(server.run());
+        connect(addr.to_string()).await;
+
+        // of a name resolving to IPv6 and IPv4 addresses, the first is served alone.
+        let taken = match TcpListener::bind("[::1]:0") {
+            Ok(listener) => listener,
+            // no IPv6 here.
+            Err(_) => return,
+        };
+        let v6 = taken.local_addr().unwrap();
+        let any_v6: SocketAddr = "[::1]:0".parse().unwrap();
+        let any_v4: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server = app.clone().listen(&[any_v6, any_v4][..]).await.unwrap();
+        assert!(server.local_addr().is_ipv6(), "{}", server.local_addr());
+
+        // addresses that cannot be bound are skipped.
+        let server = app.clone().listen(&[v6, any_v4][..]).await.unwrap();
+        let addr = server.local_addr();
+        assert!(addr.is_ipv4(), "{}", addr);
+        tokio::spawn(server.run());
+        connect(addr.to_string()).await;
+
+        // and listed when none can be.
+        let also_taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let v4 = also_taken.local_addr().unwrap();
+        let message = app.clone().serve(&[v6, v4][..]).await.unwrap_err();
+        let message = message.message();
+        assert!(
+            message.starts_with("could not bind any address"),
+            "{}",
+            message
+        );
+        assert!(message.contains(&v6.to_string()), "{}", message);
+        assert!(message.contains(&v4.to_string()), "{}", message);
+        drop((taken, also_taken));
+    }
+
+    #[tokio::test]
+    async fn test_app_serve_listener() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::net::SocketAddr;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::{TcpListener, TcpStream},
+            sync::oneshot,
+        };
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        // the listener is bound already, so the server can be reached at once.
+        async fn get(addr: SocketAddr) -> String {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut res = String::new();
+            stream.read_to_string(&mut res).await.unwrap();
+            res
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(hello));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(app.clone().serve_std_listener(listener));
+        assert!(get(addr).await.ends_with("hello"));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = oneshot::channel();
+        let server =
+            tokio::spawn(app.serve_listener_with_shutdown(listener, async {
+                stopped.await.unwrap_or_default()
+            }));
+        assert!(get(addr).await.ends_with("hello"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_app_listen() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+            sync::oneshot,
+        };
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(hello));
+
+        let server = app.listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        assert_ne!(addr.port(), 0);
+
+        // connections made before the server runs are answered once it does.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let (stop, stopped) = oneshot::channel();
+        let running =
+            tokio::spawn(server.run_with_shutdown(async { stopped.await.unwrap_or_default() }));
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("hello"), "{}", res);
+
+        stop.send(()).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "unix")]
+    #[tokio::test]
+    async fn test_app_listen_unix() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::UnixStream,
+        };
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(hello));
+
+        let path = std::env::temp_dir().join(format!("ratpack-listen-{}.sock", std::process::id()));
+        std::fs::remove_file(&path).unwrap_or_default();
+
+        let server = app.listen_unix(path.clone()).unwrap();
+        assert_eq!(server.local_addr().as_pathname(), Some(path.as_path()));
+        tokio::spawn(server.run());
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        assert!(res.ends_with("hello"), "{}", res);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_app_header_read_timeout() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::time::Duration;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+        };
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(hello));
+        app.set_header_read_timeout(Duration::from_millis(200));
+
+        let server = app.listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        tokio::spawn(server.run());
+
+        // a client stalling partway through its headers is cut off.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: local")
+            .await
+            .unwrap();
+        let mut res = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut res))
+            .await
+            .expect("connection was not closed")
+            .unwrap_or_default();
+        assert!(res.is_empty(), "{}", String::from_utf8_lossy(&res));
+
+        // as is one sending nothing at all.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut res = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut res))
+            .await
+            .expect("connection was not closed")
+            .unwrap_or_default();
+        assert!(res.is_empty(), "{}", String::from_utf8_lossy(&res));
+
+        // prompt clients are answered.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("hello"), "{}", res);
+    }
+
+    #[tokio::test]
+    async fn test_app_connection_reuse() {
+        use super::App;
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::{net::SocketAddr, time::Duration};
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+        };
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            if let Some(wait) = req.uri().query() {
+                tokio::time::sleep(Duration::from_millis(wait.parse().unwrap())).await;
+            }
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        async fn start(mut app: App<(), NoState>) -> SocketAddr {
+            app.get("/", compose_handler!(hello));
+            let server = app.listen("127.0.0.1:0").await.unwrap();
+            let addr = server.local_addr();
+            tokio::spawn(server.run());
+            addr
+        }
+
+        // the responses to the requests sent, once the server closes the connection.
+        async fn exchange(addr: SocketAddr, requests: &str) -> String {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(requests.as_bytes()).await.unwrap();
+            let mut res = String::new();
+            tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut res))
+                .await
+                .expect("connection was not closed")
+                .unwrap();
+            res
+        }
+
+        const GET: &str = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let mut app = App::new();
+        app.set_keep_alive(false);
+        let mut handle = app.routes_handle();
+        let addr = start(app).await;
+        let res = exchange(addr, GET).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("hello"), "{}", res);
+
+        // settings are read as the server starts, so changing them leaves it serving as it was.
+        handle.set_keep_alive(true);
+        let res = exchange(addr, &GET.repeat(2)).await;
+        assert_eq!(res.matches("HTTP/1.1 200 OK\r\n").count(), 1, "{}", res);
+
+        // idle connections are closed, but not while a slow request is in progress.
+        let mut app = App::new();
+        app.set_idle_timeout(Duration::from_millis(200));
+        let addr = start(app).await;
+        let res = exchange(addr, GET).await;
+        assert!(res.ends_with("hello"), "{}", res);
+        let slow = GET.replacen("/ ", "/?400 ", 1);
+        let res = exchange(addr, &slow).await;
+        assert!(res.ends_with("hello"), "{}", res);
+
+        let mut app = App::new();
+        app.set_max_requests_per_connection(2);
+        let res = exchange(start(app).await, &GET.repeat(2)).await;
+        assert_eq!(res.matches("HTTP/1.1 200 OK\r\n").count(), 2, "{}", res);
+    }
+
+    #[test]
+    #[should_panic(expected = "max requests per connection must be above zero")]
+    fn test_app_max_requests_per_connection_zero() {
+        use super::App;
+        use crate::NoState;
+
+        App::<(), NoState>::new().set_max_requests_per_connection(0);
+    }
+
+    #[tokio::test]
+    async fn test_app_max_connections() {
+        use super::{App, ConnectionOverflow};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::{net::SocketAddr, time::Duration};
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+        };
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        async fn start(overflow: ConnectionOverflow) -> (App<(), NoState>, SocketAddr) {
+            let mut app = App::new();
+            app.get("/", compose_handler!(hello));
+            app.set_max_connections(1, overflow);
+            let server = app.clone().listen("127.0.0.1:0").await.unwrap();
+            let addr = server.local_addr();
+            tokio::spawn(server.run());
+            (app, addr)
+        }
+
+        // a connection kept open after its request is answered, taking up the one allowed.
+        async fn hold(addr: SocketAddr) -> TcpStream {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = vec![0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(String::from_utf8_lossy(&buf[..n]).ends_with("hello"));
+            stream
+        }
+
+        async fn get(stream: &mut TcpStream) -> String {
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut res = String::new();
+            stream.read_to_string(&mut res).await.unwrap();
+            res
+        }
+
+        // connections beyond the limit are answered with a 503.
+        let (app, addr) = start(ConnectionOverflow::Reject).await;
+        let held = hold(addr).await;
+        let res = get(&mut TcpStream::connect(addr).await.unwrap()).await;
+        assert!(
+            res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            res
+        );
+        assert_eq!(app.stats().limited_connections, 1);
+
+        drop(held);
+        while app.stats().connections > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let res = get(&mut TcpStream::connect(addr).await.unwrap()).await;
+        assert!(res.ends_with("hello"), "{}", res);
+
+        // or left waiting until there is room for them.
+        let (app, addr) = start(ConnectionOverflow::Wait).await;
+        let held = hold(addr).await;
+        let mut waiting = TcpStream::connect(addr).await.unwrap();
+        let answer = tokio::spawn(async move { get(&mut waiting).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!answer.is_finished());
+        assert_eq!(app.stats().limited_connections, 1);
+
+        drop(held);
+        let res = answer.await.unwrap();
+        assert!(res.ends_with("hello"), "{}", res);
+    }
+
+    #[test]
+    #[should_panic(expected = "max connections must be above zero")]
+    fn test_app_max_connections_zero() {
+        use super::{App, ConnectionOverflow};
+        use crate::NoState;
+
+        App::<(), NoState>::new().set_max_connections(0, ConnectionOverflow::Wait);
+    }
+
+    #[tokio::test]
+    async fn test_app_accept_errors() {
+        use super::{Acceptor, App};
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::{
+            future::Future,
+            net::SocketAddr,
+            sync::atomic::{AtomicUsize, Ordering},
+            task::{Context, Poll},
+        };
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::{TcpListener, TcpStream},
+            sync::oneshot,
+        };
+
+        // fails to accept, as a process out of file descriptors does, before accepting at all.
+        struct Exhausted {
+            listener: TcpListener,
+            failures: AtomicUsize,
+        }
+
+        impl Acceptor for Exhausted {
+            type Stream = TcpStream;
+            type Io = TcpStream;
+
+            fn poll_accept(
+                &self,
+                cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<(Self::Stream, Option<SocketAddr>)>> {
+                if self.failures.fetch_add(1, Ordering::SeqCst) < 3 {
+                    return Poll::Ready(Err(std::io::Error::other("too many open files")));
+                }
+                Acceptor::poll_accept(&self.listener, cx)
+            }
+
+            fn establish(
+                &self,
+                stream: Self::Stream,
+            ) -> impl Future<Output = std::io::Result<Self::Io>> + Send + 'static {
+                std::future::ready(Ok(stream))
+            }
+        }
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(hello));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = Exhausted {
+            listener,
+            failures: AtomicUsize::new(0),
+        };
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            app.accept_connections(acceptor, async { stopped.await.unwrap_or_default() }),
+        );
+
+        // the server keeps accepting once the errors pass.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{}", res);
+        assert!(res.ends_with("hello"), "{}", res);
+
+        stop.send(()).unwrap();
+        server.await.unwrap();
     }
 }
@@ -0,0 +1,1169 @@
+use std::{
+    future::Future,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+
+use crate::Error;
+
+#[cfg(debug_assertions)]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[cfg(debug_assertions)]
+const CONSUMED: &str = "request body already consumed by earlier handler";
+
+/// Read the whole body of the request, returning the request with a replacement body holding the
+/// same bytes alongside them. The next handler in the chain can therefore read the body again,
+/// with this function or otherwise. This is the way to inspect a body in a handler that is not the
+/// last in its chain, such as one verifying a signature:
+///
+/// ```ignore
+///     let (req, body) = ratpack::body::buffer(req, 1 << 20).await?;
+///     verify(req.headers(), &body)?;
+///     Ok((req, None, state))
+/// ```
+///
+/// If the body is larger than `cap` bytes, a 413 Payload Too Large is returned.
+///
+/// In debug builds, the [crate::app::App] watches for handlers that read the body without
+/// replacing it. Reading the body of such a request, with this function or directly, fails with
+/// an error saying so rather than silently yielding no data.
+pub async fn buffer(mut req: Request<Body>, cap: usize) -> Result<(Request<Body>, Bytes), Error> {
+    let bytes = read_limited(&mut req, cap).await?;
+    *req.body_mut() = Body::from(bytes.clone());
+
+    #[cfg(debug_assertions)]
+    track(&mut req);
+
+    Ok((req, bytes))
+}
+
+/// Read the whole body of the request, failing with a 413 Payload Too Large as soon as more than
+/// `limit` bytes have arrived, so that clients cannot exhaust memory by sending endless bodies. If
+/// the request's `Content-Length` already exceeds the limit, it fails without reading anything.
+///
+/// ```ignore
+///     let body = ratpack::body::read_limited(&mut req, 64 * 1024).await?;
+/// ```
+///
+/// The body is consumed; see [buffer] to read it in a handler that is not the last in its chain.
+pub async fn read_limited(req: &mut Request<Body>, limit: usize) -> Result<Bytes, Error> {
+    #[cfg(debug_assertions)]
+    if req.extensions().get::<BodyConsumed>().is_some() {
+        return Err(Error::InternalServerError(CONSUMED.to_string()));
+    }
+
+    if content_length(req).is_some_and(|length| length > limit as u64) {
+        return Err(too_large(limit as u64));
+    }
+
+    let mut buf = Vec::new();
+    let body = req.body_mut();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(error)?;
+        if buf.len() + chunk.len() > limit {
+            return Err(too_large(limit as u64));
+        }
+
+        buf.extend_from_slice(&chunk);
+    }
+
+    if let Some(trailers) = body.trailers().await.map_err(error)? {
+        req.extensions_mut().insert(Trailers(trailers));
+    }
+
+    Ok(Bytes::from(buf))
+}
+
+// The trailers a request's body ended with, kept by [read_limited] for [trailers].
+#[derive(Debug, Clone)]
+struct Trailers(HeaderMap);
+
+/// Return the trailers the request's body ended with, once it has been read with [read_limited],
+/// [buffer] or [read_to_string_limited], or [std::option::Option::None] if it had none:
+///
+/// ```ignore
+///     let body = ratpack::body::read_limited(&mut req, 1 << 20).await?;
+///     let checksum = ratpack::body::trailers(&req).and_then(|trailers| trailers.get("x-checksum"));
+/// ```
+///
+/// Trailers are only received over HTTP/2, as hyper discards those of HTTP/1.1 chunked bodies.
+/// Bodies of unknown length cut off by [crate::app::App::set_max_body_size] lose their trailers
+/// too. Handlers reading the body by other means can ask it for its trailers with
+/// [hyper::body::HttpBody::trailers] once it has ended.
+pub fn trailers<B>(req: &Request<B>) -> Option<&HeaderMap> {
+    req.extensions()
+        .get::<Trailers>()
+        .map(|trailers| &trailers.0)
+}
+
+/// Read the whole body of the request as UTF-8 text, within `limit` bytes as with
+/// [read_limited]. A body that is not valid UTF-8 fails with a 400 Bad Request.
+pub async fn read_to_string_limited(
+    req: &mut Request<Body>,
+    limit: usize,
+) -> Result<String, Error> {
+    let bytes = read_limited(req, limit).await?;
+    String::from_utf8(bytes.to_vec()).map_err(|e| {
+        Error::new_status(
+            StatusCode::BAD_REQUEST,
+            format!("request body is not valid UTF-8: {}", e),
+        )
+    })
+}
+
+fn content_length(req: &Request<Body>) -> Option<u64> {
+    req.headers()
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn too_large(limit: u64) -> Error {
+    Error::new_status(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("request body exceeds limit of {} bytes", limit),
+    )
+}
+
+/// Whether the request was sent with `Expect: 100-continue`, its client waiting to be told to
+/// continue before sending the body. It is told so once the body is first read, so a handler
+/// answering without reading the body, such as to refuse the request, spares the client sending
+/// it. See [crate::app::App::on_expect_continue].
+pub fn expects_continue<B>(req: &Request<B>) -> bool {
+    req.version() == http::Version::HTTP_11
+        && req
+            .headers()
+            .get(header::EXPECT)
+            .is_some_and(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+}
+
+/// Convert an error reading a request body, so that bodies cut off by
+/// [crate::app::App::set_max_body_size] fail with a 413 Payload Too Large.
+pub(crate) fn error(e: hyper::Error) -> Error {
+    match std::error::Error::source(&e).and_then(|source| source.downcast_ref::<TooLarge>()) {
+        Some(TooLarge(limit)) => too_large(*limit),
+        None => e.into(),
+    }
+}
+
+/// Enforce the App's limit on the size of the request's body: bodies whose `Content-Length`
+/// exceeds it are refused now, and those sent without one are replaced with a body failing once
+/// past it. Bodies with a `Content-Length` within the limit are left alone, as hyper already
+/// refuses to read beyond it.
+pub(crate) fn limit(req: &mut Request<Body>, limit: u64) -> Result<(), Error> {
+    match content_length(req) {
+        Some(length) if length > limit => Err(too_large(limit)),
+        Some(_) => Ok(()),
+        None if req.body().is_end_stream() => Ok(()),
+        None => {
+            let body = std::mem::replace(req.body_mut(), Body::empty());
+            *req.body_mut() = Body::wrap_stream(Capped {
+                body,
+                remaining: limit,
+                limit,
+            });
+            Ok(())
+        }
+    }
+}
+
+// The error ending a body cut off at the App's limit.
+#[derive(Debug)]
+struct TooLarge(u64);
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request body exceeds limit of {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+// A body failing once more than its limit has been read from it.
+struct Capped {
+    body: Body,
+    remaining: u64,
+    limit: u64,
+}
+
+impl futures_core::Stream for Capped {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = match Pin::new(&mut self.body).poll_data(cx) {
+            Poll::Ready(Some(Ok(chunk))) => chunk,
+            Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e.into()))),
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if chunk.len() as u64 > self.remaining {
+            self.remaining = 0;
+            return Poll::Ready(Some(Err(TooLarge(self.limit).into())));
+        }
+
+        self.remaining -= chunk.len() as u64;
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+/// Remove the body of a response to a HEAD request, keeping its headers. A `Content-Length` is
+/// added for bodies of a known size that lack one, so that clients learn the length a GET would
+/// have had.
+pub(crate) fn strip(resp: &mut http::Response<Body>) {
+    let headers = resp.headers();
+    if !headers.contains_key(header::CONTENT_LENGTH)
+        && !headers.contains_key(header::TRANSFER_ENCODING)
+    {
+        if let Some(length) = resp.body().size_hint().exact() {
+            resp.headers_mut()
+                .insert(header::CONTENT_LENGTH, length.into());
+        }
+    }
+
+    *resp.body_mut() = Body::empty();
+}
+
+/// BodyWriter writes the chunks of a body produced by [stream].
+pub struct BodyWriter(mpsc::Sender<Result<Bytes, Aborted>>);
+
+impl BodyWriter {
+    /// Write a chunk of the body. If the chunk before it has not yet been taken by the
+    /// connection, this waits until it has, so that a slow client slows the writer down rather
+    /// than chunks piling up in memory. Fails if the client has gone away.
+    pub async fn write_chunk(&mut self, chunk: impl Into<Bytes>) -> Result<(), Error> {
+        self.0
+            .send(Ok(chunk.into()))
+            .await
+            .map_err(|_| Error::new("response body closed by the client"))
+    }
+
+    /// Wait until the chunks written so far have been taken by the connection. Fails if the
+    /// client has gone away.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.0
+            .reserve()
+            .await
+            .map(drop)
+            .map_err(|_| Error::new("response body closed by the client"))
+    }
+}
+
+/// Produce a body incrementally: `f` is given a [BodyWriter] to write the chunks of the body with,
+/// and is run in a task of its own while the response is sent, so that the client receives each
+/// chunk as it is written.
+///
+/// ```ignore
+///     let body = ratpack::body::stream(|mut writer| async move {
+///         while let Some(row) = rows.next().await? {
+///             writer.write_chunk(format!("{}\n", row)).await?;
+///         }
+///         Ok(())
+///     });
+///     Ok((req, Some(Response::new(body)), state))
+/// ```
+///
+/// The body ends once `f` returns. If `f` fails, the body ends with an error instead, and the
+/// connection is reset, so that the client sees a truncated response rather than one that seems
+/// complete.
+pub fn stream<F, Fut>(f: F) -> Body
+where
+    F: FnOnce(BodyWriter) -> Fut,
+    Fut: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    let abort = sender.clone();
+    let writing = f(BodyWriter(sender));
+
+    tokio::spawn(async move {
+        if let Err(e) = writing.await {
+            let message = e.message();
+
+            #[cfg(all(feature = "logging", not(feature = "trace")))]
+            log::error!("aborting streamed response body: {}", message);
+            #[cfg(feature = "trace")]
+            tracing::error!(error = %message, "aborting streamed response body");
+
+            abort.send(Err(Aborted(message))).await.unwrap_or_default();
+        }
+    });
+
+    Body::wrap_stream(Chunks(receiver))
+}
+
+/// A response whose body is produced incrementally as with [stream], and ends with the trailers
+/// `f` returns, such as a checksum of what it wrote. The trailers are declared up front in the
+/// response's `Trailer` header, so their names must be known before the body is written:
+///
+/// ```ignore
+///     let resp = ratpack::body::stream_with_trailers(&["x-checksum"], |mut writer| async move {
+///         let mut hasher = Hasher::new();
+///         for row in rows {
+///             hasher.update(row.as_bytes());
+///             writer.write_chunk(row).await?;
+///         }
+///
+///         let mut trailers = HeaderMap::new();
+///         trailers.insert("x-checksum", hasher.finish().to_string().parse()?);
+///         Ok(trailers)
+///     });
+/// ```
+///
+/// Trailers not declared are left out. The version of hyper ratpack serves with sends trailers
+/// over HTTP/2 only, where it also drops the `Trailer` header; HTTP/1.1 clients receive the header
+/// and the body, but not the trailers. If `f` fails, the body ends
+/// with an error and no trailers, resetting the connection. Panics if a name is not a valid header
+/// name.
+pub fn stream_with_trailers<F, Fut>(names: &[&str], f: F) -> Response<Body>
+where
+    F: FnOnce(BodyWriter) -> Fut,
+    Fut: Future<Output = Result<HeaderMap, Error>> + Send + 'static,
+{
+    let names: Vec<HeaderName> = names
+        .iter()
+        .map(|name| match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => panic!("invalid header name `{}`", name),
+        })
+        .collect();
+
+    let mut response = Response::new(Body::empty());
+    if !names.is_empty() {
+        let declared = names
+            .iter()
+            .map(HeaderName::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        response.headers_mut().insert(
+            header::TRAILER,
+            HeaderValue::from_str(&declared).expect("header names are valid header values"),
+        );
+    }
+
+    let (mut sender, body) = Body::channel();
+    let (writer, mut chunks) = mpsc::channel(1);
+    let writing = f(BodyWriter(writer));
+
+    // the chunks are passed on to the body as the connection takes them, followed by the
+    // trailers once the writer is done.
+    tokio::spawn(async move {
+        // the chunks are dropped if the client goes away, failing the writer's next write.
+        let forwarding = async move {
+            while let Some(Ok(chunk)) = chunks.recv().await {
+                sender.send_data(chunk).await.ok()?;
+            }
+            Some(sender)
+        };
+
+        let (trailers, sender) = tokio::join!(writing, forwarding);
+        let mut sender = match sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        let trailers = match trailers {
+            Ok(trailers) => trailers,
+            Err(e) => {
+                let _message = e.message();
+
+                #[cfg(all(feature = "logging", not(feature = "trace")))]
+                log::error!("aborting streamed response body: {}", _message);
+                #[cfg(feature = "trace")]
+                tracing::error!(error = %_message, "aborting streamed response body");
+
+                sender.abort();
+                return;
+            }
+        };
+
+        let mut declared = HeaderMap::new();
+        for (name, value) in trailers.iter() {
+            if names.contains(name) {
+                declared.append(name.clone(), value.clone());
+            }
+        }
+
+        sender.send_trailers(declared).await.unwrap_or_default();
+    });
+
+    *response.body_mut() = body;
+    response
+}
+
+/// A body of the chunks of a stream, such as one reading from another service. An error from the
+/// stream ends the body, resetting the connection as with [stream].
+pub fn from_stream<S, E>(stream: S) -> Body
+where
+    S: futures_core::Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+{
+    Body::wrap_stream(stream)
+}
+
+/// A body read from `reader` in chunks of up to `chunk_size` bytes, such as a file or the output
+/// of a child process, without holding more than a chunk of it in memory:
+///
+/// ```ignore
+///     let export = tokio::fs::File::open(&path).await?;
+///     let body = ratpack::body::from_async_read(export, 64 << 10);
+///     Ok((req, Some(Response::new(body)), state))
+/// ```
+///
+/// The body is written with [stream], so the reader is only read from as fast as the client takes
+/// the chunks, and a read error ends the body, resetting the connection. Panics if `chunk_size` is
+/// zero.
+pub fn from_async_read<R>(mut reader: R, chunk_size: usize) -> Body
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    if chunk_size == 0 {
+        panic!("chunk_size must be above zero");
+    }
+
+    stream(move |mut writer| async move {
+        loop {
+            let mut chunk = vec![0; chunk_size];
+            let read = reader.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(());
+            }
+
+            chunk.truncate(read);
+            writer.write_chunk(chunk).await?;
+        }
+    })
+}
+
+/// A response streaming the file at `path` as with [from_async_read], for downloads. Its
+/// `Content-Length` is the size of the file, and its `Content-Type` follows its extension as
+/// [crate::files::Files] would serve it. Fails with a 404 Not Found if there is no such file.
+pub async fn from_path(path: impl AsRef<Path>, chunk_size: usize) -> Result<Response<Body>, Error> {
+    let path = path.as_ref();
+    let not_found = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::NotFound => Error::new_status(StatusCode::NOT_FOUND, "not found"),
+        _ => e.into(),
+    };
+
+    let file = tokio::fs::File::open(path).await.map_err(not_found)?;
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return Err(Error::new_status(StatusCode::NOT_FOUND, "not found"));
+    }
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, crate::files::content_type(path))
+        .header(header::CONTENT_LENGTH, metadata.len())
+        .body(from_async_read(file, chunk_size))?)
+}
+
+// The error ending a body whose writer failed.
+#[derive(Debug)]
+struct Aborted(String);
+
+impl std::fmt::Display for Aborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Aborted {}
+
+// The chunks written by a BodyWriter, as a stream; it ends when every writer is gone.
+struct Chunks(mpsc::Receiver<Result<Bytes, Aborted>>);
+
+impl futures_core::Stream for Chunks {
+    type Item = Result<Bytes, Aborted>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+// Marks a request that arrived with a body that has not yet been read by a handler. HTTP/1 bodies
+// are watched as they are read; HTTP/2 bodies, whose trailers would be lost to a wrapper, say
+// themselves when they have ended.
+#[cfg(debug_assertions)]
+#[derive(Clone)]
+pub(crate) struct BodyPending(Option<Arc<Watch>>);
+
+// What is known of a watched body: whether it has been read to its end, and whether it has been
+// dropped, as it is when a handler replaces it.
+#[cfg(debug_assertions)]
+#[derive(Default)]
+pub(crate) struct Watch {
+    ended: AtomicBool,
+    dropped: AtomicBool,
+}
+
+// A request body watched for being read to its end.
+#[cfg(debug_assertions)]
+struct Watched {
+    body: Body,
+    watch: Arc<Watch>,
+}
+
+#[cfg(debug_assertions)]
+impl futures_core::Stream for Watched {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = std::task::ready!(Pin::new(&mut self.body).poll_data(cx));
+        if item.is_none() {
+            self.watch.ended.store(true, Ordering::SeqCst);
+        }
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for Watched {
+    fn drop(&mut self) {
+        self.watch.dropped.store(true, Ordering::SeqCst);
+    }
+}
+
+// Marks a request whose body was read by a handler that did not replace it.
+#[cfg(debug_assertions)]
+#[derive(Clone, Copy)]
+struct BodyConsumed;
+
+// A body that fails on its first read, standing in for one consumed by an earlier handler.
+#[cfg(debug_assertions)]
+struct Consumed(bool);
+
+#[cfg(debug_assertions)]
+impl futures_core::Stream for Consumed {
+    type Item = Result<Bytes, &'static str>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.0 {
+            return Poll::Ready(None);
+        }
+
+        self.0 = true;
+        Poll::Ready(Some(Err(CONSUMED)))
+    }
+}
+
+/// Record that the request has a body, so [guard_consumed] can tell when it is gone. Called again
+/// for a body put in place of the one the request arrived with, which is then watched instead.
+#[cfg(debug_assertions)]
+pub(crate) fn track(req: &mut Request<Body>) {
+    req.extensions_mut().remove::<BodyPending>();
+    if req.body().is_end_stream() {
+        return;
+    }
+
+    let pending = if req.version() < http::Version::HTTP_2 {
+        let watch = Arc::new(Watch::default());
+        let body = std::mem::replace(req.body_mut(), Body::empty());
+        *req.body_mut() = Body::wrap_stream(Watched {
+            body,
+            watch: watch.clone(),
+        });
+        BodyPending(Some(watch))
+    } else {
+        BodyPending(None)
+    };
+
+    req.extensions_mut().insert(pending);
+}
+
+/// Called after each handler stage: if the stage exhausted the body without putting a replacement
+/// in its place, swap in a body that errors when read so later stages fail loudly.
+#[cfg(debug_assertions)]
+pub(crate) fn guard_consumed(req: &mut Request<Body>, _stage: &'static str) {
+    let consumed = match req.extensions().get::<BodyPending>() {
+        Some(BodyPending(Some(watch))) => {
+            watch.ended.load(Ordering::SeqCst) && !watch.dropped.load(Ordering::SeqCst)
+        }
+        Some(BodyPending(None)) => req.body().is_end_stream(),
+        None => false,
+    };
+
+    if !consumed {
+        return;
+    }
+
+    #[cfg(all(feature = "logging", not(feature = "trace")))]
+    log::debug!(
+        "handler stage `{}` consumed the request body without replacing it",
+        _stage
+    );
+    #[cfg(feature = "trace")]
+    tracing::debug!(
+        stage = _stage,
+        "handler stage consumed the request body without replacing it"
+    );
+
+    req.extensions_mut().remove::<BodyPending>();
+    req.extensions_mut().insert(BodyConsumed);
+    *req.body_mut() = Body::wrap_stream(Consumed(false));
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_body_buffer() {
+        use super::buffer;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, Error, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn verify(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let (req, body) = buffer(req, 16).await?;
+            if !body.starts_with(b"signed:") {
+                return Err(Error::new_status(StatusCode::UNAUTHORIZED, "unsigned"));
+            }
+
+            Ok((req, None, state))
+        }
+
+        // the footgun: reads the body and passes the request on with nothing left in it.
+        async fn verify_badly(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            hyper::body::to_bytes(req.body_mut()).await?;
+            Ok((req, None, state))
+        }
+
+        async fn echo(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let (req, body) = buffer(req, 16).await?;
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn echo_raw(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = hyper::body::to_bytes(req.body_mut()).await?;
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.post("/good", compose_handler!(verify, echo));
+        app.post("/good_raw", compose_handler!(verify, echo_raw));
+        app.post("/bad", compose_handler!(verify_badly, echo));
+        app.post("/bad_raw", compose_handler!(verify_badly, echo_raw));
+        app.post("/empty", compose_handler!(verify_badly, echo_raw));
+        let app = TestApp::new(app);
+
+        for path in ["/good", "/good_raw"] {
+            let mut res = app.post(path, Body::from("signed:hello")).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(body, "signed:hello".as_bytes());
+        }
+
+        let res = app.post("/good", Body::from("hello")).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = app.post("/good", Body::from("signed:a long message")).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // the guard only runs in debug builds.
+        if cfg!(debug_assertions) {
+            let mut res = app.post("/bad", Body::from("signed:hello")).await;
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(
+                body,
+                "request body already consumed by earlier handler".as_bytes()
+            );
+
+            // reading the body directly fails too, with the same explanation.
+            let mut res = app.post("/bad_raw", Body::from("signed:hello")).await;
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert!(String::from_utf8_lossy(&body)
+                .contains("request body already consumed by earlier handler"));
+        }
+
+        // a request that never had a body has nothing to consume.
+        let res = app.post("/empty", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_body_read_limited() {
+        use super::{read_limited, read_to_string_limited};
+        use crate::Error;
+        use http::{Request, StatusCode};
+        use hyper::{body::Bytes, Body};
+
+        use std::{
+            pin::Pin,
+            task::{Context, Poll},
+        };
+
+        // yields chunks of `a` forever, or fails at once.
+        struct Endless(bool);
+
+        impl futures_core::Stream for Endless {
+            type Item = Result<Bytes, &'static str>;
+
+            fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+                match self.0 {
+                    true => Poll::Ready(Some(Ok(Bytes::from("a")))),
+                    false => Poll::Ready(Some(Err("read"))),
+                }
+            }
+        }
+
+        let status = |res: Result<_, Error>| match res {
+            Err(Error::StatusCode(status, _)) => status,
+            _ => panic!("expected an error status"),
+        };
+
+        let mut req = Request::new(Body::from("hello"));
+        assert_eq!(read_limited(&mut req, 5).await.unwrap(), "hello".as_bytes());
+
+        // reading stops once past the limit, even if the body never ends.
+        let mut req = Request::new(Body::wrap_stream(Endless(true)));
+        assert_eq!(
+            status(read_limited(&mut req, 1024).await),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        // a declared length over the limit fails without reading; this body fails if read.
+        let mut req = Request::builder()
+            .header("content-length", "2048")
+            .body(Body::wrap_stream(Endless(false)))
+            .unwrap();
+        assert_eq!(
+            status(read_limited(&mut req, 1024).await),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let mut req = Request::new(Body::from("héllo"));
+        assert_eq!(read_to_string_limited(&mut req, 16).await.unwrap(), "héllo");
+
+        let mut req = Request::new(Body::from(vec![0xff, 0xfe]));
+        assert_eq!(
+            status(read_to_string_limited(&mut req, 16).await.map(Bytes::from)),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_stream() {
+        use super::{from_stream, stream};
+        use crate::Error;
+        use hyper::body::{Bytes, HttpBody};
+        use std::{
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            time::Duration,
+        };
+
+        let written = Arc::new(AtomicUsize::new(0));
+        let counter = written.clone();
+        let mut body = stream(|mut writer| async move {
+            for chunk in ["one ", "two ", "three"] {
+                writer.write_chunk(chunk).await?;
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+            writer.flush().await
+        });
+
+        // the writer waits for the chunks to be taken, rather than writing them all at once.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(written.load(Ordering::SeqCst) < 3);
+        assert_eq!(body.data().await.unwrap().unwrap(), "one ");
+        let rest = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(rest, "two three");
+        assert_eq!(written.load(Ordering::SeqCst), 3);
+
+        // a failing writer fails the body, after what it wrote.
+        let mut body = stream(|mut writer| async move {
+            writer.write_chunk("partial").await?;
+            Err(Error::new("database went away"))
+        });
+        assert_eq!(body.data().await.unwrap().unwrap(), "partial");
+        let e = body.data().await.unwrap().unwrap_err();
+        assert!(e.to_string().contains("database went away"), "{}", e);
+
+        // a writer whose client has gone away is told so.
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        drop(stream(|mut writer| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            sender.send(writer.write_chunk("nobody").await).unwrap();
+            Ok(())
+        }));
+        assert!(receiver.await.unwrap().is_err());
+
+        struct Countdown(u8);
+
+        impl futures_core::Stream for Countdown {
+            type Item = Result<Bytes, std::io::Error>;
+
+            fn poll_next(
+                mut self: std::pin::Pin<&mut Self>,
+                _cx: &mut std::task::Context<'_>,
+            ) -> std::task::Poll<Option<Self::Item>> {
+                if self.0 == 0 {
+                    return std::task::Poll::Ready(None);
+                }
+
+                self.0 -= 1;
+                std::task::Poll::Ready(Some(Ok(Bytes::from(self.0.to_string()))))
+            }
+        }
+
+        let body = hyper::body::to_bytes(from_stream(Countdown(3)))
+            .await
+            .unwrap();
+        assert_eq!(body, "210");
+    }
+
+    #[tokio::test]
+    async fn test_body_from_async_read() {
+        use super::{from_async_read, from_path};
+        use http::StatusCode;
+        use hyper::body::HttpBody;
+        use std::{
+            io,
+            pin::Pin,
+            task::{Context, Poll},
+        };
+        use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+
+        // chunks hold no more than the chunk size.
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let mut body = from_async_read(reader, 4);
+        writer.write_all(b"hello, world").await.unwrap();
+        drop(writer);
+        assert_eq!(body.data().await.unwrap().unwrap(), "hell");
+        let rest = hyper::body::to_bytes(body).await.unwrap();
+        assert_eq!(rest, "o, world");
+
+        // the reader is not read from while the client is not taking chunks.
+        let (mut writer, reader) = tokio::io::duplex(8);
+        let _body = from_async_read(reader, 8);
+        let mut written = 0;
+        while let Ok(Ok(n)) =
+            tokio::time::timeout(std::time::Duration::from_millis(50), writer.write(&[0; 8])).await
+        {
+            written += n;
+        }
+        assert!(written <= 32, "{} bytes read ahead of the client", written);
+
+        // a failing reader fails the body, after what it read.
+        struct Failing(bool);
+
+        impl AsyncRead for Failing {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                buf: &mut ReadBuf<'_>,
+            ) -> Poll<io::Result<()>> {
+                if self.0 {
+                    return Poll::Ready(Err(io::Error::other("disk on fire")));
+                }
+
+                self.0 = true;
+                buf.put_slice(b"partial");
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let mut body = from_async_read(Failing(false), 1024);
+        assert_eq!(body.data().await.unwrap().unwrap(), "partial");
+        let e = body.data().await.unwrap().unwrap_err();
+        assert!(e.to_string().contains("disk on fire"), "{}", e);
+
+        let path =
+            std::env::temp_dir().join(format!("ratpack-download-{}.csv", std::process::id()));
+        std::fs::write(&path, "a,b\n1,2\n").unwrap();
+        let res = from_path(&path, 3).await.unwrap();
+        assert_eq!(res.headers().get("content-length").unwrap(), "8");
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body, "a,b\n1,2\n");
+        std::fs::remove_file(&path).unwrap();
+
+        for missing in [path, std::env::temp_dir()] {
+            assert_eq!(
+                from_path(&missing, 3).await.unwrap_err().status(),
+                StatusCode::NOT_FOUND
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_stream_with_trailers() {
+        use crate::{app::App, Error, HTTPResult, NoState, Params};
+        use http::{HeaderMap, Request, Response};
+        use hyper::{body::HttpBody, Body, Client};
+
+        async fn checksummed(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let resp = super::stream_with_trailers(&["x-checksum"], |mut writer| async move {
+                let mut sum = 0u32;
+                for chunk in ["one", "two", "three"] {
+                    sum += chunk.bytes().map(u32::from).sum::<u32>();
+                    writer.write_chunk(chunk).await?;
+                }
+
+                let mut trailers = HeaderMap::new();
+                trailers.insert("x-checksum", sum.into());
+                // undeclared trailers are left out.
+                trailers.insert("x-undeclared", "1".parse().unwrap());
+                Ok(trailers)
+            });
+            Ok((req, Some(resp), state))
+        }
+
+        async fn failing(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let resp = super::stream_with_trailers(&["x-checksum"], |mut writer| async move {
+                writer.write_chunk("partial").await?;
+                Err(Error::new("lost the database"))
+            });
+            Ok((req, Some(resp), state))
+        }
+
+        let mut app = App::new();
+        app.get("/checksummed", crate::compose_handler!(checksummed));
+        app.get("/failing", crate::compose_handler!(failing));
+
+        let server = app.listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        tokio::spawn(server.run());
+
+        // trailers go over the wire with HTTP/2 only.
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let uri: http::Uri = format!("http://{}/checksummed", addr).parse().unwrap();
+        let mut resp = client.get(uri.clone()).await.unwrap();
+
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.body_mut().data().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(body, b"onetwothree");
+
+        let expected: u32 = b"onetwothree".iter().map(|b| u32::from(*b)).sum();
+        let trailers = resp.body_mut().trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["x-checksum"], expected.to_string());
+        assert!(!trailers.contains_key("x-undeclared"));
+
+        // HTTP/1.1 clients are told of the trailers, but get the body alone.
+        let mut resp = Client::new().get(uri).await.unwrap();
+        assert_eq!(resp.headers()["trailer"], "x-checksum");
+        let body = hyper::body::to_bytes(resp.body_mut()).await.unwrap();
+        assert_eq!(body, "onetwothree");
+        assert!(resp.body_mut().trailers().await.unwrap().is_none());
+
+        // a failing writer resets the stream rather than sending trailers.
+        let uri = format!("http://{}/failing", addr).parse().unwrap();
+        let mut resp = client.get(uri).await.unwrap();
+        let mut failed = false;
+        while let Some(chunk) = resp.body_mut().data().await {
+            if chunk.is_err() {
+                failed = true;
+                break;
+            }
+        }
+        assert!(failed);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid header name")]
+    fn test_body_stream_with_trailers_invalid_name() {
+        super::stream_with_trailers(&["x checksum"], |_writer| async { Ok(Default::default()) });
+    }
+
+    #[tokio::test]
+    async fn test_body_trailers() {
+        use crate::{app::App, HTTPResult, NoState, Params};
+        use http::{HeaderMap, Request, Response};
+        use hyper::{Body, Client};
+
+        async fn upload(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = super::read_to_string_limited(&mut req, 1024).await?;
+            let checksum = super::trailers(&req)
+                .and_then(|trailers| trailers.get("x-checksum"))
+                .map_or("none", |value| value.to_str().unwrap());
+            let reply = format!("{} {}", body, checksum);
+            Ok((req, Some(Response::new(Body::from(reply))), state))
+        }
+
+        let mut app = App::new();
+        app.post("/upload", crate::compose_handler!(upload));
+
+        let server = app.listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        tokio::spawn(server.run());
+
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let upload = |trailers: Option<HeaderMap>| {
+            let client = client.clone();
+            async move {
+                let (mut sender, body) = Body::channel();
+                let req = Request::post(format!("http://{}/upload", addr))
+                    .header("trailer", "x-checksum")
+                    .body(body)
+                    .unwrap();
+                let resp = tokio::spawn(client.request(req));
+
+                sender.send_data("hello".into()).await.unwrap();
+                if let Some(trailers) = trailers {
+                    sender.send_trailers(trailers).await.unwrap();
+                }
+                drop(sender);
+
+                let resp = resp.await.unwrap().ok()?;
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                Some(String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-checksum", "532".parse().unwrap());
+        assert_eq!(upload(Some(trailers)).await.unwrap(), "hello 532");
+
+        assert_eq!(upload(None).await.unwrap(), "hello none");
+    }
+
+    #[tokio::test]
+    async fn test_body_consumed_over_connection() {
+        use super::buffer;
+        use crate::{app::App, compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+        };
+
+        async fn read_badly(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            hyper::body::to_bytes(req.body_mut()).await?;
+            Ok((req, None, state))
+        }
+
+        async fn read_buffered(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let (req, _) = buffer(req, 1024).await?;
+            Ok((req, None, state))
+        }
+
+        async fn echo(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let (req, body) = buffer(req, 1024).await?;
+            let reply = format!("echo:{}", String::from_utf8_lossy(&body));
+            Ok((req, Some(Response::new(Body::from(reply))), state))
+        }
+
+        let mut app = App::new();
+        app.post("/bad", compose_handler!(read_badly, echo));
+        app.post("/good", compose_handler!(read_buffered, echo));
+
+        let server = app.listen("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr();
+        tokio::spawn(server.run());
+
+        let post = |path: &'static str, chunks: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let req = format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+                 Connection: close\r\n\r\n{}",
+                path, chunks
+            );
+            stream.write_all(req.as_bytes()).await.unwrap();
+            let mut res = Vec::new();
+            stream.read_to_end(&mut res).await.unwrap();
+            String::from_utf8(res).unwrap()
+        };
+
+        // a chunked body carries no length, and is still known to have been read.
+        let res = post("/bad", "5\r\nhello\r\n0\r\n\r\n").await;
+        if cfg!(debug_assertions) {
+            assert!(res.starts_with("HTTP/1.1 500"), "{}", res);
+            assert!(res.contains("request body already consumed by earlier handler"));
+        }
+
+        let res = post("/good", "5\r\nhello\r\n0\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+        assert!(res.ends_with("echo:hello"), "{}", res);
+
+        // an empty body passed on by buffer can be read again.
+        let res = post("/good", "0\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+        assert!(res.ends_with("echo:"), "{}", res);
+    }
+}
@@ -0,0 +1,754 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+
+use crate::{handler::Handler, TransientState};
+
+// The headers carrying a client's credentials, which always vary what it is answered with.
+const CREDENTIALS: [HeaderName; 2] = [header::AUTHORIZATION, header::COOKIE];
+
+// What responses are cached under: the request's method, path and query, and the values of its
+// credentials and of the headers responses vary by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    method: Method,
+    path: String,
+    query: Option<String>,
+    headers: Vec<Option<HeaderValue>>,
+}
+
+// A cached response, when it was stored and last served, and how long it is kept for.
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+    ttl: Duration,
+    used: u64,
+}
+
+// The cached responses. `clock` counts lookups, so the least recently used entry is the one with
+// the smallest `used`.
+struct Store {
+    entries: HashMap<Key, Entry>,
+    clock: u64,
+}
+
+impl Store {
+    fn get(&mut self, key: &Key, now: Instant) -> Option<Response<Body>> {
+        self.clock += 1;
+
+        let entry = self.entries.get_mut(key)?;
+        let age = now.duration_since(entry.stored);
+        if age >= entry.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+
+        entry.used = self.clock;
+        let mut res = Response::new(Body::from(entry.body.clone()));
+        *res.status_mut() = entry.status;
+        *res.headers_mut() = entry.headers.clone();
+        res.headers_mut()
+            .insert(header::AGE, HeaderValue::from(age.as_secs()));
+        Some(res)
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry, max_entries: usize) {
+        if !self.entries.contains_key(&key) && self.entries.len() >= max_entries {
+            // expired entries go first, then the least recently used.
+            self.entries
+                .retain(|_, entry| entry.stored.elapsed() < entry.ttl);
+
+            if self.entries.len() >= max_entries {
+                let oldest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            Entry {
+                used: self.clock,
+                ..entry
+            },
+        );
+    }
+}
+
+/// CacheControl builds a `Cache-Control` header, telling clients and caches along the way how
+/// long they may keep a response:
+///
+/// ```ignore
+///     CacheControl::new()
+///         .public()
+///         .max_age(Duration::from_secs(3600))
+///         .stale_while_revalidate(Duration::from_secs(60))
+///         .apply(&mut resp);
+/// ```
+///
+/// It is used by [crate::files::Files] for the headers of the files it serves, and by [Cache] for
+/// deciding whether, and for how long, a response is cached. Durations are given in whole
+/// seconds, rounding down.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    must_revalidate: bool,
+    immutable: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+}
+
+impl CacheControl {
+    /// A header without directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The directives of the `Cache-Control` headers, as far as they are known; others are
+    /// ignored, as are durations that are not whole numbers of seconds.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut control = Self::new();
+        let seconds = |value: Option<&str>| {
+            value
+                .and_then(|value| value.trim_matches('"').parse().ok())
+                .map(Duration::from_secs)
+        };
+
+        for directive in headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim())),
+                None => (directive.trim(), None),
+            };
+
+            match name.to_ascii_lowercase().as_str() {
+                "public" => control.public = true,
+                "private" => control.private = true,
+                "no-cache" => control.no_cache = true,
+                "no-store" => control.no_store = true,
+                "must-revalidate" => control.must_revalidate = true,
+                "immutable" => control.immutable = true,
+                "max-age" => control.max_age = seconds(value),
+                "s-maxage" => control.s_maxage = seconds(value),
+                "stale-while-revalidate" => control.stale_while_revalidate = seconds(value),
+                _ => {}
+            }
+        }
+
+        control
+    }
+
+    /// Allow shared caches to store the response, even where they would not by default, such as
+    /// when the request was authorized. Replaces [CacheControl::private].
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self.private = false;
+        self
+    }
+
+    /// Allow only the client's own cache to store the response, as it is for that client alone.
+    /// Replaces [CacheControl::public].
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self.public = false;
+        self
+    }
+
+    /// Require caches to check that the response is still current with the server before every
+    /// use of it.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// Forbid caches from storing the response at all.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// Forbid caches from using the response once stale without checking it with the server.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Tell clients the response will never change while fresh, so they need not check it even
+    /// when the page is reloaded; for files whose names change with their contents.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    /// How long the response is fresh for.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// How long the response is fresh for in shared caches, in place of [CacheControl::max_age].
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// How long after it goes stale the response may still be used, while it is checked with the
+    /// server in the background.
+    pub fn stale_while_revalidate(mut self, stale: Duration) -> Self {
+        self.stale_while_revalidate = Some(stale);
+        self
+    }
+
+    /// The header's value.
+    pub fn to_header_value(&self) -> HeaderValue {
+        // directives are tokens and numbers, so the value is always valid.
+        HeaderValue::from_str(&self.to_string()).expect("valid Cache-Control value")
+    }
+
+    /// Set the header on the response, replacing any it has. When a `max-age` is given, an
+    /// `Expires` header that far from now is set too, for caches that only understand HTTP/1.0.
+    /// A header without directives is not set.
+    pub fn apply(&self, resp: &mut Response<Body>) {
+        if *self == Self::default() {
+            return;
+        }
+
+        resp.headers_mut()
+            .insert(header::CACHE_CONTROL, self.to_header_value());
+        if let Some(max_age) = self.max_age {
+            let expires = crate::date::format(SystemTime::now() + max_age);
+            if let Ok(expires) = HeaderValue::from_str(&expires) {
+                resp.headers_mut().insert(header::EXPIRES, expires);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.must_revalidate, "must-revalidate"),
+        ];
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+        ];
+
+        let directives = flags
+            .into_iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name.to_string())
+            .chain(durations.into_iter().filter_map(|(duration, name)| {
+                duration.map(|duration| format!("{}={}", name, duration.as_secs()))
+            }))
+            .chain(self.immutable.then(|| "immutable".to_string()))
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", directives.join(", "))
+    }
+}
+
+/// Cache keeps the responses of the chains it leads in memory for a while, and answers requests
+/// for them from there, without performing the chain:
+///
+/// ```ignore
+///     let cache = Cache::new(100, Duration::from_secs(60)).vary(&["accept"]);
+///     let handle = cache.handle();
+///     app.get("/reports/:id", cache.handler().then(compose_handler!(render_report)));
+///
+///     app.post("/reports/:id", Handler::from_fn(move |req, _resp, params, _app, state| {
+///         let handle = handle.clone();
+///         async move {
+///             let res = update_report(&req, &params).await?;
+///             handle.invalidate(&format!("/reports/{}", params["id"]));
+///             Ok((req, Some(res), state))
+///         }
+///     }));
+/// ```
+///
+/// Responses are cached under the request's method, path and query, and the values of the
+/// headers given to [Cache::vary]. The `Authorization` and `Cookie` headers are always part of
+/// this, so a response to a client with credentials is only served again to clients with the
+/// same ones. By default only 200 OK responses to GET requests are cached;
+/// responses whose [CacheControl] is `no-store`, `no-cache` or `private`, or with a `Set-Cookie`
+/// header never are, nor are those larger than [Cache::max_entry_size] or of unknown length, such
+/// as streamed ones. Cached responses are served with an `Age` header saying how long ago they
+/// were stored.
+///
+/// Responses are kept for `ttl`, or for their `s-maxage` or `max-age` if that is shorter, and once
+/// `max_entries` are held, the least recently used is dropped to make room for another. As with
+/// [crate::ratelimit::RateLimit], the responses live in the handler built by [Cache::handler] and
+/// are shared by its clones.
+pub struct Cache {
+    max_entries: usize,
+    ttl: Duration,
+    max_entry_size: u64,
+    vary: Vec<HeaderName>,
+    methods: Vec<Method>,
+    statuses: Vec<StatusCode>,
+    store: Arc<Mutex<Store>>,
+}
+
+impl Cache {
+    /// Cache up to `max_entries` responses, each for `ttl`. Panics if `max_entries` is zero.
+    pub fn new(max_entries: usize, ttl: Duration) -> Self {
+        if max_entries == 0 {
+            panic!("cache must hold responses: max_entries must be above zero");
+        }
+
+        Self {
+            max_entries,
+            ttl,
+            max_entry_size: 1 << 20,
+            vary: Vec::new(),
+            methods: vec![Method::GET],
+            statuses: vec![StatusCode::OK],
+            store: Arc::new(Mutex::new(Store {
+                entries: HashMap::new(),
+                clock: 0,
+            })),
+        }
+    }
+
+    /// Cache responses of no more than `max` bytes; 1MiB unless changed.
+    pub fn max_entry_size(mut self, max: u64) -> Self {
+        self.max_entry_size = max;
+        self
+    }
+
+    /// Cache responses separately for each value of the named request headers, such as `Accept`
+    /// for routes choosing their media type with [crate::negotiate::best]. Panics if a name is
+    /// invalid.
+    pub fn vary(mut self, headers: &[&str]) -> Self {
+        self.vary = headers
+            .iter()
+            .map(|name| match HeaderName::from_bytes(name.as_bytes()) {
+                Ok(name) => name,
+                Err(_) => panic!("invalid header name `{}`", name),
+            })
+            .collect();
+        self
+    }
+
+    /// Cache the responses to requests of these methods, rather than only GET.
+    pub fn methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Cache responses of these statuses, rather than only 200 OK.
+    pub fn statuses(mut self, statuses: &[StatusCode]) -> Self {
+        self.statuses = statuses.to_vec();
+        self
+    }
+
+    /// A handle for dropping responses from the cache, such as after the resource they are for
+    /// has changed.
+    pub fn handle(&self) -> CacheHandle {
+        CacheHandle(self.store.clone())
+    }
+
+    /// Build the handler serving and storing responses, which leads the chains it caches.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let cache = Arc::new(self);
+
+        Handler::around(
+            Arc::new(move |req, resp, params, app, state, next| {
+                let cache = cache.clone();
+
+                Box::pin(async move {
+                    if !cache.methods.contains(req.method()) {
+                        return match next {
+                            Some(next) => next.perform(req, resp, params, app, state).await,
+                            None => Ok((req, resp, state)),
+                        };
+                    }
+
+                    let key = cache.key(&req);
+                    if let Some(res) = cache.lock().get(&key, Instant::now()) {
+                        return Ok((req, Some(res), state));
+                    }
+
+                    let (req, resp, state) = match next {
+                        Some(next) => next.perform(req, resp, params, app, state).await?,
+                        None => (req, resp, state),
+                    };
+
+                    let resp = match resp {
+                        Some(resp) => match cache.lifetime(&resp) {
+                            Some(ttl) => Some(cache.store(key, resp, ttl).await?),
+                            None => Some(resp),
+                        },
+                        None => None,
+                    };
+
+                    Ok((req, resp, state))
+                })
+            }),
+            None,
+        )
+        .named("cache")
+    }
+
+    fn key<B>(&self, req: &Request<B>) -> Key {
+        Key {
+            method: req.method().clone(),
+            path: req.uri().path().to_string(),
+            query: req.uri().query().map(ToString::to_string),
+            headers: CREDENTIALS
+                .iter()
+                .chain(&self.vary)
+                .map(|name| req.headers().get(name).cloned())
+                .collect(),
+        }
+    }
+
+    // how long the response may be cached for, if it may be.
+    fn lifetime(&self, resp: &Response<Body>) -> Option<Duration> {
+        let control = CacheControl::from_headers(resp.headers());
+        if control.no_store || control.no_cache || control.private {
+            return None;
+        }
+
+        let cacheable = self.statuses.contains(&resp.status())
+            && !resp.headers().contains_key(header::SET_COOKIE)
+            && resp
+                .body()
+                .size_hint()
+                .exact()
+                .is_some_and(|size| size <= self.max_entry_size);
+
+        match control.s_maxage.or(control.max_age) {
+            Some(ttl) if ttl.is_zero() => None,
+            Some(ttl) => cacheable.then_some(ttl.min(self.ttl)),
+            None => cacheable.then_some(self.ttl),
+        }
+    }
+
+    // store the response, returning it with its body read into memory.
+    async fn store(
+        &self,
+        key: Key,
+        resp: Response<Body>,
+        ttl: Duration,
+    ) -> Result<Response<Body>, crate::Error> {
+        let (parts, body) = resp.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+
+        let entry = Entry {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            stored: Instant::now(),
+            ttl,
+            used: 0,
+        };
+        self.lock().insert(key, entry, self.max_entries);
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// CacheHandle drops responses from the [Cache] it was taken from. Clones of it share the cache,
+/// so it can be given to any handler that changes what the cached routes would answer.
+#[derive(Clone)]
+pub struct CacheHandle(Arc<Mutex<Store>>);
+
+impl CacheHandle {
+    /// Drop the responses cached for the path, whatever their method, query or headers.
+    pub fn invalidate(&self, path: &str) {
+        self.lock().entries.retain(|key, _| key.path != path);
+    }
+
+    /// Drop every cached response.
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// The number of responses cached, some of which may have expired.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether no responses are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_cache_eviction() {
+        use super::{Cache, Entry, Key};
+        use http::{HeaderMap, Method, StatusCode};
+        use hyper::body::Bytes;
+        use std::time::{Duration, Instant};
+
+        let ttl = Duration::from_secs(60);
+        let cache = Cache::new(2, ttl);
+        let key = |path: &str| Key {
+            method: Method::GET,
+            path: path.to_string(),
+            query: None,
+            headers: Vec::new(),
+        };
+        let entry = || Entry {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from("body"),
+            stored: Instant::now(),
+            ttl,
+            used: 0,
+        };
+
+        let mut store = cache.lock();
+        store.insert(key("/a"), entry(), 2);
+        store.insert(key("/b"), entry(), 2);
+        // using /a leaves /b the least recently used.
+        assert!(store.get(&key("/a"), Instant::now()).is_some());
+        store.insert(key("/c"), entry(), 2);
+        assert!(store.entries.contains_key(&key("/a")));
+        assert!(!store.entries.contains_key(&key("/b")));
+        assert!(store.entries.contains_key(&key("/c")));
+
+        // replacing an entry evicts nothing.
+        store.insert(key("/c"), entry(), 2);
+        assert_eq!(store.entries.len(), 2);
+
+        // expired entries are not served, and are dropped.
+        let later = Instant::now() + ttl;
+        assert!(store.get(&key("/a"), later).is_none());
+        assert!(!store.entries.contains_key(&key("/a")));
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        use super::Cache;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        };
+
+        static RENDERED: AtomicUsize = AtomicUsize::new(0);
+
+        async fn render(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let count = RENDERED.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut res = Response::builder().header("content-type", "text/plain");
+            match req.uri().path() {
+                "/private" => res = res.header("cache-control", "private, no-store"),
+                "/missing" => res = res.status(StatusCode::NOT_FOUND),
+                _ => {}
+            }
+            let accept = req
+                .headers()
+                .get("accept")
+                .map(|accept| accept.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let body = format!("{} {} {}", req.uri(), accept, count);
+            Ok((req, Some(res.body(Body::from(body)).unwrap()), state))
+        }
+
+        let cache = Cache::new(10, Duration::from_secs(60)).vary(&["accept"]);
+        let handle = cache.handle();
+        let mut app = App::new();
+        let chain = cache.handler().then(compose_handler!(render));
+        for path in ["/report", "/private", "/missing"] {
+            app.get(path, chain.clone());
+        }
+        app.post("/report", chain);
+        let app = TestApp::new(app);
+
+        async fn get(
+            app: &TestApp<(), NoState>,
+            uri: &str,
+            headers: &[(&str, &str)],
+        ) -> (Response<Body>, String) {
+            let mut res = app.get_with(uri, headers).await;
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            (res, String::from_utf8(body.to_vec()).unwrap())
+        }
+
+        let (res, body) = get(&app, "/report", &[]).await;
+        assert_eq!(body, "/report  1");
+        assert!(res.headers().get("age").is_none());
+        let (res, body) = get(&app, "/report", &[]).await;
+        assert_eq!(body, "/report  1");
+        assert_eq!(res.headers().get("age").unwrap(), "0");
+        assert_eq!(res.headers().get("content-type").unwrap(), "text/plain");
+
+        // queries and the headers given to vary are part of the key.
+        assert_eq!(
+            get(&app, "/report?page=2", &[]).await.1,
+            "/report?page=2  2"
+        );
+        assert_eq!(
+            get(&app, "/report?page=2", &[]).await.1,
+            "/report?page=2  2"
+        );
+        assert_eq!(
+            get(&app, "/report", &[("accept", "text/html")]).await.1,
+            "/report text/html 3"
+        );
+        assert_eq!(
+            get(&app, "/report", &[("accept", "text/html")]).await.1,
+            "/report text/html 3"
+        );
+
+        // as are credentials, so one client is never answered with what another was.
+        let alice = [("authorization", "Bearer alice")];
+        let bob = [("authorization", "Bearer bob")];
+        assert_eq!(get(&app, "/report", &alice).await.1, "/report  4");
+        assert_eq!(get(&app, "/report", &bob).await.1, "/report  5");
+        assert_eq!(get(&app, "/report", &alice).await.1, "/report  4");
+        assert_eq!(get(&app, "/report", &[]).await.1, "/report  1");
+
+        // no-store, other statuses, and other methods are not cached.
+        assert_eq!(get(&app, "/private", &[]).await.1, "/private  6");
+        assert_eq!(get(&app, "/private", &[]).await.1, "/private  7");
+        let (res, body) = get(&app, "/missing", &[]).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body, "/missing  8");
+        assert_eq!(get(&app, "/missing", &[]).await.1, "/missing  9");
+        let mut res = app.post("/report", Body::empty()).await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "/report  10");
+        assert_eq!(get(&app, "/report", &[]).await.1, "/report  1");
+
+        // invalidating a path drops every response for it.
+        assert_eq!(handle.len(), 5);
+        handle.invalidate("/report");
+        assert!(handle.is_empty());
+        assert_eq!(get(&app, "/report", &[]).await.1, "/report  11");
+        handle.clear();
+        assert_eq!(get(&app, "/report", &[]).await.1, "/report  12");
+    }
+
+    #[test]
+    fn test_cache_control() {
+        use super::{Cache, CacheControl};
+        use http::{HeaderMap, Response};
+        use hyper::Body;
+        use std::time::Duration;
+
+        let control = CacheControl::new()
+            .private()
+            .public()
+            .max_age(Duration::from_millis(3_600_900))
+            .s_maxage(Duration::from_secs(60))
+            .stale_while_revalidate(Duration::from_secs(30))
+            .immutable();
+        assert_eq!(
+            control.to_string(),
+            "public, max-age=3600, s-maxage=60, stale-while-revalidate=30, immutable"
+        );
+        assert_eq!(
+            CacheControl::new().no_store().private().to_string(),
+            "private, no-store"
+        );
+
+        let mut resp = Response::new(Body::empty());
+        control.apply(&mut resp);
+        assert_eq!(
+            resp.headers().get("cache-control").unwrap(),
+            &control.to_header_value()
+        );
+        let expires = resp.headers().get("expires").unwrap().to_str().unwrap();
+        assert!(crate::date::parse(expires).is_some());
+
+        // nothing is set without directives, or an Expires without a max-age.
+        let mut resp = Response::new(Body::empty());
+        CacheControl::new().apply(&mut resp);
+        assert!(resp.headers().is_empty());
+        CacheControl::new().no_cache().apply(&mut resp);
+        assert!(resp.headers().get("expires").is_none());
+
+        // parsing keeps the known directives, and ignores the rest.
+        let mut headers = HeaderMap::new();
+        headers.append("cache-control", "Public, max-age=\"600\"".parse().unwrap());
+        headers.append(
+            "cache-control",
+            "s-maxage=soon, community=\"UCI\", immutable"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            CacheControl::from_headers(&headers),
+            CacheControl::new()
+                .public()
+                .max_age(Duration::from_secs(600))
+                .immutable()
+        );
+
+        // the cache keeps responses for their max-age or s-maxage, if shorter than its own ttl.
+        let cache = Cache::new(1, Duration::from_secs(60));
+        let lifetime = |value: &str| {
+            let mut resp = Response::new(Body::from("body"));
+            resp.headers_mut()
+                .insert("cache-control", value.parse().unwrap());
+            cache.lifetime(&resp)
+        };
+        assert_eq!(lifetime("public"), Some(Duration::from_secs(60)));
+        assert_eq!(lifetime("max-age=10"), Some(Duration::from_secs(10)));
+        assert_eq!(
+            lifetime("max-age=10, s-maxage=20"),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(lifetime("max-age=600"), Some(Duration::from_secs(60)));
+        assert_eq!(lifetime("max-age=0"), None);
+        assert_eq!(lifetime("private, max-age=10"), None);
+        assert_eq!(lifetime("no-cache"), None);
+    }
+}
@@ -0,0 +1,194 @@
+use std::{sync::Arc, time::Duration};
+
+use http::{header, Response, StatusCode};
+use hyper::Body;
+use tokio::sync::Semaphore;
+
+use crate::{handler::Handler, TransientState};
+
+/// ConcurrencyLimit bounds how many requests may be in the chains it leads at once, such as to
+/// protect a fragile service behind them. Requests beyond the limit are answered with a 503
+/// Service Unavailable and a `Retry-After` header, or made to wait for their turn for a while with
+/// [ConcurrencyLimit::queue]:
+///
+/// ```ignore
+///     let limit = ConcurrencyLimit::new(8).queue(Duration::from_secs(2)).handler();
+///     app.post("/reports", limit.then(compose_handler!(generate_report)));
+/// ```
+///
+/// [crate::handler::Handler::limit_concurrency] is a shorthand for the common case of a single
+/// chain refusing requests at once.
+///
+/// As with [crate::ratelimit::RateLimit], the count lives in the handler built by
+/// [ConcurrencyLimit::handler] and is shared by its clones, so a handler used for several routes
+/// bounds them together. A request counts until the rest of the chain has produced its response;
+/// sending a streamed body afterwards does not count.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    queue: Option<Duration>,
+    retry_after: Duration,
+}
+
+impl ConcurrencyLimit {
+    /// Allow `max` requests at once. Panics if it is zero, as no request could then be allowed.
+    pub fn new(max: usize) -> Self {
+        if max == 0 {
+            panic!("concurrency limit must allow requests: max must be above zero");
+        }
+
+        Self {
+            max,
+            queue: None,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+
+    /// Make requests beyond the limit wait up to `timeout` for another to finish, rather than
+    /// refusing them at once. Those still waiting after it are refused.
+    pub fn queue(mut self, timeout: Duration) -> Self {
+        self.queue = Some(timeout);
+        self
+    }
+
+    /// Tell refused clients to try again after this long, rounded up to the second and at least
+    /// one; one second unless changed.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Build the handler performing the limiting, which leads the chains it limits.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let semaphore = Arc::new(Semaphore::new(self.max));
+        let queue = self.queue;
+        let retry_after = (self.retry_after.as_secs_f64().ceil() as u64).max(1);
+
+        Handler::around(
+            Arc::new(move |req, resp, params, app, state, next| {
+                let semaphore = semaphore.clone();
+
+                Box::pin(async move {
+                    let permit = match queue {
+                        Some(timeout) => tokio::time::timeout(timeout, semaphore.acquire_owned())
+                            .await
+                            .ok()
+                            .and_then(Result::ok),
+                        None => semaphore.try_acquire_owned().ok(),
+                    };
+
+                    let _permit = match permit {
+                        Some(permit) => permit,
+                        None => {
+                            let res = Response::builder()
+                                .status(StatusCode::SERVICE_UNAVAILABLE)
+                                .header(header::RETRY_AFTER, retry_after)
+                                .body(Body::from("too many concurrent requests"))?;
+                            return Ok((req, Some(res), state));
+                        }
+                    };
+
+                    match next {
+                        Some(next) => next.perform(req, resp, params, app, state).await,
+                        None => Ok((req, resp, state)),
+                    }
+                })
+            }),
+            None,
+        )
+        .named("concurrency_limit")
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        use super::ConcurrencyLimit;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use std::{
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            time::Duration,
+        };
+
+        // the requests in the handler, and the most there have been at once.
+        static CURRENT: AtomicUsize = AtomicUsize::new(0);
+        static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+        async fn slow(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let current = CURRENT.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK.fetch_max(current, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            CURRENT.fetch_sub(1, Ordering::SeqCst);
+            Ok((req, Some(Response::new(Body::from("done"))), state))
+        }
+
+        let mut app = App::new();
+        app.get(
+            "/queued",
+            ConcurrencyLimit::new(3)
+                .queue(Duration::from_secs(10))
+                .handler()
+                .then(compose_handler!(slow)),
+        );
+        app.get("/refused", compose_handler!(slow).limit_concurrency(2));
+        let app = TestApp::new(app);
+
+        // every request is served, but no more than three at once.
+        let mut requests = Vec::new();
+        for _ in 0..10 {
+            let app = app.clone();
+            requests.push(tokio::spawn(async move { app.get("/queued").await }));
+        }
+        for request in requests {
+            assert_eq!(request.await.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(PEAK.load(Ordering::SeqCst), 3);
+
+        // without a queue, those beyond the limit are refused.
+        PEAK.store(0, Ordering::SeqCst);
+        let refused = Arc::new(AtomicUsize::new(0));
+        let mut requests = Vec::new();
+        for _ in 0..6 {
+            let app = app.clone();
+            let refused = refused.clone();
+            requests.push(tokio::spawn(async move {
+                let res = app.get("/refused").await;
+                if res.status() == StatusCode::SERVICE_UNAVAILABLE {
+                    assert_eq!(res.headers().get("retry-after").unwrap(), "1");
+                    refused.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    assert_eq!(res.status(), StatusCode::OK);
+                }
+            }));
+        }
+        for request in requests {
+            request.await.unwrap();
+        }
+        assert_eq!(PEAK.load(Ordering::SeqCst), 2);
+        assert!(refused.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "concurrency limit must allow requests: max must be above zero")]
+    fn test_concurrency_limit_rejects_zero() {
+        super::ConcurrencyLimit::new(0);
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http::{header, Request, Response};
+use hyper::Body;
+use percent_encoding::percent_decode_str;
+
+use crate::Error;
+
+#[cfg(feature = "secure-cookies")]
+mod secure;
+#[cfg(feature = "secure-cookies")]
+pub use secure::{PrivateCookies, SignedCookies};
+
+/// CookieMap holds the cookies a request was sent with, by name. See [get].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CookieMap(BTreeMap<String, String>);
+
+impl CookieMap {
+    /// Parse the cookies in the values of `Cookie` headers. Pairs are separated by `;`, and values
+    /// wrapped in double quotes are unwrapped. Values may be empty, but pairs without a `=` are
+    /// ignored. When a name appears more than once, the first value is kept; browsers send the
+    /// cookie with the most specific path first.
+    pub fn parse<'a>(headers: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut cookies = BTreeMap::new();
+
+        for pair in headers.into_iter().flat_map(|header| header.split(';')) {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(unquoted) => unquoted,
+                None => value,
+            };
+
+            cookies
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+
+        Self(cookies)
+    }
+
+    /// Return the value of the cookie, as it was sent.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Return the value of the cookie, percent-decoded. Sequences that do not decode to UTF-8 are
+    /// replaced with U+FFFD.
+    pub fn decoded(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.0
+            .get(name)
+            .map(|value| percent_decode_str(value).decode_utf8_lossy())
+    }
+
+    /// Return whether the request was sent with the cookie.
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Iterate over the cookies as they were sent, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Return the number of cookies.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Return whether there are no cookies.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// Inserted into every request by the App, so that cookies are parsed at most once however many
+// handlers read them.
+#[derive(Clone, Default)]
+pub(crate) struct ParsedCookies(Arc<OnceLock<Arc<CookieMap>>>);
+
+/// Return the cookies the request was sent with, from all of its `Cookie` headers; see
+/// [CookieMap::parse] for the details. For requests dispatched by an [crate::app::App], the
+/// cookies are parsed once and shared by every handler that asks for them.
+pub fn get<B>(req: &Request<B>) -> Arc<CookieMap> {
+    let parse = || {
+        Arc::new(CookieMap::parse(
+            req.headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok()),
+        ))
+    };
+
+    match req.extensions().get::<ParsedCookies>() {
+        Some(parsed) => parsed.0.get_or_init(parse).clone(),
+        None => parse(),
+    }
+}
+
+/// SameSite is the value of a cookie's `SameSite` attribute, which controls whether it is sent
+/// with requests from other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl std::fmt::Display for SameSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Strict => write!(f, "Strict"),
+            Self::Lax => write!(f, "Lax"),
+            Self::None => write!(f, "None"),
+        }
+    }
+}
+
+/// Cookie is a cookie to set on a response with [set]:
+///
+/// ```ignore
+///     cookies::set(
+///         &mut resp,
+///         Cookie::new("session", &token)
+///             .path("/")
+///             .max_age(Duration::from_secs(86400))
+///             .http_only()
+///             .secure()
+///             .same_site(SameSite::Lax),
+///     )?;
+/// ```
+///
+/// Values are sent as they are given, so values that may hold characters other than letters,
+/// digits and punctuation other than `"`, `,`, `;` and `\` should be percent-encoded; see
+/// [CookieMap::decoded].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A cookie with the name and value and no attributes, which browsers keep until they close.
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that removes the named cookie from the browser, by having expired. Its path and
+    /// domain must be set to those of the cookie to be removed.
+    pub fn remove(name: &str) -> Self {
+        Self::new(name, "")
+            .max_age(Duration::ZERO)
+            .expires(UNIX_EPOCH)
+    }
+
+    /// Limit the cookie to requests for paths under `path`.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    /// Send the cookie with requests to `domain` and its subdomains, rather than only to the host
+    /// that set it.
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Keep the cookie for `max_age`, to the second. Browsers prefer this to [Cookie::expires].
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Keep the cookie until `expires`, to the second.
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    /// Hide the cookie from scripts in the page.
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// Control whether the cookie is sent with requests from other sites. As browsers require it,
+    /// [SameSite::None] makes the cookie [Cookie::secure].
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+        self
+    }
+
+    // check the name and value can be sent without changing their meaning (RFC 6265 §4.1.1).
+    fn validate(&self) -> Result<(), Error> {
+        let separator = |c: char| "()<>@,;:\\\"/[]?={} \t".contains(c);
+        if self.name.is_empty()
+            || self
+                .name
+                .chars()
+                .any(|c| !c.is_ascii_graphic() || separator(c))
+        {
+            return Err(Error::new(format!("invalid cookie name `{}`", self.name)));
+        }
+
+        if self
+            .value
+            .chars()
+            .any(|c| !c.is_ascii_graphic() || "\",;\\".contains(c))
+        {
+            return Err(Error::new(format!(
+                "invalid value for cookie `{}`: it must be percent-encoded",
+                self.name
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", crate::date::format(expires))?;
+        }
+
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Set the cookie on the response, adding a `Set-Cookie` header for it; each cookie set has a
+/// header of its own. An error is returned if the cookie's name is not a valid token, or its value
+/// holds characters that must be encoded, as browsers would misread them.
+pub fn set(resp: &mut Response<Body>, cookie: Cookie) -> Result<(), Error> {
+    cookie.validate()?;
+    resp.headers_mut()
+        .append(header::SET_COOKIE, cookie.to_string().parse()?);
+    Ok(())
+}
+
+mod tests {
+    #[test]
+    fn test_cookie_map() {
+        use super::CookieMap;
+
+        let cookies = CookieMap::parse([
+            "session=abc123; theme=\"dark\"; empty=; flag; =nameless",
+            "session=shadowed; name=J%C3%BCrgen%20S",
+        ]);
+
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies.get("session"), Some("abc123"));
+        assert_eq!(cookies.get("theme"), Some("dark"));
+        assert_eq!(cookies.get("empty"), Some(""));
+        assert!(!cookies.contains("flag"));
+        assert_eq!(cookies.get("name"), Some("J%C3%BCrgen%20S"));
+        assert_eq!(cookies.decoded("name").unwrap(), "Jürgen S");
+        assert_eq!(
+            cookies.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            vec!["empty", "name", "session", "theme"]
+        );
+        assert!(CookieMap::parse([]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cookies_memoized() {
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, cookies, HTTPResult, NoState, Params,
+        };
+        use http::{HeaderMap, Request, Response};
+        use hyper::Body;
+        use std::sync::Arc;
+
+        async fn first(
+            mut req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let parsed = cookies::get(&req);
+            // later changes to the headers are not seen, as the parse is kept.
+            req.headers_mut().remove("cookie");
+            req.extensions_mut().insert(parsed);
+            Ok((req, resp, state))
+        }
+
+        async fn second(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let parsed = cookies::get(&req);
+            let first = req.extensions().get::<Arc<cookies::CookieMap>>().unwrap();
+            assert!(Arc::ptr_eq(first, &parsed));
+            let body = parsed.get("session").unwrap_or_default().to_string();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(first, second));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", "session=abc".parse().unwrap());
+        let app = TestApp::new(app).with_headers(headers);
+
+        let mut res = app.get("/").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "abc".as_bytes());
+    }
+
+    #[test]
+    fn test_cookie_set() {
+        use super::{set, Cookie, CookieMap, SameSite};
+        use http::Response;
+        use hyper::Body;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut resp = Response::new(Body::empty());
+        set(
+            &mut resp,
+            Cookie::new("session", "abc123")
+                .path("/app")
+                .domain("example.com")
+                .max_age(Duration::from_secs(3600))
+                .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+                .http_only()
+                .same_site(SameSite::None),
+        )
+        .unwrap();
+        set(&mut resp, Cookie::new("theme", "dark")).unwrap();
+        set(&mut resp, Cookie::remove("old").path("/")).unwrap();
+
+        let headers: Vec<&str> = resp
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                "session=abc123; Path=/app; Domain=example.com; Max-Age=3600; \
+                 Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=None",
+                "theme=dark",
+                "old=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+            ]
+        );
+
+        // what was set parses back as it was given.
+        let cookies = CookieMap::parse(headers[..1].iter().copied());
+        assert_eq!(cookies.get("session"), Some("abc123"));
+        assert_eq!(cookies.get("Path"), Some("/app"));
+        assert_eq!(cookies.get("Max-Age"), Some("3600"));
+        assert_eq!(cookies.get("SameSite"), Some("None"));
+        let cookies = CookieMap::parse(headers[2..].iter().copied());
+        assert_eq!(cookies.get("old"), Some(""));
+
+        for cookie in [
+            Cookie::new("", "a"),
+            Cookie::new("a b", "c"),
+            Cookie::new("a", "b;c"),
+            Cookie::new("a", "b c"),
+            Cookie::new("a", "ü"),
+        ] {
+            assert!(set(&mut resp, cookie.clone()).is_err(), "{}", cookie);
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use http::{Request, Response};
+use hyper::Body;
+use sha2::Sha256;
+
+use super::{get, set, Cookie};
+use crate::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// the length of an AES-GCM nonce.
+const NONCE_LEN: usize = 12;
+
+// The signing and encryption keys derived from each key given, newest first. Deriving them keeps
+// a key given to both SignedCookies and PrivateCookies from being used for two purposes.
+#[derive(Clone)]
+struct Keys(Vec<[u8; 32]>);
+
+impl Keys {
+    fn new(key: &[u8], purpose: &str) -> Self {
+        let mut keys = Self(Vec::new());
+        keys.push(key, purpose);
+        keys
+    }
+
+    fn push(&mut self, key: &[u8], purpose: &str) {
+        if key.len() < 32 {
+            panic!("cookie keys must be at least 32 bytes, not {}", key.len());
+        }
+
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+        mac.update(purpose.as_bytes());
+        self.0.push(mac.finalize().into_bytes().into());
+    }
+
+    fn newest(&self) -> &[u8; 32] {
+        &self.0[0]
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &[u8; 32]> {
+        self.0.iter()
+    }
+}
+
+impl std::fmt::Debug for Keys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Keys({} hidden)", self.0.len())
+    }
+}
+
+/// SignedCookies sets and reads cookies whose values are signed with HMAC-SHA256, so that clients
+/// can read them but not change or forge them, as is needed for session IDs:
+///
+/// ```ignore
+///     let signed = SignedCookies::new(&key).old_keys(&[&previous_key]);
+///     signed.set(&mut resp, Cookie::new("session", &id).http_only().secure())?;
+///     // in a later request:
+///     let id = signed.get(&req, "session");
+/// ```
+///
+/// The signature is appended to the value, and covers the name of the cookie too, so that a
+/// signed value cannot be moved to another cookie. Values must be encodable as for
+/// [super::set]. Keys must be random and at least 32 bytes long; to rotate them, sign with the new
+/// key and give the ones it replaces to [SignedCookies::old_keys], so that cookies signed with them
+/// keep verifying until they expire.
+#[derive(Debug, Clone)]
+pub struct SignedCookies(Keys);
+
+impl SignedCookies {
+    /// Sign cookies with the key. Panics if it is shorter than 32 bytes.
+    pub fn new(key: &[u8]) -> Self {
+        Self(Keys::new(key, "ratpack signed cookies"))
+    }
+
+    /// Also accept cookies signed with these keys, which are never signed with. Panics if any is
+    /// shorter than 32 bytes.
+    pub fn old_keys(mut self, keys: &[&[u8]]) -> Self {
+        for key in keys {
+            self.0.push(key, "ratpack signed cookies");
+        }
+        self
+    }
+
+    /// Return the value of the cookie, as it was set, if the request was sent with it and its
+    /// signature is valid for one of the keys. Cookies that were changed, cut short or signed
+    /// with another key are treated as if they were not sent.
+    pub fn get<B>(&self, req: &Request<B>, name: &str) -> Option<String> {
+        self.verify(name, get(req).get(name)?)
+    }
+
+    /// Set the cookie on the response, with its value signed. See [super::set] for the errors.
+    pub fn set(&self, resp: &mut Response<Body>, cookie: Cookie) -> Result<(), Error> {
+        set(resp, self.sign(cookie))
+    }
+
+    /// Sign the value of the cookie with the newest key.
+    pub fn sign(&self, mut cookie: Cookie) -> Cookie {
+        let mac = mac(self.0.newest(), &cookie.name, &cookie.value);
+        cookie.value = format!("{}.{}", cookie.value, URL_SAFE_NO_PAD.encode(mac));
+        cookie
+    }
+
+    /// Return the value a cookie with the name was signed with, if the signature is valid.
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        self.0.iter().find_map(|key| {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(key).ok()?;
+            mac.update(&signed_bytes(name, value));
+            mac.verify_slice(&signature).ok()?;
+            Some(value.to_string())
+        })
+    }
+}
+
+/// PrivateCookies sets and reads cookies whose values are encrypted and authenticated with
+/// AES-256-GCM, so that clients can neither read nor change them, such as for remember-me tokens
+/// or anything else a client should not see. It is otherwise used as [SignedCookies] is, key
+/// rotation included:
+///
+/// ```ignore
+///     let private = PrivateCookies::new(&key);
+///     private.set(&mut resp, Cookie::new("remember", &token).http_only().secure())?;
+///     let token = private.get(&req, "remember");
+/// ```
+///
+/// As values are encrypted, they need not be percent-encoded. Each is encrypted with a random
+/// nonce, so the same value is set differently every time. The name of the cookie is
+/// authenticated with it, so that an encrypted value cannot be moved to another cookie.
+#[derive(Debug, Clone)]
+pub struct PrivateCookies(Keys);
+
+impl PrivateCookies {
+    /// Encrypt cookies with the key. Panics if it is shorter than 32 bytes.
+    pub fn new(key: &[u8]) -> Self {
+        Self(Keys::new(key, "ratpack private cookies"))
+    }
+
+    /// Also accept cookies encrypted with these keys, which are never encrypted with. Panics if
+    /// any is shorter than 32 bytes.
+    pub fn old_keys(mut self, keys: &[&[u8]]) -> Self {
+        for key in keys {
+            self.0.push(key, "ratpack private cookies");
+        }
+        self
+    }
+
+    /// Return the value of the cookie, decrypted, if the request was sent with it and it decrypts
+    /// with one of the keys. Cookies that were changed, cut short or encrypted with another key are
+    /// treated as if they were not sent.
+    pub fn get<B>(&self, req: &Request<B>, name: &str) -> Option<String> {
+        self.decrypt(name, get(req).get(name)?)
+    }
+
+    /// Set the cookie on the response, with its value encrypted. See [super::set] for the errors.
+    pub fn set(&self, resp: &mut Response<Body>, cookie: Cookie) -> Result<(), Error> {
+        set(resp, self.encrypt(cookie)?)
+    }
+
+    /// Encrypt the value of the cookie with the newest key.
+    pub fn encrypt(&self, mut cookie: Cookie) -> Result<Cookie, Error> {
+        let cipher = Aes256Gcm::new(self.0.newest().into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = cipher.encrypt(
+            &nonce,
+            Payload {
+                msg: cookie.value.as_bytes(),
+                aad: cookie.name.as_bytes(),
+            },
+        )?;
+
+        let mut value = nonce.to_vec();
+        value.extend(sealed);
+        cookie.value = URL_SAFE_NO_PAD.encode(value);
+        Ok(cookie)
+    }
+
+    /// Return the value a cookie with the name was encrypted from, if it decrypts.
+    pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+        let encrypted = URL_SAFE_NO_PAD.decode(encrypted).ok()?;
+        if encrypted.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = encrypted.split_at(NONCE_LEN);
+
+        self.0.iter().find_map(|key| {
+            let value = Aes256Gcm::new(key.into())
+                .decrypt(
+                    Nonce::from_slice(nonce),
+                    Payload {
+                        msg: sealed,
+                        aad: name.as_bytes(),
+                    },
+                )
+                .ok()?;
+            String::from_utf8(value).ok()
+        })
+    }
+}
+
+// the bytes signed for a cookie; the name is length-prefixed so that no other name and value
+// sign the same bytes.
+fn signed_bytes(name: &str, value: &str) -> Vec<u8> {
+    let mut bytes = (name.len() as u64).to_be_bytes().to_vec();
+    bytes.extend(name.as_bytes());
+    bytes.extend(value.as_bytes());
+    bytes
+}
+
+fn mac(key: &[u8; 32], name: &str, value: &str) -> Vec<u8> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&signed_bytes(name, value));
+    mac.finalize().into_bytes().to_vec()
+}
+
+mod tests {
+    // set the cookie on a response and send it back in a request, as a browser would.
+    #[allow(dead_code)]
+    fn round_trip(
+        set: impl FnOnce(&mut http::Response<hyper::Body>),
+    ) -> (String, http::Request<()>) {
+        use crate::cookies::CookieMap;
+
+        let mut resp = http::Response::new(hyper::Body::empty());
+        set(&mut resp);
+        let header = resp.headers().get("set-cookie").unwrap().to_str().unwrap();
+        let pair = header.split(';').next().unwrap().to_string();
+        let (name, value) = pair.split_once('=').unwrap();
+        assert_eq!(CookieMap::parse([header]).get(name), Some(value));
+
+        let req = http::Request::builder()
+            .header("cookie", &pair)
+            .body(())
+            .unwrap();
+        (pair, req)
+    }
+
+    #[test]
+    fn test_signed_cookies() {
+        use super::SignedCookies;
+        use crate::cookies::Cookie;
+        use http::Request;
+
+        let old = [1u8; 32];
+        let new = [2u8; 64];
+        let signed = SignedCookies::new(&new).old_keys(&[&old]);
+        let request = |cookie: &str| {
+            Request::builder()
+                .header("cookie", cookie)
+                .body(())
+                .unwrap()
+        };
+
+        let (pair, req) = round_trip(|resp| {
+            signed
+                .set(resp, Cookie::new("session", "abc123").http_only())
+                .unwrap()
+        });
+        assert!(pair.starts_with("session=abc123."));
+        assert_eq!(signed.get(&req, "session").as_deref(), Some("abc123"));
+        assert_eq!(signed.get(&req, "missing"), None);
+
+        // changed values, names, signatures, and values cut short are all rejected.
+        let value = pair.strip_prefix("session=").unwrap();
+        let (_, signature) = value.rsplit_once('.').unwrap();
+        for forged in [
+            format!("session=abc124.{}", signature),
+            format!("other={}", value),
+            format!("session={}", &value[..value.len() - 1]),
+            format!("session={}A", value),
+            "session=abc123".to_string(),
+            "session=abc123.".to_string(),
+        ] {
+            assert_eq!(signed.get(&request(&forged), "session"), None, "{}", forged);
+            assert_eq!(signed.get(&request(&forged), "other"), None, "{}", forged);
+        }
+
+        // cookies signed with an old key still verify, but only the newest key signs.
+        let (pair, req) = round_trip(|resp| {
+            SignedCookies::new(&old)
+                .set(resp, Cookie::new("session", "abc123"))
+                .unwrap()
+        });
+        assert_eq!(signed.get(&req, "session").as_deref(), Some("abc123"));
+        assert_eq!(SignedCookies::new(&new).get(&req, "session"), None);
+        assert_ne!(
+            signed.sign(Cookie::new("session", "abc123")).to_string(),
+            pair
+        );
+
+        // values must still be encodable.
+        let mut resp = http::Response::new(hyper::Body::empty());
+        assert!(signed.set(&mut resp, Cookie::new("a", "b c")).is_err());
+    }
+
+    #[test]
+    fn test_private_cookies() {
+        use super::{PrivateCookies, SignedCookies};
+        use crate::cookies::Cookie;
+        use http::Request;
+
+        let old = [1u8; 32];
+        let new = [2u8; 32];
+        let private = PrivateCookies::new(&new).old_keys(&[&old]);
+        let request = |cookie: &str| {
+            Request::builder()
+                .header("cookie", cookie)
+                .body(())
+                .unwrap()
+        };
+
+        // values are hidden, and need no encoding.
+        let (pair, req) = round_trip(|resp| {
+            private
+                .set(resp, Cookie::new("remember", "jürgen; token=1"))
+                .unwrap()
+        });
+        assert!(!pair.contains("token"));
+        assert_eq!(
+            private.get(&req, "remember").as_deref(),
+            Some("jürgen; token=1")
+        );
+        let (again, _) = round_trip(|resp| {
+            private
+                .set(resp, Cookie::new("remember", "jürgen; token=1"))
+                .unwrap()
+        });
+        assert_ne!(pair, again);
+
+        let value = pair.strip_prefix("remember=").unwrap();
+        let mut flipped = value.to_string().into_bytes();
+        flipped[20] = if flipped[20] == b'A' { b'B' } else { b'A' };
+        let flipped = String::from_utf8(flipped).unwrap();
+        for forged in [
+            format!("remember={}", flipped),
+            format!("other={}", value),
+            format!("remember={}", &value[..value.len() - 2]),
+            format!("remember={}", &value[..10]),
+            "remember=".to_string(),
+            "remember=not%20base64".to_string(),
+        ] {
+            assert_eq!(
+                private.get(&request(&forged), "remember"),
+                None,
+                "{}",
+                forged
+            );
+            assert_eq!(private.get(&request(&forged), "other"), None, "{}", forged);
+        }
+
+        // cookies encrypted with an old key still decrypt.
+        let (_, req) = round_trip(|resp| {
+            PrivateCookies::new(&old)
+                .set(resp, Cookie::new("remember", "abc"))
+                .unwrap()
+        });
+        assert_eq!(private.get(&req, "remember").as_deref(), Some("abc"));
+        assert_eq!(PrivateCookies::new(&new).get(&req, "remember"), None);
+
+        // the same key signs and encrypts with keys of its own.
+        let (_, req) = round_trip(|resp| {
+            SignedCookies::new(&new)
+                .set(resp, Cookie::new("remember", "abc"))
+                .unwrap()
+        });
+        assert_eq!(private.get(&req, "remember"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "cookie keys must be at least 32 bytes, not 16")]
+    fn test_cookie_keys_too_short() {
+        super::SignedCookies::new(&[0; 16]);
+    }
+}
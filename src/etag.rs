@@ -0,0 +1,248 @@
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+
+use crate::{handler::Handler, TransientState};
+
+// Headers a 304 Not Modified carries over from the response it stands in for; see RFC 9110
+// §15.4.5.
+const NOT_MODIFIED_HEADERS: [header::HeaderName; 6] = [
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::VARY,
+];
+
+/// Compute a strong ETag for a body from a hash of its bytes, quoted as the `ETag` header expects.
+/// Handlers serving cached content can compute it once, and set it on their responses to spare
+/// [handler] hashing the body on every request.
+pub fn compute(bytes: &[u8]) -> HeaderValue {
+    let tag = format!(
+        "\"{:x}-{:016x}\"",
+        bytes.len(),
+        crate::weighted::fnv1a(bytes)
+    );
+    HeaderValue::from_str(&tag).expect("hex digits are a valid header value")
+}
+
+/// Whether the `If-None-Match` header of the request matches the ETag, meaning the client already
+/// holds the representation. Tags are compared weakly, as RFC 9110 §13.1.2 requires, and `*`
+/// matches any ETag.
+pub fn if_none_match<B>(req: &Request<B>, etag: &HeaderValue) -> bool {
+    let etag = opaque(etag.as_bytes());
+
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .flat_map(|value| value.as_bytes().split(|b| *b == b','))
+        .map(|tag| tag.trim_ascii())
+        .any(|tag| tag == b"*" || opaque(tag) == etag)
+}
+
+// the quoted part of an entity tag, without any weak indicator.
+fn opaque(tag: &[u8]) -> &[u8] {
+    tag.strip_prefix(b"W/").unwrap_or(tag)
+}
+
+/// A handler stage for the end of a chain, giving successful responses to GET and HEAD requests
+/// an `ETag` and answering with a 304 Not Modified when the request's `If-None-Match` matches it:
+///
+/// ```ignore
+///     app.get("/report", compose_handler!(report).then(etag::handler(1 << 20)));
+/// ```
+///
+/// The ETag is a hash of the body computed with [compute], unless an earlier handler already set
+/// one. Only bodies of a known size of at most `max_bytes` are hashed, so streaming responses
+/// pass through untouched unless they carry a `Content-Length`, as files served by
+/// [crate::files::Files] do.
+pub fn handler<S, T>(max_bytes: u64) -> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    Handler::from_fn(move |req, resp, _params, _app, state| async move {
+        let resp = match resp {
+            Some(resp) if req.method() == Method::GET || req.method() == Method::HEAD => {
+                Some(tag(&req, resp, max_bytes).await?)
+            }
+            resp => resp,
+        };
+
+        Ok((req, resp, state))
+    })
+    .named("etag")
+}
+
+async fn tag(
+    req: &Request<Body>,
+    mut resp: Response<Body>,
+    max_bytes: u64,
+) -> Result<Response<Body>, crate::Error> {
+    if resp.status() != StatusCode::OK {
+        return Ok(resp);
+    }
+
+    let etag = match resp.headers().get(header::ETAG) {
+        Some(etag) => etag.clone(),
+        None => {
+            let length = resp
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok()?.parse().ok());
+            match resp.body().size_hint().exact().or(length) {
+                Some(size) if size <= max_bytes => {}
+                _ => return Ok(resp),
+            }
+
+            let bytes: Bytes = hyper::body::to_bytes(resp.body_mut()).await?;
+            let etag = compute(&bytes);
+            *resp.body_mut() = Body::from(bytes);
+            resp.headers_mut().insert(header::ETAG, etag.clone());
+            etag
+        }
+    };
+
+    if !if_none_match(req, &etag) {
+        return Ok(resp);
+    }
+
+    let mut not_modified = Response::new(Body::empty());
+    *not_modified.status_mut() = StatusCode::NOT_MODIFIED;
+    for name in NOT_MODIFIED_HEADERS {
+        for value in resp.headers().get_all(&name) {
+            not_modified.headers_mut().append(&name, value.clone());
+        }
+    }
+
+    Ok(not_modified)
+}
+
+mod tests {
+    #[test]
+    fn test_etag_if_none_match() {
+        use super::{compute, if_none_match};
+        use http::Request;
+
+        let etag = compute(b"hello");
+        assert_eq!(etag, compute(b"hello"));
+        assert_ne!(etag, compute(b"hello!"));
+        assert!(etag.to_str().unwrap().starts_with("\"5-"));
+
+        let req = |value: &str| {
+            Request::builder()
+                .header("if-none-match", value)
+                .body(())
+                .unwrap()
+        };
+        let tag = etag.to_str().unwrap();
+        assert!(if_none_match(&req(tag), &etag));
+        assert!(if_none_match(&req("*"), &etag));
+        assert!(if_none_match(&req(&format!("\"a\", W/{}", tag)), &etag));
+        assert!(!if_none_match(&req("\"a\",\"b\""), &etag));
+        assert!(!if_none_match(&Request::new(()), &etag));
+    }
+
+    #[tokio::test]
+    async fn test_etag_handler() {
+        use super::handler;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, HTTPResult, NoState, Params,
+        };
+        use http::{Method, Request, Response, StatusCode};
+        use hyper::{body::Bytes, Body};
+
+        async fn report(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let res = Response::builder()
+                .header("cache-control", "max-age=60")
+                .header("content-type", "text/plain")
+                .body(Body::from("quarterly numbers"))
+                .unwrap();
+            Ok((req, Some(res), state))
+        }
+
+        async fn stream(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let (mut sender, body) = Body::channel();
+            tokio::spawn(async move { sender.send_data(Bytes::from("live")).await });
+            Ok((req, Some(Response::new(body)), state))
+        }
+
+        let mut app = App::new();
+        app.get("/report", compose_handler!(report).then(handler(1024)));
+        app.post("/report", compose_handler!(report).then(handler(1024)));
+        app.get("/small", compose_handler!(report).then(handler(4)));
+        app.get("/stream", compose_handler!(stream).then(handler(1024)));
+        let app = TestApp::new(app);
+
+        let request = |method: Method, path: &str, etag: Option<&str>| {
+            let mut req = Request::builder().method(method).uri(path);
+            if let Some(etag) = etag {
+                req = req.header("if-none-match", etag);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        let mut res = app.dispatch(request(Method::GET, "/report", None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let etag = res
+            .headers()
+            .get("etag")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "quarterly numbers");
+
+        let mut res = app
+            .dispatch(request(Method::GET, "/report", Some(&etag)))
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get("etag").unwrap(), etag.as_str());
+        assert_eq!(res.headers().get("cache-control").unwrap(), "max-age=60");
+        assert!(res.headers().get("content-type").is_none());
+        assert!(hyper::body::to_bytes(res.body_mut())
+            .await
+            .unwrap()
+            .is_empty());
+
+        let res = app
+            .dispatch(request(Method::GET, "/report", Some("\"stale\"")))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // only GET and HEAD are answered conditionally, and only bounded bodies are hashed.
+        let res = app
+            .dispatch(request(Method::POST, "/report", Some(&etag)))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("etag").is_none());
+        let res = app
+            .dispatch(request(Method::GET, "/small", Some("*")))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("etag").is_none());
+        let mut res = app
+            .dispatch(request(Method::GET, "/stream", Some("*")))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("etag").is_none());
+        assert_eq!(hyper::body::to_bytes(res.body_mut()).await.unwrap(), "live");
+    }
+}
@@ -0,0 +1,127 @@
+use http::Request;
+
+/// Store `value` in the request's extensions, returning the value of the same type it replaces.
+///
+/// Handlers pass data forward in one of two ways. A [crate::TransientState] is a type of the App's
+/// choosing, created afresh for each request and handed from stage to stage; it suits data that
+/// every stage of an App agrees on. The request's extensions are a map keyed by type which
+/// travels with the request itself; they suit the occasional value a middleware wants to pass on,
+/// and let middleware that know nothing of each other (or of the App's state type) share values.
+///
+/// ```ignore
+///     struct User { name: String }
+///
+///     async fn authenticate(mut req: Request<Body>, resp, params, app, state) -> HTTPResult<NoState> {
+///         let user = lookup(req.headers())?;
+///         ext::insert(&mut req, user);
+///         Ok((req, resp, state))
+///     }
+///
+///     async fn hello(req: Request<Body>, resp, params, app, state) -> HTTPResult<NoState> {
+///         let user = ext::get::<User>(&req).unwrap();
+///         ...
+///     }
+/// ```
+///
+/// As values are keyed by their type, prefer types of your own to shared ones such as `String`,
+/// which another middleware may also insert. Values last as long as the request does: a stage that
+/// replaces the request with a new one drops them, and stages that only see a copy of the request,
+/// such as those given to [crate::app::App::wrap_after], do not have them.
+///
+/// The App stores its own values here too, and provides accessors for them: among others,
+/// [crate::matched_path], [crate::raw_params], [crate::query] and, when the request arrived over
+/// TCP, [crate::peer_ip].
+pub fn insert<V>(req: &mut Request<impl Sized>, value: V) -> Option<V>
+where
+    V: Send + Sync + 'static,
+{
+    req.extensions_mut().insert(value)
+}
+
+/// Return the value of type `V` stored in the request's extensions.
+pub fn get<V>(req: &Request<impl Sized>) -> Option<&V>
+where
+    V: Send + Sync + 'static,
+{
+    req.extensions().get::<V>()
+}
+
+/// Remove the value of type `V` from the request's extensions, returning it.
+pub fn remove<V>(req: &mut Request<impl Sized>) -> Option<V>
+where
+    V: Send + Sync + 'static,
+{
+    req.extensions_mut().remove::<V>()
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_ext() {
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, ext, matched_path, peer_ip, Error, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use std::net::IpAddr;
+
+        struct User(String);
+
+        async fn authenticate(
+            mut req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let name = match req.headers().get("x-user").map(|name| name.to_str()) {
+                Some(Ok(name)) => name.to_string(),
+                _ => return Err(Error::new_status(StatusCode::UNAUTHORIZED, "")),
+            };
+            assert!(ext::insert(&mut req, User(name)).is_none());
+            Ok((req, resp, state))
+        }
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let user = ext::get::<User>(&req).unwrap();
+            let body = format!(
+                "hello, {} at {} from {}",
+                user.0,
+                matched_path(&req).unwrap(),
+                peer_ip(&req).unwrap()
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/hello/:page", compose_handler!(authenticate, hello));
+        let app = TestApp::new(app);
+
+        let mut req = Request::builder()
+            .uri("/hello/1")
+            .header("x-user", "erik")
+            .body(Body::empty())
+            .unwrap();
+        ext::insert(&mut req, "127.0.0.1".parse::<IpAddr>().unwrap());
+        let mut res = app.dispatch(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(
+            body,
+            "hello, erik at /hello/:page from 127.0.0.1".as_bytes()
+        );
+
+        assert_eq!(app.get("/hello/1").await.status(), StatusCode::UNAUTHORIZED);
+
+        let mut req = Request::new(());
+        ext::insert(&mut req, User("erik".to_string()));
+        assert_eq!(ext::remove::<User>(&mut req).unwrap().0, "erik");
+        assert!(ext::get::<User>(&req).is_none());
+    }
+}
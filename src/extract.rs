@@ -0,0 +1,367 @@
+use std::fmt;
+
+use http::{Request, StatusCode};
+use hyper::Body;
+use serde::{
+    de::{
+        self,
+        value::{MapDeserializer, SeqDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+
+use crate::{Error, Query};
+
+/// Deserialize the query string of the request into `T`. Each name in the query string is a field;
+/// fields that may be absent should be an [std::option::Option] or carry `#[serde(default)]`, and
+/// names that may be repeated (`?tag=a&tag=b`) should be collected into a [std::vec::Vec]. Other
+/// fields take the first value provided for their name.
+///
+/// If the query string does not fit `T`, a 400 Bad Request is returned describing why.
+///
+/// ```
+/// use ratpack::prelude::*;
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Pagination {
+///     page: u32,
+///     per_page: Option<u32>,
+/// }
+///
+/// let req = Request::builder()
+///     .uri("/items?page=2")
+///     .body(Body::empty())
+///     .unwrap();
+///
+/// let pagination: Pagination = ratpack::extract::query(&req).unwrap();
+/// assert_eq!(pagination.page, 2);
+/// assert_eq!(pagination.per_page, None);
+///
+/// let req = Request::builder()
+///     .uri("/items?page=two")
+///     .body(Body::empty())
+///     .unwrap();
+///
+/// match ratpack::extract::query::<Pagination>(&req) {
+///     Err(Error::StatusCode(status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+///     _ => panic!("expected a bad request"),
+/// }
+/// ```
+pub fn query<T: DeserializeOwned>(req: &Request<Body>) -> Result<T, Error> {
+    // requests dispatched through an App have already had their query string parsed.
+    let parsed;
+    let query = match crate::query(req) {
+        Some(query) => query,
+        None => {
+            parsed = Query::parse(req.uri().query().unwrap_or_default());
+            &parsed
+        }
+    };
+
+    T::deserialize(QueryDeserializer(query)).map_err(|e| {
+        Error::new_status(
+            StatusCode::BAD_REQUEST,
+            format!("invalid query string: {}", e.0),
+        )
+    })
+}
+
+#[derive(Debug)]
+struct ExtractError(String);
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+impl de::Error for ExtractError {
+    fn custom<M: fmt::Display>(msg: M) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+// The whole query string, presented as a map of name -> values.
+struct QueryDeserializer<'de>(&'de Query);
+
+impl<'de> de::Deserializer<'de> for QueryDeserializer<'de> {
+    type Error = ExtractError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut map = MapDeserializer::new(
+            self.0
+                .iter()
+                .map(|(name, values)| (name.as_str(), Values(values))),
+        );
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+// All values provided for one name. Sequences take every value; anything else takes the first.
+struct Values<'de>(&'de [String]);
+
+impl<'de> Values<'de> {
+    fn first(&self) -> Result<Value<'de>, ExtractError> {
+        self.0
+            .first()
+            .map(|value| Value(value))
+            .ok_or_else(|| de::Error::custom("no value provided"))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ExtractError> for Values<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! forward_to_first {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.first()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Values<'de> {
+    type Error = ExtractError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.first()?.deserialize_any(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut seq = SeqDeserializer::new(self.0.iter().map(|value| Value(value)));
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.first()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_first! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+// A single value, parsed into whatever type is asked of it.
+struct Value<'de>(&'de str);
+
+impl<'de> IntoDeserializer<'de, ExtractError> for Value<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(e) => Err(ExtractError(format!("invalid value `{}`: {}", self.0, e))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Value<'de> {
+    type Error = ExtractError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(IntoDeserializer::<ExtractError>::into_deserializer(self.0))
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool
+        deserialize_i8 => visit_i8
+        deserialize_i16 => visit_i16
+        deserialize_i32 => visit_i32
+        deserialize_i64 => visit_i64
+        deserialize_i128 => visit_i128
+        deserialize_u8 => visit_u8
+        deserialize_u16 => visit_u16
+        deserialize_u32 => visit_u32
+        deserialize_u64 => visit_u64
+        deserialize_u128 => visit_u128
+        deserialize_f32 => visit_f32
+        deserialize_f64 => visit_f64
+        deserialize_char => visit_char
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_extract_query() {
+        use super::query;
+        use crate::{app::App, compose_handler, Error, HTTPResult, NoState, Params};
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        #[serde(rename_all = "lowercase")]
+        enum Order {
+            Asc,
+            Desc,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Search {
+            q: String,
+            page: Option<u32>,
+            #[serde(default)]
+            exact: bool,
+            #[serde(default, rename = "tag")]
+            tags: Vec<String>,
+            order: Option<Order>,
+        }
+
+        let search =
+            |uri: &str| query::<Search>(&Request::builder().uri(uri).body(Body::empty()).unwrap());
+
+        assert_eq!(
+            search("/?q=hello+world&tag=a&tag=b&page=3&exact=true&order=desc").unwrap(),
+            Search {
+                q: "hello world".to_string(),
+                page: Some(3),
+                exact: true,
+                tags: vec!["a".to_string(), "b".to_string()],
+                order: Some(Order::Desc),
+            }
+        );
+
+        assert_eq!(
+            search("/?q=x").unwrap(),
+            Search {
+                q: "x".to_string(),
+                page: None,
+                exact: false,
+                tags: Vec::new(),
+                order: None,
+            }
+        );
+
+        for (uri, message) in [
+            ("/", "invalid query string: missing field `q`"),
+            (
+                "/?q=x&page=two",
+                "invalid query string: invalid value `two`",
+            ),
+            (
+                "/?q=x&order=up",
+                "invalid query string: unknown variant `up`",
+            ),
+        ] {
+            match search(uri) {
+                Err(Error::StatusCode(status, msg)) => {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                    assert!(msg.starts_with(message), "{}", msg);
+                }
+                other => panic!("{}: unexpected {:?}", uri, other),
+            }
+        }
+
+        async fn page(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            #[derive(Deserialize)]
+            struct Pagination {
+                page: u32,
+            }
+
+            let pagination: Pagination = query(&req)?;
+            Ok((
+                req,
+                Some(Response::new(Body::from(pagination.page.to_string()))),
+                state,
+            ))
+        }
+
+        let mut app = App::new();
+        app.get("/items", compose_handler!(page));
+        let app = crate::app::TestApp::new(app);
+
+        let mut res = app.get("/items?page=7").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "7".as_bytes());
+
+        let res = app.get("/items?page=-1").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
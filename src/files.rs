@@ -0,0 +1,911 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http::{header, Method, Request, Response, StatusCode};
+use hyper::Body;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+
+use crate::{cache::CacheControl, handler::Handler, problem::json_string, Error, TransientState};
+
+// Content types by file extension; files with others are served as application/octet-stream.
+const CONTENT_TYPES: [(&str, &str); 21] = [
+    ("css", "text/css; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("gif", "image/gif"),
+    ("htm", "text/html; charset=utf-8"),
+    ("html", "text/html; charset=utf-8"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("wasm", "application/wasm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+];
+
+// the size of the chunks files are streamed in.
+const CHUNK_SIZE: usize = 64 << 10;
+
+/// Files serves the files beneath a directory, and is mounted with
+/// [crate::app::App::get_prefix]; the `suffix` of the request is the path of the file relative to
+/// the directory:
+///
+/// ```ignore
+///     app.get_prefix("/assets", Files::new("./public").handler());
+/// ```
+///
+/// Requests for a directory are served its index file, `index.html` unless changed with
+/// [Files::index]. Directories without one are answered with a 404 Not Found, unless listings are
+/// enabled with [Files::listing].
+///
+/// Files are served with a `Last-Modified` header from their modification time, and requests
+/// carrying `If-Modified-Since` or `If-Unmodified-Since` are answered with a 304 Not Modified or a
+/// 412 Precondition Failed when the file has not, or has, changed since the date. Files and their
+/// 304s carry the `Cache-Control` header given with [Files::cache_control], if any. Where a request
+/// also carries `If-None-Match`, that wins over `If-Modified-Since`; to answer it, follow the
+/// handler with [crate::etag::handler].
+///
+/// Only files beneath the directory are served: paths with `..` segments are refused, as are
+/// symbolic links leading outside of it. Files that cannot be found or read are answered with a
+/// 404 Not Found.
+#[derive(Debug, Clone)]
+pub struct Files {
+    root: PathBuf,
+    index: Option<String>,
+    listing: bool,
+    cache_control: CacheControl,
+}
+
+impl Files {
+    /// Serve the files beneath `root`.
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            index: Some("index.html".to_string()),
+            listing: false,
+            cache_control: CacheControl::new(),
+        }
+    }
+
+    /// Serve the named file for requests for the directory holding it, or no file with None.
+    pub fn index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(ToString::to_string);
+        self
+    }
+
+    /// List the contents of directories without an index file, with the name, size and
+    /// modification time of each entry; subdirectories are listed first. The listing is HTML,
+    /// or JSON for requests accepting `application/json`. Symbolic links leading outside of the
+    /// served directory are left out.
+    pub fn listing(mut self) -> Self {
+        self.listing = true;
+        self
+    }
+
+    /// Serve files, and answer conditional requests for them, with this `Cache-Control` header,
+    /// and the `Expires` header that goes with it. Directory listings are served without it.
+    ///
+    /// ```ignore
+    ///     let cache_control = CacheControl::new().public().max_age(Duration::from_secs(86400));
+    ///     app.get_prefix("/assets", Files::new("./public").cache_control(cache_control).handler());
+    /// ```
+    pub fn cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    /// Build the handler serving the files. It responds itself, so is the last in its chain.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let files = Arc::new(self);
+
+        Handler::from_fn(move |req, _resp, params, _app, state| {
+            let files = files.clone();
+            async move {
+                let suffix = params.get("suffix").map(String::as_str).unwrap_or_default();
+                let resp = files.serve(&req, suffix).await?;
+                Ok((req, Some(resp), state))
+            }
+        })
+        .named("files")
+    }
+
+    async fn serve(&self, req: &Request<Body>, suffix: &str) -> Result<Response<Body>, Error> {
+        let root = canonical(&self.root).await.ok_or_else(not_found)?;
+        let path = self.resolve(&root, suffix).await.ok_or_else(not_found)?;
+
+        let metadata = tokio::fs::metadata(&path).await.map_err(|_| not_found())?;
+        if !metadata.is_dir() {
+            return file(req, &path, &self.cache_control).await;
+        }
+
+        if let Some(index) = &self.index {
+            if let Some(index) = self.resolve(&root, &format!("{}/{}", suffix, index)).await {
+                if tokio::fs::metadata(&index)
+                    .await
+                    .is_ok_and(|metadata| metadata.is_file())
+                {
+                    return file(req, &index, &self.cache_control).await;
+                }
+            }
+        }
+
+        if !self.listing {
+            return Err(not_found());
+        }
+
+        let mut entries = Vec::new();
+        let mut dir = tokio::fs::read_dir(&path).await.map_err(|_| not_found())?;
+        while let Some(entry) = dir.next_entry().await.map_err(|_| not_found())? {
+            // symbolic links are described by what they lead to, if that is inside the root.
+            let target = match canonical(&entry.path()).await {
+                Some(target) if target.starts_with(&root) => target,
+                _ => continue,
+            };
+            let metadata = match tokio::fs::metadata(&target).await {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            entries.push(Entry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        entries.sort_by(|a, b| b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name)));
+
+        let json = req
+            .headers()
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("application/json"));
+
+        let (content_type, body) = if json {
+            ("application/json", listing_json(&entries))
+        } else {
+            (
+                "text/html; charset=utf-8",
+                listing_html(req.uri().path(), !suffix.is_empty(), &entries),
+            )
+        };
+
+        Ok(Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(Body::from(body))?)
+    }
+
+    // the canonical path of the suffix beneath the root, if it is one.
+    async fn resolve(&self, root: &Path, suffix: &str) -> Option<PathBuf> {
+        canonical(&root.join(relative(suffix)?))
+            .await
+            .filter(|canonical| canonical.starts_with(root))
+    }
+}
+
+/// Build the handler serving the files of a directory embedded in the binary; shorthand for
+/// [Embedded::new] with its defaults:
+///
+/// ```ignore
+///     static ASSETS: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/public");
+///
+///     app.get_prefix("/assets", files::embedded(&ASSETS));
+/// ```
+///
+/// Available with the `embed` feature.
+#[cfg(feature = "embed")]
+pub fn embedded<S, T>(dir: &'static include_dir::Dir<'static>) -> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    Embedded::new(dir).handler()
+}
+
+/// Embedded serves the files of a directory embedded in the binary with [include_dir], for
+/// deployments of a single executable. It is mounted as [Files] is, and like it, serves the index
+/// file of directories, types files by their extension, refuses paths with `..` segments, and
+/// answers requests for anything else with a 404 Not Found.
+///
+/// As embedded files never change while the binary runs, they are served with a strong `ETag`
+/// from a hash of their contents, computed once when the handler is built, and requests whose
+/// `If-None-Match` matches it are answered with a 304 Not Modified. Directories are never listed.
+///
+/// Available with the `embed` feature.
+#[cfg(feature = "embed")]
+#[derive(Debug, Clone)]
+pub struct Embedded {
+    dir: &'static include_dir::Dir<'static>,
+    index: Option<String>,
+    cache_control: CacheControl,
+}
+
+#[cfg(feature = "embed")]
+impl Embedded {
+    /// Serve the files of the embedded directory.
+    pub fn new(dir: &'static include_dir::Dir<'static>) -> Self {
+        Self {
+            dir,
+            index: Some("index.html".to_string()),
+            cache_control: CacheControl::new(),
+        }
+    }
+
+    /// Serve the named file for requests for the directory holding it, or no file with None.
+    pub fn index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(ToString::to_string);
+        self
+    }
+
+    /// Serve files, and answer conditional requests for them, with this `Cache-Control` header,
+    /// as with [Files::cache_control].
+    pub fn cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.cache_control = cache_control;
+        self
+    }
+
+    /// Build the handler serving the files, hashing each of them for its ETag. It responds
+    /// itself, so is the last in its chain.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let mut etags = std::collections::HashMap::new();
+        let mut dirs = vec![self.dir];
+        while let Some(dir) = dirs.pop() {
+            for file in dir.files() {
+                etags.insert(
+                    file.path().to_path_buf(),
+                    crate::etag::compute(file.contents()),
+                );
+            }
+            dirs.extend(dir.dirs());
+        }
+
+        let embedded = Arc::new((self, etags));
+
+        Handler::from_fn(move |req, _resp, params, _app, state| {
+            let embedded = embedded.clone();
+            async move {
+                let (embedded, etags) = &*embedded;
+                let suffix = params.get("suffix").map(String::as_str).unwrap_or_default();
+                let resp = embedded.serve(&req, suffix, etags)?;
+                Ok((req, Some(resp), state))
+            }
+        })
+        .named("embedded_files")
+    }
+
+    fn serve(
+        &self,
+        req: &Request<Body>,
+        suffix: &str,
+        etags: &std::collections::HashMap<PathBuf, http::HeaderValue>,
+    ) -> Result<Response<Body>, Error> {
+        let path = relative(suffix).ok_or_else(not_found)?;
+
+        let file = match self.dir.get_file(&path) {
+            Some(file) => file,
+            None => {
+                let is_dir = path.as_os_str().is_empty() || self.dir.get_dir(&path).is_some();
+                match &self.index {
+                    Some(index) if is_dir => self.dir.get_file(path.join(index)),
+                    _ => None,
+                }
+                .ok_or_else(not_found)?
+            }
+        };
+
+        let etag = etags.get(file.path()).ok_or_else(not_found)?;
+        let mut resp = if crate::etag::if_none_match(req, etag) {
+            Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header(header::ETAG, etag)
+                .body(Body::empty())?
+        } else {
+            Response::builder()
+                .header(header::CONTENT_TYPE, content_type(file.path()))
+                .header(header::CONTENT_LENGTH, file.contents().len())
+                .header(header::ETAG, etag)
+                .body(Body::from(file.contents()))?
+        };
+
+        self.cache_control.apply(&mut resp);
+        Ok(resp)
+    }
+}
+
+// An entry of a directory listing.
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+// the suffix as a path relative to the served directory, or None if it could lead outside of it.
+fn relative(suffix: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+
+    for component in Path::new(suffix).components() {
+        match component {
+            Component::Normal(name) => path.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    Some(path)
+}
+
+// the content type of the file at the path, from its extension.
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    CONTENT_TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, content_type)| *content_type)
+        .unwrap_or("application/octet-stream")
+}
+
+async fn canonical(path: &Path) -> Option<PathBuf> {
+    tokio::fs::canonicalize(path).await.ok()
+}
+
+fn not_found() -> Error {
+    Error::new_status(StatusCode::NOT_FOUND, "not found")
+}
+
+async fn file(
+    req: &Request<Body>,
+    path: &Path,
+    cache_control: &CacheControl,
+) -> Result<Response<Body>, Error> {
+    let metadata = tokio::fs::metadata(path).await.map_err(|_| not_found())?;
+    let modified = metadata.modified().ok().map(whole_seconds);
+
+    if let Some(modified) = modified {
+        if let Some(status) = precondition(req, modified) {
+            let mut resp = Response::builder()
+                .status(status)
+                .header(header::LAST_MODIFIED, crate::date::format(modified))
+                .body(Body::empty())?;
+            if status == StatusCode::NOT_MODIFIED {
+                cache_control.apply(&mut resp);
+            }
+            return Ok(resp);
+        }
+    }
+
+    let contents = tokio::fs::File::open(path).await.map_err(|_| not_found())?;
+
+    let mut resp = Response::builder()
+        .header(header::CONTENT_TYPE, content_type(path))
+        .header(header::CONTENT_LENGTH, metadata.len());
+    if let Some(modified) = modified {
+        resp = resp.header(header::LAST_MODIFIED, crate::date::format(modified));
+    }
+
+    let mut resp = resp.body(crate::body::from_async_read(contents, CHUNK_SIZE))?;
+    cache_control.apply(&mut resp);
+    Ok(resp)
+}
+
+// HTTP dates have a resolution of a second, so modification times are compared at that too.
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => UNIX_EPOCH,
+    }
+}
+
+// the status to answer with instead of the file, if the request's date preconditions say so; see
+// RFC 9110 §13.2.2. If-Match and If-None-Match take precedence over the dates, and are left to an
+// ETag handler such as [crate::etag::handler], so the dates are ignored when they are present.
+// Dates that cannot be parsed, and those in the future, are ignored too.
+fn precondition(req: &Request<Body>, modified: SystemTime) -> Option<StatusCode> {
+    let date = |name| {
+        let date = crate::date::parse(req.headers().get(name)?.to_str().ok()?)?;
+        (date <= SystemTime::now()).then_some(date)
+    };
+    let headers = req.headers();
+
+    if !headers.contains_key(header::IF_MATCH) {
+        if let Some(date) = date(header::IF_UNMODIFIED_SINCE) {
+            if modified > date {
+                return Some(StatusCode::PRECONDITION_FAILED);
+            }
+        }
+    }
+
+    if !headers.contains_key(header::IF_NONE_MATCH)
+        && (req.method() == Method::GET || req.method() == Method::HEAD)
+    {
+        if let Some(date) = date(header::IF_MODIFIED_SINCE) {
+            if modified <= date {
+                return Some(StatusCode::NOT_MODIFIED);
+            }
+        }
+    }
+
+    None
+}
+
+fn listing_html(path: &str, parent: bool, entries: &[Entry]) -> String {
+    // links are made absolute from the request path, so that they keep the mount prefix.
+    let base = format!("{}/", path.trim_end_matches('/'));
+    let title = escape_html(&format!(
+        "Index of {}",
+        percent_decode_str(&base).decode_utf8_lossy()
+    ));
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n\
+         <body>\n<h1>{}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        title, title
+    );
+
+    if parent {
+        let parent = match base.trim_end_matches('/').rsplit_once('/') {
+            Some((parent, _)) => format!("{}/", parent),
+            None => "/".to_string(),
+        };
+        html += &format!(
+            "<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n",
+            escape_html(&parent)
+        );
+    }
+
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        let href = format!(
+            "{}{}{}",
+            base,
+            utf8_percent_encode(&entry.name, crate::path::SEGMENT),
+            slash
+        );
+        let size = if entry.dir {
+            String::new()
+        } else {
+            entry.size.to_string()
+        };
+
+        html += &format!(
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&href),
+            escape_html(&entry.name),
+            slash,
+            size,
+            entry.modified.map(crate::date::format).unwrap_or_default()
+        );
+    }
+
+    html + "</table>\n</body>\n</html>\n"
+}
+
+fn listing_json(entries: &[Entry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            let modified = match entry.modified {
+                Some(modified) => format!("\"{}\"", crate::date::format(modified)),
+                None => "null".to_string(),
+            };
+
+            format!(
+                "{{\"name\":{},\"dir\":{},\"size\":{},\"modified\":{}}}",
+                json_string(&entry.name),
+                entry.dir,
+                entry.size,
+                modified
+            )
+        })
+        .collect();
+
+    format!("[{}]", entries.join(","))
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_files() {
+        use super::Files;
+        use crate::{
+            app::{App, TestApp},
+            cache::CacheControl,
+            NoState,
+        };
+        use http::{HeaderMap, Response, StatusCode};
+        use hyper::Body;
+        use std::time::Duration;
+
+        let root = std::env::temp_dir().join(format!("ratpack-files-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs/guide")).unwrap();
+        std::fs::create_dir_all(root.join("site")).unwrap();
+        std::fs::write(root.join("docs/a.txt"), "alpha").unwrap();
+        std::fs::write(root.join("docs/<b&c>.json"), "{}").unwrap();
+        std::fs::write(root.join("site/index.html"), "<p>home</p>").unwrap();
+        let large = "0123456789abcdef".repeat(super::CHUNK_SIZE / 8);
+        std::fs::write(root.join("large.txt"), &large).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/etc", root.join("docs/escape")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("site"), root.join("docs/site")).unwrap();
+
+        let mut app: App<(), NoState> = App::new();
+        app.get_prefix("/plain", Files::new(&root).handler());
+        app.get_prefix("/browse", Files::new(&root).listing().handler());
+        app.get_prefix(
+            "/cached",
+            Files::new(&root)
+                .cache_control(
+                    CacheControl::new()
+                        .public()
+                        .max_age(Duration::from_secs(60)),
+                )
+                .handler(),
+        );
+        let app = TestApp::new(app);
+
+        async fn body(res: &mut Response<Body>) -> String {
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        }
+
+        let mut res = app.get("/plain/docs/a.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(res.headers().get("content-length").unwrap(), "5");
+        assert_eq!(body(&mut res).await, "alpha");
+        assert!(res.headers().get("cache-control").is_none());
+
+        // files larger than a chunk are streamed whole.
+        let mut res = app.get("/plain/large.txt").await;
+        assert_eq!(
+            res.headers().get("content-length").unwrap(),
+            &large.len().to_string()
+        );
+        assert_eq!(body(&mut res).await, large);
+
+        let res = app.get("/cached/docs/a.txt").await;
+        assert_eq!(
+            res.headers().get("cache-control").unwrap(),
+            "public, max-age=60"
+        );
+        assert!(res.headers().get("expires").is_some());
+        let res = app.get("/cached/docs").await;
+        assert!(res.headers().get("cache-control").is_none());
+
+        let mut res = app.get("/plain/site").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(body(&mut res).await, "<p>home</p>");
+
+        // listings are off by default, and nothing outside the root is served.
+        for path in [
+            "/plain/docs",
+            "/plain/missing.txt",
+            "/plain/docs/%2e%2e/%2e%2e/etc/passwd",
+            "/plain/docs/escape/passwd",
+        ] {
+            assert_eq!(
+                app.get(path).await.status(),
+                StatusCode::NOT_FOUND,
+                "{}",
+                path
+            );
+        }
+
+        let mut res = app.get("/browse/docs/").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let html = body(&mut res).await;
+        assert!(html.contains("<title>Index of /browse/docs/</title>"));
+        let rows: Vec<&str> = html.lines().filter(|line| line.contains("<a ")).collect();
+        let mut expected = vec![
+            "<tr><td><a href=\"/browse/\">../</a></td><td></td><td></td></tr>",
+            "<a href=\"/browse/docs/guide/\">guide/</a>",
+        ];
+        #[cfg(unix)]
+        expected.push("<a href=\"/browse/docs/site/\">site/</a>");
+        expected.push(
+            "<a href=\"/browse/docs/%3Cb&amp;c%3E.json\">&lt;b&amp;c&gt;.json</a></td><td>2</td>",
+        );
+        expected.push("<a href=\"/browse/docs/a.txt\">a.txt</a></td><td>5</td>");
+        assert_eq!(rows.len(), expected.len(), "{}", html);
+        for (row, expected) in rows.iter().zip(expected) {
+            assert!(row.contains(expected), "{} lacks {}", row, expected);
+        }
+        assert!(!html.contains("escape"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", "application/json".parse().unwrap());
+        let mut res = app.with_headers(headers).get("/browse/docs/guide").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(body(&mut res).await, "[]");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_files_listing_json() {
+        use super::{listing_json, Entry};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let entries = [
+            Entry {
+                name: "sub".to_string(),
+                dir: true,
+                size: 4096,
+                modified: None,
+            },
+            Entry {
+                name: "say \"hi\"\n.txt".to_string(),
+                dir: false,
+                size: 3,
+                modified: Some(UNIX_EPOCH + Duration::from_secs(784111777)),
+            },
+        ];
+
+        assert_eq!(
+            listing_json(&entries),
+            "[{\"name\":\"sub\",\"dir\":true,\"size\":4096,\"modified\":null},\
+             {\"name\":\"say \\\"hi\\\"\\n.txt\",\"dir\":false,\"size\":3,\
+             \"modified\":\"Sun, 06 Nov 1994 08:49:37 GMT\"}]"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_files_last_modified() {
+        use super::Files;
+        use crate::{
+            app::{App, TestApp},
+            NoState,
+        };
+        use http::{Method, Request, StatusCode};
+        use hyper::Body;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let root = std::env::temp_dir().join(format!("ratpack-modified-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let report = root.join("report.txt");
+        std::fs::write(&report, "numbers").unwrap();
+        // pinned, with a fraction of a second that dates cannot express.
+        std::fs::File::options()
+            .write(true)
+            .open(&report)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_millis(784_111_777_500))
+            .unwrap();
+
+        let mut app: App<(), NoState> = App::new();
+        app.get_prefix("/files", Files::new(&root).handler());
+        app.get_prefix(
+            "/tagged",
+            Files::new(&root).handler().then(crate::etag::handler(1024)),
+        );
+        let app = TestApp::new(app);
+
+        let res = app.get("/files/report.txt").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("last-modified").unwrap(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+
+        for (headers, status) in [
+            (
+                vec![("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![("if-modified-since", "Sunday, 06-Nov-94 08:49:38 GMT")],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![("if-modified-since", "Sun Nov  6 08:49:36 1994")],
+                StatusCode::OK,
+            ),
+            (vec![("if-modified-since", "garbage")], StatusCode::OK),
+            (
+                vec![("if-modified-since", "Sun, 06 Nov 2172 08:49:37 GMT")],
+                StatusCode::OK,
+            ),
+            (
+                vec![("if-unmodified-since", "Sun, 06 Nov 1994 08:49:37 GMT")],
+                StatusCode::OK,
+            ),
+            (
+                vec![("if-unmodified-since", "Sun, 06 Nov 1994 08:49:36 GMT")],
+                StatusCode::PRECONDITION_FAILED,
+            ),
+            (
+                vec![
+                    ("if-unmodified-since", "Sun, 06 Nov 1994 08:49:36 GMT"),
+                    ("if-match", "*"),
+                ],
+                StatusCode::OK,
+            ),
+            // If-None-Match wins over the date.
+            (
+                vec![
+                    ("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                    ("if-none-match", "\"other\""),
+                ],
+                StatusCode::OK,
+            ),
+        ] {
+            let res = app.get_with("/files/report.txt", &headers).await;
+            assert_eq!(res.status(), status, "{:?}", headers);
+            assert_eq!(
+                res.headers().get("last-modified").unwrap(),
+                "Sun, 06 Nov 1994 08:49:37 GMT"
+            );
+        }
+
+        // with an ETag handler following, If-None-Match is answered by it instead.
+        let res = app.get("/tagged/report.txt").await;
+        let etag = res
+            .headers()
+            .get("etag")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let res = app
+            .get_with(
+                "/tagged/report.txt",
+                &[
+                    ("if-none-match", &etag),
+                    ("if-modified-since", "Sun, 06 Nov 1994 08:49:36 GMT"),
+                ],
+            )
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        // only GET and HEAD are answered with a 304.
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            super::precondition(&req, UNIX_EPOCH + Duration::from_secs(784111777)),
+            None
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "embed")]
+    #[tokio::test]
+    async fn test_embedded() {
+        use super::{embedded, Embedded};
+        use crate::{
+            app::{App, TestApp},
+            cache::CacheControl,
+            NoState,
+        };
+        use http::{HeaderMap, StatusCode};
+
+        static ASSETS: include_dir::Dir =
+            include_dir::include_dir!("$CARGO_MANIFEST_DIR/examples/assets");
+
+        let mut app: App<(), NoState> = App::new();
+        app.get_prefix("/static", embedded(&ASSETS));
+        app.get_prefix(
+            "/bare",
+            Embedded::new(&ASSETS)
+                .index(None)
+                .cache_control(CacheControl::new().no_cache())
+                .handler(),
+        );
+        let app = TestApp::new(app);
+
+        let mut res = app.get("/static/css/site.css").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/css; charset=utf-8"
+        );
+        let etag = res.headers().get("etag").unwrap().clone();
+        assert_eq!(
+            etag,
+            crate::etag::compute(include_bytes!("../examples/assets/css/site.css"))
+        );
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, &include_bytes!("../examples/assets/css/site.css")[..]);
+
+        // directories are served their index, which has an ETag of its own.
+        for path in ["/static", "/static/", "/static/./index.html"] {
+            let res = app.get(path).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", path);
+            assert_eq!(
+                res.headers().get("content-type").unwrap(),
+                "text/html; charset=utf-8"
+            );
+            assert_ne!(res.headers().get("etag").unwrap(), &etag);
+        }
+
+        // a matching If-None-Match is answered with a 304.
+        let mut headers = HeaderMap::new();
+        headers.insert("if-none-match", etag.clone());
+        let res = app
+            .clone()
+            .with_headers(headers)
+            .get("/bare/css/site.css")
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get("etag").unwrap(), &etag);
+        assert_eq!(res.headers().get("cache-control").unwrap(), "no-cache");
+        let mut headers = HeaderMap::new();
+        headers.insert("if-none-match", "\"other\"".parse().unwrap());
+        let res = app
+            .clone()
+            .with_headers(headers)
+            .get("/bare/css/site.css")
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // missing files, directories without an index, and paths leading outside are not found.
+        for path in [
+            "/static/missing.css",
+            "/static/css",
+            "/bare",
+            "/static/css/../../embedded.rs",
+            "/static/%2e%2e/embedded.rs",
+            "/static/..%2fembedded.rs",
+        ] {
+            assert_eq!(
+                app.get(path).await.status(),
+                StatusCode::NOT_FOUND,
+                "{}",
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use http::{Request, StatusCode};
+use hyper::Body;
+
+use crate::{handler::Handler, Error, TransientState};
+
+/// The flags decided for a request by [Gate] handlers it passed through. See
+/// [crate::flag_enabled].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Decisions(pub(crate) BTreeMap<String, bool>);
+
+/// FlagProvider decides whether a feature flag is on for a request. Implementations may key off
+/// anything in the request, such as a header, a cookie, or a hash of a user ID for a gradual
+/// rollout. Functions taking the flag and the request are providers:
+///
+/// ```ignore
+///     let beta = |_flag: &str, req: &Request<Body>| req.headers().contains_key("x-beta");
+///     app.get("/search", flag::gate("new_search", beta).then(compose_handler!(search)));
+/// ```
+///
+/// [StaticFlags] is a provider whose flags are set by the application.
+pub trait FlagProvider: Send + Sync {
+    /// Whether the flag is on for the request.
+    fn is_enabled(&self, flag: &str, req: &Request<Body>) -> bool;
+}
+
+impl<F> FlagProvider for F
+where
+    F: Fn(&str, &Request<Body>) -> bool + Send + Sync,
+{
+    fn is_enabled(&self, flag: &str, req: &Request<Body>) -> bool {
+        self(flag, req)
+    }
+}
+
+/// StaticFlags holds flags that are on or off for every request, and are off until set. Clones
+/// share their flags, so a clone kept aside after giving one to [gate] turns flags on and off
+/// while the App runs:
+///
+/// ```ignore
+///     let flags = StaticFlags::new();
+///     app.get("/search", flag::gate("new_search", flags.clone()).then(compose_handler!(search)));
+///     // later, from an admin endpoint or a signal handler:
+///     flags.enable("new_search");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StaticFlags(Arc<RwLock<HashMap<String, bool>>>);
+
+impl StaticFlags {
+    /// A set of flags, all of them off.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn the flag on or off.
+    pub fn set(&self, flag: &str, enabled: bool) {
+        self.0
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(flag.to_string(), enabled);
+    }
+
+    /// Turn the flag on.
+    pub fn enable(&self, flag: &str) {
+        self.set(flag, true)
+    }
+
+    /// Turn the flag off.
+    pub fn disable(&self, flag: &str) {
+        self.set(flag, false)
+    }
+
+    /// Whether the flag is on.
+    pub fn is_set(&self, flag: &str) -> bool {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(flag)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl FlagProvider for StaticFlags {
+    fn is_enabled(&self, flag: &str, _req: &Request<Body>) -> bool {
+        self.is_set(flag)
+    }
+}
+
+#[derive(Clone)]
+enum Disabled<S: Send, T: TransientState + 'static> {
+    Status(StatusCode),
+    Otherwise(Handler<S, T>),
+}
+
+/// Build a handler performing the chains it leads only for requests the provider turns the flag
+/// on for; the rest are answered with a 404 Not Found, just as if the route did not exist. See
+/// [Gate] for answering them otherwise.
+pub fn gate<S, T>(flag: &str, provider: impl FlagProvider + 'static) -> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    Gate::new(flag, provider).handler()
+}
+
+/// Gate performs the chains it leads only for requests a [FlagProvider] turns a feature flag on
+/// for, so that a feature can be rolled out gradually. Other requests are answered with a 404 Not
+/// Found, another status, or an alternate chain, such as the implementation being replaced:
+///
+/// ```ignore
+///     app.get(
+///         "/search",
+///         Gate::new("new_search", flags.clone())
+///             .otherwise(compose_handler!(search))
+///             .handler()
+///             .then(compose_handler!(search_v2)),
+///     );
+/// ```
+///
+/// Whether the flag was on is available to later handlers through [crate::flag_enabled], so that
+/// it can be logged. An alternate chain is performed in place of the gated one; nothing appended
+/// to the gate is performed after it.
+#[derive(Clone)]
+pub struct Gate<S: Send, T: TransientState + 'static> {
+    flag: String,
+    provider: Arc<dyn FlagProvider>,
+    disabled: Disabled<S, T>,
+}
+
+impl<S, T> Gate<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    /// Gate on the flag, as decided by the provider.
+    pub fn new(flag: &str, provider: impl FlagProvider + 'static) -> Self {
+        Self {
+            flag: flag.to_string(),
+            provider: Arc::new(provider),
+            disabled: Disabled::Status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    /// Answer requests the flag is off for with this status, rather than a 404.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.disabled = Disabled::Status(status);
+        self
+    }
+
+    /// Perform this chain for requests the flag is off for, rather than answering with a status.
+    pub fn otherwise(mut self, handler: Handler<S, T>) -> Self {
+        self.disabled = Disabled::Otherwise(handler);
+        self
+    }
+
+    /// Build the handler performing the gating, which leads the chains it gates.
+    pub fn handler(self) -> Handler<S, T> {
+        let gate = Arc::new(self);
+
+        Handler::around(
+            Arc::new(move |mut req, resp, params, app, state, next| {
+                let gate = gate.clone();
+                let enabled = gate.provider.is_enabled(&gate.flag, &req);
+
+                let mut decisions = req
+                    .extensions_mut()
+                    .remove::<Decisions>()
+                    .unwrap_or_default();
+                decisions.0.insert(gate.flag.clone(), enabled);
+                req.extensions_mut().insert(decisions);
+
+                Box::pin(async move {
+                    if enabled {
+                        return match next {
+                            Some(next) => next.perform(req, resp, params, app, state).await,
+                            None => Ok((req, resp, state)),
+                        };
+                    }
+
+                    match &gate.disabled {
+                        Disabled::Status(status) => Err(Error::StatusCode(*status, String::new())),
+                        Disabled::Otherwise(handler) => {
+                            handler.perform(req, resp, params, app, state).await
+                        }
+                    }
+                })
+            }),
+            None,
+        )
+        .named("flag_gate")
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_gate() {
+        use super::{gate, Gate, StaticFlags};
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, flag_enabled, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn search(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("old {:?}", flag_enabled(&req, "new_search"));
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn search_v2(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!(
+                "new {:?} {:?}",
+                flag_enabled(&req, "new_search"),
+                flag_enabled(&req, "beta")
+            );
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let flags = StaticFlags::new();
+        let beta = |_flag: &str, req: &Request<Body>| req.headers().contains_key("x-beta");
+
+        let mut app = App::new();
+        app.get(
+            "/search",
+            gate("new_search", flags.clone()).then(compose_handler!(search_v2)),
+        );
+        app.get(
+            "/forbidden",
+            Gate::new("new_search", flags.clone())
+                .status(StatusCode::FORBIDDEN)
+                .handler()
+                .then(compose_handler!(search_v2)),
+        );
+        app.get(
+            "/fallback",
+            Gate::new("new_search", flags.clone())
+                .otherwise(compose_handler!(search))
+                .handler()
+                .then(gate("beta", beta))
+                .then(compose_handler!(search_v2)),
+        );
+        let app = TestApp::new(app);
+
+        let body = |path: &'static str, headers: &'static [(&'static str, &'static str)]| {
+            let app = app.clone();
+            async move {
+                let mut req = Request::get(path);
+                for (name, value) in headers {
+                    req = req.header(*name, *value);
+                }
+                let mut res = app.dispatch(req.body(Body::empty()).unwrap()).await;
+                let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+                (res.status(), String::from_utf8(bytes.to_vec()).unwrap())
+            }
+        };
+
+        // the flag is off until set.
+        assert!(!flags.is_set("new_search"));
+        assert_eq!(body("/search", &[]).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(body("/forbidden", &[]).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(
+            body("/fallback", &[]).await,
+            (StatusCode::OK, "old Some(false)".to_string())
+        );
+
+        flags.enable("new_search");
+        assert_eq!(
+            body("/search", &[]).await,
+            (StatusCode::OK, "new Some(true) None".to_string())
+        );
+        assert_eq!(body("/forbidden", &[]).await.0, StatusCode::OK);
+
+        // gates on other flags are decided per request.
+        assert_eq!(body("/fallback", &[]).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body("/fallback", &[("x-beta", "1")]).await,
+            (StatusCode::OK, "new Some(true) Some(true)".to_string())
+        );
+
+        flags.disable("new_search");
+        assert_eq!(body("/search", &[]).await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use http::{header, HeaderValue, Request, Response};
+use hyper::Body;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::{
+    cookies::{self, Cookie, SameSite},
+    handler::Handler,
+    Error, TransientState,
+};
+
+// the name of the cookie carrying the messages.
+const COOKIE: &str = "flash";
+
+// the longest value the cookie may have; browsers keep cookies of up to 4096 bytes, name and
+// attributes included.
+const MAX_SIZE: usize = 4000;
+
+// how long messages are kept for if they are never taken.
+const MAX_AGE: Duration = Duration::from_secs(300);
+
+/// Level is the kind of a flash [Message], for choosing how it is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Success => "success",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+
+    fn parse(level: &str) -> Option<Self> {
+        match level {
+            "info" => Some(Self::Info),
+            "success" => Some(Self::Success),
+            "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Message is a flash message, as returned by [take].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+}
+
+// Inserted into requests by [handler], so that [take] can ask for the messages to be cleared.
+#[derive(Clone, Default)]
+struct Taken(Arc<AtomicBool>);
+
+/// Add a message to be shown on the next page the client loads, usually along with a redirect
+/// after a form is submitted:
+///
+/// ```ignore
+///     let mut resp = respond::see_other("/posts")?;
+///     flash::set(&mut resp, Level::Success, "Saved!")?;
+/// ```
+///
+/// The messages are carried in a short-lived cookie, and are kept in the order they were added
+/// to the response. An error is returned if they would not fit in a cookie, which holds about 4KB
+/// of them.
+pub fn set(resp: &mut Response<Body>, level: Level, text: &str) -> Result<(), Error> {
+    // keep the messages already added to the response, replacing its cookie for them.
+    let mut messages = Vec::new();
+    let mut others = Vec::new();
+    for value in resp.headers().get_all(header::SET_COOKIE) {
+        let pair = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.split(';').next());
+        match pair.and_then(|pair| pair.strip_prefix("flash=")) {
+            Some(encoded) => messages.extend(decode(encoded)),
+            None => others.push(value.clone()),
+        }
+    }
+
+    messages.push(Message {
+        level,
+        text: text.to_string(),
+    });
+
+    let encoded = encode(&messages);
+    if encoded.len() > MAX_SIZE {
+        return Err(Error::new(format!(
+            "flash messages take {} bytes, more than the {} a cookie can hold",
+            encoded.len(),
+            MAX_SIZE
+        )));
+    }
+
+    resp.headers_mut().remove(header::SET_COOKIE);
+    for value in others {
+        resp.headers_mut().append(header::SET_COOKIE, value);
+    }
+    cookies::set(resp, cookie(&encoded).max_age(MAX_AGE))
+}
+
+/// Return the flash messages the request was sent with, in the order they were added. Text is
+/// returned as it was given, and should be escaped when rendered, as clients can send any
+/// messages they like.
+///
+/// When [handler] wraps the request, taking the messages also removes them from the client, by
+/// removing their cookie with the response, so that they are shown only once. Requests that do
+/// not take them, such as those for images or scripts the page loads, leave them in place.
+pub fn take<B>(req: &Request<B>) -> Vec<Message> {
+    let messages = match cookies::get(req).get(COOKIE) {
+        Some(encoded) => decode(encoded),
+        None => return Vec::new(),
+    };
+
+    if let Some(taken) = req.extensions().get::<Taken>() {
+        taken.0.store(true, Ordering::SeqCst);
+    }
+
+    messages
+}
+
+/// Build the handler removing the messages from clients once they have been taken, which is
+/// given to [crate::app::App::wrap]:
+///
+/// ```ignore
+///     app.wrap(flash::handler());
+/// ```
+///
+/// The cookie is removed with the response of any route that takes the messages, unless the
+/// response adds messages of its own, which replace them. Responses rendering errors do not
+/// remove it.
+pub fn handler<S, T>() -> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    Handler::around(
+        Arc::new(move |mut req, resp, params, app, state, next| {
+            let taken = Taken::default();
+            req.extensions_mut().insert(taken.clone());
+
+            Box::pin(async move {
+                let (req, mut resp, state) = match next {
+                    Some(next) => next.perform(req, resp, params, app, state).await?,
+                    None => (req, resp, state),
+                };
+
+                if let Some(resp) = &mut resp {
+                    if taken.0.load(Ordering::SeqCst) && !sets_messages(resp) {
+                        let removal: HeaderValue =
+                            cookie("").max_age(Duration::ZERO).to_string().parse()?;
+                        resp.headers_mut().append(header::SET_COOKIE, removal);
+                    }
+                }
+
+                Ok((req, resp, state))
+            })
+        }),
+        None,
+    )
+    .named("flash")
+}
+
+// the cookie carrying the messages, without a lifetime.
+fn cookie(value: &str) -> Cookie {
+    Cookie::new(COOKIE, value)
+        .path("/")
+        .http_only()
+        .same_site(SameSite::Lax)
+}
+
+// whether the response already sets the cookie carrying the messages.
+fn sets_messages(resp: &Response<Body>) -> bool {
+    resp.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .any(|value| value.as_bytes().starts_with(b"flash="))
+}
+
+// messages are encoded as `level=text` pairs joined by `&`, with their text percent-encoded, so
+// that they hold only characters allowed in a cookie value.
+fn encode(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            format!(
+                "{}={}",
+                message.level,
+                utf8_percent_encode(&message.text, NON_ALPHANUMERIC)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// decode the messages, skipping any that are not validly encoded.
+fn decode(encoded: &str) -> Vec<Message> {
+    encoded
+        .split('&')
+        .filter_map(|pair| {
+            let (level, text) = pair.split_once('=')?;
+            Some(Message {
+                level: Level::parse(level)?,
+                text: percent_decode_str(text).decode_utf8().ok()?.into_owned(),
+            })
+        })
+        .collect()
+}
+
+mod tests {
+    #[test]
+    fn test_flash_encoding() {
+        use super::{decode, encode, set, Level, Message};
+        use http::Response;
+        use hyper::Body;
+
+        let messages = vec![
+            Message {
+                level: Level::Success,
+                text: "Saved \"draft\"; 100% & done=yes, ü".to_string(),
+            },
+            Message {
+                level: Level::Error,
+                text: String::new(),
+            },
+        ];
+        let encoded = encode(&messages);
+        assert!(encoded.starts_with("success=Saved%20%22draft%22%3B%20100%25%20%26"));
+        assert_eq!(decode(&encoded), messages);
+        assert_eq!(
+            decode("info=ok&bogus=no&warning&warning=%FF"),
+            vec![Message {
+                level: Level::Info,
+                text: "ok".to_string()
+            }]
+        );
+
+        // messages are added to those the response already carries, keeping its other cookies.
+        let mut resp = Response::new(Body::empty());
+        resp.headers_mut()
+            .append("set-cookie", "session=abc".parse().unwrap());
+        set(&mut resp, Level::Info, "one").unwrap();
+        set(&mut resp, Level::Warning, "two").unwrap();
+        let cookies: Vec<&str> = resp
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            cookies,
+            vec![
+                "session=abc",
+                "flash=info=one&warning=two; Path=/; Max-Age=300; HttpOnly; SameSite=Lax"
+            ]
+        );
+
+        // messages too large for a cookie are refused, leaving those already added.
+        assert!(set(&mut resp, Level::Info, &"x".repeat(4000)).is_err());
+        assert_eq!(resp.headers().get_all("set-cookie").iter().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_flash() {
+        use super::{handler, set, take, Level};
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, respond, HTTPResult, NoState, Params,
+        };
+        use http::{HeaderMap, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn save(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let mut resp = respond::see_other("/posts")?;
+            set(&mut resp, Level::Success, "Saved!")?;
+            set(&mut resp, Level::Warning, "Title was <empty>")?;
+            Ok((req, Some(resp), state))
+        }
+
+        async fn posts(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let page = take(&req)
+                .iter()
+                .map(|message| format!("[{}] {}\n", message.level, message.text))
+                .collect::<String>();
+            Ok((req, Some(Response::new(Body::from(page))), state))
+        }
+
+        let mut app = App::new();
+        app.post("/posts", compose_handler!(save));
+        app.get("/posts", compose_handler!(posts));
+        app.wrap(handler());
+        let app = TestApp::new(app);
+
+        let res = app.post("/posts", Body::empty()).await;
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let flash = res.headers().get("set-cookie").unwrap().to_str().unwrap();
+        let pair = flash.split(';').next().unwrap();
+
+        // the page after the redirect shows the messages, and removes them.
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", pair.parse().unwrap());
+        let mut res = app.with_headers(headers).get("/posts").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "[success] Saved!\n[warning] Title was <empty>\n");
+        assert_eq!(
+            res.headers().get("set-cookie").unwrap(),
+            "flash=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax"
+        );
+
+        // without messages, nothing is removed.
+        let mut res = app.get("/posts").await;
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert!(body.is_empty());
+        assert!(res.headers().get("set-cookie").is_none());
+    }
+}
@@ -1,6 +1,9 @@
-use std::future::Future;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, PoisonError},
+};
 
-use crate::{app::App, HTTPResult, PinBox, TransientState};
+use crate::{app::App, Error, HTTPResult, PinBox, TransientState};
 use async_recursion::async_recursion;
 
 use http::{Request, Response};
@@ -32,6 +35,8 @@ use hyper::Body;
 /// }
 /// ```
 ///
+/// Closures, which may capture their environment, can be made into handlers with
+/// [Handler::from_fn].
 pub type HandlerFunc<S, T> = fn(
     req: Request<Body>,
     response: Option<Response<Body>>,
@@ -40,32 +45,316 @@ pub type HandlerFunc<S, T> = fn(
     state: T,
 ) -> PinBox<dyn Future<Output = HTTPResult<T>> + Send>;
 
+// The form every stage function is stored in, so that closures capturing their environment can be
+// stages as well as plain functions.
+type StageFunc<S, T> = Arc<
+    dyn Fn(
+            Request<Body>,
+            Option<Response<Body>>,
+            crate::Params,
+            App<S, T>,
+            T,
+        ) -> PinBox<dyn Future<Output = HTTPResult<T>> + Send>
+        + Send
+        + Sync,
+>;
+
+/// The name [crate::compose_handler!] gives the stage for the expression it was given.
+#[doc(hidden)]
+pub fn stage_name(expr: &'static str) -> &'static str {
+    if expr.starts_with('|') || expr.starts_with("move") || expr.starts_with("async") {
+        "<closure>"
+    } else {
+        expr
+    }
+}
+
+/// The name of the stage an error came from, recorded by the first stage of a request's chain to
+/// fail. The [crate::app::App] inserts it into requests, and reads it when a chain fails, so that
+/// the error can be logged with the stage it came from. Errors turned into responses by
+/// [Handler::on_error] are forgotten.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FailedStage(Arc<Mutex<Option<&'static str>>>);
+
+impl FailedStage {
+    // the stage of the request, if it was dispatched through an App.
+    fn of<B>(req: &Request<B>) -> Option<Self> {
+        req.extensions().get::<Self>().cloned()
+    }
+
+    // record the stage as the one that failed, unless a stage it performed failed first.
+    fn record(&self, name: &'static str) {
+        self.lock().get_or_insert(name);
+    }
+
+    fn clear(&self) {
+        self.lock().take();
+    }
+
+    pub(crate) fn get(&self) -> Option<&'static str> {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<&'static str>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// AroundFunc is the type signature of a stage that wraps the remainder of its chain. Unlike a
+/// [HandlerFunc], it is handed the next [Handler] (if any) and is responsible for performing it.
+/// This is how adapters such as the tower support surround the rest of a chain.
+pub(crate) type AroundFunc<S, T> = Arc<
+    dyn Fn(
+            Request<Body>,
+            Option<Response<Body>>,
+            crate::Params,
+            App<S, T>,
+            T,
+            Option<Handler<S, T>>,
+        ) -> PinBox<dyn Future<Output = HTTPResult<T>> + Send>
+        + Send
+        + Sync,
+>;
+
+/// ErrorMapper renders an error returned by a handler chain as a response; see
+/// [Handler::on_error]. It is given the error and a copy of the head of the request the chain was
+/// performing.
+pub type ErrorMapper = fn(Error, Request<Body>) -> Result<Response<Body>, Error>;
+
+enum Stage<S: Send, T: TransientState + 'static> {
+    Func(StageFunc<S, T>),
+    Around(AroundFunc<S, T>),
+}
+
+impl<S: Send, T: TransientState> Clone for Stage<S, T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Func(f) => Self::Func(f.clone()),
+            Self::Around(f) => Self::Around(f.clone()),
+        }
+    }
+}
+
 /// Handler is the structure of the handler. Typically, you will not use this directly, and instead
 /// interact with the [crate::compose_handler!] macro. That said, if you wanted to define your own
 /// macros or otherwise compose more complicated structures for your handlers, this is available to
 /// you.
-#[derive(Clone)]
-pub struct Handler<S: Clone + Send, T: TransientState + 'static> {
-    handler: HandlerFunc<S, T>,
+pub struct Handler<S: Send, T: TransientState + 'static> {
+    stage: Stage<S, T>,
+    name: &'static str,
+    on_error: Option<ErrorMapper>,
+    stop_on_response: bool,
     next: Box<Option<Handler<S, T>>>,
 }
 
-impl<S: Clone + Send, T: TransientState> Handler<S, T>
+impl<S: Send, T: TransientState> Clone for Handler<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            stage: self.stage.clone(),
+            name: self.name,
+            on_error: self.on_error,
+            stop_on_response: self.stop_on_response,
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<S: Send + 'static, T: TransientState> From<HandlerFunc<S, T>> for Handler<S, T> {
+    fn from(handler: HandlerFunc<S, T>) -> Self {
+        Self::new(handler, None)
+    }
+}
+
+// constructors need S to be 'static, as stage functions are stored as trait objects.
+impl<S: Send + 'static, T: TransientState> Handler<S, T> {
+    /// Construct a new handler composed of a HandlerFunc with state, and an optional next handler
+    /// in the chain. The stage is named `<closure>` until renamed with [Handler::named].
+    pub fn new(handler: HandlerFunc<S, T>, next: Option<Handler<S, T>>) -> Self {
+        Self {
+            stage: Stage::Func(Arc::new(handler)),
+            name: "<closure>",
+            on_error: None,
+            stop_on_response: false,
+            next: Box::new(next),
+        }
+    }
+
+    /// Construct a handler from a closure, or any other function of the [HandlerFunc] shape that
+    /// returns a future. Unlike a [HandlerFunc], a closure may capture its environment, which
+    /// allows handlers to be bound to configuration without going through the App's state:
+    ///
+    /// ```ignore
+    ///     let templates = Arc::new(Templates::load("templates")?);
+    ///     app.get(
+    ///         "/",
+    ///         Handler::from_fn(move |req, _resp, _params, _app, state| {
+    ///             let templates = templates.clone();
+    ///             async move {
+    ///                 let body = templates.render("index")?;
+    ///                 Ok((req, Some(Response::new(Body::from(body))), state))
+    ///             }
+    ///         }),
+    ///     );
+    /// ```
+    ///
+    /// The stage is named `<closure>` until renamed with [Handler::named].
+    pub fn from_fn<F, Fut>(handler: F) -> Self
+    where
+        F: Fn(Request<Body>, Option<Response<Body>>, crate::Params, App<S, T>, T) -> Fut
+            + Send
+            + Sync
+            + 'static,
+        Fut: Future<Output = HTTPResult<T>> + Send + 'static,
+    {
+        Self {
+            stage: Stage::Func(Arc::new(move |req, resp, params, app, state| {
+                Box::pin(handler(req, resp, params, app, state))
+            })),
+            name: "<closure>",
+            on_error: None,
+            stop_on_response: false,
+            next: Box::new(None),
+        }
+    }
+
+    /// Construct a handler from a terminal function, which answers the request with anything that
+    /// is a [crate::respond::Responder] rather than passing a response along the chain. It is not
+    /// given the response of earlier stages, and its state is only read; what it returns is the
+    /// response of the chain:
+    ///
+    /// ```ignore
+    ///     async fn show(
+    ///         _req: Request<Body>,
+    ///         params: Params,
+    ///         app: App<State, NoState>,
+    ///         _state: NoState,
+    ///     ) -> Result<impl Responder, Error> {
+    ///         match find_user(&app, &params["id"]).await? {
+    ///             Some(user) => Ok(Json(user)),
+    ///             None => Err(Error::new_status(StatusCode::NOT_FOUND, "no such user")),
+    ///         }
+    ///     }
+    ///
+    ///     app.get("/users/:id", Handler::from_responder(show));
+    /// ```
+    ///
+    /// The function consumes the request, so stages after it are given a copy with only its
+    /// method, URI, version and headers. The stage is named `<closure>` until renamed with
+    /// [Handler::named].
+    pub fn from_responder<F, Fut, R>(handler: F) -> Self
+    where
+        F: Fn(Request<Body>, crate::Params, App<S, T>, T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, Error>> + Send + 'static,
+        R: crate::respond::Responder,
+    {
+        Self::from_fn(move |req, _resp, params, app, state: T| {
+            let head = crate::request_head(&req);
+            let responded = handler(req, params, app, state.clone());
+            async move {
+                let resp = responded.await?.into_response()?;
+                Ok((head, Some(resp), state))
+            }
+        })
+    }
+}
+
+impl<S: Send, T: TransientState> Handler<S, T>
 where
     Self: Send,
-    S: Clone + Send,
+    S: Send,
 {
-    /// Construct a new handler composed of a HandlerFunc with state, and an optional next handler
-    /// in the chain.
-    pub fn new(handler: HandlerFunc<S, T>, next: Option<Handler<S, T>>) -> Self {
+    /// Name the first stage of this handler; [crate::compose_handler!] names each stage after the
+    /// function it was given. Names are used in diagnostics, such as logging which stage of a chain
+    /// returned an error.
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Return the names of each stage in the chain, in the order they are performed.
+    pub fn stages(&self) -> impl Iterator<Item = &'static str> + '_ {
+        std::iter::successors(Some(self), |handler| handler.next.as_ref().as_ref())
+            .map(|handler| handler.name)
+    }
+
+    /// Construct a handler whose stage wraps the next handler in the chain; the stage is
+    /// responsible for performing (or skipping) the rest of the chain itself.
+    pub(crate) fn around(handler: AroundFunc<S, T>, next: Option<Handler<S, T>>) -> Self {
         Self {
-            handler,
+            stage: Stage::Around(handler),
+            name: "<around>",
+            on_error: None,
+            stop_on_response: false,
             next: Box::new(next),
         }
     }
 
+    /// Append the `next` chain to the end of this one, returning the combined chain. This allows
+    /// shared chains (such as authentication) to be built once and combined with per-route
+    /// handlers:
+    ///
+    /// ```ignore
+    ///     let auth = compose_handler!(validate_authtoken);
+    ///     app.get("/a", auth.clone().then(compose_handler!(show_a)));
+    ///     app.get("/b", auth.then(compose_handler!(show_b)));
+    /// ```
+    pub fn then(mut self, next: Handler<S, T>) -> Self {
+        let tail = match *self.next {
+            Some(existing) => existing.then(next),
+            None => next,
+        };
+
+        self.next = Box::new(Some(tail));
+        self
+    }
+
+    /// Prepend the `prev` chain to the front of this one, returning the combined chain. This is
+    /// the mirror of [Handler::then].
+    pub fn prepend(self, prev: Handler<S, T>) -> Self {
+        prev.then(self)
+    }
+
+    /// Render errors returned by this chain with `mapper` rather than the App's default, which
+    /// responds with the status and message of the error. This lets routes render errors
+    /// differently, such as JSON APIs alongside HTML pages:
+    ///
+    /// ```ignore
+    ///     fn json_error(e: Error, _req: Request<Body>) -> Result<Response<Body>, Error> {
+    ///         Ok(Response::builder()
+    ///             .status(e.status())
+    ///             .header("content-type", "application/json")
+    ///             .body(Body::from(format!("{{\"error\": {:?}}}", e.message())))?)
+    ///     }
+    ///
+    ///     app.get("/api/users/:id", compose_handler!(load_user, show_user).on_error(json_error));
+    /// ```
+    ///
+    /// The mapped response is returned as though the chain had produced it. Errors from the
+    /// mapper itself are rendered as usual. Stages appended with [Handler::then] are covered, but
+    /// those prepended with [Handler::prepend] are not.
+    pub fn on_error(mut self, mapper: ErrorMapper) -> Self {
+        self.on_error = Some(mapper);
+        self
+    }
+
+    /// End this chain as soon as a stage returns a response, rather than performing the remaining
+    /// stages with it. This suits chains whose early stages may answer on their own, such as a
+    /// cache lookup before the handler computing the response:
+    ///
+    /// ```ignore
+    ///     app.get("/report", compose_handler!(cached, compute_report).stop_on_response());
+    /// ```
+    ///
+    /// By default every stage is performed, so that later stages may amend the response of earlier
+    /// ones. As with [Handler::on_error], stages appended with [Handler::then] are covered, but
+    /// those prepended with [Handler::prepend] are not, nor are those performed by an adapter
+    /// stage, such as a tower layer, on its own.
+    pub fn stop_on_response(mut self) -> Self {
+        self.stop_on_response = true;
+        self
+    }
+
     /// Perform the function, this will recursively execute all handlers in the chain.
-    #[async_recursion]
     pub async fn perform(
         &self,
         req: Request<hyper::Body>,
@@ -74,12 +363,104 @@ where
         app: App<S, T>,
         state: T,
     ) -> HTTPResult<T> {
-        let (req, response, state) =
-            (self.handler)(req, response, params.clone(), app.clone(), state).await?;
+        self.perform_from(req, response, params, app, state, false)
+            .await
+    }
+
+    // perform the chain from this stage, stopping once there is a response if `stop` is set by
+    // an earlier stage.
+    #[async_recursion]
+    async fn perform_from(
+        &self,
+        req: Request<hyper::Body>,
+        response: Option<Response<hyper::Body>>,
+        params: crate::Params,
+        app: App<S, T>,
+        state: T,
+        stop: bool,
+    ) -> HTTPResult<T> {
+        let stop = stop || self.stop_on_response;
+        let mapper = match self.on_error {
+            Some(mapper) => mapper,
+            None => {
+                return self
+                    .perform_stages(req, response, params, app, state, stop)
+                    .await
+            }
+        };
+
+        // the request is consumed by the chain, so the mapper is given its head.
+        let head = crate::request_head(&req);
+        let failed = FailedStage::of(&req);
+        match self
+            .perform_stages(req, response, params, app, state, stop)
+            .await
+        {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                if let Some(failed) = failed {
+                    failed.clear();
+                }
+
+                let req = crate::request_head(&head);
+                Ok((req, Some(mapper(e, head)?), T::initial()))
+            }
+        }
+    }
+
+    #[async_recursion]
+    async fn perform_stages(
+        &self,
+        req: Request<hyper::Body>,
+        response: Option<Response<hyper::Body>>,
+        params: crate::Params,
+        app: App<S, T>,
+        state: T,
+        stop: bool,
+    ) -> HTTPResult<T> {
+        let failed = FailedStage::of(&req);
+        let handler = match &self.stage {
+            Stage::Func(handler) => handler,
+            Stage::Around(around) => {
+                let res = around(req, response, params, app, state, *self.next.clone()).await;
+                if let (Err(_), Some(failed)) = (&res, failed) {
+                    failed.record(self.name);
+                }
+
+                return res;
+            }
+        };
+
+        #[cfg_attr(not(debug_assertions), allow(unused_mut))]
+        let (mut req, response, state) =
+            match (handler)(req, response, params.clone(), app.share(), state).await {
+                Ok(res) => res,
+                Err(e) => {
+                    #[cfg(all(feature = "logging", not(feature = "trace")))]
+                    log::debug!("handler stage `{}` returned error: {:?}", self.name, e);
+                    #[cfg(feature = "trace")]
+                    tracing::debug!(stage = self.name, "handler stage returned error: {:?}", e);
+
+                    if let Some(failed) = failed {
+                        failed.record(self.name);
+                    }
+
+                    return Err(e);
+                }
+            };
+
+        #[cfg(debug_assertions)]
+        crate::body::guard_consumed(&mut req, self.name);
+
+        if stop && response.is_some() {
+            return Ok((req, response, state));
+        }
+
+        #[allow(clippy::needless_question_mark)]
         if self.next.is_some() {
             return Ok((*self.clone().next)
                 .unwrap()
-                .perform(req, response, params, app, state)
+                .perform_from(req, response, params, app, state, stop)
                 .await?);
         }
 
@@ -87,6 +468,75 @@ where
     }
 }
 
+impl<S, T> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    /// Allow no more than `max` requests in this chain at once, answering those beyond it with a
+    /// 503 Service Unavailable. The count is shared by the clones of the returned chain, so the
+    /// limit holds however many requests the route is dispatched for. See
+    /// [crate::concurrency::ConcurrencyLimit] to make requests wait instead, or to share a limit
+    /// between different chains.
+    pub fn limit_concurrency(self, max: usize) -> Self {
+        crate::concurrency::ConcurrencyLimit::new(max)
+            .handler()
+            .then(self)
+    }
+}
+
+/// Make a stage performing `handler` only for requests the predicate is true for; others pass
+/// through it with their request, response and state untouched. `handler` may be any chain, such
+/// as one built by another middleware, and the stage is chained like one:
+///
+/// ```ignore
+///     let auth = auth::basic_static("admin", "admin", &password);
+///     app.wrap(
+///         when(|req| !req.uri().path().starts_with("/public"), auth)
+///             .then(compose_handler!(record_visit)),
+///     );
+/// ```
+///
+/// The predicate is called for every request, so should be quick to decide. If `handler` returns
+/// a response, as when authentication refuses a request, it answers the request and the stages
+/// after this one are skipped; otherwise they are performed with the request and state `handler`
+/// returned.
+pub fn when<S, T, P>(predicate: P, handler: Handler<S, T>) -> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+    P: Fn(&Request<Body>) -> bool + Send + Sync + 'static,
+{
+    Handler::around(
+        Arc::new(move |req, resp, params, app, state, next| {
+            let handler = predicate(&req).then(|| handler.clone());
+
+            Box::pin(async move {
+                let (req, resp, state) = match handler {
+                    Some(handler) => {
+                        let res = handler
+                            .perform(req, resp, params.clone(), app.share(), state)
+                            .await?;
+                        if res.1.is_some() {
+                            return Ok(res);
+                        }
+
+                        res
+                    }
+                    None => (req, resp, state),
+                };
+
+                match next {
+                    Some(next) => next.perform(req, resp, params, app, state).await,
+                    None => Ok((req, resp, state)),
+                }
+            })
+        }),
+        None,
+    )
+    .named("when")
+}
+
 mod tests {
     #[tokio::test]
     async fn test_handler_basic() {
@@ -97,6 +547,7 @@ mod tests {
         #[derive(Clone)]
         struct State;
 
+        #[allow(dead_code)]
         #[derive(Clone)]
         struct TransientState;
 
@@ -186,4 +637,497 @@ mod tests {
 
         drop(bh)
     }
+
+    #[tokio::test]
+    async fn test_handler_then_prepend() {
+        use crate::{app::App, compose_handler, HTTPResult, Params, TransientState};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        #[derive(Clone)]
+        struct Trail(Vec<&'static str>);
+
+        impl TransientState for Trail {
+            fn initial() -> Self {
+                Self(Vec::new())
+            }
+        }
+
+        async fn one(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Trail>,
+            mut state: Trail,
+        ) -> HTTPResult<Trail> {
+            state.0.push("one");
+            Ok((req, resp, state))
+        }
+
+        async fn two(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Trail>,
+            mut state: Trail,
+        ) -> HTTPResult<Trail> {
+            state.0.push("two");
+            Ok((req, resp, state))
+        }
+
+        async fn three(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Trail>,
+            mut state: Trail,
+        ) -> HTTPResult<Trail> {
+            state.0.push("three");
+            Ok((
+                req,
+                Some(Response::new(Body::from(state.0.join(",")))),
+                state,
+            ))
+        }
+
+        let shared = compose_handler!(one, two);
+
+        for (handler, expected) in [
+            (
+                shared.clone().then(compose_handler!(three)),
+                "one,two,three",
+            ),
+            (
+                compose_handler!(three).prepend(shared.clone()),
+                "one,two,three",
+            ),
+            (
+                compose_handler!(two).prepend(compose_handler!(one)),
+                "one,two",
+            ),
+            (
+                compose_handler!(one).then(shared.clone().then(compose_handler!(three))),
+                "one,one,two,three",
+            ),
+        ] {
+            let (_, _, state) = handler
+                .perform(
+                    Request::default(),
+                    None,
+                    Params::new(),
+                    App::new(),
+                    Trail::initial(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(state.0.join(","), expected);
+        }
+
+        // the shared chain is left untouched by combining it.
+        let (_, response, state) = shared
+            .perform(
+                Request::default(),
+                None,
+                Params::new(),
+                App::new(),
+                Trail::initial(),
+            )
+            .await
+            .unwrap();
+
+        assert!(response.is_none());
+        assert_eq!(state.0, vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_handler_stages() {
+        use crate::{app::App, compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn validate_authtoken(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, resp, state))
+        }
+
+        async fn hello(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, resp, state))
+        }
+
+        let handler = compose_handler!(validate_authtoken, hello);
+        assert_eq!(
+            handler.stages().collect::<Vec<_>>(),
+            vec!["validate_authtoken", "hello"]
+        );
+
+        let closure: super::Handler<(), NoState> = super::Handler::new(
+            |req, resp, _, _, state| Box::pin(async { Ok((req, resp, state)) }),
+            None,
+        );
+        assert_eq!(closure.stages().collect::<Vec<_>>(), vec!["<closure>"]);
+
+        let handler = closure.named("auth").then(compose_handler!(hello));
+        assert_eq!(handler.stages().collect::<Vec<_>>(), vec!["auth", "hello"]);
+    }
+
+    #[tokio::test]
+    async fn test_handler_on_error() {
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, Error, HTTPResult, NoState, Params,
+        };
+        use http::{header, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn missing(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            Err(Error::new_status(StatusCode::NOT_FOUND, "no such user"))
+        }
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        fn json_error(e: Error, req: Request<Body>) -> Result<Response<Body>, Error> {
+            if req.uri().path().ends_with("/broken") {
+                return Err(Error::new("mapper failed"));
+            }
+
+            Ok(Response::builder()
+                .status(e.status())
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(format!("{{\"error\": {:?}}}", e.message())))?)
+        }
+
+        let mut app = App::new();
+        app.get(
+            "/api/missing",
+            compose_handler!(missing).on_error(json_error),
+        );
+        app.get(
+            "/api/broken",
+            compose_handler!(missing).on_error(json_error),
+        );
+        // appended stages are covered by the mapper.
+        app.get(
+            "/api/later",
+            compose_handler!(ok)
+                .on_error(json_error)
+                .then(compose_handler!(missing)),
+        );
+        app.get("/ok", compose_handler!(ok).on_error(json_error));
+        app.get("/html/missing", compose_handler!(missing));
+        let app = TestApp::new(app);
+
+        for (uri, status, body) in [
+            (
+                "/api/missing",
+                StatusCode::NOT_FOUND,
+                r#"{"error": "no such user"}"#,
+            ),
+            (
+                "/api/later",
+                StatusCode::NOT_FOUND,
+                r#"{"error": "no such user"}"#,
+            ),
+            (
+                "/api/broken",
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "mapper failed",
+            ),
+            ("/ok", StatusCode::OK, "ok"),
+            ("/html/missing", StatusCode::NOT_FOUND, "no such user"),
+        ] {
+            let mut res = app.get(uri).await;
+            assert_eq!(res.status(), status, "{}", uri);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_from_fn() {
+        use super::{Handler, HandlerFunc};
+        use crate::{app::App, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+        use std::sync::Arc;
+
+        fn greet(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> crate::PinBox<dyn std::future::Future<Output = HTTPResult<NoState>> + Send> {
+            Box::pin(async move { Ok((req, Some(Response::new(Body::from("hello"))), state)) })
+        }
+
+        // a closure capturing its configuration.
+        let greeting = Arc::new("hello, ".to_string());
+        let handler: Handler<(), NoState> =
+            Handler::from_fn(move |req, _resp, params: Params, _app, state| {
+                let greeting = greeting.clone();
+                async move {
+                    let body = format!("{}{}", greeting, params.get("name").unwrap());
+                    Ok((req, Some(Response::new(Body::from(body))), state))
+                }
+            })
+            .named("greeting");
+        assert_eq!(handler.stages().collect::<Vec<_>>(), vec!["greeting"]);
+
+        let mut params = Params::new();
+        params.insert("name".to_string(), "erik".to_string());
+        let (_, response, _) = handler
+            .perform(Request::default(), None, params, App::new(), NoState {})
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.unwrap().into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, "hello, erik".as_bytes());
+
+        // plain functions convert as they always have.
+        let handler: Handler<(), NoState> = (greet as HandlerFunc<(), NoState>).into();
+        let (_, response, _) = handler
+            .perform(
+                Request::default(),
+                None,
+                Params::new(),
+                App::new(),
+                NoState {},
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.unwrap().into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, "hello".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_handler_stop_on_response() {
+        use crate::{app::App, compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        // answers requests with a `hit` header.
+        async fn cached(
+            req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            if req.headers().contains_key("hit") {
+                return Ok((req, Some(Response::new(Body::from("cached"))), state));
+            }
+            Ok((req, resp, state))
+        }
+
+        async fn compute(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("computed"))), state))
+        }
+
+        async fn body(
+            handler: &crate::handler::Handler<(), NoState>,
+            hit: bool,
+        ) -> hyper::body::Bytes {
+            let mut req = Request::builder();
+            if hit {
+                req = req.header("hit", "1");
+            }
+            let (_, response, _) = handler
+                .perform(
+                    req.body(Body::empty()).unwrap(),
+                    None,
+                    Params::new(),
+                    App::new(),
+                    NoState {},
+                )
+                .await
+                .unwrap();
+            hyper::body::to_bytes(response.unwrap().into_body())
+                .await
+                .unwrap()
+        }
+
+        // by default, later stages see and may replace the response.
+        let handler = compose_handler!(cached, compute);
+        assert_eq!(body(&handler, true).await, "computed".as_bytes());
+
+        let handler = compose_handler!(cached, compute).stop_on_response();
+        assert_eq!(body(&handler, true).await, "cached".as_bytes());
+        assert_eq!(body(&handler, false).await, "computed".as_bytes());
+
+        // appended stages are covered.
+        let handler = compose_handler!(cached)
+            .stop_on_response()
+            .then(compose_handler!(compute));
+        assert_eq!(body(&handler, true).await, "cached".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_handler_when() {
+        use crate::{
+            app::{App, TestApp},
+            auth, compose_handler, when, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn tag(
+            mut req: Request<Body>,
+            resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            req.headers_mut().insert("x-tagged", "1".parse().unwrap());
+            Ok((req, resp, state))
+        }
+
+        async fn show(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let tagged = req.headers().contains_key("x-tagged");
+            let body = format!("{} tagged={}", req.uri().path(), tagged);
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut app = App::new();
+        app.get("/public/page", compose_handler!(show));
+        app.get("/private/page", compose_handler!(show));
+        app.wrap(
+            when(
+                |req| !req.uri().path().starts_with("/public"),
+                auth::basic_static("private", "alice", "secret"),
+            )
+            .then(when(
+                |req| req.headers().contains_key("x-tag"),
+                compose_handler!(tag),
+            )),
+        );
+        let app = TestApp::new(app);
+
+        for (path, headers, status, body) in [
+            (
+                "/public/page",
+                vec![],
+                StatusCode::OK,
+                "/public/page tagged=false",
+            ),
+            (
+                "/public/page",
+                vec![("x-tag", "1")],
+                StatusCode::OK,
+                "/public/page tagged=true",
+            ),
+            (
+                "/private/page",
+                vec![],
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+            ),
+            // "alice:secret"
+            (
+                "/private/page",
+                vec![("authorization", "Basic YWxpY2U6c2VjcmV0")],
+                StatusCode::OK,
+                "/private/page tagged=false",
+            ),
+        ] {
+            let mut res = app.get_with(path, &headers).await;
+            assert_eq!(res.status(), status, "{} {:?}", path, headers);
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body.as_bytes(), "{} {:?}", path, headers);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_from_responder() {
+        use super::Handler;
+        use crate::{
+            app::{App, TestApp},
+            elapsed, Error, NoState, Params,
+        };
+        use http::{Request, StatusCode};
+        use hyper::Body;
+
+        async fn greet(
+            req: Request<Body>,
+            params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> Result<(StatusCode, String), Error> {
+            match params.get("name").map(String::as_str) {
+                Some("nobody") => Err(Error::new_status(StatusCode::NOT_FOUND, "no one")),
+                Some(name) => Ok((
+                    StatusCode::ACCEPTED,
+                    format!("hello, {} via {}", name, req.uri()),
+                )),
+                None => unreachable!(),
+            }
+        }
+
+        let mut app = App::new();
+        app.get(
+            "/:name",
+            Handler::from_responder(greet).then(Handler::from_fn(
+                |req, resp, _params, _app, state| async move {
+                    // later stages still have the request's head.
+                    assert_eq!(req.uri(), "/erik");
+                    assert!(elapsed(&req).is_some());
+                    Ok((req, resp, state))
+                },
+            )),
+        );
+        app.get(
+            "/",
+            Handler::from_responder(|_req, _params, _app, _state| async {
+                Ok::<_, Error>(StatusCode::NO_CONTENT)
+            }),
+        );
+        let app = TestApp::new(app);
+
+        let mut res = app.get("/erik").await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "hello, erik via /erik");
+        assert_eq!(app.get("/nobody").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(app.get("/").await.status(), StatusCode::NO_CONTENT);
+    }
 }
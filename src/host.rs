@@ -0,0 +1,175 @@
+use http::Request;
+
+use crate::Params;
+
+/// The name a `*` label is stored under in [crate::Params].
+const SUBDOMAIN: &str = "subdomain";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Label {
+    Literal(String),
+    Param(String),
+}
+
+/// HostPattern is the host a route is restricted to, such as `api.example.com`. Labels may be
+/// parameters: `:tenant.example.com` captures the first label as `tenant`, and `*.example.com` is
+/// shorthand for `:subdomain.example.com`. Each parameter matches exactly one label, and literal
+/// labels are compared without regard to case.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HostPattern(Vec<Label>);
+
+impl HostPattern {
+    /// Parse a host pattern. Panics if the pattern is empty or has an empty label, so that
+    /// misconfigured routes fail at startup rather than at request time.
+    pub(crate) fn new(pattern: &str) -> Self {
+        let pattern = pattern.trim_end_matches('.');
+        let mut labels = Vec::new();
+
+        for label in pattern.split('.') {
+            labels.push(match label {
+                "" | ":" => panic!("invalid host pattern `{}`: empty label", pattern),
+                "*" => Label::Param(SUBDOMAIN.to_string()),
+                _ => match label.strip_prefix(':') {
+                    Some(name) => Label::Param(name.to_string()),
+                    None => Label::Literal(label.to_ascii_lowercase()),
+                },
+            });
+        }
+
+        Self(labels)
+    }
+
+    /// The names of the parameters captured from the host.
+    pub(crate) fn params(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .filter_map(|label| match label {
+                Label::Param(name) => Some(name.clone()),
+                Label::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    /// Match the host of the request, returning the parameters captured from it. Requests without
+    /// a host never match.
+    pub(crate) fn matches<B>(&self, req: &Request<B>) -> Option<Params> {
+        let host = host(req)?;
+        let labels: Vec<&str> = host.split('.').collect();
+
+        if labels.len() != self.0.len() {
+            return None;
+        }
+
+        let mut params = Params::default();
+
+        for (label, provided) in self.0.iter().zip(labels) {
+            match label {
+                Label::Literal(literal) => {
+                    if !literal.eq_ignore_ascii_case(provided) {
+                        return None;
+                    }
+                }
+                Label::Param(name) => {
+                    if provided.is_empty() {
+                        return None;
+                    }
+
+                    params.insert(name.clone(), provided.to_ascii_lowercase());
+                }
+            }
+        }
+
+        Some(params)
+    }
+}
+
+impl std::fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let labels: Vec<String> = self
+            .0
+            .iter()
+            .map(|label| match label {
+                Label::Literal(literal) => literal.clone(),
+                Label::Param(name) if name == SUBDOMAIN => "*".to_string(),
+                Label::Param(name) => format!(":{}", name),
+            })
+            .collect();
+
+        write!(f, "{}", labels.join("."))
+    }
+}
+
+// The host the request was made to, without port or trailing dot: the authority of an
+// absolute-form request target if there is one, and the Host header otherwise.
+fn host<B>(req: &Request<B>) -> Option<String> {
+    let host = match req.uri().host() {
+        Some(host) => host.to_string(),
+        None => {
+            let header = req.headers().get(http::header::HOST)?.to_str().ok()?;
+            let authority: http::uri::Authority = header.parse().ok()?;
+            authority.host().to_string()
+        }
+    };
+
+    Some(host.trim_end_matches('.').to_string())
+}
+
+mod tests {
+    #[test]
+    fn test_host_pattern() {
+        use super::HostPattern;
+        use crate::Params;
+        use http::Request;
+
+        let host = |uri: &str, header: Option<&str>| {
+            let mut req = Request::builder().uri(uri);
+            if let Some(header) = header {
+                req = req.header("Host", header);
+            }
+            req.body(()).unwrap()
+        };
+
+        let api = HostPattern::new("api.example.com");
+        assert_eq!(api.to_string(), "api.example.com");
+        assert_eq!(
+            api.matches(&host("/", Some("api.example.com"))),
+            Some(Params::default())
+        );
+        assert!(api
+            .matches(&host("/", Some("API.Example.com:8080")))
+            .is_some());
+        assert!(api.matches(&host("/", Some("api.example.com."))).is_some());
+        assert!(api.matches(&host("/", Some("www.example.com"))).is_none());
+        assert!(api.matches(&host("/", Some("x.api.example.com"))).is_none());
+        assert!(api.matches(&host("/", None)).is_none());
+        // the authority of an absolute-form request wins over the Host header.
+        assert!(api
+            .matches(&host("http://api.example.com/", Some("www.example.com")))
+            .is_some());
+
+        let wildcard = HostPattern::new("*.example.com");
+        assert_eq!(wildcard.to_string(), "*.example.com");
+        let params = wildcard
+            .matches(&host("/", Some("Acme.example.com")))
+            .unwrap();
+        assert_eq!(params.get("subdomain").unwrap(), "acme");
+        assert!(wildcard.matches(&host("/", Some("example.com"))).is_none());
+        assert!(wildcard
+            .matches(&host("/", Some("a.b.example.com")))
+            .is_none());
+
+        let named = HostPattern::new(":tenant.:region.example.com");
+        assert_eq!(named.to_string(), ":tenant.:region.example.com");
+        let params = named
+            .matches(&host("/", Some("acme.eu.example.com")))
+            .unwrap();
+        assert_eq!(params.get("tenant").unwrap(), "acme");
+        assert_eq!(params.get("region").unwrap(), "eu");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid host pattern `api..example.com`: empty label")]
+    fn test_host_pattern_rejects_empty_labels() {
+        super::HostPattern::new("api..example.com");
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+type Subscribers<M> = Mutex<HashMap<SubscriberId, mpsc::Sender<M>>>;
+
+/// SubscriberId identifies a [Subscription] to a [Hub], for sending messages to it alone with
+/// [Hub::send_to].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriberId(u64);
+
+impl std::fmt::Display for SubscriberId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Overflow is what a [Hub] does when a subscriber's queue is full, because its client is reading
+/// messages more slowly than they are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the message for that subscriber, which misses it but stays subscribed.
+    Drop,
+    /// Unsubscribe the subscriber, which receives the messages already queued and then none.
+    Disconnect,
+}
+
+/// Hub sends messages to any number of subscribers, such as the connections to a chat room, each
+/// of which forwards them to its client from a task of its own:
+///
+/// ```ignore
+///     let hub = Hub::new(32);
+///
+///     let subscriber = hub.clone();
+///     app.get("/room", Handler::from_fn(move |req, _resp, _params, _app, state| {
+///         let mut subscription = subscriber.subscribe();
+///         async move {
+///             let body = body::stream(move |mut writer| async move {
+///                 while let Some(message) = subscription.recv().await {
+///                     writer.write_chunk(message).await?;
+///                 }
+///                 Ok(())
+///             });
+///             Ok((req, Some(Response::new(body)), state))
+///         }
+///     }));
+///
+///     hub.broadcast("hello, everyone!\n".to_string());
+/// ```
+///
+/// Each subscriber has a queue of `capacity` messages; what happens when it is full is chosen with
+/// [Hub::overflow]. Subscribers are unsubscribed when their [Subscription] is dropped, such as when
+/// the task forwarding from it ends because the client went away. Clones of a hub share its
+/// subscribers, so it can be captured by any number of handlers, and by the clones of the App
+/// they belong to; once every clone is gone, subscriptions receive no more messages.
+#[derive(Debug)]
+pub struct Hub<M> {
+    capacity: usize,
+    overflow: Overflow,
+    next_id: Arc<AtomicU64>,
+    subscribers: Arc<Subscribers<M>>,
+}
+
+impl<M> Clone for Hub<M> {
+    fn clone(&self) -> Self {
+        Self {
+            capacity: self.capacity,
+            overflow: self.overflow,
+            next_id: self.next_id.clone(),
+            subscribers: self.subscribers.clone(),
+        }
+    }
+}
+
+impl<M: Clone + Send + 'static> Hub<M> {
+    /// A hub queueing up to `capacity` messages for each subscriber, dropping those that do not
+    /// fit. Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        if capacity == 0 {
+            panic!("hub subscribers must be able to queue messages: capacity must be above zero");
+        }
+
+        Self {
+            capacity,
+            overflow: Overflow::Drop,
+            next_id: Arc::new(AtomicU64::new(0)),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// What to do when a subscriber's queue is full, rather than dropping the message.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Subscribe to the messages sent from now on.
+    pub fn subscribe(&self) -> Subscription<M> {
+        let id = SubscriberId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        self.lock().insert(id, sender);
+
+        Subscription {
+            id,
+            receiver,
+            subscribers: Arc::downgrade(&self.subscribers),
+        }
+    }
+
+    /// Send the message to every subscriber, returning how many it was queued for.
+    pub fn broadcast(&self, message: M) -> usize {
+        let mut subscribers = self.lock();
+        let mut sent = 0;
+
+        subscribers.retain(|_, sender| match self.deliver(sender, message.clone()) {
+            Delivery::Sent => {
+                sent += 1;
+                true
+            }
+            Delivery::Dropped => true,
+            Delivery::Gone => false,
+        });
+
+        sent
+    }
+
+    /// Send the message to one subscriber, returning whether it was queued; it is not if the
+    /// subscriber is gone or its queue is full.
+    pub fn send_to(&self, id: SubscriberId, message: M) -> bool {
+        let mut subscribers = self.lock();
+        let delivery = match subscribers.get(&id) {
+            Some(sender) => self.deliver(sender, message),
+            None => return false,
+        };
+
+        if delivery == Delivery::Gone {
+            subscribers.remove(&id);
+        }
+
+        delivery == Delivery::Sent
+    }
+
+    /// The number of subscribers.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether there are no subscribers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn deliver(&self, sender: &mpsc::Sender<M>, message: M) -> Delivery {
+        match sender.try_send(message) {
+            Ok(()) => Delivery::Sent,
+            Err(TrySendError::Full(_)) if self.overflow == Overflow::Drop => Delivery::Dropped,
+            Err(_) => Delivery::Gone,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SubscriberId, mpsc::Sender<M>>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// What became of a message sent to a subscriber.
+#[derive(Debug, PartialEq, Eq)]
+enum Delivery {
+    Sent,
+    Dropped,
+    // the subscriber is gone, or is to be disconnected.
+    Gone,
+}
+
+/// Subscription receives the messages a [Hub] sends to a subscriber, in the order they were sent.
+/// Dropping it unsubscribes.
+#[derive(Debug)]
+pub struct Subscription<M> {
+    id: SubscriberId,
+    receiver: mpsc::Receiver<M>,
+    subscribers: Weak<Subscribers<M>>,
+}
+
+impl<M> Subscription<M> {
+    /// The subscriber's ID, for [Hub::send_to].
+    pub fn id(&self) -> SubscriberId {
+        self.id
+    }
+
+    /// Wait for the next message, or None once the subscriber has been disconnected or the hub is
+    /// gone, and the messages queued before then have been received.
+    pub async fn recv(&mut self) -> Option<M> {
+        self.receiver.recv().await
+    }
+}
+
+impl<M> Drop for Subscription<M> {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&self.id);
+        }
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_hub() {
+        use super::Hub;
+
+        let hub = Hub::new(4);
+        let mut alice = hub.subscribe();
+        let mut bob = hub.clone().subscribe();
+        assert_ne!(alice.id(), bob.id());
+        assert_eq!(hub.len(), 2);
+
+        assert_eq!(hub.broadcast("hello".to_string()), 2);
+        assert!(hub.send_to(bob.id(), "psst".to_string()));
+        assert_eq!(alice.recv().await.unwrap(), "hello");
+        assert_eq!(bob.recv().await.unwrap(), "hello");
+        assert_eq!(bob.recv().await.unwrap(), "psst");
+
+        // dropped subscriptions are unsubscribed.
+        let id = bob.id();
+        drop(bob);
+        assert_eq!(hub.len(), 1);
+        assert!(!hub.send_to(id, "gone".to_string()));
+        assert_eq!(hub.broadcast("still here?".to_string()), 1);
+
+        // once the hub is gone, queued messages are received, then none.
+        drop(hub);
+        assert_eq!(alice.recv().await.unwrap(), "still here?");
+        assert!(alice.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_hub_overflow() {
+        use super::{Hub, Overflow};
+
+        // messages that do not fit are dropped for the slow subscriber alone.
+        let hub = Hub::new(2);
+        let mut slow = hub.subscribe();
+        let mut fast = hub.subscribe();
+        for n in 0..3 {
+            hub.broadcast(n);
+            assert_eq!(fast.recv().await.unwrap(), n);
+        }
+        assert_eq!(hub.len(), 2);
+        assert_eq!(slow.recv().await.unwrap(), 0);
+        assert_eq!(slow.recv().await.unwrap(), 1);
+        assert!(hub.send_to(slow.id(), 3));
+        assert_eq!(slow.recv().await.unwrap(), 3);
+
+        // or the slow subscriber is disconnected, once it has what was queued.
+        let hub = Hub::new(2).overflow(Overflow::Disconnect);
+        let mut slow = hub.subscribe();
+        let mut fast = hub.subscribe();
+        for n in 0..3 {
+            assert_eq!(hub.broadcast(n), if n < 2 { 2 } else { 1 });
+            assert_eq!(fast.recv().await.unwrap(), n);
+        }
+        assert_eq!(hub.len(), 1);
+        assert_eq!(slow.recv().await.unwrap(), 0);
+        assert_eq!(slow.recv().await.unwrap(), 1);
+        assert!(slow.recv().await.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be above zero")]
+    fn test_hub_capacity() {
+        use super::Hub;
+
+        Hub::<String>::new(0);
+    }
+}
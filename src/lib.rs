@@ -1,20 +1,103 @@
 /// Application/Server-level management and routing configuration and testing support; outermost functionality.
 pub mod app;
+/// HTTP authentication
+pub mod auth;
+/// Reading request bodies and streaming response bodies
+pub mod body;
+/// Caching responses in memory
+pub mod cache;
+/// Limiting how many requests may be handled at once
+pub mod concurrency;
+/// Reading and setting cookies
+pub mod cookies;
+/// HTTP dates
+pub(crate) mod date;
+/// Decompressing request bodies
+#[cfg(feature = "decompression")]
+pub mod decompress;
+/// ETags and conditional responses
+pub mod etag;
+/// Passing values between handlers in request extensions
+pub mod ext;
+/// Typed extraction of request data with serde
+#[cfg(feature = "serde")]
+pub mod extract;
+/// Serving files from a directory, or embedded in the binary
+pub mod files;
+/// Gating handlers behind feature flags
+pub mod flag;
+/// One-time messages carried across a redirect
+pub mod flash;
 /// Handler construction and prototypes
 pub mod handler;
+/// Host patterns for Routes
+pub(crate) mod host;
+/// Broadcasting messages to many subscribers, such as the clients in a chat room
+pub mod hub;
 /// Macros for quality-of-life when interacting with Handlers
 pub mod macros;
+/// Shadowing requests to a second upstream
+#[cfg(feature = "proxy")]
+pub mod mirror;
+/// Reading multipart/form-data request bodies
+#[cfg(feature = "multipart")]
+pub mod multipart;
+/// Choosing a response media type from the Accept header
+pub mod negotiate;
 /// Path management for Routes
 pub(crate) mod path;
+/// Errors in the problem+json format
+pub mod problem;
+/// Forwarding requests to an upstream server
+#[cfg(feature = "proxy")]
+pub mod proxy;
+/// Query string parsing
+pub(crate) mod query;
+/// Limiting how often clients may make requests
+pub mod ratelimit;
+/// Helpers for building and transforming responses
+pub mod respond;
 /// Router, Route management and organization
 pub(crate) mod router;
+/// Adding security headers to every response
+pub mod security;
+/// Adapters for using tower Layers as handler stages
+#[cfg(feature = "tower")]
+pub mod tower;
+/// Splitting traffic between handler chains
+pub mod weighted;
 
 use http::{Request, Response};
-use std::{collections::BTreeMap, pin::Pin};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    pin::Pin,
+    time::{Duration, Instant},
+};
 
-/// Params are a mapping of name -> parameter for the purposes of routing.
+pub use handler::when;
+pub use query::Query;
+
+/// Params are a mapping of name -> parameter for the purposes of routing. Values are
+/// percent-decoded; see [crate::raw_params] for the values as they appeared in the request path.
 pub type Params = BTreeMap<String, String>;
 
+// The undecoded parameters of the route a request was dispatched to.
+#[derive(Clone)]
+pub(crate) struct RawParams(pub(crate) Params);
+
+// The methods answered by the routes matching the path of a request no route would answer.
+#[derive(Clone)]
+pub(crate) struct AllowedMethods(pub(crate) Vec<http::Method>);
+
+// The method a request arrived with, when it was overridden.
+#[derive(Clone)]
+pub(crate) struct OriginalMethod(pub(crate) http::Method);
+
+// The path template of the route a request was dispatched to.
+#[derive(Clone)]
+pub(crate) struct MatchedPath(pub(crate) String);
+
 pub(crate) type PinBox<F> = Pin<Box<F>>;
 
 /// An error for server-related issues.
@@ -30,13 +113,25 @@ where
     }
 }
 
-/// General errors for ratpack handlers. Yield either a StatusCode for a literal status, or a
-/// String for a 500 Internal Server Error. Other status codes should be yielded through
+impl ServerError {
+    /// The message describing the error.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+/// General errors for ratpack handlers. Yield either a StatusCode for a literal status, a String
+/// for a 500 Internal Server Error, or a [crate::problem::Problem] for an error in the
+/// `application/problem+json` format. Other status codes should be yielded through
 /// [http::Response] returns.
+///
+/// More variants may be added in later releases, so matches on it need a wildcard arm.
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum Error {
     StatusCode(http::StatusCode, String),
     InternalServerError(String),
+    Problem(Box<problem::Problem>),
 }
 
 impl Default for Error {
@@ -61,6 +156,23 @@ impl Error {
     {
         Self::StatusCode(error, message.to_string())
     }
+
+    /// The status the error is rendered with.
+    pub fn status(&self) -> http::StatusCode {
+        match self {
+            Self::StatusCode(status, _) => *status,
+            Self::InternalServerError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Problem(problem) => problem.status(),
+        }
+    }
+
+    /// The message describing the error; for a problem, its detail or else its title.
+    pub fn message(&self) -> String {
+        match self {
+            Self::StatusCode(_, message) | Self::InternalServerError(message) => message.clone(),
+            Self::Problem(problem) => problem.message(),
+        }
+    }
 }
 
 impl<T> From<T> for Error
@@ -113,6 +225,170 @@ impl TransientState for NoState {
     }
 }
 
+/// RequestStart records the moment [crate::app::App::dispatch] began processing a request. It is
+/// inserted into the request's extensions before any handlers run, and is what the App's own
+/// request logging measures durations against.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestStart(pub Instant);
+
+impl RequestStart {
+    pub(crate) fn now() -> Self {
+        Self(Instant::now())
+    }
+}
+
+/// Return how long the request has been dispatching, or [std::option::Option::None] if the request
+/// was not dispatched through an [crate::app::App].
+pub fn elapsed<B>(req: &Request<B>) -> Option<Duration> {
+    req.extensions()
+        .get::<RequestStart>()
+        .map(|start| start.0.elapsed())
+}
+
+/// Disconnected tells whether the client of a request went away before it was answered; see
+/// [crate::disconnected]. Clones watch the same request.
+#[derive(Debug, Clone)]
+pub struct Disconnected(pub(crate) tokio::sync::watch::Receiver<bool>);
+
+impl Disconnected {
+    /// Whether the client has gone away.
+    pub fn is_disconnected(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait for the client to go away. This never completes for requests that are answered, so
+    /// is meant to be raced against the work it would cancel.
+    pub async fn wait(&self) {
+        let mut receiver = self.0.clone();
+        if receiver
+            .wait_for(|disconnected| *disconnected)
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Return a signal of the client going away before the request is answered, or
+/// [std::option::Option::None] if the request was not dispatched through an [crate::app::App].
+/// Work done on behalf of the request, such as in a spawned task, can race against it to stop
+/// once nobody is waiting for the result:
+///
+/// ```ignore
+///     let disconnected = ratpack::disconnected(&req).unwrap();
+///     tokio::spawn(async move {
+///         tokio::select! {
+///             _ = disconnected.wait() => log::info!("report abandoned"),
+///             report = build_report() => store(report).await,
+///         }
+///     });
+/// ```
+///
+/// The client is taken to have gone away when the server stops waiting for the response: hyper
+/// does so when the connection closes, or an HTTP/2 client resets the request's stream. The
+/// handlers of the request are stopped along with it, so only work they handed off sees the
+/// signal. HTTP/1.1 has no way to abandon a request but closing the connection, and hyper only
+/// notices a closed connection when it next reads from it, so a client that gives up may still
+/// look connected for a while; a keep-alive connection stays open after a request is answered,
+/// and is no sign of interest in it. Once the response has been produced, its request counts as
+/// answered, even if the client leaves while its body is being sent; the writer of a streamed
+/// body sees that as a failed write instead.
+pub fn disconnected<B>(req: &Request<B>) -> Option<Disconnected> {
+    req.extensions().get::<Disconnected>().cloned()
+}
+
+/// Return the parsed query string of the request, or [std::option::Option::None] if the request was
+/// not dispatched through an [crate::app::App].
+pub fn query<B>(req: &Request<B>) -> Option<&Query> {
+    req.extensions().get::<Query>()
+}
+
+/// Return the parameters of the request path as they were provided, without percent-decoding, or
+/// [std::option::Option::None] if the request was not dispatched through an [crate::app::App].
+pub fn raw_params<B>(req: &Request<B>) -> Option<&Params> {
+    req.extensions().get::<RawParams>().map(|raw| &raw.0)
+}
+
+/// Return the path template of the route the request was dispatched to, such as `/users/:id`, or
+/// [std::option::Option::None] if the request was not dispatched through an [crate::app::App].
+/// Unlike the request path, this is suitable as a label in metrics and logs.
+pub fn matched_path<B>(req: &Request<B>) -> Option<&str> {
+    req.extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.0.as_str())
+}
+
+/// Return the address of the peer the request came from, or [std::option::Option::None] if it
+/// did not arrive over TCP, such as requests served on a unix socket.
+pub fn peer_ip<B>(req: &Request<B>) -> Option<IpAddr> {
+    req.extensions().get::<IpAddr>().copied()
+}
+
+/// Return the methods that routes for the request path do answer, when the request is being handled
+/// by the handler given to [crate::app::App::method_not_allowed]. Otherwise,
+/// [std::option::Option::None] is returned.
+pub fn allowed_methods<B>(req: &Request<B>) -> Option<&[http::Method]> {
+    req.extensions()
+        .get::<AllowedMethods>()
+        .map(|allowed| allowed.0.as_slice())
+}
+
+/// Return the method the request arrived with. This is its method, unless it was a POST routed as
+/// another method by [crate::app::App::enable_method_override], in which case it is POST.
+pub fn original_method<B>(req: &Request<B>) -> &http::Method {
+    req.extensions()
+        .get::<OriginalMethod>()
+        .map_or(req.method(), |original| &original.0)
+}
+
+// A copy of the method, URI, version and headers of the request, with an empty body, for stages
+// that run once the request itself has been consumed. Of its extensions, only the start of the
+// request is kept, so that [crate::elapsed] works on the copy.
+pub(crate) fn request_head(req: &Request<hyper::Body>) -> Request<hyper::Body> {
+    let mut head = Request::new(hyper::Body::empty());
+    *head.method_mut() = req.method().clone();
+    *head.uri_mut() = req.uri().clone();
+    *head.version_mut() = req.version();
+    *head.headers_mut() = req.headers().clone();
+    if let Some(start) = req.extensions().get::<RequestStart>() {
+        head.extensions_mut().insert(*start);
+    }
+    head
+}
+
+/// Return the position of the chain a [crate::weighted::Weighted] handler chose for the request,
+/// or [std::option::Option::None] if the request did not pass through one.
+pub fn variant<B>(req: &Request<B>) -> Option<usize> {
+    req.extensions()
+        .get::<weighted::Variant>()
+        .map(|variant| variant.0)
+}
+
+/// Return whether the feature flag was on for the request, as decided by a
+/// [crate::flag::Gate] it passed through, or [std::option::Option::None] if it passed through no
+/// gate on the flag.
+pub fn flag_enabled<B>(req: &Request<B>, flag: &str) -> Option<bool> {
+    req.extensions()
+        .get::<flag::Decisions>()
+        .and_then(|decisions| decisions.0.get(flag).copied())
+}
+
+/// Return the nonce a [crate::security::CspNonce] handler generated for the request, for
+/// embedding in the tags of inline scripts, or [std::option::Option::None] if the request did not
+/// pass through one.
+pub fn csp_nonce<B>(req: &Request<B>) -> Option<&str> {
+    req.extensions()
+        .get::<security::Nonce>()
+        .map(|nonce| nonce.0.as_str())
+}
+
+/// Return the metadata of the route the request was dispatched to, or
+/// [std::option::Option::None] if the request was not dispatched through an [crate::app::App].
+pub fn route_meta<B>(req: &Request<B>) -> Option<&app::RouteMeta> {
+    req.extensions().get::<app::RouteMeta>()
+}
+
 /// A convenience import to gather all of `ratpack`'s dependencies in one easy place.
 /// To use:
 ///
@@ -121,8 +397,9 @@ impl TransientState for NoState {
 /// ```
 pub mod prelude {
     pub use crate::{
-        app::App, compose_handler, Error, HTTPResult, NoState, Params, ServerError, ToStatus,
-        TransientState,
+        allowed_methods, app::App, compose_handler, disconnected, elapsed, ext, query, raw_params,
+        respond, respond::Responder, route_meta, when, Error, HTTPResult, NoState, Params, Query,
+        ServerError, ToStatus, TransientState,
     };
     pub use http::{Request, Response, StatusCode};
     pub use hyper::Body;
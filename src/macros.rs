@@ -1,36 +1,43 @@
-/// compose_handler allows you to combine multiple [crate::handler::HandlerFunc] functions into a
-/// single [crate::handler::Handler], so that they cascade through a chain of responsibility. This
-/// means that each handler will feed its output into the input of the next. To start, the first
+/// compose_handler allows you to combine multiple handlers into a single
+/// [crate::handler::Handler], so that they cascade through a chain of responsibility. This means
+/// that each handler will feed its output into the input of the next. To start, the first
 /// [http::Response] is [std::option::Option::None], and the final return Response must be
 /// non-None; otherwise a 500 Internal Server Error is returned. Handlers may do anything they wish
 /// to the [http::Request] between processing periods, including replacing the request entirely.
+///
+/// Each handler may be anything [crate::handler::Handler::from_fn] accepts: the path of an `async
+/// fn` (turbofish included, such as `show::<User>`, or a method such as `Users::show`), a closure
+/// returning a future, or any other expression evaluating to one of those:
+///
+/// ```ignore
+///     compose_handler!(authenticate, |req, _resp, _params, _app, state| async move {
+///         Ok((req, Some(Response::new(Body::from("hello"))), state))
+///     })
+/// ```
+///
+/// Stages are named after the expression given for them, except for closures, which are named
+/// `<closure>`.
 #[macro_export]
 macro_rules! compose_handler {
-    ($( $x:path ),*) => {
+    ($( $x:expr ),+ $(,)?) => {
         {
-            use $crate::handler::{HandlerFunc, Handler};
+            use $crate::handler::Handler;
             {
-                let mut funcs: Vec<HandlerFunc<_, _>> = Vec::new();
+                let mut stages = Vec::new();
 
                 $(
-                    funcs.push(|req, resp, params, app, state| Box::pin($x(req, resp, params, app, state)));
-                )*
-
-                if funcs.len() == 0 {
-                    panic!("compose_handler requires at least one handler to be supplied")
-                }
-
-
-                let mut handlers = Vec::new();
-                let mut last: Option<Handler<_, _>> = None;
-                funcs.reverse();
-
-                for func in funcs {
-                    last = Some(Handler::new(func, last.clone()));
-                    handlers.push(last.clone());
+                    stages.push(
+                        Handler::from_fn($x)
+                            .named($crate::handler::stage_name(stringify!($x))),
+                    );
+                )+
+
+                let mut last = stages.pop().unwrap();
+                while let Some(stage) = stages.pop() {
+                    last = stage.then(last);
                 }
 
-                last.unwrap()
+                last
             }
         }
     };
@@ -135,4 +142,80 @@ mod tests {
             .await
             .is_err());
     }
+
+    #[tokio::test]
+    async fn test_handler_macro_expressions() {
+        use http::{Request, Response};
+        use hyper::Body;
+
+        use crate::{app::App, HTTPResult, NoState, Params};
+
+        trait Greeting {
+            fn greeting() -> &'static str;
+        }
+
+        struct English;
+
+        impl Greeting for English {
+            fn greeting() -> &'static str {
+                "hello"
+            }
+        }
+
+        async fn greet<G: Greeting>(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from(G::greeting()))), state))
+        }
+
+        struct Users;
+
+        impl Users {
+            async fn show(
+                req: Request<Body>,
+                resp: Option<Response<Body>>,
+                params: Params,
+                _app: App<(), NoState>,
+                state: NoState,
+            ) -> HTTPResult<NoState> {
+                let name = params.get("name").unwrap();
+                let body = hyper::body::to_bytes(resp.unwrap().into_body()).await?;
+                let body = format!("{}, {}", String::from_utf8_lossy(&body), name);
+                Ok((req, Some(Response::new(Body::from(body))), state))
+            }
+        }
+
+        let suffix = "!".to_string();
+        let handler = compose_handler!(
+            greet::<English>,
+            Users::show,
+            move |req, resp: Option<Response<Body>>, _params, _app, state| {
+                let suffix = suffix.clone();
+                async move {
+                    let body = hyper::body::to_bytes(resp.unwrap().into_body()).await?;
+                    let body = format!("{}{}", String::from_utf8_lossy(&body), suffix);
+                    Ok((req, Some(Response::new(Body::from(body))), state))
+                }
+            },
+        );
+        assert_eq!(
+            handler.stages().collect::<Vec<_>>(),
+            vec!["greet::<English>", "Users::show", "<closure>"]
+        );
+
+        let mut params = Params::new();
+        params.insert("name".to_string(), "erik".to_string());
+        let (_, response, _) = handler
+            .perform(Request::default(), None, params, App::new(), NoState {})
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.unwrap().into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, "hello, erik!".as_bytes());
+    }
 }
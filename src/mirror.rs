@@ -0,0 +1,345 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use http::{
+    header,
+    uri::{PathAndQuery, Scheme},
+    Request, Uri,
+};
+use hyper::{body::HttpBody, client::HttpConnector, Body, Client};
+
+use crate::{
+    handler::Handler,
+    proxy::strip_hop_by_hop,
+    weighted::{split_mix, GAMMA},
+    Error, TransientState,
+};
+
+type FilterFunc = Arc<dyn Fn(&Request<Body>) -> bool + Send + Sync>;
+
+/// Mirror sends copies of requests to a second upstream, such as a rewrite being validated
+/// against production traffic, while the chain it leads answers them as usual:
+///
+/// ```ignore
+///     let mirror = Mirror::new("http://10.0.0.7:8080")
+///         .sample(0.05)
+///         .filter(|req| req.headers().contains_key("x-tenant"))
+///         .handler();
+///     app.wrap(mirror);
+/// ```
+///
+/// Copies carry the method, path, query, headers and body of the request, and are sent from a
+/// spawned task, so that the response to the request neither waits for the mirror nor depends
+/// on it. The response of the mirror is read and thrown away; it and any failure to reach the
+/// mirror are only logged.
+///
+/// Bodies are copied by reading them into memory before the rest of the chain is performed, so
+/// only requests whose `Content-Length` is no more than [Mirror::max_body] are mirrored; those
+/// with larger bodies, or bodies of unknown length, are passed on untouched.
+#[derive(Clone)]
+pub struct Mirror {
+    upstream: Uri,
+    sample: f64,
+    filter: Option<FilterFunc>,
+    max_body: u64,
+    timeout: Duration,
+}
+
+impl Mirror {
+    /// Mirror requests to the upstream URL, which must be an `http://` URL; its path, if any,
+    /// is prepended to those of the requests. Panics if it is not one.
+    pub fn new(upstream: &str) -> Self {
+        let uri: Uri = match upstream.parse() {
+            Ok(uri) => uri,
+            Err(e) => panic!("invalid mirror upstream `{}`: {}", upstream, e),
+        };
+
+        if uri.scheme() != Some(&Scheme::HTTP) || uri.authority().is_none() {
+            panic!(
+                "invalid mirror upstream `{}`: must be an http:// URL",
+                upstream
+            );
+        }
+
+        Self {
+            upstream: uri,
+            sample: 1.0,
+            filter: None,
+            max_body: 64 * 1024,
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Mirror this fraction of requests, between 0 and 1, chosen at random; all of them unless
+    /// changed. Panics if it is out of range.
+    pub fn sample(mut self, rate: f64) -> Self {
+        if !(0.0..=1.0).contains(&rate) {
+            panic!("mirror sample rate must be between 0 and 1, not {}", rate);
+        }
+
+        self.sample = rate;
+        self
+    }
+
+    /// Only mirror requests the function returns true for, such as those carrying a header.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Mirror requests with bodies of up to `max` bytes; 64KiB unless changed.
+    pub fn max_body(mut self, max: u64) -> Self {
+        self.max_body = max;
+        self
+    }
+
+    /// Give up on the mirror if it has not answered within this long; 10 seconds unless
+    /// changed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Build the handler mirroring requests, which leads the chains it mirrors.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        let rng = Arc::new(AtomicU64::new(seed));
+        let mirror = Arc::new(self);
+        let client: Client<HttpConnector, Body> = Client::new();
+
+        Handler::around(
+            Arc::new(move |req, resp, params, app, state, next| {
+                let roll = split_mix(rng.fetch_add(GAMMA, Ordering::Relaxed));
+                let selected = mirror.selects(&req, roll);
+                let mirror = mirror.clone();
+                let client = client.clone();
+
+                Box::pin(async move {
+                    let req = match selected {
+                        true => mirror.send(client, req).await?,
+                        false => req,
+                    };
+
+                    match next {
+                        Some(next) => next.perform(req, resp, params, app, state).await,
+                        None => Ok((req, resp, state)),
+                    }
+                })
+            }),
+            None,
+        )
+        .named("mirror")
+    }
+
+    // whether the request is to be mirrored, given a random roll.
+    fn selects(&self, req: &Request<Body>, roll: u64) -> bool {
+        // the top 53 bits of the roll, as a fraction between 0 and 1.
+        let fraction = (roll >> 11) as f64 / (1u64 << 53) as f64;
+        if fraction >= self.sample {
+            return false;
+        }
+
+        if self.filter.as_ref().is_some_and(|filter| !filter(req)) {
+            return false;
+        }
+
+        req.body().is_end_stream()
+            || req
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok()?.parse::<u64>().ok())
+                .is_some_and(|length| length <= self.max_body)
+    }
+
+    // send a copy of the request to the mirror, returning the request with its body restored.
+    async fn send(
+        self: Arc<Self>,
+        client: Client<HttpConnector, Body>,
+        req: Request<Body>,
+    ) -> Result<Request<Body>, Error> {
+        let (req, body) = crate::body::buffer(req, self.max_body as usize).await?;
+
+        let base = self.upstream.path().trim_end_matches('/');
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}{}?{}", base, req.uri().path(), query),
+            None => format!("{}{}", base, req.uri().path()),
+        };
+        let mut uri = self.upstream.clone().into_parts();
+        uri.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+
+        let mut copy = Request::builder()
+            .method(req.method().clone())
+            .uri(Uri::from_parts(uri)?)
+            .body(Body::from(body))?;
+        *copy.headers_mut() = req.headers().clone();
+        strip_hop_by_hop(copy.headers_mut());
+        // the client sets the Host of the mirror.
+        copy.headers_mut().remove(header::HOST);
+
+        tokio::spawn(async move {
+            let _target = copy.uri().to_string();
+            let sent = async {
+                let response = client.request(copy).await?;
+                let status = response.status();
+                hyper::body::to_bytes(response.into_body()).await?;
+                Ok::<_, hyper::Error>(status)
+            };
+
+            match tokio::time::timeout(self.timeout, sent).await {
+                Ok(Ok(_status)) => {
+                    #[cfg(all(feature = "logging", not(feature = "trace")))]
+                    log::debug!("mirror {} responded with status {}", _target, _status);
+                    #[cfg(feature = "trace")]
+                    tracing::debug!("mirror {} responded with status {}", _target, _status);
+                }
+                Ok(Err(_e)) => {
+                    #[cfg(all(feature = "logging", not(feature = "trace")))]
+                    log::warn!("mirror request to {} failed: {}", _target, _e);
+                    #[cfg(feature = "trace")]
+                    tracing::warn!("mirror request to {} failed: {}", _target, _e);
+                }
+                Err(_) => {
+                    #[cfg(all(feature = "logging", not(feature = "trace")))]
+                    log::warn!("mirror request to {} timed out", _target);
+                    #[cfg(feature = "trace")]
+                    tracing::warn!("mirror request to {} timed out", _target);
+                }
+            }
+        });
+
+        Ok(req)
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_mirror() {
+        use super::Mirror;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::{
+            service::{make_service_fn, service_fn},
+            Body, Server,
+        };
+        use std::{convert::Infallible, time::Duration};
+        use tokio::sync::mpsc;
+
+        async fn echo(
+            mut req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = hyper::body::to_bytes(req.body_mut()).await?;
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        // the mirror reports each request it is sent.
+        let (sender, mut mirrored) = mpsc::unbounded_channel();
+        let server =
+            Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(move |_| {
+                let sender = sender.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let sender = sender.clone();
+                        async move {
+                            let mut summary = format!("{} {}", req.method(), req.uri());
+                            if let Some(value) = req.headers().get("x-tenant") {
+                                summary += &format!(" x-tenant={}", value.to_str().unwrap());
+                            }
+                            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                            summary += &format!(" {}", String::from_utf8_lossy(&body));
+                            sender.send(summary).unwrap();
+                            Ok::<_, Infallible>(Response::new(Body::from("ignored")))
+                        }
+                    }))
+                }
+            }));
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut app = App::new();
+        app.post(
+            "/events",
+            Mirror::new(&format!("http://{}/shadow", addr))
+                .filter(|req| req.headers().contains_key("x-tenant"))
+                .max_body(16)
+                .handler()
+                .then(compose_handler!(echo)),
+        );
+        app.post(
+            "/never",
+            Mirror::new(&format!("http://{}", addr))
+                .sample(0.0)
+                .handler()
+                .then(compose_handler!(echo)),
+        );
+        // nothing listens on the discard port.
+        app.post(
+            "/down",
+            Mirror::new("http://127.0.0.1:9")
+                .handler()
+                .then(compose_handler!(echo)),
+        );
+        let app = TestApp::new(app);
+
+        let post = |uri: &str, tenant: bool, body: &'static str| {
+            let mut req = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-length", body.len());
+            if tenant {
+                req = req.header("x-tenant", "acme");
+            }
+            let req = req.body(Body::from(body)).unwrap();
+            let app = app.clone();
+            async move {
+                let mut res = app.dispatch(req).await;
+                assert_eq!(res.status(), StatusCode::OK);
+                let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+                assert_eq!(bytes, body.as_bytes());
+            }
+        };
+
+        // the handler sees the body the mirror was sent a copy of.
+        post("/events?page=1", true, "hello").await;
+        assert_eq!(
+            mirrored.recv().await.unwrap(),
+            "POST /shadow/events?page=1 x-tenant=acme hello"
+        );
+
+        // requests are not mirrored when filtered out, when not sampled, or when their bodies are
+        // too large; nor does a mirror that is down affect them.
+        post("/events", false, "filtered").await;
+        post("/never", true, "unsampled").await;
+        post("/events", true, "far too large for the mirror").await;
+        post("/down", true, "down").await;
+        let nothing = tokio::time::timeout(Duration::from_millis(50), mirrored.recv()).await;
+        assert!(nothing.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "mirror sample rate must be between 0 and 1, not 1.5")]
+    fn test_mirror_rejects_sample_rate() {
+        super::Mirror::new("http://127.0.0.1").sample(1.5);
+    }
+}
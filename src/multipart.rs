@@ -0,0 +1,504 @@
+use std::path::Path;
+
+use http::{header, Request, StatusCode};
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::Error;
+
+// the most bytes the headers of a part may take.
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+
+/// MultipartLimits bounds the size of a `multipart/form-data` body; see [Multipart::limits].
+/// Exceeding either limit fails with a 413 Payload Too Large.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultipartLimits {
+    /// The most bytes the body of any one part may have. Defaults to 10MiB.
+    pub max_part_bytes: usize,
+    /// The most bytes the whole request body may have, boundaries and headers included. Defaults
+    /// to 50MiB.
+    pub max_total_bytes: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_part_bytes: 10 * 1024 * 1024,
+            max_total_bytes: 50 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // before the headers of the next part, right after a delimiter.
+    Delimiter,
+    // within the body of a part.
+    Body,
+    // after the closing delimiter.
+    End,
+}
+
+/// Multipart reads the parts of a `multipart/form-data` request body, such as a form uploading
+/// files, as they arrive. See [parse].
+///
+/// Parts are read one at a time with [Multipart::next_part], and each part's body streams from the
+/// request's, so must be read before moving on to the next part; whatever is left of it is
+/// skipped.
+pub struct Multipart {
+    body: Body,
+    // `\r\n--` and the boundary; the buffer starts with `\r\n` so the first delimiter has one too.
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+    limits: MultipartLimits,
+    total: usize,
+}
+
+/// Start reading the `multipart/form-data` body of the request, taking it from the request:
+///
+/// ```ignore
+///     let mut multipart = ratpack::multipart::parse(&mut req)?;
+///     while let Some(mut part) = multipart.next_part().await? {
+///         match part.filename() {
+///             Some(filename) => part.save(uploads.join(sanitize(filename))).await?,
+///             None => fields.insert(part.name().to_string(), part.text(1024).await?),
+///         };
+///     }
+/// ```
+///
+/// A request that is not `multipart/form-data` fails with a 415 Unsupported Media Type, and one
+/// without a boundary with a 400 Bad Request; as does a malformed body, once it is read.
+pub fn parse(req: &mut Request<Body>) -> Result<Multipart, Error> {
+    let content_type = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let mut params = content_type.split(';');
+    if !params
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return Err(Error::new_status(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "request body is not multipart/form-data",
+        ));
+    }
+
+    let boundary = params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .filter(|boundary| (1..=70).contains(&boundary.len()))
+        .ok_or_else(|| bad_request("multipart/form-data request has no valid boundary"))?;
+
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    Ok(Multipart {
+        body: std::mem::replace(req.body_mut(), Body::empty()),
+        delimiter,
+        buf: b"\r\n".to_vec(),
+        state: State::Body,
+        limits: MultipartLimits::default(),
+        total: 0,
+    })
+}
+
+impl Multipart {
+    /// Bound the size of the body; see [MultipartLimits] for the defaults.
+    pub fn limits(mut self, limits: MultipartLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Return the next part, or [std::option::Option::None] once all have been read. Any of the
+    /// previous part that was not read is skipped.
+    pub async fn next_part(&mut self) -> Result<Option<Part<'_>>, Error> {
+        // skip whatever is left of the previous part; the preamble is skipped the same way.
+        while self.state == State::Body {
+            self.body_chunk().await?;
+        }
+
+        if self.state == State::End {
+            return Ok(None);
+        }
+
+        // the delimiter is followed by `--` if it is the last, and otherwise by the end of its
+        // line, which the headers of the next part follow up to an empty line.
+        let headers = loop {
+            if self.buf.starts_with(b"--") {
+                self.state = State::End;
+                return Ok(None);
+            }
+
+            if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                let headers = String::from_utf8_lossy(&self.buf[..end]).to_string();
+                self.buf.drain(..end + 4);
+                break headers;
+            }
+
+            if self.buf.len() > MAX_HEADER_BYTES {
+                return Err(bad_request("multipart part headers are too large"));
+            }
+
+            if !self.fill().await? {
+                return Err(bad_request("multipart body ends within part headers"));
+            }
+        };
+
+        // the rest of the delimiter's line may only hold whitespace.
+        let mut lines = headers.split("\r\n");
+        if !lines.next().unwrap_or_default().trim().is_empty() {
+            return Err(bad_request("multipart delimiter is followed by garbage"));
+        }
+
+        let mut part = Part {
+            multipart: self,
+            name: String::new(),
+            filename: None,
+            content_type: None,
+            read: 0,
+            done: false,
+        };
+
+        let mut named = false;
+        for line in lines {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| bad_request("multipart part has a malformed header"))?;
+            let value = value.trim();
+
+            if name.trim().eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    match param.split_once('=') {
+                        Some((key, value)) if key.trim() == "name" => {
+                            part.name = unquote(value);
+                            named = true;
+                        }
+                        Some((key, value)) if key.trim() == "filename" => {
+                            part.filename = Some(unquote(value))
+                        }
+                        _ => {}
+                    }
+                }
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            }
+        }
+
+        if !named {
+            return Err(bad_request("multipart part has no name"));
+        }
+
+        part.multipart.state = State::Body;
+        Ok(Some(part))
+    }
+
+    // read more of the request body into the buffer, returning false if there is no more.
+    async fn fill(&mut self) -> Result<bool, Error> {
+        match self.body.data().await {
+            Some(chunk) => {
+                let chunk = chunk.map_err(crate::body::error)?;
+                self.total += chunk.len();
+                if self.total > self.limits.max_total_bytes {
+                    return Err(Error::new_status(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "request body exceeds limit of {} bytes",
+                            self.limits.max_total_bytes
+                        ),
+                    ));
+                }
+
+                self.buf.extend_from_slice(&chunk);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // return the next bytes of the current part's body, or None once its delimiter is reached.
+    async fn body_chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                if i > 0 {
+                    return Ok(Some(self.buf.drain(..i).collect::<Vec<u8>>().into()));
+                }
+
+                self.buf.drain(..self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(None);
+            }
+
+            // all but what could be the start of a delimiter is the part's.
+            let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                return Ok(Some(self.buf.drain(..safe).collect::<Vec<u8>>().into()));
+            }
+
+            if !self.fill().await? {
+                return Err(bad_request("multipart body ends within a part"));
+            }
+        }
+    }
+}
+
+/// Part is one part of a [Multipart] body, such as a form field or an uploaded file.
+pub struct Part<'a> {
+    multipart: &'a mut Multipart,
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    read: usize,
+    done: bool,
+}
+
+impl Part<'_> {
+    /// The name of the form field.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the uploaded file, as the client gave it. This must not be trusted as a path.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// The content type of the part, if given.
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Return the next chunk of the part's body, or [std::option::Option::None] once it has all
+    /// been read.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+
+        match self.multipart.body_chunk().await? {
+            Some(chunk) => {
+                self.read += chunk.len();
+                if self.read > self.multipart.limits.max_part_bytes {
+                    return Err(Error::new_status(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!(
+                            "multipart part `{}` exceeds limit of {} bytes",
+                            self.name, self.multipart.limits.max_part_bytes
+                        ),
+                    ));
+                }
+
+                Ok(Some(chunk))
+            }
+            None => {
+                self.done = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Read the whole body of the part, failing with a 413 Payload Too Large if it is larger than
+    /// `cap` bytes.
+    pub async fn bytes(&mut self, cap: usize) -> Result<Bytes, Error> {
+        let mut buf = Vec::new();
+
+        while let Some(chunk) = self.chunk().await? {
+            if buf.len() + chunk.len() > cap {
+                return Err(Error::new_status(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "multipart part `{}` exceeds limit of {} bytes",
+                        self.name, cap
+                    ),
+                ));
+            }
+
+            buf.extend_from_slice(&chunk);
+        }
+
+        Ok(Bytes::from(buf))
+    }
+
+    /// Read the whole body of the part as UTF-8 text, within `cap` bytes as with [Part::bytes]. A
+    /// body that is not valid UTF-8 fails with a 400 Bad Request.
+    pub async fn text(&mut self, cap: usize) -> Result<String, Error> {
+        let bytes = self.bytes(cap).await?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| bad_request(format!("multipart part `{}` is not valid UTF-8", self.name)))
+    }
+
+    /// Write the body of the part to a new file at `path`, returning the number of bytes written.
+    /// The part's size is bounded by the limits of the [Multipart] alone.
+    pub async fn save(&mut self, path: impl AsRef<Path>) -> Result<u64, Error> {
+        let mut file = tokio::fs::File::create(path).await?;
+        let mut written = 0;
+
+        while let Some(chunk) = self.chunk().await? {
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        file.flush().await?;
+        Ok(written)
+    }
+}
+
+fn bad_request(message: impl ToString) -> Error {
+    Error::new_status(StatusCode::BAD_REQUEST, message)
+}
+
+// the value of a header parameter, without any quotes around it.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\\\"", "\""),
+        None => value.to_string(),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_multipart() {
+        use super::{parse, MultipartLimits};
+        use crate::Error;
+        use http::{Request, StatusCode};
+        use hyper::Body;
+
+        const BODY: &str = "preamble\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            hello\r\nworld\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"a \\\"b\\\".txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            --XyZ is not a delimiter without its line break\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"skipped\"\r\n\
+            \r\n\
+            unread\r\n\
+            --XyZ--\r\n\
+            epilogue";
+
+        // sends the body a few bytes at a time, so delimiters span chunks.
+        let request = |body: &'static str, chunk: usize| {
+            let (mut sender, stream) = Body::channel();
+            tokio::spawn(async move {
+                for piece in body.as_bytes().chunks(chunk) {
+                    if sender.send_data(piece.to_vec().into()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Request::builder()
+                .header("content-type", "multipart/form-data; boundary=\"XyZ\"")
+                .body(stream)
+                .unwrap()
+        };
+
+        let status = |e: Error| e.status();
+
+        for chunk in [1, 3, 7, 1024] {
+            let mut req = request(BODY, chunk);
+            let mut multipart = parse(&mut req).unwrap();
+
+            let mut part = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(part.name(), "title");
+            assert_eq!(part.filename(), None);
+            assert_eq!(part.text(64).await.unwrap(), "hello\r\nworld");
+
+            let mut part = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(part.name(), "upload");
+            assert_eq!(part.filename(), Some("a \"b\".txt"));
+            assert_eq!(part.content_type(), Some("text/plain"));
+            assert_eq!(
+                part.bytes(64).await.unwrap(),
+                "--XyZ is not a delimiter without its line break".as_bytes()
+            );
+
+            let part = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(part.name(), "skipped");
+            assert!(multipart.next_part().await.unwrap().is_none());
+            assert!(multipart.next_part().await.unwrap().is_none());
+        }
+
+        // limits on parts and on the whole body.
+        let mut req = request(BODY, 16);
+        let mut multipart = parse(&mut req).unwrap().limits(MultipartLimits {
+            max_part_bytes: 4,
+            ..Default::default()
+        });
+        let mut part = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(
+            status(part.bytes(64).await.unwrap_err()),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let mut req = request(BODY, 16);
+        let mut multipart = parse(&mut req).unwrap().limits(MultipartLimits {
+            max_total_bytes: 64,
+            ..Default::default()
+        });
+        let mut failed = None;
+        for _ in 0..4 {
+            if let Err(e) = multipart.next_part().await {
+                failed = Some(status(e));
+                break;
+            }
+        }
+        assert_eq!(failed, Some(StatusCode::PAYLOAD_TOO_LARGE));
+
+        // malformed bodies.
+        for body in [
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno closing delimiter",
+            "--XyZ\r\nContent-Type: text/plain\r\n\r\nunnamed\r\n--XyZ--",
+            "--XyZ\r\nno header separator\r\n\r\nvalue\r\n--XyZ--",
+        ] {
+            let mut req = request(body, 5);
+            let mut multipart = parse(&mut req).unwrap();
+            let res = match multipart.next_part().await {
+                Ok(Some(mut part)) => part.bytes(64).await.map(|_| ()),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            assert_eq!(
+                status(res.unwrap_err()),
+                StatusCode::BAD_REQUEST,
+                "{}",
+                body
+            );
+        }
+
+        let mut req = Request::builder()
+            .header("content-type", "application/json")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            status(parse(&mut req).err().unwrap()),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        let mut req = Request::builder()
+            .header("content-type", "multipart/form-data")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            status(parse(&mut req).err().unwrap()),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use http::{header, HeaderMap, Request, StatusCode};
+
+use crate::Error;
+
+// A media range of an Accept header, such as `text/*;q=0.5`.
+#[derive(Debug, Clone, PartialEq)]
+struct MediaRange {
+    kind: String,
+    subtype: String,
+    params: Vec<(String, String)>,
+    quality: f32,
+}
+
+impl MediaRange {
+    // how specifically the range matches the media type, if it does: exact types with parameters
+    // are most specific, and `*/*` least.
+    fn specificity(&self, media_type: &MediaType) -> Option<usize> {
+        if self.kind == "*" {
+            return Some(0);
+        }
+
+        if self.kind != media_type.kind {
+            return None;
+        }
+
+        if self.subtype == "*" {
+            return Some(1);
+        }
+
+        if self.subtype != media_type.subtype
+            || !self
+                .params
+                .iter()
+                .all(|param| media_type.params.contains(param))
+        {
+            return None;
+        }
+
+        Some(2 + self.params.len())
+    }
+}
+
+// A media type offered by the handler, such as `text/html; charset=utf-8`.
+struct MediaType {
+    kind: String,
+    subtype: String,
+    params: Vec<(String, String)>,
+}
+
+/// Choose which of the media types the handler can produce best suits the request's `Accept`
+/// header, following RFC 9110 §12.5.1: each type is given the quality of the most specific media
+/// range matching it, and the type with the highest quality above zero wins. Types of the same
+/// quality are preferred in the order given.
+///
+/// ```ignore
+///     match negotiate::best(&req, &["application/json", "text/html"]) {
+///         Some("text/html") => render_html(&report),
+///         _ => render_json(&report),
+///     }
+/// ```
+///
+/// Requests without an Accept header accept anything, as do those with a malformed one, so the
+/// first type is chosen for them. None is returned if none of the types are acceptable.
+pub fn best<'a, B>(req: &Request<B>, offers: &[&'a str]) -> Option<&'a str> {
+    best_in(req.headers(), offers)
+}
+
+// [best], for the Accept headers among the headers.
+pub(crate) fn best_in<'a>(headers: &HeaderMap, offers: &[&'a str]) -> Option<&'a str> {
+    let ranges = accept(headers);
+    let mut best: Option<(&'a str, f32)> = None;
+
+    for offer in offers {
+        let media_type = match parse_media_type(offer) {
+            Some(media_type) => media_type,
+            None => continue,
+        };
+
+        let quality = ranges
+            .iter()
+            .filter_map(|range| Some((range.specificity(&media_type)?, range.quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+            best = Some((offer, quality));
+        }
+    }
+
+    best.map(|(offer, _)| offer)
+}
+
+/// The same as [best], but failing with a 406 Not Acceptable when none of the types are
+/// acceptable, so that handlers can simply use `?`.
+pub fn require<'a, B>(req: &Request<B>, offers: &[&'a str]) -> Result<&'a str, Error> {
+    best(req, offers).ok_or_else(|| {
+        Error::new_status(
+            StatusCode::NOT_ACCEPTABLE,
+            format!("acceptable media types are: {}", offers.join(", ")),
+        )
+    })
+}
+
+// the media ranges of the request's Accept headers; `*/*` if it has none or they are malformed.
+fn accept(headers: &HeaderMap) -> Vec<MediaRange> {
+    let anything = || {
+        vec![MediaRange {
+            kind: "*".to_string(),
+            subtype: "*".to_string(),
+            params: Vec::new(),
+            quality: 1.0,
+        }]
+    };
+
+    let mut ranges = Vec::new();
+    for value in headers.get_all(header::ACCEPT) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(_) => return anything(),
+        };
+
+        for range in value.split(',').filter(|range| !range.trim().is_empty()) {
+            match parse_range(range) {
+                Some(range) => ranges.push(range),
+                None => return anything(),
+            }
+        }
+    }
+
+    if ranges.is_empty() {
+        return anything();
+    }
+
+    ranges
+}
+
+fn parse_range(range: &str) -> Option<MediaRange> {
+    let media_type = parse_media_type(range)?;
+    if media_type.kind == "*" && media_type.subtype != "*" {
+        return None;
+    }
+
+    // parameters after the weight are extensions, which are ignored.
+    let mut params = Vec::new();
+    let mut quality = 1.0;
+    for (name, value) in media_type.params {
+        if name == "q" {
+            quality = value.parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+            break;
+        }
+
+        params.push((name, value));
+    }
+
+    Some(MediaRange {
+        kind: media_type.kind,
+        subtype: media_type.subtype,
+        params,
+        quality,
+    })
+}
+
+fn parse_media_type(media_type: &str) -> Option<MediaType> {
+    let mut parts = media_type.split(';');
+    let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+    if !is_token(kind) || !is_token(subtype) {
+        return None;
+    }
+
+    let mut params = Vec::new();
+    for param in parts {
+        let (name, value) = param.trim().split_once('=')?;
+        let value = value.trim().trim_matches('"');
+        params.push((name.trim().to_ascii_lowercase(), value.to_string()));
+    }
+
+    Some(MediaType {
+        kind: kind.to_ascii_lowercase(),
+        subtype: subtype.to_ascii_lowercase(),
+        params,
+    })
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+mod tests {
+    #[test]
+    fn test_negotiate_best() {
+        use super::best;
+        use http::Request;
+
+        let offers = ["application/json", "text/html"];
+        let req = |accept: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(accept) = accept {
+                req = req.header("accept", accept);
+            }
+            req.body(()).unwrap()
+        };
+
+        for (accept, expected) in [
+            (None, Some("application/json")),
+            (Some("*/*"), Some("application/json")),
+            (Some("text/html"), Some("text/html")),
+            (Some("TEXT/HTML"), Some("text/html")),
+            (Some("text/*"), Some("text/html")),
+            (Some("image/png"), None),
+            (Some("application/json;q=0.5, text/html"), Some("text/html")),
+            (
+                Some("text/html;q=0.8, application/json;q=0.8"),
+                Some("application/json"),
+            ),
+            // the most specific range decides: json is excluded despite */*.
+            (Some("application/json;q=0, */*"), Some("text/html")),
+            (Some("text/*;q=0.2, */*;q=0.5"), Some("application/json")),
+            (
+                Some("text/*;q=0.9, text/html;q=0.1, */*;q=0.5"),
+                Some("application/json"),
+            ),
+            (Some("application/*;q=0, text/html;q=0"), None),
+            // malformed headers accept anything.
+            (Some("garbage"), Some("application/json")),
+            (Some("text/html;q=2"), Some("application/json")),
+            (Some("*/html"), Some("application/json")),
+            (Some(""), Some("application/json")),
+        ] {
+            assert_eq!(best(&req(accept), &offers), expected, "{:?}", accept);
+        }
+
+        // parameters of a range must be present on the offer.
+        let offers = ["text/plain", "text/plain; format=flowed"];
+        assert_eq!(
+            best(&req(Some("text/plain;format=flowed")), &offers),
+            Some("text/plain; format=flowed")
+        );
+        assert_eq!(
+            best(
+                &req(Some("text/plain;format=flowed;q=0.1, text/*")),
+                &offers
+            ),
+            Some("text/plain")
+        );
+        // extension parameters after the weight are ignored.
+        assert_eq!(
+            best(&req(Some("text/plain;q=0.5;level=1")), &offers),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_negotiate_require() {
+        use super::require;
+        use crate::Error;
+        use http::{Request, StatusCode};
+
+        let req = Request::builder()
+            .header("accept", "image/*")
+            .body(())
+            .unwrap();
+        assert!(matches!(
+            require(&req, &["application/json", "text/html"]),
+            Err(Error::StatusCode(StatusCode::NOT_ACCEPTABLE, msg))
+                if msg == "acceptable media types are: application/json, text/html"
+        ));
+        assert_eq!(require(&req, &["image/png"]).unwrap(), "image/png");
+    }
+}
@@ -1,17 +1,96 @@
+use http::StatusCode;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+
 use crate::{Error, Params};
 
+// The characters [Path::url] accepts in a parameter value, and leaves unencoded in literals;
+// everything else is encoded by [Path::url_from_params]. Wildcards may additionally contain
+// slashes.
+pub(crate) const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'!')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=')
+    .remove(b':')
+    .remove(b'@');
+const WILDCARD: &AsciiSet = &SEGMENT.remove(b'/');
+
+/// Constraint is a regular expression a parameter must match for its route to match, written as
+/// `:name{pattern}` in a path. The pattern is anchored to the whole segment. Patterns that fail to
+/// compile are kept so that [Path::validate] can report them at registration time.
+#[derive(Debug, Clone)]
+pub(crate) struct Constraint {
+    pattern: String,
+    regex: Result<Regex, String>,
+}
+
+impl Constraint {
+    fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            regex: Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn is_match(&self, segment: &str) -> bool {
+        match &self.regex {
+            Ok(regex) => regex.is_match(segment),
+            Err(_) => false,
+        }
+    }
+}
+
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl PartialOrd for Constraint {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.pattern.partial_cmp(&other.pattern)
+    }
+}
+
+/// MatchOptions adjust how request paths are compared against a [Path].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MatchOptions {
+    /// Compare literal components without regard to case. Parameters keep their original case.
+    pub(crate) case_insensitive: bool,
+    /// Match trailing slashes exactly, and never match paths with empty segments (`//`). By
+    /// default both are ignored.
+    pub(crate) strict_slashes: bool,
+}
+
 #[derive(Debug, Clone, PartialOrd, PartialEq)]
 pub(crate) enum RoutePart {
     PathComponent(String),
-    Param(String),
+    Param(String, Option<Constraint>),
+    Optional(String, Option<Constraint>),
+    Wildcard(String),
     Leader,
 }
 
+/// Path is a route path parsed into its parts, alongside whether it was written with a trailing
+/// slash; the slash only matters to [MatchOptions::strict_slashes].
 #[derive(Debug, Clone, PartialOrd)]
-pub(crate) struct Path(Vec<RoutePart>);
+pub(crate) struct Path(Vec<RoutePart>, bool);
 
 impl Eq for Path {}
 
+#[allow(clippy::derive_ord_xor_partial_ord)]
 impl Ord for Path {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.to_string().cmp(&other.to_string())
@@ -19,7 +98,9 @@ impl Ord for Path {
 }
 
 impl Path {
+    #[allow(clippy::comparison_to_empty)]
     pub(crate) fn new(path: String) -> Self {
+        let path_len = path.len();
         let mut parts = Self::default();
 
         let path = path.trim_end_matches("/");
@@ -28,37 +109,311 @@ impl Path {
             return Self::default();
         }
 
+        parts.1 = path.len() < path_len;
+
         let args = path.split("/");
 
         for arg in args {
             if arg.starts_with(":") {
-                // is param
-                parts.push(RoutePart::Param(arg.trim_start_matches(":").to_string()));
+                // is param, possibly constrained and possibly optional
+                let arg = arg.trim_start_matches(":");
+                let (arg, optional) = match arg.strip_suffix('?') {
+                    Some(arg) => (arg, true),
+                    None => (arg, false),
+                };
+
+                let (name, constraint) = match arg.split_once('{') {
+                    Some((name, pattern)) if pattern.ends_with('}') => (
+                        name.to_string(),
+                        Some(Constraint::new(&pattern[..pattern.len() - 1])),
+                    ),
+                    _ => (arg.to_string(), None),
+                };
+
+                if optional {
+                    parts.push(RoutePart::Optional(name, constraint));
+                } else {
+                    parts.push(RoutePart::Param(name, constraint));
+                }
+            } else if let Some(name) = arg.strip_prefix('*') {
+                // is wildcard; captures the remainder of the path
+                parts.push(RoutePart::Wildcard(name.to_string()));
             } else if arg == "" {
                 // skip empties. this will push additional leaders if there is an duplicate slash
                 // (e.g.: `//one/two`), which will fail on matching; we don't want to support this
                 // syntax in the router.
             } else {
-                // is not param
-                parts.push(RoutePart::PathComponent(arg.to_string()));
+                // is not param. literals are compared decoded, as request segments are; an invalid
+                // encoding is kept as written for [Path::check] to report.
+                parts.push(RoutePart::PathComponent(
+                    decode(arg).unwrap_or_else(|_| arg.to_string()),
+                ));
             }
         }
 
         parts
     }
 
+    /// A path of literal components alone, whatever characters they start with, for comparing
+    /// request paths against as routes are matched rather than for registering a route.
+    pub(crate) fn literal(path: &str) -> Self {
+        let mut parts = Self::default();
+        for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+            parts.push(RoutePart::PathComponent(
+                decode(segment).unwrap_or_else(|_| segment.to_string()),
+            ));
+        }
+
+        parts.1 = path.len() > 1 && path.ends_with('/');
+        parts
+    }
+
+    /// Check the text of a path intended for registration as a route, before it is parsed: it must
+    /// begin with a slash and may contain neither whitespace, empty segments nor invalid
+    /// percent-encodings. Parsing would otherwise quietly produce a path that never matches.
+    pub(crate) fn check(path: &str) -> Result<(), String> {
+        if !path.starts_with('/') {
+            return Err("path must begin with `/`".to_string());
+        }
+
+        if path.chars().any(char::is_whitespace) {
+            return Err("path contains whitespace".to_string());
+        }
+
+        if path.contains("//") {
+            return Err("path contains an empty segment (`//`)".to_string());
+        }
+
+        for segment in path.split('/') {
+            if !segment.starts_with([':', '*']) {
+                if let Err(e) = decode(segment) {
+                    return Err(format!(
+                        "segment `{}` is not validly encoded: {}",
+                        segment, e
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate a path intended for registration as a route. Request paths are never validated;
+    /// they simply fail to match.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let mut seen = Vec::new();
+
+        for (i, part) in self.0.iter().enumerate() {
+            if let RoutePart::Wildcard(name) = part {
+                if i != self.0.len() - 1 {
+                    return Err(format!(
+                        "wildcard `*{}` must be the last segment of the path",
+                        name
+                    ));
+                }
+            }
+
+            if let RoutePart::Optional(name, _) = part {
+                if self.0[i..]
+                    .iter()
+                    .any(|part| !matches!(part, RoutePart::Optional(_, _)))
+                {
+                    return Err(format!(
+                        "optional parameter `{}` may only be followed by other optional parameters",
+                        name
+                    ));
+                }
+            }
+
+            if let RoutePart::Param(name, Some(constraint))
+            | RoutePart::Optional(name, Some(constraint)) = part
+            {
+                if let Err(e) = &constraint.regex {
+                    return Err(format!(
+                        "parameter `{}` has an invalid constraint: {}",
+                        name, e
+                    ));
+                }
+            }
+
+            if let RoutePart::Param(name, _)
+            | RoutePart::Optional(name, _)
+            | RoutePart::Wildcard(name) = part
+            {
+                if name.is_empty() {
+                    return Err("parameters must be named".to_string());
+                }
+
+                if let Some(c) = name
+                    .chars()
+                    .find(|c| !(c.is_ascii_alphanumeric() || *c == '_'))
+                {
+                    return Err(format!(
+                        "parameter `{}` contains invalid character {:?}",
+                        name, c
+                    ));
+                }
+
+                if seen.contains(&name) {
+                    return Err(format!("parameter `{}` is used more than once", name));
+                }
+
+                seen.push(name);
+            }
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn push(&mut self, arg: RoutePart) -> Self {
         self.0.push(arg);
         self.clone()
     }
 
+    /// Return this path with `prefix` in front of it, as when mounting routes under a prefix.
+    pub(crate) fn prefixed(&self, prefix: &Path) -> Self {
+        let mut parts = prefix.0.clone();
+        parts.extend(
+            self.0
+                .iter()
+                .filter(|part| !matches!(part, RoutePart::Leader))
+                .cloned(),
+        );
+
+        Self(parts, self.1)
+    }
+
+    /// Build a request path from this path, substituting the given values for its parameters. All
+    /// required parameters must be given, and all given values must be used. Values are not
+    /// encoded; values containing characters that would need encoding are rejected instead, as is
+    /// a value that does not satisfy its parameter's constraint.
+    pub(crate) fn url(&self, values: &[(&str, &str)]) -> Result<String, String> {
+        self.build(values, false)
+    }
+
+    // substitute values into the path; values that are already encoded are not checked for
+    // characters needing encoding.
+    fn build(&self, values: &[(&str, &str)], encoded: bool) -> Result<String, String> {
+        let value = |name: &str| {
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| *value)
+        };
+
+        let check = |name: &str, value: &str, wildcard: bool| {
+            if let Some(c) = value.chars().find(|c| {
+                !(encoded
+                    || c.is_ascii_alphanumeric()
+                    || "-._~!$&'()*+,;=:@".contains(*c)
+                    || (wildcard && *c == '/'))
+            }) {
+                return Err(format!(
+                    "value {:?} for parameter `{}` contains {:?}, which must be encoded",
+                    value, name, c
+                ));
+            }
+
+            if value.is_empty() && !wildcard {
+                return Err(format!("value for parameter `{}` is empty", name));
+            }
+
+            Ok(())
+        };
+
+        let mut segments: Vec<String> = Vec::new();
+        let mut used = Vec::new();
+        let mut omitted = None;
+
+        for part in self.0.iter() {
+            match part {
+                RoutePart::Leader => {}
+                RoutePart::PathComponent(component) => {
+                    segments.push(utf8_percent_encode(component, SEGMENT).to_string())
+                }
+                RoutePart::Param(name, constraint) | RoutePart::Optional(name, constraint) => {
+                    let value = match value(name) {
+                        Some(value) => value,
+                        None if matches!(part, RoutePart::Optional(_, _)) => {
+                            omitted.get_or_insert(name);
+                            continue;
+                        }
+                        None => return Err(format!("missing value for parameter `{}`", name)),
+                    };
+
+                    if let Some(omitted) = omitted {
+                        return Err(format!(
+                            "optional parameter `{}` requires a value for `{}` before it",
+                            name, omitted
+                        ));
+                    }
+
+                    check(name, value, false)?;
+
+                    if let Some(constraint) = constraint {
+                        if !constraint.is_match(value) {
+                            return Err(format!(
+                                "value {:?} for parameter `{}` does not satisfy its constraint",
+                                value, name
+                            ));
+                        }
+                    }
+
+                    used.push(name.as_str());
+                    segments.push(value.to_string());
+                }
+                RoutePart::Wildcard(name) => {
+                    let value = value(name).unwrap_or_default();
+                    check(name, value, true)?;
+
+                    used.push(name.as_str());
+                    if !value.is_empty() {
+                        segments.push(value.trim_start_matches('/').to_string());
+                    }
+                }
+            }
+        }
+
+        if let Some((name, _)) = values.iter().find(|(name, _)| !used.contains(name)) {
+            return Err(format!("path has no parameter `{}`", name));
+        }
+
+        Ok(format!("/{}", segments.join("/")))
+    }
+
+    /// Build a request path from this path like [Path::url], taking the values from `params` and
+    /// percent-encoding them as needed. Entries of `params` this path has no parameter for are
+    /// ignored.
+    pub(crate) fn url_from_params(&self, params: &Params) -> Result<String, String> {
+        let mut values = Vec::new();
+
+        for part in self.0.iter() {
+            let (name, set) = match part {
+                RoutePart::Param(name, _) | RoutePart::Optional(name, _) => (name, SEGMENT),
+                RoutePart::Wildcard(name) => (name, WILDCARD),
+                _ => continue,
+            };
+
+            if let Some(value) = params.get(name) {
+                values.push((name.as_str(), utf8_percent_encode(value, set).to_string()));
+            }
+        }
+
+        let values: Vec<(&str, &str)> = values
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        self.build(&values, true)
+    }
+
     /// This method lists all the params available to the path; useful for debugging.
-    #[allow(dead_code)]
     pub(crate) fn params(&self) -> Vec<String> {
         let mut params = Vec::new();
         for arg in self.0.clone() {
             match arg {
-                RoutePart::Param(p) => params.push(p),
+                RoutePart::Param(p, _) | RoutePart::Optional(p, _) | RoutePart::Wildcard(p) => {
+                    params.push(p)
+                }
                 _ => {}
             }
         }
@@ -66,51 +421,252 @@ impl Path {
         params
     }
 
-    pub(crate) fn extract(&self, provided: String) -> Result<Params, Error> {
-        let provided = provided.trim_end_matches("/");
+    /// Rank the path against others that may match the same request; lower ranks are tried first.
+    /// Components are compared position by position: literals beat constrained parameters, which
+    /// beat plain parameters, then optional parameters, and wildcards come last.
+    pub(crate) fn specificity(&self) -> Vec<u8> {
+        self.0
+            .iter()
+            .filter_map(|part| match part {
+                RoutePart::Leader => None,
+                RoutePart::PathComponent(_) => Some(0),
+                RoutePart::Param(_, Some(_)) => Some(1),
+                RoutePart::Param(_, None) => Some(2),
+                RoutePart::Optional(_, _) => Some(3),
+                RoutePart::Wildcard(_) => Some(4),
+            })
+            .collect()
+    }
 
-        if provided == "" && self.eq(&Self::default()) {
-            return Ok(Params::default());
+    // split the provided path into its non-empty segments, alongside the offset each one starts
+    // at; the offsets allow wildcards to capture the remainder of the path verbatim.
+    fn segments(provided: &str) -> Vec<(usize, &str)> {
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        for segment in provided.split('/') {
+            if !segment.is_empty() {
+                segments.push((offset, segment));
+            }
+
+            offset += segment.len() + 1;
         }
 
-        let mut params = Params::default();
+        segments
+    }
 
-        let parts: Vec<String> = provided
-            .split("/")
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
+    /// Extract the parameters of the provided path, percent-decoded. See [Path::extract_with_raw].
+    #[allow(dead_code)]
+    pub(crate) fn extract(&self, provided: String) -> Result<Params, Error> {
+        self.extract_with_raw(provided, MatchOptions::default())
+            .map(|(params, _)| params)
+    }
+
+    /// Extract the parameters of the provided path, returning them both percent-decoded and as
+    /// they were provided. Literal components are compared against the decoded segments. If the
+    /// path matches but a parameter is not validly encoded, a 400 Bad Request is returned; any
+    /// other error means the path did not match.
+    pub(crate) fn extract_with_raw(
+        &self,
+        provided: String,
+        options: MatchOptions,
+    ) -> Result<(Params, Params), Error> {
+        if options.strict_slashes {
+            if provided.contains("//") {
+                return Err(mismatch("path contains an empty segment"));
+            }
 
-        if parts.len() != self.0.len() {
-            return Err(Error::new("invalid parameters"));
+            // a wildcard captures whatever follows, trailing slash or not.
+            let trailing = provided.len() > 1 && provided.ends_with('/');
+            if trailing != self.1 && !matches!(self.0.last(), Some(RoutePart::Wildcard(_))) {
+                return Err(mismatch("trailing slash does not match"));
+            }
         }
 
+        let provided = provided.trim_end_matches('/');
+        let segments = Self::segments(provided);
+
+        let mut params = Params::default();
+        let mut raw = Params::default();
+        let mut invalid = None;
         let mut i = 0;
 
-        for part in self.0.clone() {
+        for part in self.0.iter() {
             match part {
-                RoutePart::Param(p) => params.insert(p, parts[i].clone()),
-                RoutePart::PathComponent(part) => {
-                    if part != parts[i] {
-                        return Err(Error::new("invalid path for parameter extraction"));
+                RoutePart::Leader => continue,
+                RoutePart::Wildcard(name) => {
+                    let rest = match segments.get(i) {
+                        Some((offset, _)) => &provided[*offset..],
+                        None => "",
+                    };
+
+                    match decode(rest) {
+                        Ok(value) => {
+                            params.insert(name.clone(), value);
+                        }
+                        Err(e) => {
+                            invalid.get_or_insert((name, e));
+                        }
                     }
 
-                    None
+                    raw.insert(name.clone(), rest.to_string());
+                    i = segments.len();
+                    break;
                 }
-                RoutePart::Leader => None,
-            };
+                RoutePart::Optional(_, _) if segments.get(i).is_none() => continue,
+                RoutePart::Param(name, constraint) | RoutePart::Optional(name, constraint) => {
+                    match segments.get(i) {
+                        Some((_, segment)) => {
+                            match decode(segment) {
+                                Ok(value) => {
+                                    if let Some(constraint) = constraint {
+                                        if !constraint.is_match(&value) {
+                                            return Err(mismatch(
+                                                "parameter does not satisfy constraint",
+                                            ));
+                                        }
+                                    }
+
+                                    params.insert(name.clone(), value);
+                                }
+                                Err(e) => {
+                                    invalid.get_or_insert((name, e));
+                                }
+                            }
+
+                            raw.insert(name.clone(), segment.to_string());
+                        }
+                        None => return Err(mismatch("invalid parameters")),
+                    }
+                }
+                RoutePart::PathComponent(component) => {
+                    let matched = match segments.get(i).map(|(_, segment)| decode(segment)) {
+                        Some(Ok(segment)) if options.case_insensitive => {
+                            segment.to_lowercase() == component.to_lowercase()
+                        }
+                        Some(Ok(segment)) => segment == *component,
+                        _ => false,
+                    };
+
+                    if !matched {
+                        return Err(mismatch("invalid path for parameter extraction"));
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        if i != segments.len() {
+            return Err(mismatch("invalid parameters"));
+        }
 
-            i += 1
+        if let Some((name, e)) = invalid {
+            return Err(Error::new_status(
+                StatusCode::BAD_REQUEST,
+                format!("parameter `{}` is not validly encoded: {}", name, e),
+            ));
         }
 
-        Ok(params)
+        Ok((params, raw))
+    }
+
+    /// Whether both paths have the same shape: the same literals, and parameters of the same kind
+    /// and constraint in the same places, whatever the parameters are named. Unlike equality, a
+    /// parameter is not equivalent to a literal, so `/users/:id` and `/users/new` are not.
+    pub(crate) fn equivalent(&self, other: &Path) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(&other.0).all(|parts| match parts {
+                (RoutePart::PathComponent(a), RoutePart::PathComponent(b)) => a == b,
+                (RoutePart::Param(_, a), RoutePart::Param(_, b))
+                | (RoutePart::Optional(_, a), RoutePart::Optional(_, b)) => a == b,
+                (RoutePart::Wildcard(_), RoutePart::Wildcard(_))
+                | (RoutePart::Leader, RoutePart::Leader) => true,
+                _ => false,
+            })
     }
 
+    /// Whether the provided path matches this one with the default [MatchOptions].
+    #[allow(dead_code)]
     pub(crate) fn matches(&self, s: String) -> bool {
-        self.eq(&Self::new(s))
+        self.matches_with(s, MatchOptions::default())
+    }
+
+    /// Whether the provided path matches this one; a path that matches but carries invalid
+    /// encodings still matches, so that it may be rejected by the route rather than skipped.
+    pub(crate) fn matches_with(&self, s: String, options: MatchOptions) -> bool {
+        match self.extract_with_raw(s, options) {
+            Ok(_) => true,
+            Err(Error::StatusCode(status, _)) => status == StatusCode::BAD_REQUEST,
+            Err(_) => false,
+        }
+    }
+}
+
+/// Resolve the `.` and `..` segments of a request path, as in RFC 3986 §5.2.4, so that
+/// `/static/../admin` is the path `/admin`. Segments that decode to dots count as dots. A path
+/// resolving to above the root is an error. Other segments, empty ones included, are untouched.
+pub(crate) fn normalize(path: &str) -> Result<String, String> {
+    let mut segments: Vec<&str> = Vec::new();
+    // a path ending in a dot segment names a directory: `/a/b/..` resolves to `/a/`.
+    let mut directory = false;
+
+    for segment in path.split('/').skip(1) {
+        directory = true;
+
+        match decode(segment).as_deref() {
+            Ok(".") => {}
+            Ok("..") => {
+                if segments.pop().is_none() {
+                    return Err("path escapes the root".to_string());
+                }
+            }
+            _ => {
+                segments.push(segment);
+                directory = false;
+            }
+        }
     }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if directory && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
+}
+
+// The provided path does not have the shape of the route: the client asked for something that
+// isn't there.
+fn mismatch(message: &str) -> Error {
+    Error::new_status(StatusCode::NOT_FOUND, message)
+}
+
+// Strictly percent-decode a path segment: every `%` must begin a valid escape and the result must
+// be UTF-8. Unlike in query strings, `+` is not a space in paths. This is the one decoding step
+// for paths, applied alike to request segments, parameter values and the literals of routes, so
+// that `/caf%C3%A9` and `/café` are the same path. Encoded reserved characters decode to
+// themselves within their segment: `a%2Fb` is the single segment `a/b`, never two segments.
+fn decode(segment: &str) -> Result<String, String> {
+    let bytes = segment.as_bytes();
+
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'%'
+            && !(bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+                && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit))
+        {
+            return Err(format!("invalid escape at offset {}", i));
+        }
+    }
+
+    percent_decode_str(segment)
+        .decode_utf8()
+        .map(|s| s.into_owned())
+        .map_err(|_| "escapes are not valid UTF-8".to_string())
 }
 
 impl PartialEq for Path {
+    #[allow(clippy::explicit_counter_loop)]
     fn eq(&self, other: &Self) -> bool {
         if other.0.len() != self.0.len() {
             return false;
@@ -121,10 +677,11 @@ impl PartialEq for Path {
         for arg in other.0.clone() {
             let res = match self.0[i].clone() {
                 RoutePart::PathComponent(_) => self.0[i] == arg,
-                RoutePart::Param(_param) => {
+                RoutePart::Param(_param, _) | RoutePart::Optional(_param, _) => {
                     // FIXME advanced parameter shit here later
                     true
                 }
+                RoutePart::Wildcard(_) => matches!(arg, RoutePart::Wildcard(_)),
                 RoutePart::Leader => {
                     if leader_seen {
                         false
@@ -148,10 +705,11 @@ impl PartialEq for Path {
 
 impl Default for Path {
     fn default() -> Self {
-        Self(vec![RoutePart::Leader])
+        Self(vec![RoutePart::Leader], false)
     }
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Path {
     fn to_string(&self) -> String {
         let mut s = Vec::new();
@@ -159,9 +717,21 @@ impl ToString for Path {
         for part in self.0.clone() {
             s.push(match part {
                 RoutePart::PathComponent(pc) => pc.to_string(),
-                RoutePart::Param(param) => {
+                RoutePart::Param(param, None) => {
                     format!(":{}", param)
                 }
+                RoutePart::Param(param, Some(constraint)) => {
+                    format!(":{}{{{}}}", param, constraint.pattern)
+                }
+                RoutePart::Optional(param, None) => {
+                    format!(":{}?", param)
+                }
+                RoutePart::Optional(param, Some(constraint)) => {
+                    format!(":{}{{{}}}?", param, constraint.pattern)
+                }
+                RoutePart::Wildcard(name) => {
+                    format!("*{}", name)
+                }
                 RoutePart::Leader => "".to_string(),
             });
         }
@@ -227,4 +797,437 @@ mod tests {
         let path = Path::new("/".to_string());
         assert!(path.matches("/".to_string()));
     }
+
+    #[test]
+    fn test_path_decoding() {
+        use super::{MatchOptions, Path};
+        use crate::Error;
+        use http::StatusCode;
+
+        let path = Path::new("/hello world/:name".to_string());
+
+        for (provided, name) in [
+            ("/hello%20world/J%C3%BCrgen", "Jürgen"),
+            ("/hello%20world/a%20b", "a b"),
+            ("/hello%20world/a+b", "a+b"),
+            ("/hello%20world/a%2Fb", "a/b"),
+        ] {
+            let (params, raw) = path
+                .extract_with_raw(provided.to_string(), MatchOptions::default())
+                .unwrap();
+            assert_eq!(params.get("name").unwrap(), name);
+            assert_eq!(
+                raw.get("name").unwrap(),
+                provided.trim_start_matches("/hello%20world/")
+            );
+        }
+
+        assert!(!path.matches("/hello%2world/a".to_string()));
+
+        for provided in [
+            "/hello%20world/%ZZ",
+            "/hello%20world/%F",
+            "/hello%20world/%FF",
+        ] {
+            assert!(path.matches(provided.to_string()), "{}", provided);
+            match path.extract(provided.to_string()) {
+                Err(Error::StatusCode(status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+                other => panic!("{}: unexpected {:?}", provided, other),
+            }
+        }
+
+        let path = Path::new("/static/*rest".to_string());
+        let (params, raw) = path
+            .extract_with_raw("/static/a%20b/c.css".to_string(), MatchOptions::default())
+            .unwrap();
+        assert_eq!(params.get("rest").unwrap(), "a b/c.css");
+        assert_eq!(raw.get("rest").unwrap(), "a%20b/c.css");
+    }
+
+    #[test]
+    fn test_path_case_insensitive() {
+        use super::{MatchOptions, Path};
+
+        let options = MatchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        let path = Path::new("/api/Users/:name".to_string());
+
+        assert!(!path.matches("/API/users/Erik".to_string()));
+        assert!(path.matches_with("/API/users/Erik".to_string(), options));
+        assert!(path.matches_with("/api/USERS/erik".to_string(), options));
+        assert!(!path.matches_with("/api/people/erik".to_string(), options));
+
+        let (params, _) = path
+            .extract_with_raw("/Api/uSeRs/ErIk".to_string(), options)
+            .unwrap();
+        assert_eq!(params.get("name").unwrap(), "ErIk");
+    }
+
+    #[test]
+    fn test_path_decode() {
+        use super::decode;
+
+        for (segment, expected) in [
+            ("plain", "plain"),
+            ("caf%C3%A9", "café"),
+            ("café", "café"),
+            ("a%20b", "a b"),
+            ("a+b", "a+b"),
+            ("a%2Fb", "a/b"),
+            ("a%2fb", "a/b"),
+            ("%3F%23", "?#"),
+            ("100%25", "100%"),
+        ] {
+            assert_eq!(decode(segment).unwrap(), expected, "{}", segment);
+        }
+
+        for segment in ["%", "%Z1", "a%2", "%C3", "%FF"] {
+            assert!(decode(segment).is_err(), "{}", segment);
+        }
+    }
+
+    #[test]
+    fn test_path_non_ascii_literals() {
+        use super::{MatchOptions, Path};
+        use crate::Error;
+        use http::StatusCode;
+
+        for template in ["/café/:item", "/caf%C3%A9/:item", "/caf%c3%a9/:item"] {
+            let path = Path::new(template.to_string());
+            assert_eq!(path.to_string(), "/café/:item");
+
+            for provided in ["/café/tea", "/caf%C3%A9/tea", "/caf%c3%a9/tea"] {
+                let params = path.extract(provided.to_string()).unwrap();
+                assert_eq!(
+                    params.get("item").unwrap(),
+                    "tea",
+                    "{} {}",
+                    template,
+                    provided
+                );
+            }
+
+            assert_eq!(path.url(&[("item", "tea")]).unwrap(), "/caf%C3%A9/tea");
+        }
+
+        let options = MatchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert!(Path::new("/Café".to_string()).matches_with("/CAF%C3%89".to_string(), options));
+
+        // an encoded slash in a literal is part of its segment.
+        let path = Path::new("/a%2Fb".to_string());
+        assert!(path.matches("/a%2Fb".to_string()));
+        assert!(!path.matches("/a/b".to_string()));
+        assert_eq!(path.url(&[]).unwrap(), "/a%2Fb");
+
+        // invalid encodings of literals fail to match, and in parameters are a bad request.
+        let path = Path::new("/café/:item".to_string());
+        assert!(!path.matches("/caf%C3/tea".to_string()));
+        match path.extract("/café/%C3".to_string()) {
+            Err(Error::StatusCode(status, _)) => assert_eq!(status, StatusCode::BAD_REQUEST),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert_eq!(
+            Path::check("/caf%C3/menu"),
+            Err("segment `caf%C3` is not validly encoded: escapes are not valid UTF-8".to_string())
+        );
+    }
+
+    #[test]
+    fn test_path_normalize() {
+        use super::normalize;
+
+        for (provided, expected) in [
+            ("/", "/"),
+            ("/a/b", "/a/b"),
+            ("/a/b/", "/a/b/"),
+            ("/a/./b", "/a/b"),
+            ("/a/b/.", "/a/b/"),
+            ("/static/../admin/secret", "/admin/secret"),
+            ("/a/b/..", "/a/"),
+            ("/a/..", "/"),
+            ("/a/b/../../c", "/c"),
+            ("/a/%2e%2E/b", "/b"),
+            ("/a/%2E/b", "/a/b"),
+            ("/a/.../b", "/a/.../b"),
+            ("/a/..b/c", "/a/..b/c"),
+            ("//a/../b", "//b"),
+        ] {
+            assert_eq!(normalize(provided).unwrap(), expected, "{}", provided);
+        }
+
+        for provided in ["/..", "/../a", "/a/../..", "/a/%2e%2e/%2e%2e/b"] {
+            assert_eq!(
+                normalize(provided),
+                Err("path escapes the root".to_string()),
+                "{}",
+                provided
+            );
+        }
+    }
+
+    #[test]
+    fn test_path_strict_slashes() {
+        use super::{MatchOptions, Path};
+
+        let strict = MatchOptions {
+            strict_slashes: true,
+            ..Default::default()
+        };
+
+        let account = Path::new("/account".to_string());
+        let dir = Path::new("/account/".to_string());
+        let files = Path::new("/files/*rest".to_string());
+
+        // by default, neither trailing nor duplicate slashes matter.
+        for path in [&account, &dir] {
+            for provided in ["/account", "/account/", "//account", "/account//"] {
+                assert!(
+                    path.matches(provided.to_string()),
+                    "{} {}",
+                    path.to_string(),
+                    provided
+                );
+            }
+        }
+
+        for (path, provided, expected) in [
+            (&account, "/account", true),
+            (&account, "/account/", false),
+            (&account, "//account", false),
+            (&dir, "/account/", true),
+            (&dir, "/account", false),
+            (&dir, "/account//", false),
+            (&files, "/files/a/b", true),
+            (&files, "/files/a/", true),
+            (&files, "/files/a//b", false),
+        ] {
+            assert_eq!(
+                path.matches_with(provided.to_string(), strict),
+                expected,
+                "{} {}",
+                path.to_string(),
+                provided
+            );
+        }
+
+        let root = Path::new("/".to_string());
+        assert!(root.matches_with("/".to_string(), strict));
+        assert!(!root.matches_with("//".to_string(), strict));
+    }
+
+    #[test]
+    fn test_path_url() {
+        use super::Path;
+
+        let path = Path::new("/users/:id{[0-9]+}/posts/:post?".to_string());
+        assert_eq!(path.url(&[("id", "42")]).unwrap(), "/users/42/posts");
+        assert_eq!(
+            path.url(&[("id", "42"), ("post", "hello-world")]).unwrap(),
+            "/users/42/posts/hello-world"
+        );
+
+        for (values, error) in [
+            (vec![], "missing value for parameter `id`"),
+            (vec![("id", "erik")], "does not satisfy its constraint"),
+            (vec![("id", "4 2")], "contains ' ', which must be encoded"),
+            (vec![("id", "42"), ("post", "a/b")], "contains '/'"),
+            (
+                vec![("id", "42"), ("nope", "x")],
+                "path has no parameter `nope`",
+            ),
+        ] {
+            let err = path.url(&values).unwrap_err();
+            assert!(err.contains(error), "{}", err);
+        }
+
+        let path = Path::new("/static/*rest".to_string());
+        assert_eq!(
+            path.url(&[("rest", "css/a.css")]).unwrap(),
+            "/static/css/a.css"
+        );
+        assert_eq!(path.url(&[]).unwrap(), "/static");
+
+        assert_eq!(Path::new("/".to_string()).url(&[]).unwrap(), "/");
+    }
+
+    #[test]
+    fn test_path_validate() {
+        use super::Path;
+
+        for good in ["/", "/abc", "/abc/:def", "/:a/:b/c/:d_e", "/a/:Param1/"] {
+            assert!(Path::new(good.to_string()).validate().is_ok(), "{}", good);
+        }
+
+        for good in ["/", "/abc/", "/a/:b", "/a/*rest"] {
+            assert_eq!(Path::check(good), Ok(()), "{}", good);
+        }
+
+        for (bad, message) in [
+            ("users/:id", "path must begin with `/`"),
+            ("", "path must begin with `/`"),
+            ("/a b", "path contains whitespace"),
+            ("/a/:id\t", "path contains whitespace"),
+            ("//a", "path contains an empty segment (`//`)"),
+            ("/a//b", "path contains an empty segment (`//`)"),
+        ] {
+            assert_eq!(Path::check(bad), Err(message.to_string()), "{}", bad);
+        }
+
+        for bad in [
+            "/a/:x/:x",
+            "/:x/b/:x",
+            "/a/:/b",
+            "/a/:",
+            "/a/:x-y",
+            "/a/:x.y",
+            "/a/:na me",
+        ] {
+            assert!(Path::new(bad.to_string()).validate().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_path_wildcard() {
+        use super::Path;
+        use crate::Params;
+
+        let path = Path::new("/static/*rest".to_string());
+        assert!(path.validate().is_ok());
+        assert_eq!(path.to_string(), "/static/*rest");
+        assert_eq!(path.params(), vec!["rest".to_string()]);
+
+        for (provided, rest) in [
+            ("/static", ""),
+            ("/static/", ""),
+            ("/static/site.css", "site.css"),
+            ("/static/css/site.css", "css/site.css"),
+            ("/static/a/b/c/", "a/b/c"),
+        ] {
+            assert!(path.matches(provided.to_string()), "{}", provided);
+
+            let mut params = Params::new();
+            params.insert("rest".to_string(), rest.to_string());
+            assert_eq!(path.extract(provided.to_string()).unwrap(), params);
+        }
+
+        assert!(!path.matches("/".to_string()));
+        assert!(!path.matches("/dynamic/site.css".to_string()));
+
+        let path = Path::new("/files/:user/*path".to_string());
+        let params = path.extract("/files/erik/a/b.txt".to_string()).unwrap();
+        assert_eq!(params.get("user").unwrap(), "erik");
+        assert_eq!(params.get("path").unwrap(), "a/b.txt");
+        assert!(!path.matches("/files".to_string()));
+
+        assert!(Path::new("/static/*rest/more".to_string())
+            .validate()
+            .is_err());
+        assert!(Path::new("/static/*".to_string()).validate().is_err());
+        assert!(Path::new("/:rest/*rest".to_string()).validate().is_err());
+    }
+
+    #[test]
+    fn test_path_constraints() {
+        use super::Path;
+
+        let path = Path::new("/users/:id{[0-9]+}".to_string());
+        assert!(path.validate().is_ok());
+        assert_eq!(path.to_string(), "/users/:id{[0-9]+}");
+        assert_eq!(path.params(), vec!["id".to_string()]);
+
+        assert!(path.matches("/users/42".to_string()));
+        assert!(!path.matches("/users/erik".to_string()));
+        assert!(!path.matches("/users/42abc".to_string()));
+        assert!(!path.matches("/users/abc42".to_string()));
+        assert_eq!(
+            path.extract("/users/42".to_string()).unwrap().get("id"),
+            Some(&"42".to_string())
+        );
+        assert!(path.extract("/users/erik".to_string()).is_err());
+
+        let path = Path::new("/posts/:year{[0-9]{4}}/:slug{[a-z-]+}".to_string());
+        assert!(path.validate().is_ok());
+        assert!(path.matches("/posts/2022/hello-world".to_string()));
+        assert!(!path.matches("/posts/22/hello-world".to_string()));
+        assert!(!path.matches("/posts/2022/Hello".to_string()));
+        assert_eq!(
+            path.to_string(),
+            "/posts/:year{[0-9]{4}}/:slug{[a-z-]+}".to_string()
+        );
+
+        assert!(Path::new("/users/:id{[0-9+}".to_string())
+            .validate()
+            .is_err());
+        assert!(Path::new("/users/:{[0-9]+}".to_string())
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_path_optional() {
+        use super::Path;
+
+        let path = Path::new("/api/items/:id?".to_string());
+        assert!(path.validate().is_ok());
+        assert_eq!(path.to_string(), "/api/items/:id?");
+        assert_eq!(path.params(), vec!["id".to_string()]);
+
+        for provided in ["/api/items", "/api/items/"] {
+            assert!(path.matches(provided.to_string()));
+            assert!(path.extract(provided.to_string()).unwrap().is_empty());
+        }
+
+        assert!(path.matches("/api/items/42".to_string()));
+        assert_eq!(
+            path.extract("/api/items/42".to_string()).unwrap().get("id"),
+            Some(&"42".to_string())
+        );
+        assert!(!path.matches("/api/items/42/43".to_string()));
+        assert!(!path.matches("/api".to_string()));
+
+        let path = Path::new("/archive/:year{[0-9]{4}}?/:month?".to_string());
+        assert!(path.validate().is_ok());
+        assert_eq!(path.to_string(), "/archive/:year{[0-9]{4}}?/:month?");
+        assert!(path.matches("/archive".to_string()));
+        assert!(path.matches("/archive/2022".to_string()));
+        assert!(path.matches("/archive/2022/04".to_string()));
+        assert!(!path.matches("/archive/april".to_string()));
+
+        assert!(Path::new("/api/:id?/items".to_string()).validate().is_err());
+        assert!(Path::new("/api/:id?/:name".to_string()).validate().is_err());
+        assert!(Path::new("/api/:id?/*rest".to_string()).validate().is_err());
+    }
+
+    #[test]
+    fn test_path_equivalent() {
+        use super::Path;
+
+        for (a, b, equivalent) in [
+            ("/users/:id", "/users/:user", true),
+            ("/users/:id", "/users/new", false),
+            ("/users/new", "/users/:id", false),
+            ("/users/:id{[0-9]+}", "/users/:slug", false),
+            ("/users/:id{[0-9]+}", "/users/:n{[0-9]+}", true),
+            ("/users/:id?", "/users/:id", false),
+            ("/files/*path", "/files/*rest", true),
+            ("/files/*path", "/files/:name", false),
+            ("/users/new", "/users/new", true),
+            ("/users/new", "/users/old", false),
+        ] {
+            let (a, b) = (Path::new(a.to_string()), Path::new(b.to_string()));
+            assert_eq!(
+                a.equivalent(&b),
+                equivalent,
+                "{} {}",
+                a.to_string(),
+                b.to_string()
+            );
+        }
+    }
 }
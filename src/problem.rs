@@ -0,0 +1,312 @@
+use http::{header, HeaderMap, HeaderValue, Response, StatusCode};
+use hyper::Body;
+
+use crate::Error;
+
+// the members of a problem defined by RFC 9457, which extensions may not be named.
+#[cfg(feature = "serde")]
+const MEMBERS: [&str; 5] = ["type", "title", "status", "detail", "instance"];
+
+/// Problem is an error in the `application/problem+json` format of RFC 9457 (formerly RFC 7807),
+/// which API clients can parse the same way whatever went wrong. It converts into an [Error], so
+/// handlers can return it as they would any other:
+///
+/// ```ignore
+///     return Err(Problem::new(StatusCode::FORBIDDEN)
+///         .type_uri("https://example.com/probs/out-of-credit")
+///         .title("You do not have enough credit.")
+///         .detail("Your current balance is 30, but that costs 50.")
+///         .instance(req.uri().path())
+///         .extension("balance", &30)?
+///         .into());
+/// ```
+///
+/// The App renders it as `application/problem+json` with its status. Other errors are rendered as
+/// problems too for clients that accept them, with [crate::app::App::enable_problem_json].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    status: StatusCode,
+    type_uri: Option<String>,
+    title: Option<String>,
+    detail: Option<String>,
+    instance: Option<String>,
+    // extension members by name, each with its value already encoded as JSON.
+    extensions: Vec<(String, String)>,
+}
+
+impl Problem {
+    /// A problem with the status, whose type is `about:blank` and whose title is the status's
+    /// reason phrase until they are given.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            type_uri: None,
+            title: None,
+            detail: None,
+            instance: None,
+            extensions: Vec::new(),
+        }
+    }
+
+    /// A URI reference identifying the type of the problem, which may link to its documentation.
+    pub fn type_uri(mut self, type_uri: &str) -> Self {
+        self.type_uri = Some(type_uri.to_string());
+        self
+    }
+
+    /// A short summary of the type of the problem, which should be the same for every occurrence.
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    /// An explanation specific to this occurrence of the problem.
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// A URI reference identifying this occurrence of the problem, such as the request's path.
+    pub fn instance(mut self, instance: &str) -> Self {
+        self.instance = Some(instance.to_string());
+        self
+    }
+
+    /// Add an extension member, whose value is serialized to JSON, replacing any of the same name.
+    /// Fails if the name is one of the members the RFC defines, or the value cannot be serialized.
+    /// Available with the `serde` feature.
+    #[cfg(feature = "serde")]
+    pub fn extension<V>(mut self, name: &str, value: &V) -> Result<Self, Error>
+    where
+        V: serde::Serialize + ?Sized,
+    {
+        if MEMBERS.contains(&name) {
+            return Err(Error::new(format!(
+                "problem extension `{}` would replace a member of the problem",
+                name
+            )));
+        }
+
+        let value = serde_json::to_string(value)?;
+        match self
+            .extensions
+            .iter_mut()
+            .find(|(existing, _)| existing == name)
+        {
+            Some(existing) => existing.1 = value,
+            None => self.extensions.push((name.to_string(), value)),
+        }
+
+        Ok(self)
+    }
+
+    /// The status of the problem.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The detail of the problem if it has one, or else its title.
+    pub fn message(&self) -> String {
+        match (&self.detail, &self.title) {
+            (Some(detail), _) => detail.clone(),
+            (None, Some(title)) => title.clone(),
+            (None, None) => self.default_title().to_string(),
+        }
+    }
+
+    /// The problem as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut members = vec![
+            (
+                "type",
+                json_string(self.type_uri.as_deref().unwrap_or("about:blank")),
+            ),
+            (
+                "title",
+                json_string(self.title.as_deref().unwrap_or(self.default_title())),
+            ),
+            ("status", self.status.as_u16().to_string()),
+        ];
+
+        if let Some(detail) = &self.detail {
+            members.push(("detail", json_string(detail)));
+        }
+
+        if let Some(instance) = &self.instance {
+            members.push(("instance", json_string(instance)));
+        }
+
+        let members = members
+            .into_iter()
+            .chain(
+                self.extensions
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.clone())),
+            )
+            .map(|(name, value)| format!("{}:{}", json_string(name), value))
+            .collect::<Vec<_>>();
+
+        format!("{{{}}}", members.join(","))
+    }
+
+    /// The response rendering the problem, with its status and the `application/problem+json`
+    /// content type.
+    pub fn to_response(&self) -> Response<Body> {
+        let json = self.to_json();
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = self.status;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(json.len()));
+        *response.body_mut() = Body::from(json);
+        response
+    }
+
+    fn default_title(&self) -> &'static str {
+        self.status.canonical_reason().unwrap_or("Unknown Status")
+    }
+}
+
+impl From<Problem> for Error {
+    fn from(problem: Problem) -> Self {
+        Self::Problem(Box::new(problem))
+    }
+}
+
+// whether a client sending these headers would rather have a problem than plain text.
+pub(crate) fn accepted(headers: &HeaderMap) -> bool {
+    matches!(
+        crate::negotiate::best_in(
+            headers,
+            &["text/plain", "application/problem+json", "application/json"]
+        ),
+        Some("application/problem+json" | "application/json")
+    )
+}
+
+// the error as a problem: a problem as it is, and other errors as the status they carry, detailed
+// by their message if they have one.
+pub(crate) fn from_error(e: Error) -> Problem {
+    let (status, message) = match e {
+        Error::Problem(problem) => return *problem,
+        Error::StatusCode(status, message) => (status, message),
+        Error::InternalServerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
+    };
+
+    let problem = Problem::new(status);
+    match message.is_empty() {
+        true => problem,
+        false => problem.detail(&message),
+    }
+}
+
+// the string as a JSON string, quoted and escaped.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+mod tests {
+    #[test]
+    fn test_problem() {
+        use super::{from_error, Problem};
+        use crate::Error;
+        use http::StatusCode;
+
+        let problem = Problem::new(StatusCode::NOT_FOUND);
+        assert_eq!(
+            problem.to_json(),
+            r#"{"type":"about:blank","title":"Not Found","status":404}"#
+        );
+        assert_eq!(problem.message(), "Not Found");
+
+        let problem = Problem::new(StatusCode::FORBIDDEN)
+            .type_uri("https://example.com/probs/out-of-credit")
+            .title("You do not have enough credit.")
+            .detail("Your \"balance\" is 30,\nbut that costs 50.\u{1}")
+            .instance("/account/12345/msgs/abc");
+        assert_eq!(
+            problem.to_json(),
+            r#"{"type":"https://example.com/probs/out-of-credit","title":"You do not have enough credit.","status":403,"detail":"Your \"balance\" is 30,\nbut that costs 50.\u0001","instance":"/account/12345/msgs/abc"}"#
+        );
+        assert_eq!(
+            problem.message(),
+            "Your \"balance\" is 30,\nbut that costs 50.\u{1}"
+        );
+
+        // problems survive the trip through an Error, and other errors become problems.
+        let e: Error = problem.clone().into();
+        assert_eq!(e.status(), StatusCode::FORBIDDEN);
+        assert_eq!(from_error(e), problem);
+        assert_eq!(
+            from_error(Error::new_status(StatusCode::CONFLICT, "taken")),
+            Problem::new(StatusCode::CONFLICT).detail("taken")
+        );
+        assert_eq!(
+            from_error(Error::new_status(StatusCode::CONFLICT, "")),
+            Problem::new(StatusCode::CONFLICT)
+        );
+
+        let res = Problem::new(StatusCode::GONE).to_response();
+        assert_eq!(res.status(), StatusCode::GONE);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/problem+json"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_problem_extensions() {
+        use super::Problem;
+        use http::StatusCode;
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Account {
+            balance: u32,
+            accounts: Vec<&'static str>,
+        }
+
+        let problem = Problem::new(StatusCode::FORBIDDEN)
+            .extension("balance", &20)
+            .unwrap()
+            .extension(
+                "account",
+                &Account {
+                    balance: 30,
+                    accounts: vec!["/account/12345", "/account/67890"],
+                },
+            )
+            .unwrap()
+            .extension("balance", &30)
+            .unwrap();
+        assert_eq!(
+            problem.to_json(),
+            r#"{"type":"about:blank","title":"Forbidden","status":403,"balance":30,"account":{"balance":30,"accounts":["/account/12345","/account/67890"]}}"#
+        );
+
+        assert!(Problem::new(StatusCode::FORBIDDEN)
+            .extension("status", &500)
+            .is_err());
+    }
+}
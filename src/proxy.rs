@@ -0,0 +1,320 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    uri::{PathAndQuery, Scheme},
+    Request, StatusCode, Uri,
+};
+use hyper::{client::HttpConnector, Body, Client};
+
+use crate::{handler::Handler, raw_params, Error, TransientState};
+
+// Headers describing a single connection, which a proxy must not forward; see RFC 9110 §7.6.1.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Proxy forwards requests to an upstream HTTP server, streaming bodies in both directions. Use
+/// [to] for the defaults, or configure one and turn it into a handler with [Proxy::handler]:
+///
+/// ```ignore
+///     app.get_prefix(
+///         "/upstream",
+///         Proxy::new("http://127.0.0.1:8080")
+///             .timeout(Duration::from_secs(5))
+///             .handler(),
+///     );
+/// ```
+///
+/// Mounted on a prefix route (see [crate::app::App::get_prefix]), the remainder of the path is
+/// appended to the path of the upstream URL; otherwise the whole request path is. The query string
+/// is forwarded as is. The Host header is that of the upstream; the original is passed on as
+/// `X-Forwarded-Host`, alongside `X-Forwarded-For` and `X-Forwarded-Proto`.
+///
+/// If the upstream cannot be reached a 502 Bad Gateway is returned, and if it does not respond
+/// within the timeout a 504 Gateway Timeout.
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    upstream: Uri,
+    timeout: Duration,
+}
+
+impl Proxy {
+    /// Proxy to the upstream URL, which must be an `http://` URL. Panics if it is not, so that
+    /// misconfigured proxies fail at startup rather than at request time.
+    pub fn new(upstream: &str) -> Self {
+        let uri: Uri = match upstream.parse() {
+            Ok(uri) => uri,
+            Err(e) => panic!("invalid proxy upstream `{}`: {}", upstream, e),
+        };
+
+        if uri.scheme() != Some(&Scheme::HTTP) || uri.authority().is_none() {
+            panic!(
+                "invalid proxy upstream `{}`: must be an http:// URL",
+                upstream
+            );
+        }
+
+        Self {
+            upstream: uri,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Limit how long to wait for the upstream to respond, from sending the request to receiving
+    /// the head of the response. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Build the handler performing the proxying. It responds with the upstream's response, which
+    /// stages chained after it, such as [crate::etag::handler], see and may change.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let proxy = Arc::new(self);
+        let client: Client<HttpConnector, Body> = Client::new();
+
+        Handler::from_fn(move |mut req, _resp, _params, _app, state| {
+            let proxy = proxy.clone();
+            let client = client.clone();
+
+            async move {
+                let upstream = proxy.request(&mut req)?;
+                let response =
+                    match tokio::time::timeout(proxy.timeout, client.request(upstream)).await {
+                        Ok(Ok(response)) => response,
+                        Ok(Err(e)) => {
+                            return Err(Error::StatusCode(
+                                StatusCode::BAD_GATEWAY,
+                                format!("upstream request failed: {}", e),
+                            ))
+                        }
+                        Err(_) => {
+                            return Err(Error::StatusCode(
+                                StatusCode::GATEWAY_TIMEOUT,
+                                "upstream timed out".to_string(),
+                            ))
+                        }
+                    };
+
+                let (mut parts, body) = response.into_parts();
+                strip_hop_by_hop(&mut parts.headers);
+                Ok((req, Some(http::Response::from_parts(parts, body)), state))
+            }
+        })
+        .named("proxy")
+    }
+
+    // build the upstream request, taking the body of the incoming one.
+    fn request(&self, req: &mut Request<Body>) -> Result<Request<Body>, Error> {
+        let base = self.upstream.path().trim_end_matches('/');
+        let path = match raw_params(req).and_then(|params| params.get("suffix")) {
+            Some(suffix) => format!("{}/{}", base, suffix),
+            None => format!("{}{}", base, req.uri().path()),
+        };
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        let mut uri = self.upstream.clone().into_parts();
+        uri.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+        let uri = Uri::from_parts(uri)?;
+
+        let mut headers = req.headers().clone();
+        strip_hop_by_hop(&mut headers);
+
+        if let Some(host) = headers.remove(header::HOST) {
+            headers.insert(HeaderName::from_static("x-forwarded-host"), host);
+        }
+
+        if let Some(ip) = req.extensions().get::<IpAddr>() {
+            let forwarded = match headers.get("x-forwarded-for").map(HeaderValue::to_str) {
+                Some(Ok(prior)) => format!("{}, {}", prior, ip),
+                _ => ip.to_string(),
+            };
+            headers.insert(
+                HeaderName::from_static("x-forwarded-for"),
+                forwarded.parse()?,
+            );
+        }
+
+        let proto = req
+            .extensions()
+            .get::<Scheme>()
+            .or(req.uri().scheme())
+            .unwrap_or(&Scheme::HTTP);
+        headers.insert(
+            HeaderName::from_static("x-forwarded-proto"),
+            proto.as_str().parse()?,
+        );
+
+        let mut upstream = Request::builder()
+            .method(req.method().clone())
+            .uri(uri)
+            .body(std::mem::replace(req.body_mut(), Body::empty()))?;
+        *upstream.headers_mut() = headers;
+        Ok(upstream)
+    }
+}
+
+/// Proxy to the upstream URL with the default settings. See [Proxy].
+pub fn to<S, T>(upstream: &str) -> Handler<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    Proxy::new(upstream).handler()
+}
+
+// remove the hop-by-hop headers, including any the Connection header names.
+pub(crate) fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for name in HOP_BY_HOP
+        .iter()
+        .copied()
+        .chain(named.iter().map(String::as_str))
+    {
+        headers.remove(name);
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_proxy() {
+        use super::Proxy;
+        use crate::{
+            app::{App, TestApp},
+            NoState,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::{
+            service::{make_service_fn, service_fn},
+            Body, Server,
+        };
+        use std::{convert::Infallible, net::IpAddr, time::Duration};
+
+        // describes the request it was sent, and echoes its body.
+        async fn upstream(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+            if req.uri().path() == "/api/slow" {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+
+            let mut summary = format!("{} {}", req.method(), req.uri());
+            for name in [
+                "host",
+                "x-forwarded-host",
+                "x-forwarded-for",
+                "x-forwarded-proto",
+                "x-custom",
+                "x-private",
+            ] {
+                if let Some(value) = req.headers().get(name) {
+                    summary += &format!("\n{}: {}", name, value.to_str().unwrap());
+                }
+            }
+
+            let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            summary += &format!("\n\n{}", String::from_utf8_lossy(&body));
+
+            Ok(Response::builder()
+                .header("x-upstream", "1")
+                .header("keep-alive", "timeout=5")
+                .body(Body::from(summary))
+                .unwrap())
+        }
+
+        let server =
+            Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service_fn(|_| async {
+                Ok::<_, Infallible>(service_fn(upstream))
+            }));
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut app: App<(), NoState> = App::new();
+        app.get_prefix(
+            "/upstream",
+            Proxy::new(&format!("http://{}/api/", addr))
+                .timeout(Duration::from_millis(50))
+                .handler(),
+        );
+        app.post("/echo", super::to(&format!("http://{}", addr)));
+        app.get(
+            "/tagged",
+            super::to(&format!("http://{}", addr)).then(crate::etag::handler(1024)),
+        );
+        // nothing listens on the discard port.
+        app.get("/down", super::to("http://127.0.0.1:9"));
+        let app = TestApp::new(app);
+
+        let mut req = Request::builder()
+            .uri("/upstream/users/a%20b?page=2")
+            .header("host", "front.example.com")
+            .header("x-custom", "kept")
+            .header("connection", "x-private")
+            .header("x-private", "dropped")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert("10.0.0.1".parse::<IpAddr>().unwrap());
+
+        let mut res = app.dispatch(req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("x-upstream").unwrap(), "1");
+        assert!(res.headers().get("keep-alive").is_none());
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&body),
+            format!(
+                "GET /api/users/a%20b?page=2\nhost: {}\nx-forwarded-host: front.example.com\n\
+                 x-forwarded-for: 10.0.0.1\nx-forwarded-proto: http\nx-custom: kept\n\n",
+                addr
+            )
+        );
+
+        let mut res = app.post("/echo", Body::from("hello")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).starts_with("POST /echo\n"));
+        assert!(String::from_utf8_lossy(&body).ends_with("\n\nhello"));
+
+        // stages after the proxy see its response.
+        let res = app.get("/tagged").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key("etag"));
+
+        assert_eq!(app.get("/down").await.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            app.get("/upstream/slow").await.status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid proxy upstream `https://example.com`: must be an http:// URL"
+    )]
+    fn test_proxy_rejects_https() {
+        super::Proxy::new("https://example.com");
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+
+use percent_encoding::percent_decode_str;
+
+/// Query is the parsed query string of a request: a mapping of name -> values, as names may be
+/// repeated (`?tag=a&tag=b`). Names without a value (`?flag`) map to an empty string. It is parsed
+/// once per request by the router and may be retrieved from handlers with [crate::query].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query(BTreeMap<String, Vec<String>>);
+
+// `+` is a space in application/x-www-form-urlencoded, so it must be replaced before decoding, or
+// an encoded `%2B` would become a space too.
+fn decode(s: &str) -> String {
+    percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .to_string()
+}
+
+impl Query {
+    /// Parse a raw query string, without the leading `?`.
+    pub fn parse(query: &str) -> Self {
+        let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for pair in query.split('&') {
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (decode(key), decode(value)),
+                None => (decode(pair), String::new()),
+            };
+
+            map.entry(key).or_default().push(value);
+        }
+
+        Self(map)
+    }
+
+    /// Return the first value for the name, if it was provided.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .get(key)
+            .and_then(|values| values.first())
+            .map(|s| s.as_str())
+    }
+
+    /// Return all values for the name, in the order they were provided.
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.0
+            .get(key)
+            .map(|values| values.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Whether the name was provided at all, with or without a value.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    /// Whether the query string was empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate the names and their values, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.0.iter()
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_query_parse() {
+        use super::Query;
+
+        let query =
+            Query::parse("page=2&sort=asc&tag=a&tag=b+c&flag&empty=&name=J%C3%BCrgen&plus=%2B");
+        assert_eq!(query.get("page"), Some("2"));
+        assert_eq!(query.get("sort"), Some("asc"));
+        assert_eq!(query.get("tag"), Some("a"));
+        assert_eq!(query.get_all("tag"), &["a".to_string(), "b c".to_string()]);
+        assert!(query.contains_key("flag"));
+        assert_eq!(query.get("flag"), Some(""));
+        assert_eq!(query.get("empty"), Some(""));
+        assert_eq!(query.get("name"), Some("Jürgen"));
+        assert_eq!(query.get("plus"), Some("+"));
+        assert_eq!(query.get("missing"), None);
+        assert!(query.get_all("missing").is_empty());
+
+        assert!(Query::parse("").is_empty());
+        assert!(Query::parse("&&").is_empty());
+        assert_eq!(Query::parse("a%20b=c%3Dd").get("a b"), Some("c=d"));
+    }
+}
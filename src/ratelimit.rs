@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use http::{header, Request, Response, StatusCode};
+use hyper::Body;
+
+use crate::{handler::Handler, TransientState};
+
+type KeyFunc = Arc<dyn Fn(&Request<Body>) -> Option<String> + Send + Sync>;
+
+// A client's bucket: the tokens it held when last seen.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// The buckets of every client seen recently.
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    swept: Instant,
+}
+
+/// RateLimit limits how often each client may make requests, with a token bucket per client: a
+/// client may make `burst` requests at once, and its bucket then refills at `per_second` requests
+/// a second. Requests from a client with an empty bucket are answered with a 429 Too Many
+/// Requests, and a `Retry-After` header saying when it may try again.
+///
+/// ```ignore
+///     let limit = RateLimit::new(5.0, 20).handler();
+///     app.post("/login", limit.clone().then(compose_handler!(login)));
+///     app.post("/signup", limit.then(compose_handler!(signup)));
+/// ```
+///
+/// Clients are told apart by [crate::peer_ip] unless a [RateLimit::key] function is given. Behind
+/// a proxy the peer is always the proxy, so key on the header it adds instead:
+///
+/// ```ignore
+///     RateLimit::new(5.0, 20).key(|req| {
+///         let forwarded = req.headers().get("x-forwarded-for")?.to_str().ok()?;
+///         Some(forwarded.split(',').next_back()?.trim().to_string())
+///     })
+/// ```
+///
+/// The buckets live in the handler built by [RateLimit::handler], not in the App's state, so
+/// that limiting does not depend on the App's state type. Clones of the handler share them: a
+/// handler used for several routes limits requests to all of them together, while handlers built
+/// separately keep separate counts. Buckets that have refilled are forgotten every minute or so,
+/// so clients that stop making requests take no memory.
+#[derive(Clone)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: u32,
+    key: KeyFunc,
+}
+
+impl RateLimit {
+    /// Allow each client `burst` requests at once, refilling at `per_second` requests a second.
+    /// Panics if either is not above zero, as no request could then be allowed.
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        if per_second.is_nan() || per_second <= 0.0 || burst == 0 {
+            panic!("rate limit must allow requests: rate and burst must be above zero");
+        }
+
+        Self {
+            per_second,
+            burst,
+            key: Arc::new(|req| crate::peer_ip(req).map(|ip| ip.to_string())),
+        }
+    }
+
+    /// Tell clients apart by the key the function returns for their requests. Requests it returns
+    /// None for are not limited, so it can also exempt clients, such as health checks.
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Request<Body>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Build the handler performing the limiting, which leads the chains it limits.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let limit = Arc::new(self);
+        let buckets = Arc::new(Mutex::new(Buckets {
+            buckets: HashMap::new(),
+            swept: Instant::now(),
+        }));
+
+        Handler::around(
+            Arc::new(move |req, resp, params, app, state, next| {
+                let wait = (limit.key)(&req).and_then(|key| {
+                    let mut buckets = buckets.lock().unwrap_or_else(PoisonError::into_inner);
+                    limit.take(&mut buckets, key, Instant::now())
+                });
+
+                Box::pin(async move {
+                    if let Some(wait) = wait {
+                        let res = Response::builder()
+                            .status(StatusCode::TOO_MANY_REQUESTS)
+                            .header(header::RETRY_AFTER, wait.as_secs())
+                            .body(Body::from("too many requests"))?;
+                        return Ok((req, Some(res), state));
+                    }
+
+                    match next {
+                        Some(next) => next.perform(req, resp, params, app, state).await,
+                        None => Ok((req, resp, state)),
+                    }
+                })
+            }),
+            None,
+        )
+        .named("rate_limit")
+    }
+
+    // take a token from the client's bucket, returning how long to wait (rounded up to the
+    // second, as Retry-After expects) if there is none.
+    fn take(&self, buckets: &mut Buckets, key: String, now: Instant) -> Option<Duration> {
+        let burst = self.burst as f64;
+
+        if now.duration_since(buckets.swept) >= Duration::from_secs(60) {
+            buckets
+                .buckets
+                .retain(|_, bucket| self.refill(bucket, now) < burst);
+            buckets.swept = now;
+        }
+
+        let bucket = buckets.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return None;
+        }
+
+        let wait = ((1.0 - bucket.tokens) / self.per_second).ceil();
+        Some(Duration::from_secs(wait.max(1.0) as u64))
+    }
+
+    // the tokens in the bucket at `now`.
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second).min(self.burst as f64)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_rate_limit_buckets() {
+        use super::{Buckets, RateLimit};
+        use std::{
+            collections::HashMap,
+            time::{Duration, Instant},
+        };
+
+        let start = Instant::now();
+        let limit = RateLimit::new(0.5, 2);
+        let mut buckets = Buckets {
+            buckets: HashMap::new(),
+            swept: start,
+        };
+        let mut take = |key: &str, after: u64| {
+            limit.take(
+                &mut buckets,
+                key.to_string(),
+                start + Duration::from_secs(after),
+            )
+        };
+
+        assert_eq!(take("a", 0), None);
+        assert_eq!(take("a", 0), None);
+        assert_eq!(take("a", 0), Some(Duration::from_secs(2)));
+        // other clients have buckets of their own.
+        assert_eq!(take("b", 0), None);
+        // a token every two seconds.
+        assert_eq!(take("a", 1), Some(Duration::from_secs(1)));
+        assert_eq!(take("a", 2), None);
+        assert_eq!(take("a", 2), Some(Duration::from_secs(2)));
+        // the bucket holds no more than the burst.
+        assert_eq!(take("a", 100), None);
+        assert_eq!(take("a", 100), None);
+        assert_eq!(take("a", 100), Some(Duration::from_secs(2)));
+
+        // buckets that have refilled are forgotten once a minute has passed since the last sweep,
+        // which was at 100 seconds.
+        assert_eq!(take("b", 150), None);
+        assert_eq!(take("c", 150), None);
+        assert_eq!(take("c", 150), None);
+        assert_eq!(take("b", 170), None);
+        assert!(buckets.buckets.contains_key("b"));
+        assert!(!buckets.buckets.contains_key("a"));
+        assert!(!buckets.buckets.contains_key("c"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        use super::RateLimit;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn hello(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("hello"))), state))
+        }
+
+        let limit = RateLimit::new(0.01, 2)
+            .key(|req| {
+                let client = req.headers().get("x-client")?.to_str().ok()?;
+                Some(client.to_string())
+            })
+            .handler();
+        let mut app = App::new();
+        app.get("/a", limit.clone().then(compose_handler!(hello)));
+        app.get("/b", limit.then(compose_handler!(hello)));
+        let app = TestApp::new(app);
+
+        // routes sharing the handler share the buckets.
+        assert_eq!(
+            app.get_with("/a", &[("x-client", "alice")]).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            app.get_with("/b", &[("x-client", "alice")]).await.status(),
+            StatusCode::OK
+        );
+        let res = app.get_with("/a", &[("x-client", "alice")]).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("retry-after").unwrap(), "100");
+
+        assert_eq!(
+            app.get_with("/a", &[("x-client", "bob")]).await.status(),
+            StatusCode::OK
+        );
+        for _ in 0..5 {
+            assert_eq!(app.get("/a").await.status(), StatusCode::OK);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "rate limit must allow requests: rate and burst must be above zero")]
+    fn test_rate_limit_rejects_zero_burst() {
+        super::RateLimit::new(1.0, 0);
+    }
+}
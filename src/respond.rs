@@ -0,0 +1,553 @@
+use std::future::Future;
+
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use hyper::{
+    body::{Bytes, HttpBody},
+    Body,
+};
+
+use crate::Error;
+
+// Aggregate the body, failing once more than max bytes have been seen.
+async fn buffer(body: &mut Body, max: usize) -> Result<Bytes, Error> {
+    let mut buf = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if buf.len() + chunk.len() > max {
+            return Err(Error::new(format!(
+                "response body exceeds buffer limit of {} bytes",
+                max
+            )));
+        }
+
+        buf.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(buf))
+}
+
+fn replace_body(mut response: Response<Body>, bytes: Bytes) -> Response<Body> {
+    // a response using Transfer-Encoding must not also carry a Content-Length, so it is only
+    // recomputed for responses that are not using one.
+    if !response.headers().contains_key(header::TRANSFER_ENCODING) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    }
+
+    *response.body_mut() = Body::from(bytes);
+    response
+}
+
+/// Transform the body of a response, preserving its status and headers. The body is buffered in
+/// full before the transform is applied; if it is larger than `max_buffer` bytes an error is
+/// returned instead. `Content-Length` is recomputed for the new body unless the response uses
+/// `Transfer-Encoding`, which is left alone.
+///
+/// This is intended for handlers late in a chain that post-process the response of an earlier
+/// handler:
+///
+/// ```ignore
+///     let response = map_body(response.unwrap(), 1 << 20, |body| {
+///         Bytes::from(format!("{{\"data\": {}}}", String::from_utf8_lossy(&body)))
+///     })
+///     .await?;
+/// ```
+pub async fn map_body<F>(
+    mut response: Response<Body>,
+    max_buffer: usize,
+    f: F,
+) -> Result<Response<Body>, Error>
+where
+    F: FnOnce(Bytes) -> Bytes,
+{
+    let bytes = buffer(response.body_mut(), max_buffer).await?;
+    Ok(replace_body(response, f(bytes)))
+}
+
+/// The asynchronous variant of [map_body], for transforms that need to await something, such as
+/// another service or a file.
+pub async fn map_body_async<F, Fut>(
+    mut response: Response<Body>,
+    max_buffer: usize,
+    f: F,
+) -> Result<Response<Body>, Error>
+where
+    F: FnOnce(Bytes) -> Fut,
+    Fut: Future<Output = Result<Bytes, Error>>,
+{
+    let bytes = buffer(response.body_mut(), max_buffer).await?;
+    Ok(replace_body(response, f(bytes).await?))
+}
+
+/// A plain text response with the status, its content type `text/plain; charset=utf-8`:
+///
+/// ```ignore
+///     return Ok((req, Some(respond::text(StatusCode::OK, format!("hello, {}!\n", name))), state));
+/// ```
+pub fn text(status: StatusCode, body: impl Into<String>) -> Response<Body> {
+    with_type(status, "text/plain; charset=utf-8", body.into().into())
+}
+
+/// An HTML response with the status, its content type `text/html; charset=utf-8`. The body is
+/// sent as it is given, so text from clients must be escaped before it is included.
+pub fn html(status: StatusCode, body: impl Into<String>) -> Response<Body> {
+    with_type(status, "text/html; charset=utf-8", body.into().into())
+}
+
+/// A response with the status, content type and body, such as an image. The content type must be
+/// a valid header value, which as it is static is checked with a panic; text types should name
+/// their charset.
+pub fn bytes(
+    status: StatusCode,
+    content_type: &'static str,
+    body: impl Into<Bytes>,
+) -> Response<Body> {
+    with_type(status, content_type, body.into())
+}
+
+fn with_type(status: StatusCode, content_type: &'static str, body: Bytes) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    replace_body(response, body)
+}
+
+/// Responder is anything a handler can answer with, turned into a response by
+/// [Responder::into_response]. It spares terminal handlers lifted with
+/// [crate::handler::Handler::from_responder] building responses by hand:
+///
+/// ```ignore
+///     async fn hello(
+///         _req: Request<Body>,
+///         params: Params,
+///         _app: App<(), NoState>,
+///         _state: NoState,
+///     ) -> Result<impl Responder, Error> {
+///         Ok(format!("hello, {}!\n", params["name"]))
+///     }
+///
+///     app.get("/:name", Handler::from_responder(hello));
+/// ```
+///
+/// Strings are answered as plain text with a 200 OK, a status alone with an empty body, and a
+/// status paired with any other responder replaces its status. With the `serde` feature, [Json]
+/// answers with a value serialized to JSON.
+pub trait Responder {
+    /// The response, or the error to answer with instead.
+    fn into_response(self) -> Result<Response<Body>, Error>;
+}
+
+impl Responder for Response<Body> {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        Ok(self)
+    }
+}
+
+impl Responder for String {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        Ok(text(StatusCode::OK, self))
+    }
+}
+
+impl Responder for &'static str {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        Ok(text(StatusCode::OK, self))
+    }
+}
+
+impl Responder for StatusCode {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        let mut response = replace_body(Response::new(Body::empty()), Bytes::new());
+        *response.status_mut() = self;
+        Ok(response)
+    }
+}
+
+impl<R: Responder> Responder for (StatusCode, R) {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        let mut response = self.1.into_response()?;
+        *response.status_mut() = self.0;
+        Ok(response)
+    }
+}
+
+/// Json is a [Responder] answering with its value serialized to JSON, with a 200 OK unless paired
+/// with another status:
+///
+/// ```ignore
+///     Ok((StatusCode::CREATED, Json(user)))
+/// ```
+///
+/// Values that cannot be serialized are answered with the error. Available with the `serde`
+/// feature.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> Responder for Json<T> {
+    fn into_response(self) -> Result<Response<Body>, Error> {
+        let json = serde_json::to_vec(&self.0)?;
+        Ok(bytes(StatusCode::OK, "application/json", json))
+    }
+}
+
+/// A 307 Temporary Redirect to the location, which clients follow with the same method and body.
+/// Fails if the location is not a valid header value.
+///
+/// ```ignore
+///     return Ok((req, Some(respond::redirect_temporary("/maintenance")?), state));
+/// ```
+pub fn redirect_temporary(location: &str) -> Result<Response<Body>, Error> {
+    redirect(StatusCode::TEMPORARY_REDIRECT, location)
+}
+
+/// A 308 Permanent Redirect to the location, which clients follow with the same method and body,
+/// and may remember.
+pub fn redirect_permanent(location: &str) -> Result<Response<Body>, Error> {
+    redirect(StatusCode::PERMANENT_REDIRECT, location)
+}
+
+/// A 303 See Other redirect to the location, which clients follow with a GET; the usual answer to
+/// a form submitted by POST.
+pub fn see_other(location: &str) -> Result<Response<Body>, Error> {
+    redirect(StatusCode::SEE_OTHER, location)
+}
+
+/// A redirect with the given 3xx status to the location, resolved against the request's path as
+/// a link in a page at that path would be: `edit` from `/posts/7/` redirects to `/posts/7/edit`,
+/// `../` from `/posts/7` to `/`, and `?page=2` to the same path with a new query. Absolute paths
+/// and URLs are used as they are.
+///
+/// ```ignore
+///     let res = respond::redirect_relative(&req, StatusCode::SEE_OTHER, "../")?;
+/// ```
+pub fn redirect_relative<B>(
+    req: &Request<B>,
+    status: StatusCode,
+    location: &str,
+) -> Result<Response<Body>, Error> {
+    redirect(status, &resolve(req.uri().path(), location))
+}
+
+fn redirect(status: StatusCode, location: &str) -> Result<Response<Body>, Error> {
+    if !status.is_redirection() {
+        return Err(Error::new(format!(
+            "redirect status {} is not a 3xx status",
+            status
+        )));
+    }
+
+    let value = match HeaderValue::from_str(location) {
+        Ok(value) if !location.is_empty() => value,
+        _ => {
+            return Err(Error::new(format!(
+                "invalid redirect location {:?}",
+                location
+            )))
+        }
+    };
+
+    let location = crate::files::escape_html(location);
+    let body = format!(
+        "<!DOCTYPE html>\n<title>Redirecting</title>\n<p>Redirecting to <a href=\"{}\">{}</a>.</p>\n",
+        location, location
+    );
+
+    Ok(Response::builder()
+        .status(status)
+        .header(header::LOCATION, value)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body))?)
+}
+
+// resolve the reference against the base path, as RFC 3986 §5.2 does, yielding an absolute path.
+fn resolve(base: &str, reference: &str) -> String {
+    // references with a scheme or an authority, and absolute paths, are not relative to the base.
+    let scheme = reference
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && !scheme.contains(['/', '?', '#']));
+    if scheme || reference.starts_with('/') {
+        return reference.to_string();
+    }
+
+    let split = reference.find(['?', '#']).unwrap_or(reference.len());
+    let (path, rest) = reference.split_at(split);
+    if path.is_empty() {
+        return format!("{}{}", base, rest);
+    }
+
+    let merged = match base.rfind('/') {
+        Some(slash) => format!("{}{}", &base[..=slash], path),
+        None => format!("/{}", path),
+    };
+
+    // remove the dot segments; a trailing one leaves the path ending with a slash.
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = merged.split('/').skip(1).peekable();
+    while let Some(segment) = parts.next() {
+        let last = parts.peek().is_none();
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => {
+                segments.push(segment);
+                continue;
+            }
+        }
+
+        if last {
+            segments.push("");
+        }
+    }
+
+    format!("/{}{}", segments.join("/"), rest)
+}
+
+mod tests {
+    #[test]
+    fn test_resolve() {
+        use super::resolve;
+
+        for (base, reference, expected) in [
+            ("/posts/7/", "edit", "/posts/7/edit"),
+            ("/posts/7", "edit", "/posts/edit"),
+            ("/posts/7", "../", "/"),
+            ("/posts/7/", "..", "/posts/"),
+            ("/posts/7/", "./", "/posts/7/"),
+            ("/a/b/c", "../../../../d", "/d"),
+            ("/posts/7", "?page=2", "/posts/7?page=2"),
+            ("/posts/7", "#top", "/posts/7#top"),
+            ("/posts/7", "8?page=2", "/posts/8?page=2"),
+            ("/posts/7", "/login", "/login"),
+            ("/posts/7", "//example.com/", "//example.com/"),
+            ("/posts/7", "https://example.com/", "https://example.com/"),
+            ("/", "a/./b/../c", "/a/c"),
+            ("", "a", "/a"),
+        ] {
+            assert_eq!(resolve(base, reference), expected, "{} {}", base, reference);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        use super::{redirect_permanent, redirect_relative, redirect_temporary, see_other};
+        use http::{header, Request, StatusCode};
+
+        for (res, status) in [
+            (
+                redirect_temporary("/a?b=<c>"),
+                StatusCode::TEMPORARY_REDIRECT,
+            ),
+            (
+                redirect_permanent("/a?b=<c>"),
+                StatusCode::PERMANENT_REDIRECT,
+            ),
+            (see_other("/a?b=<c>"), StatusCode::SEE_OTHER),
+        ] {
+            let mut res = res.unwrap();
+            assert_eq!(res.status(), status);
+            assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/a?b=<c>");
+            assert_eq!(
+                res.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/html; charset=utf-8"
+            );
+            let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert!(String::from_utf8_lossy(&body)
+                .contains("<a href=\"/a?b=&lt;c&gt;\">/a?b=&lt;c&gt;</a>"));
+        }
+
+        assert!(see_other("/a\r\nSet-Cookie: a=b").is_err());
+        assert!(see_other("").is_err());
+
+        let req = Request::builder()
+            .uri("/posts/7/comments?page=3")
+            .body(())
+            .unwrap();
+        let res = redirect_relative(&req, StatusCode::SEE_OTHER, "../edit").unwrap();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/posts/edit");
+        assert!(redirect_relative(&req, StatusCode::OK, "../edit").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_map_body() {
+        use super::{map_body, map_body_async};
+        use http::{header, Response, StatusCode};
+        use hyper::{body::Bytes, Body};
+
+        let response = || {
+            Response::builder()
+                .status(StatusCode::CREATED)
+                .header("x-custom", "preserved")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::CONTENT_LENGTH, "7")
+                .body(Body::from("[1,2,3]"))
+                .unwrap()
+        };
+
+        let mut res = map_body(response(), 7, |body| {
+            Bytes::from(format!("{{\"data\":{}}}", String::from_utf8_lossy(&body)))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get("x-custom").unwrap(), "preserved");
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "16");
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "{\"data\":[1,2,3]}".as_bytes());
+
+        assert!(map_body(response(), 6, |body| body).await.is_err());
+
+        let mut res = map_body_async(response(), 1024, |body| async move { Ok(body.slice(1..6)) })
+            .await
+            .unwrap();
+
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "5");
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "1,2,3".as_bytes());
+
+        assert!(
+            map_body_async(response(), 6, |body| async move { Ok(body) })
+                .await
+                .is_err()
+        );
+
+        // chunked responses keep their Transfer-Encoding and gain no Content-Length.
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            sender.send_data(Bytes::from("abc")).await.unwrap();
+            sender.send_data(Bytes::from("def")).await.unwrap();
+        });
+
+        let res = Response::builder()
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .body(body)
+            .unwrap();
+        let mut res = map_body(res, 1024, |body| body).await.unwrap();
+        assert_eq!(
+            res.headers().get(header::TRANSFER_ENCODING).unwrap(),
+            "chunked"
+        );
+        assert!(res.headers().get(header::CONTENT_LENGTH).is_none());
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "abcdef".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_content_types() {
+        use super::{bytes, html, text};
+        use http::StatusCode;
+
+        for (mut res, status, content_type, body) in [
+            (
+                text(StatusCode::OK, "hello, ünïcode!"),
+                StatusCode::OK,
+                "text/plain; charset=utf-8",
+                "hello, ünïcode!".as_bytes(),
+            ),
+            (
+                html(StatusCode::NOT_FOUND, String::from("<p>gone</p>")),
+                StatusCode::NOT_FOUND,
+                "text/html; charset=utf-8",
+                b"<p>gone</p>",
+            ),
+            (
+                bytes(
+                    StatusCode::CREATED,
+                    "image/png",
+                    vec![0x89, b'P', b'N', b'G'],
+                ),
+                StatusCode::CREATED,
+                "image/png",
+                &[0x89, b'P', b'N', b'G'],
+            ),
+            (
+                text(StatusCode::NO_CONTENT, ""),
+                StatusCode::NO_CONTENT,
+                "text/plain; charset=utf-8",
+                b"",
+            ),
+        ] {
+            assert_eq!(res.status(), status);
+            assert_eq!(res.headers().get("content-type").unwrap(), content_type);
+            assert_eq!(
+                res.headers().get("content-length").unwrap(),
+                &body.len().to_string()
+            );
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            assert_eq!(bytes, body);
+        }
+    }
+
+    #[test]
+    fn test_responder() {
+        use super::Responder;
+        use http::StatusCode;
+
+        let res = "hello".into_response().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(res.headers().get("content-length").unwrap(), "5");
+
+        let res = (StatusCode::CONFLICT, "taken".to_string())
+            .into_response()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(res.headers().get("content-length").unwrap(), "5");
+
+        let res = StatusCode::NO_CONTENT.into_response().unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert!(res.headers().get("content-type").is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn test_responder_json() {
+        use super::{Json, Responder};
+        use http::StatusCode;
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        struct User {
+            id: u32,
+            name: &'static str,
+        }
+
+        let mut res = (
+            StatusCode::CREATED,
+            Json(User {
+                id: 7,
+                name: "erik",
+            }),
+        )
+            .into_response()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, r#"{"id":7,"name":"erik"}"#);
+
+        // maps with keys JSON cannot have are errors.
+        let map = BTreeMap::from([((1, 2), "pair")]);
+        assert!(Json(map).into_response().is_err());
+    }
+}
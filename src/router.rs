@@ -1,112 +1,891 @@
+use std::collections::HashMap;
+
 use http::{Request, Response};
 use hyper::Body;
 
-use crate::{app::App, handler::Handler, path::Path, Error, HTTPResult, TransientState};
+use crate::{
+    app::{App, PathLimits, RouteInfo, RouteMeta, RouteOptions},
+    handler::Handler,
+    path::{MatchOptions, Path},
+    AllowedMethods, Error, HTTPResult, MatchedPath, OriginalMethod, Params, Query, RawParams,
+    TransientState,
+};
+
+// The most of a form body read for its `_method` field when the App sets no limit on bodies.
+const OVERRIDE_FORM_LIMIT: u64 = 1 << 20;
+
+/// Methods are the request methods a route answers. Sets are kept sorted and free of duplicates,
+/// so that equal sets compare equal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Methods {
+    Any,
+    Set(Vec<http::Method>),
+}
+
+impl Methods {
+    /// Construct a set of methods. Panics if the set is empty, as such a route could never match.
+    pub(crate) fn set(methods: &[http::Method]) -> Self {
+        if methods.is_empty() {
+            panic!("a route must answer at least one method");
+        }
+
+        let mut methods = methods.to_vec();
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        methods.dedup();
+        Self::Set(methods)
+    }
+
+    fn contains(&self, method: &http::Method) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Set(methods) => methods.contains(method),
+        }
+    }
+
+    // GET routes also serve HEAD; the router strips the body of the response.
+    fn serves(&self, method: &http::Method) -> bool {
+        self.contains(method) || (method == http::Method::HEAD && self.contains(&http::Method::GET))
+    }
+
+    // whether a request could be answered by routes with either set of methods.
+    fn overlaps(&self, other: &Methods) -> bool {
+        match (self, other) {
+            (Self::Set(methods), Self::Set(others)) => methods.iter().any(|m| others.contains(m)),
+            _ => true,
+        }
+    }
+
+    // the methods to list in an Allow header for a route whose path matched.
+    fn allowed(&self) -> Vec<http::Method> {
+        match self {
+            Self::Any => Vec::new(),
+            Self::Set(methods) => methods.clone(),
+        }
+    }
+}
+
+impl From<http::Method> for Methods {
+    fn from(method: http::Method) -> Self {
+        Self::Set(vec![method])
+    }
+}
+
+impl std::fmt::Display for Methods {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "*"),
+            Self::Set(methods) => write!(
+                f,
+                "{}",
+                methods
+                    .iter()
+                    .map(|method| method.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+}
 
-#[derive(Clone)]
-pub(crate) struct Route<S: Clone + Send, T: TransientState + 'static> {
-    method: http::Method,
+pub(crate) struct Route<S: Send, T: TransientState + 'static> {
+    methods: Methods,
     path: Path,
     handler: Handler<S, T>,
+    options: RouteOptions,
+}
+
+impl<S: Send, T: TransientState> Clone for Route<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            methods: self.methods.clone(),
+            path: self.path.clone(),
+            handler: self.handler.clone(),
+            options: self.options.clone(),
+        }
+    }
 }
 
-impl<S: Clone + Send, T: TransientState> PartialEq for Route<S, T> {
+impl<S: Send, T: TransientState> PartialEq for Route<S, T> {
     fn eq(&self, other: &Self) -> bool {
-        self.method.to_string() == other.method.to_string() && self.path.eq(&other.path)
+        self.methods == other.methods && self.path.eq(&other.path)
     }
 }
 
-impl<S: Clone + Send, T: TransientState> Eq for Route<S, T> {}
+impl<S: Send, T: TransientState> Eq for Route<S, T> {}
 
-impl<S: Clone + Send, T: TransientState> PartialOrd for Route<S, T> {
+impl<S: Send, T: TransientState> PartialOrd for Route<S, T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<S: Clone + Send, T: TransientState> Ord for Route<S, T> {
+impl<S: Send, T: TransientState> Ord for Route<S, T> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        let left = self.method.to_string() + " " + &self.path.to_string();
-        let right = other.method.to_string() + " " + &other.path.to_string();
+        let left = self.methods.to_string() + " " + &self.path.to_string();
+        let right = other.methods.to_string() + " " + &other.path.to_string();
 
         left.to_string().cmp(&right.to_string())
     }
 }
 
-impl<S: Clone + Send, T: TransientState> Route<S, T> {
-    fn new(method: http::Method, path: String, handler: Handler<S, T>) -> Self {
+impl<S: Send, T: TransientState> Route<S, T> {
+    fn new(methods: impl Into<Methods>, path: String, handler: Handler<S, T>) -> Self {
         Self {
-            method,
+            methods: methods.into(),
             handler,
             path: Path::new(path),
+            options: RouteOptions::default(),
         }
     }
 
+    // whether both routes would answer the same requests, so that one would shadow the other:
+    // their paths are equivalent, they share a method and are for the same host.
+    fn conflicts(&self, other: &Route<S, T>) -> bool {
+        self.methods.overlaps(&other.methods)
+            && self.path.equivalent(&other.path)
+            && self.options.host == other.options.host
+    }
+
+    // a route without a guard accepts every request that matches its method and path.
+    fn accepts(&self, req: &Request<Body>) -> bool {
+        self.options.guard.as_ref().map_or(true, |guard| guard(req))
+    }
+
+    // whether the route serves the request, its method aside.
+    fn matches(&self, path: &str, req: &Request<Body>, options: MatchOptions) -> bool {
+        // routes for other hosts, and those whose guard rejects the request, are invisible: they
+        // neither serve the request nor show up in the Allow header of a 405.
+        self.serves_host(req)
+            && self.path.matches_with(path.to_string(), options)
+            && self.accepts(req)
+    }
+
+    // a route without a host pattern serves every host.
+    fn serves_host(&self, req: &Request<Body>) -> bool {
+        self.options
+            .host
+            .as_ref()
+            .map_or(true, |host| host.matches(req).is_some())
+    }
+
     async fn dispatch(
         &self,
         provided: String,
         req: Request<hyper::Body>,
         app: App<S, T>,
         state: T,
+        options: MatchOptions,
     ) -> HTTPResult<T> {
-        let params = self.path.extract(provided)?;
+        let (req, params) = self.prepare(provided, req, options)?;
+        self.run(&self.handler, req, params, app, state).await
+    }
 
-        if self.method != req.method() {
+    // check the request against the route, returning its parameters, and record what was matched
+    // in its extensions.
+    fn prepare(
+        &self,
+        provided: String,
+        mut req: Request<hyper::Body>,
+        options: MatchOptions,
+    ) -> Result<(Request<hyper::Body>, Params), Error> {
+        // checking the method is cheaper than extracting parameters, so comes first.
+        if !self.methods.serves(req.method()) {
             return Err(Error::StatusCode(
-                http::StatusCode::NOT_FOUND,
+                http::StatusCode::METHOD_NOT_ALLOWED,
                 String::new(),
             ));
         }
 
-        self.handler.perform(req, None, params, app, state).await
+        let (mut params, mut raw) = self.path.extract_with_raw(provided, options)?;
+
+        if self.options.reject_encoded_slashes {
+            if let Some((name, _)) = raw
+                .iter()
+                .find(|(_, value)| value.to_ascii_uppercase().contains("%2F"))
+            {
+                return Err(Error::new_status(
+                    http::StatusCode::BAD_REQUEST,
+                    format!("parameter `{}` contains an encoded slash", name),
+                ));
+            }
+        }
+
+        // registration ensures the names of host and path parameters are distinct.
+        if let Some(host) = self
+            .options
+            .host
+            .as_ref()
+            .and_then(|host| host.matches(&req))
+        {
+            raw.extend(host.clone());
+            params.extend(host);
+        }
+
+        req.extensions_mut()
+            .insert(RouteMeta(self.options.meta.clone()));
+        req.extensions_mut().insert(RawParams(raw));
+        req.extensions_mut()
+            .insert(MatchedPath(self.path.to_string()));
+
+        Ok((req, params))
+    }
+
+    // perform the chain for the route, within its timeout.
+    async fn run(
+        &self,
+        chain: &Handler<S, T>,
+        req: Request<hyper::Body>,
+        params: Params,
+        app: App<S, T>,
+        state: T,
+    ) -> HTTPResult<T> {
+        let fut = chain.perform(req, None, params, app, state);
+        match self.options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut).await.map_err(|_| {
+                Error::new_status(http::StatusCode::SERVICE_UNAVAILABLE, "route timed out")
+            })?,
+            None => fut.await,
+        }
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct Router<S: Clone + Send, T: TransientState + 'static>(Vec<Route<S, T>>);
+pub(crate) struct Router<S: Send, T: TransientState + 'static> {
+    routes: Vec<Route<S, T>>,
+    // positions in `routes` of the routes answering each method, and of those answering any.
+    by_method: HashMap<http::Method, Vec<usize>>,
+    any: Vec<usize>,
+    options: MatchOptions,
+    auto_options: bool,
+    method_override: bool,
+    problem_json: bool,
+    stage_header: bool,
+    normalize: bool,
+    limits: PathLimits,
+    max_body_size: Option<u64>,
+    method_not_allowed: Option<Handler<S, T>>,
+    wrap: Option<Handler<S, T>>,
+    after: Option<Handler<S, T>>,
+    expect_continue: Option<Handler<S, T>>,
+}
+
+impl<S: Send, T: TransientState> Clone for Router<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            routes: self.routes.clone(),
+            by_method: self.by_method.clone(),
+            any: self.any.clone(),
+            options: self.options,
+            auto_options: self.auto_options,
+            method_override: self.method_override,
+            problem_json: self.problem_json,
+            stage_header: self.stage_header,
+            normalize: self.normalize,
+            limits: self.limits,
+            max_body_size: self.max_body_size,
+            method_not_allowed: self.method_not_allowed.clone(),
+            wrap: self.wrap.clone(),
+            after: self.after.clone(),
+            expect_continue: self.expect_continue.clone(),
+        }
+    }
+}
 
-impl<S: Clone + Send, T: TransientState + Clone + Send> Router<S, T> {
+impl<S: Send, T: TransientState + Clone + Send> Router<S, T> {
     pub fn new() -> Self {
-        Self(Vec::new())
+        Self {
+            routes: Vec::new(),
+            by_method: HashMap::new(),
+            any: Vec::new(),
+            options: MatchOptions::default(),
+            auto_options: false,
+            method_override: false,
+            problem_json: false,
+            stage_header: false,
+            normalize: true,
+            limits: PathLimits::default(),
+            max_body_size: None,
+            method_not_allowed: None,
+            wrap: None,
+            after: None,
+            expect_continue: None,
+        }
+    }
+
+    /// Perform `handler` instead of responding with a plain 405. See
+    /// [crate::app::App::method_not_allowed].
+    pub(crate) fn set_method_not_allowed(&mut self, handler: Handler<S, T>) {
+        self.method_not_allowed = Some(handler);
+    }
+
+    /// Perform `ch` before the chain of every route. See [crate::app::App::wrap].
+    pub(crate) fn wrap(&mut self, ch: Handler<S, T>) {
+        self.wrap = Some(match self.wrap.take() {
+            Some(wrap) => wrap.then(ch),
+            None => ch,
+        });
+    }
+
+    /// Perform `ch` on every response. See [crate::app::App::wrap_after].
+    pub(crate) fn wrap_after(&mut self, ch: Handler<S, T>) {
+        self.after = Some(match self.after.take() {
+            Some(after) => after.then(ch),
+            None => ch,
+        });
+    }
+
+    /// The chain to perform on every response, if any.
+    pub(crate) fn after(&self) -> Option<&Handler<S, T>> {
+        self.after.as_ref()
+    }
+
+    /// Perform `ch` before routing requests that expect a 100 Continue. See
+    /// [crate::app::App::on_expect_continue].
+    pub(crate) fn on_expect_continue(&mut self, ch: Handler<S, T>) {
+        self.expect_continue = Some(match self.expect_continue.take() {
+            Some(expect_continue) => expect_continue.then(ch),
+            None => ch,
+        });
+    }
+
+    /// The chain to perform before routing requests that expect a 100 Continue, if any.
+    pub(crate) fn expect_continue(&self) -> Option<&Handler<S, T>> {
+        self.expect_continue.as_ref()
+    }
+
+    /// Bound the size of request paths. See [crate::app::App::set_path_limits].
+    pub(crate) fn set_path_limits(&mut self, limits: PathLimits) {
+        self.limits = limits;
+    }
+
+    /// Bound the size of request bodies. See [crate::app::App::set_max_body_size].
+    pub(crate) fn set_max_body_size(&mut self, max: u64) {
+        self.max_body_size = Some(max);
+    }
+
+    /// Resolve dot segments of request paths before matching them. See
+    /// [crate::app::App::set_normalize_paths].
+    pub(crate) fn set_normalize_paths(&mut self, normalize: bool) {
+        self.normalize = normalize;
+    }
+
+    /// Answer OPTIONS requests for paths without an OPTIONS route. See
+    /// [crate::app::App::enable_auto_options].
+    pub(crate) fn enable_auto_options(&mut self) {
+        self.auto_options = true;
+    }
+
+    /// Let POST requests ask to be routed as PUT, PATCH or DELETE. See
+    /// [crate::app::App::enable_method_override].
+    pub(crate) fn enable_method_override(&mut self) {
+        self.method_override = true;
+    }
+
+    /// Render errors as problems for clients that accept them. See
+    /// [crate::app::App::enable_problem_json].
+    pub(crate) fn enable_problem_json(&mut self) {
+        self.problem_json = true;
+    }
+
+    pub(crate) fn problem_json(&self) -> bool {
+        self.problem_json
+    }
+
+    /// Name the stage errors came from in a response header. See
+    /// [crate::app::App::enable_stage_header].
+    pub(crate) fn enable_stage_header(&mut self) {
+        self.stage_header = true;
     }
 
-    pub(crate) fn add(&mut self, method: http::Method, path: String, ch: Handler<S, T>) -> Self {
-        self.0.push(Route::new(method, path, ch));
+    pub(crate) fn stage_header(&self) -> bool {
+        self.stage_header
+    }
+
+    /// Whether the request is for `path`, taken literally, as routing would decide it: against the
+    /// path the request is routed on, `/` for a CONNECT and otherwise its path with its dot
+    /// segments resolved if paths are normalized, with the slash and case options of the routes.
+    pub(crate) fn requests_path(&self, req: &Request<Body>, path: &str) -> bool {
+        let routed = if req.uri().authority().is_some() && req.uri().scheme().is_none() {
+            "/".to_string()
+        } else if self.normalize {
+            match crate::path::normalize(req.uri().path()) {
+                Ok(routed) => routed,
+                Err(_) => return false,
+            }
+        } else {
+            req.uri().path().to_string()
+        };
+
+        Path::literal(path).matches_with(routed, self.options)
+    }
+
+    /// Match trailing and duplicate slashes exactly for all routes. See
+    /// [crate::app::App::set_strict_slashes].
+    pub(crate) fn set_strict_slashes(&mut self, strict_slashes: bool) {
+        self.options.strict_slashes = strict_slashes;
+    }
+
+    /// Compare literal path components without regard to case for all routes. See
+    /// [crate::app::App::set_case_insensitive].
+    pub(crate) fn set_case_insensitive(&mut self, case_insensitive: bool) {
+        self.options.case_insensitive = case_insensitive;
+    }
+
+    /// Add a route. Panics if the path is not a valid route path, such as one missing its leading
+    /// slash, so that misconfigured routes fail at startup rather than at request time.
+    pub(crate) fn add(
+        &mut self,
+        methods: impl Into<Methods>,
+        path: String,
+        ch: Handler<S, T>,
+    ) -> Self {
+        self.add_with_options(methods.into(), path, ch, RouteOptions::default())
+    }
+
+    /// Add a route with options. Routes are kept ordered by descending priority, and then by
+    /// specificity of their paths; see [Path::specificity]. Routes of equal priority and
+    /// specificity are tried in the order they were added.
+    pub(crate) fn add_with_options(
+        &mut self,
+        methods: Methods,
+        path: String,
+        ch: Handler<S, T>,
+        options: RouteOptions,
+    ) -> Self {
+        if let Err(e) = Path::check(&path) {
+            panic!("invalid route path `{}`: {}", path, e);
+        }
+
+        let mut route = Route::new(methods, path, ch);
+        route.options = options;
+        self.push(route);
+        self.clone()
+    }
+
+    /// Add every route of `other` to this router, with `prefix` prepended to each of their paths.
+    /// Parameters in the prefix are extracted alongside those of the mounted routes.
+    pub(crate) fn mount(&mut self, prefix: String, other: &Router<S, T>) -> Self {
+        if let Err(e) = Path::check(&prefix) {
+            panic!("invalid mount prefix `{}`: {}", prefix, e);
+        }
+
+        let prefix = Path::new(prefix);
+
+        for route in &other.routes {
+            let mut route = route.clone();
+            route.path = route.path.prefixed(&prefix);
+            self.push(route);
+        }
+
         self.clone()
     }
 
+    /// Add every route of `other` to this router as it is. If any of them conflicts with a route
+    /// of this router or shares its name, an error is returned and no routes are added.
+    pub(crate) fn merge(&mut self, other: &Router<S, T>) -> Result<(), Error> {
+        for route in &other.routes {
+            if let Some(existing) = self.routes.iter().find(|r| r.conflicts(route)) {
+                return Err(Error::new(format!(
+                    "route `{} {}` conflicts with `{} {}`",
+                    route.methods,
+                    route.path.to_string(),
+                    existing.methods,
+                    existing.path.to_string()
+                )));
+            }
+
+            if let Some(name) = &route.options.name {
+                if self
+                    .routes
+                    .iter()
+                    .any(|r| r.options.name.as_ref() == Some(name))
+                {
+                    return Err(Error::new(format!(
+                        "route name `{}` is already registered",
+                        name
+                    )));
+                }
+            }
+        }
+
+        for route in &other.routes {
+            self.push(route.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Stop answering `method` at `path`, returning whether any route did. A route answering
+    /// several methods keeps answering the others; routes answering any method are left alone.
+    /// Paths are compared as they are for conflicts, so parameter names do not matter, but a
+    /// literal path removes no route with a parameter in its place, nor the reverse.
+    pub(crate) fn remove(&mut self, method: &http::Method, path: String) -> bool {
+        let path = Path::new(path);
+        let mut removed = false;
+
+        self.routes.retain_mut(|route| {
+            if !route.path.equivalent(&path) {
+                return true;
+            }
+
+            match &mut route.methods {
+                Methods::Set(methods) if methods.contains(method) => {
+                    methods.retain(|m| m != method);
+                    removed = true;
+                    !methods.is_empty()
+                }
+                _ => true,
+            }
+        });
+
+        self.reindex();
+        removed
+    }
+
+    fn push(&mut self, route: Route<S, T>) {
+        if let Err(e) = route.path.validate() {
+            panic!("invalid route path `{}`: {}", route.path.to_string(), e);
+        }
+
+        if let Some(host) = &route.options.host {
+            let params = route.path.params();
+            if let Some(name) = host.params().into_iter().find(|name| params.contains(name)) {
+                panic!(
+                    "invalid route path `{}`: parameter `{}` is also captured from host `{}`",
+                    route.path.to_string(),
+                    name,
+                    host
+                );
+            }
+        }
+
+        if let Some(name) = &route.options.name {
+            if self
+                .routes
+                .iter()
+                .any(|r| r.options.name.as_ref() == Some(name))
+            {
+                panic!("route name `{}` is already registered", name);
+            }
+        }
+
+        self.routes.push(route);
+        // within a priority, more specific paths are tried first, so `/users/new` wins over
+        // `/users/:id` whichever was registered first. sorting is stable, which preserves
+        // registration order among routes of equal priority and specificity.
+        self.routes.sort_by_key(|route| {
+            (
+                std::cmp::Reverse(route.options.priority),
+                route.path.specificity(),
+            )
+        });
+
+        self.reindex();
+    }
+
+    // rebuild the per-method indexes after `routes` has changed; each index lists positions in
+    // ascending order, so preserves the order routes are tried in.
+    fn reindex(&mut self) {
+        self.by_method.clear();
+        self.any.clear();
+
+        for (i, route) in self.routes.iter().enumerate() {
+            match &route.methods {
+                Methods::Any => self.any.push(i),
+                Methods::Set(methods) => {
+                    for method in methods {
+                        self.by_method.entry(method.clone()).or_default().push(i);
+                    }
+                }
+            }
+        }
+    }
+
+    // the routes answering `method`, in the order they are to be tried.
+    fn candidates(&self, method: &http::Method) -> impl Iterator<Item = &Route<S, T>> {
+        let mut positions: Vec<usize> = self
+            .by_method
+            .get(method)
+            .into_iter()
+            .flatten()
+            .chain(self.any.iter())
+            .copied()
+            .collect();
+        positions.sort_unstable();
+
+        positions.into_iter().map(move |i| &self.routes[i])
+    }
+
+    /// Describe the registered routes, ordered by method and then path.
+    pub(crate) fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes: Vec<&Route<S, T>> = self.routes.iter().collect();
+        routes.sort();
+
+        routes
+            .into_iter()
+            .map(|route| RouteInfo {
+                method: route.methods.to_string(),
+                path: route.path.to_string(),
+                handlers: route.handler.stages().count(),
+                name: route.options.name.clone(),
+                host: route.options.host(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, Error> {
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.options.name.as_deref() == Some(name))
+            .ok_or_else(|| Error::new(format!("no route named `{}`", name)))?;
+
+        route
+            .path
+            .url(params)
+            .map_err(|e| Error::new(format!("cannot generate URL for route `{}`: {}", name, e)))
+    }
+
+    async fn perform(
+        &self,
+        route: &Route<S, T>,
+        path: String,
+        mut req: Request<Body>,
+        app: App<S, T>,
+    ) -> Result<Response<Body>, Error> {
+        if let Some(max) = route.options.max_body_size.or(self.max_body_size) {
+            crate::body::limit(&mut req, max)?;
+        }
+
+        let (_, response, _) = match &self.wrap {
+            Some(wrap) => {
+                let (req, params) = route.prepare(path, req, self.options)?;
+                let chain = wrap.clone().then(route.handler.clone());
+                route.run(&chain, req, params, app, T::initial()).await?
+            }
+            None => {
+                route
+                    .dispatch(path, req, app, T::initial(), self.options)
+                    .await?
+            }
+        };
+
+        response.ok_or_else(|| {
+            Error::StatusCode(http::StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        })
+    }
+
     pub(crate) async fn dispatch(
         &self,
-        req: Request<Body>,
+        mut req: Request<Body>,
         app: App<S, T>,
     ) -> Result<Response<Body>, Error> {
-        let path = req.uri().path().to_string();
-
-        for route in self.0.clone() {
-            if route.path.matches(path.to_string()) && route.method.eq(req.method()) {
-                let (_, response, _) = route
-                    .dispatch(path.to_string(), req, app, T::initial())
-                    .await?;
-                if response.is_none() {
-                    return Err(Error::StatusCode(
-                        http::StatusCode::INTERNAL_SERVER_ERROR,
-                        String::new(),
-                    ));
+        // the target of a CONNECT names the authority to tunnel to rather than a path; it is
+        // routed as `/`.
+        let path = if canonicalize_target(&mut req)? {
+            "/".to_string()
+        } else {
+            // before anything else, so oversized paths cost as little as possible.
+            self.limits.check(req.uri().path())?;
+
+            if self.normalize {
+                normalize_uri(&mut req)?;
+            }
+
+            req.uri().path().to_string()
+        };
+        let query = Query::parse(req.uri().query().unwrap_or_default());
+        req.extensions_mut().insert(query);
+
+        if self.method_override && req.method() == http::Method::POST {
+            override_method(&mut req, self.max_body_size.unwrap_or(OVERRIDE_FORM_LIMIT)).await?;
+        }
+
+        // only routes answering the method of the request are considered at first.
+        for route in self.candidates(req.method()) {
+            if route.matches(&path, &req, self.options) {
+                return self.perform(route, path, req, app).await;
+            }
+        }
+
+        // HEAD requests are served by GET routes when there is no HEAD route for the path.
+        if req.method() == http::Method::HEAD {
+            if let Some(route) = self
+                .candidates(&http::Method::GET)
+                .find(|route| route.matches(&path, &req, self.options))
+            {
+                // status and headers, Content-Length included, are those the GET would have had.
+                let mut response = self.perform(route, path, req, app).await?;
+                crate::body::strip(&mut response);
+                return Ok(response);
+            }
+        }
+
+        // the path may still be served for other methods, which a 405 lists in its Allow header.
+        let mut allowed: Vec<http::Method> = Vec::new();
+        for route in &self.routes {
+            if route.matches(&path, &req, self.options) {
+                for method in route.methods.allowed() {
+                    if !allowed.contains(&method) {
+                        allowed.push(method);
+                    }
                 }
+            }
+        }
+
+        if allowed.contains(&http::Method::GET) && !allowed.contains(&http::Method::HEAD) {
+            allowed.push(http::Method::HEAD);
+        }
+
+        if allowed.is_empty() {
+            return Err(Error::StatusCode(
+                http::StatusCode::NOT_FOUND,
+                String::new(),
+            ));
+        }
+
+        if self.auto_options && req.method() == http::Method::OPTIONS {
+            allowed.push(http::Method::OPTIONS);
 
-                return Ok(response.unwrap());
+            return Ok(Response::builder()
+                .status(http::StatusCode::NO_CONTENT)
+                .header(http::header::ALLOW, allow(&allowed))
+                .body(Body::empty())?);
+        }
+
+        let allow_header = allow(&allowed);
+
+        if let Some(handler) = &self.method_not_allowed {
+            req.extensions_mut().insert(AllowedMethods(allowed));
+
+            let (_, response, _) = handler
+                .perform(req, None, Params::default(), app, T::initial())
+                .await?;
+            let mut response = response.ok_or_else(|| {
+                Error::StatusCode(http::StatusCode::INTERNAL_SERVER_ERROR, String::new())
+            })?;
+
+            if !response.headers().contains_key(http::header::ALLOW) {
+                response
+                    .headers_mut()
+                    .insert(http::header::ALLOW, allow_header.parse()?);
             }
+
+            return Ok(response);
+        }
+
+        Ok(Response::builder()
+            .status(http::StatusCode::METHOD_NOT_ALLOWED)
+            .header(http::header::ALLOW, allow_header)
+            .body(Body::empty())?)
+    }
+}
+
+// Bring the request target into origin-form (RFC 9112 §3.2). The authority of an absolute-form
+// target replaces the Host header, as the RFC requires of servers, and the target is reduced to its
+// path and query. Authority-form targets are left as they are, and true is returned for them; only
+// CONNECT may use them.
+fn canonicalize_target(req: &mut Request<Body>) -> Result<bool, Error> {
+    let authority = match req.uri().authority() {
+        Some(authority) => authority.clone(),
+        None => return Ok(false),
+    };
+
+    let authority_form = req.uri().scheme().is_none();
+    if authority_form && req.method() != http::Method::CONNECT {
+        return Err(Error::new_status(
+            http::StatusCode::BAD_REQUEST,
+            "authority-form request target is only allowed for CONNECT",
+        ));
+    }
+
+    // any user information is not part of the host.
+    let host = match authority.port() {
+        Some(port) => format!("{}:{}", authority.host(), port),
+        None => authority.host().to_string(),
+    };
+    req.headers_mut().insert(http::header::HOST, host.parse()?);
+
+    if !authority_form {
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", req.uri().path(), query),
+            None => req.uri().path().to_string(),
+        };
+        *req.uri_mut() = path_and_query.parse()?;
+    }
+
+    Ok(authority_form)
+}
+
+// Replace the method of a POST request with the one named by its `X-HTTP-Method-Override` header,
+// or failing that the `_method` field of its form body, if that is PUT, PATCH or DELETE. The form
+// body is read within `limit` bytes and put back for the handlers.
+async fn override_method(req: &mut Request<Body>, limit: u64) -> Result<(), Error> {
+    let requested = match req.headers().get("x-http-method-override") {
+        Some(value) => value.to_str().unwrap_or_default().trim().to_string(),
+        None if is_form(req) => {
+            let bytes = crate::body::read_limited(req, limit as usize).await?;
+            *req.body_mut() = Body::from(bytes.clone());
+            let form = Query::parse(&String::from_utf8_lossy(&bytes));
+            form.get("_method").unwrap_or_default().trim().to_string()
         }
+        None => return Ok(()),
+    };
+
+    let method = match requested.to_ascii_uppercase().as_str() {
+        "PUT" => http::Method::PUT,
+        "PATCH" => http::Method::PATCH,
+        "DELETE" => http::Method::DELETE,
+        _ => return Ok(()),
+    };
+
+    let original = std::mem::replace(req.method_mut(), method);
+    req.extensions_mut().insert(OriginalMethod(original));
+    Ok(())
+}
+
+fn is_form(req: &Request<Body>) -> bool {
+    req.headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|value| {
+            value
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+}
 
-        Err(Error::StatusCode(
-            http::StatusCode::METHOD_NOT_ALLOWED,
-            String::new(),
-        ))
+// Resolve the dot segments of the request's path, so handlers see the path that was matched.
+fn normalize_uri(req: &mut Request<Body>) -> Result<(), Error> {
+    let path = crate::path::normalize(req.uri().path())
+        .map_err(|e| Error::new_status(http::StatusCode::BAD_REQUEST, e))?;
+    if path == req.uri().path() {
+        return Ok(());
     }
+
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse()?);
+    *req.uri_mut() = http::Uri::from_parts(parts)?;
+    Ok(())
+}
+
+// The value of an Allow header listing the methods.
+fn allow(methods: &[http::Method]) -> String {
+    methods
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 mod tests {
     #[tokio::test]
+    #[allow(clippy::needless_return)]
     async fn test_route_dynamic() {
         use http::{Method, Request, Response};
         use hyper::Body;
@@ -114,6 +893,7 @@ mod tests {
         use crate::{app::App, handler::Handler, HTTPResult, NoState, Params};
 
         use super::Route;
+        use crate::path::MatchOptions;
 
         #[derive(Clone)]
         struct State;
@@ -147,7 +927,13 @@ mod tests {
         );
 
         assert!(route
-            .dispatch("/a".to_string(), Request::default(), App::new(), NoState {})
+            .dispatch(
+                "/a".to_string(),
+                Request::default(),
+                App::new(),
+                NoState {},
+                MatchOptions::default()
+            )
             .await
             .is_err());
         assert!(route
@@ -159,6 +945,7 @@ mod tests {
                     .unwrap(),
                 App::new(),
                 NoState {},
+                MatchOptions::default(),
             )
             .await
             .is_err());
@@ -171,7 +958,8 @@ mod tests {
                     "/a/:name/c".to_string(),
                     Request::default(),
                     App::new(),
-                    NoState {}
+                    NoState {},
+                    MatchOptions::default()
                 )
                 .await
                 .is_ok());
@@ -180,7 +968,13 @@ mod tests {
 
             let body = hyper::body::to_bytes(
                 route
-                    .dispatch(path.clone(), Request::default(), App::new(), NoState {})
+                    .dispatch(
+                        path.clone(),
+                        Request::default(),
+                        App::new(),
+                        NoState {},
+                        MatchOptions::default(),
+                    )
                     .await
                     .unwrap()
                     .1
@@ -193,7 +987,13 @@ mod tests {
             assert_eq!(body, format!("hello, {}", name).as_bytes());
 
             let status = route
-                .dispatch(path, Request::default(), App::new(), NoState {})
+                .dispatch(
+                    path,
+                    Request::default(),
+                    App::new(),
+                    NoState {},
+                    MatchOptions::default(),
+                )
                 .await
                 .unwrap()
                 .1
@@ -205,6 +1005,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[allow(clippy::needless_return)]
     async fn test_route_static() {
         use http::{Method, Request, Response};
         use hyper::Body;
@@ -212,6 +1013,7 @@ mod tests {
         use crate::{app::App, handler::Handler, HTTPResult, NoState, Params};
 
         use super::Route;
+        use crate::path::MatchOptions;
 
         #[derive(Clone)]
         struct State;
@@ -246,7 +1048,13 @@ mod tests {
         );
 
         assert!(route
-            .dispatch("/a".to_string(), Request::default(), App::new(), NoState {})
+            .dispatch(
+                "/a".to_string(),
+                Request::default(),
+                App::new(),
+                NoState {},
+                MatchOptions::default()
+            )
             .await
             .is_err());
         assert!(route
@@ -258,6 +1066,7 @@ mod tests {
                     .unwrap(),
                 App::new(),
                 NoState {},
+                MatchOptions::default(),
             )
             .await
             .is_err());
@@ -267,7 +1076,8 @@ mod tests {
                 "/a/b/c".to_string(),
                 Request::default(),
                 App::new(),
-                NoState {}
+                NoState {},
+                MatchOptions::default()
             )
             .await
             .is_ok());
@@ -279,6 +1089,7 @@ mod tests {
                     Request::default(),
                     App::new(),
                     NoState {},
+                    MatchOptions::default(),
                 )
                 .await
                 .unwrap()
@@ -297,6 +1108,7 @@ mod tests {
                 Request::default(),
                 App::new(),
                 NoState {},
+                MatchOptions::default(),
             )
             .await
             .unwrap()
@@ -308,6 +1120,7 @@ mod tests {
     }
 
     #[tokio::test]
+    #[allow(clippy::needless_return, clippy::useless_vec)]
     async fn test_router() {
         use super::Router;
         use crate::{
@@ -489,4 +1302,684 @@ mod tests {
             assert!(response.is_err());
         }
     }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid route path `/a/:x/:x`: parameter `x` is used more than once"
+    )]
+    fn test_router_rejects_duplicate_params() {
+        use super::Router;
+        use crate::{handler::Handler, NoState};
+        use http::Method;
+
+        let mut router: Router<(), NoState> = Router::new();
+        router.add(
+            Method::GET,
+            "/a/:x/:x".to_string(),
+            Handler::new(
+                |req, resp, _, _, state| Box::pin(async { Ok((req, resp, state)) }),
+                None,
+            ),
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "invalid route path `/sites/:subdomain`: parameter `subdomain` is also captured from host `*.example.com`"
+    )]
+    fn test_router_rejects_params_shared_with_host() {
+        use super::{Methods, Router};
+        use crate::{app::RouteOptions, handler::Handler, host::HostPattern, NoState};
+        use http::Method;
+
+        let mut router: Router<(), NoState> = Router::new();
+        router.add_with_options(
+            Methods::from(Method::GET),
+            "/sites/:subdomain".to_string(),
+            Handler::new(
+                |req, resp, _, _, state| Box::pin(async { Ok((req, resp, state)) }),
+                None,
+            ),
+            RouteOptions {
+                host: Some(HostPattern::new("*.example.com")),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_router_constraints() {
+        use super::Router;
+        use crate::{app::App, handler::Handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response};
+        use hyper::Body;
+
+        async fn by_id(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("id {}", params.get("id").unwrap());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn by_name(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("name {}", params.get("name").unwrap());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut router = Router::new();
+        router.add(
+            Method::GET,
+            "/users/:id{[0-9]+}".to_string(),
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(by_id(req, resp, params, app, state)),
+                None,
+            ),
+        );
+        router.add(
+            Method::GET,
+            "/users/:name".to_string(),
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(by_name(req, resp, params, app, state)),
+                None,
+            ),
+        );
+
+        for (uri, expected) in [("/users/42", "id 42"), ("/users/erik", "name erik")] {
+            let response = router
+                .dispatch(
+                    Request::builder().uri(uri).body(Body::default()).unwrap(),
+                    App::new(),
+                )
+                .await
+                .unwrap();
+
+            let body = hyper::body::to_bytes(response).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid route path `/users/:id{[0-9}`")]
+    fn test_router_rejects_invalid_constraints() {
+        use super::Router;
+        use crate::{handler::Handler, NoState};
+        use http::Method;
+
+        let mut router: Router<(), NoState> = Router::new();
+        router.add(
+            Method::GET,
+            "/users/:id{[0-9}".to_string(),
+            Handler::new(
+                |req, resp, _, _, state| Box::pin(async { Ok((req, resp, state)) }),
+                None,
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_router_optional() {
+        use super::Router;
+        use crate::{app::App, handler::Handler, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response};
+        use hyper::Body;
+
+        async fn items(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = match params.get("id") {
+                Some(id) => format!("item {}", id),
+                None => "all items".to_string(),
+            };
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let mut router = Router::new();
+        router.add(
+            Method::GET,
+            "/api/items/:id?".to_string(),
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(items(req, resp, params, app, state)),
+                None,
+            ),
+        );
+
+        for (uri, expected) in [
+            ("/api/items", "all items"),
+            ("/api/items/", "all items"),
+            ("/api/items/42", "item 42"),
+        ] {
+            let response = router
+                .dispatch(
+                    Request::builder().uri(uri).body(Body::default()).unwrap(),
+                    App::new(),
+                )
+                .await
+                .unwrap();
+
+            let body = hyper::body::to_bytes(response).await.unwrap();
+            assert_eq!(body, expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_not_found_and_method_not_allowed() {
+        use super::Router;
+        use crate::{app::App, handler::Handler, Error, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("ok"))), state))
+        }
+
+        let handler = || {
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(ok(req, resp, params, app, state)),
+                None,
+            )
+        };
+
+        let mut router = Router::new();
+        router.add(Method::GET, "/items/:id".to_string(), handler());
+        router.add(Method::DELETE, "/items/:id".to_string(), handler());
+        router.add(Method::GET, "/items/:id".to_string(), handler());
+        router.add(Method::POST, "/items".to_string(), handler());
+
+        let dispatch = |method: Method, uri: &str| {
+            router.dispatch(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::default())
+                    .unwrap(),
+                App::new(),
+            )
+        };
+
+        let response = dispatch(Method::GET, "/items/1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        match dispatch(Method::GET, "/nothing/here").await {
+            Err(Error::StatusCode(status, _)) => assert_eq!(status, StatusCode::NOT_FOUND),
+            _ => panic!("expected a 404"),
+        }
+
+        let response = dispatch(Method::PUT, "/items/1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers().get(header::ALLOW).unwrap(),
+            "GET, DELETE, HEAD"
+        );
+
+        let response = dispatch(Method::GET, "/items").await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers().get(header::ALLOW).unwrap(), "POST");
+    }
+
+    #[tokio::test]
+    async fn test_router_head_fallback() {
+        use super::Router;
+        use crate::{app::App, handler::Handler, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn thing(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("{} thing", req.method());
+            Ok((
+                req,
+                Some(
+                    Response::builder()
+                        .status(StatusCode::ACCEPTED)
+                        .header("x-thing", "yes")
+                        .header(header::CONTENT_LENGTH, body.len())
+                        .body(Body::from(body))?,
+                ),
+                state,
+            ))
+        }
+
+        let handler = || {
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(thing(req, resp, params, app, state)),
+                None,
+            )
+        };
+
+        let mut router = Router::new();
+        router.add(Method::GET, "/thing".to_string(), handler());
+        router.add(Method::GET, "/explicit".to_string(), handler());
+        router.add(Method::HEAD, "/explicit".to_string(), handler());
+
+        let dispatch = |method: Method, uri: &str| {
+            router.dispatch(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::default())
+                    .unwrap(),
+                App::new(),
+            )
+        };
+
+        let mut response = dispatch(Method::GET, "/thing").await.unwrap();
+        let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+        assert_eq!(body, "GET thing".as_bytes());
+
+        let mut response = dispatch(Method::HEAD, "/thing").await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers().get("x-thing").unwrap(), "yes");
+        assert_eq!(
+            response.headers().get(header::CONTENT_LENGTH).unwrap(),
+            "10"
+        );
+        let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+        assert!(body.is_empty());
+
+        // the explicit HEAD route wins, and keeps its body.
+        let mut response = dispatch(Method::HEAD, "/explicit").await.unwrap();
+        let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+        assert_eq!(body, "HEAD thing".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_router_auto_options() {
+        use super::Router;
+        use crate::{app::App, handler::Handler, Error, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn ok(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, Some(Response::new(Body::from("explicit"))), state))
+        }
+
+        let handler = || {
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(ok(req, resp, params, app, state)),
+                None,
+            )
+        };
+
+        let mut router = Router::new();
+        router.add(Method::GET, "/items/:id".to_string(), handler());
+        router.add(Method::PUT, "/items/:id".to_string(), handler());
+        router.add(Method::POST, "/explicit".to_string(), handler());
+        router.add(Method::OPTIONS, "/explicit".to_string(), handler());
+
+        let options = |uri: &str| {
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri(uri)
+                .body(Body::default())
+                .unwrap()
+        };
+
+        let response = router
+            .dispatch(options("/items/1"), App::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        router.enable_auto_options();
+
+        let response = router
+            .dispatch(options("/items/1"), App::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers().get(header::ALLOW).unwrap(),
+            "GET, PUT, HEAD, OPTIONS"
+        );
+
+        let mut response = router
+            .dispatch(options("/explicit"), App::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+        assert_eq!(body, "explicit".as_bytes());
+
+        match router.dispatch(options("/nothing"), App::new()).await {
+            Err(Error::StatusCode(status, _)) => assert_eq!(status, StatusCode::NOT_FOUND),
+            _ => panic!("expected a 404"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_method_set() {
+        use super::{Methods, Router};
+        use crate::{app::App, handler::Handler, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn form(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("{} form", req.method());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        assert_eq!(
+            Methods::set(&[Method::POST, Method::GET, Method::POST]),
+            Methods::set(&[Method::GET, Method::POST])
+        );
+        assert_eq!(
+            Methods::set(&[Method::POST, Method::GET]).to_string(),
+            "GET,POST"
+        );
+
+        let mut router = Router::new();
+        router.add(
+            Methods::set(&[Method::GET, Method::POST]),
+            "/form".to_string(),
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(form(req, resp, params, app, state)),
+                None,
+            ),
+        );
+
+        let request = |method: Method| {
+            Request::builder()
+                .method(method)
+                .uri("/form")
+                .body(Body::default())
+                .unwrap()
+        };
+
+        for method in [Method::GET, Method::POST] {
+            let mut response = router
+                .dispatch(request(method.clone()), App::new())
+                .await
+                .unwrap();
+            let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+            assert_eq!(body, format!("{} form", method).as_bytes());
+        }
+
+        let response = router
+            .dispatch(request(Method::DELETE), App::new())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers().get(header::ALLOW).unwrap(),
+            "GET, POST, HEAD"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "a route must answer at least one method")]
+    fn test_router_rejects_empty_method_set() {
+        super::Methods::set(&[]);
+    }
+
+    #[tokio::test]
+    async fn test_router_specificity() {
+        use super::Router;
+        use crate::{app::App, handler::Handler, matched_path, HTTPResult, NoState, Params};
+        use http::{Method, Request, Response};
+        use hyper::Body;
+
+        async fn template(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = matched_path(&req).unwrap().to_string();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let paths = ["/users/:id", "/users/new", "/users/:id/edit"];
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+
+        for order in orders {
+            let mut router = Router::new();
+            for i in order {
+                router.add(
+                    Method::GET,
+                    paths[i].to_string(),
+                    Handler::new(
+                        |req, resp, params, app, state| {
+                            Box::pin(template(req, resp, params, app, state))
+                        },
+                        None,
+                    ),
+                );
+            }
+            // the wildcard catches everything, but only serves what nothing else does.
+            router.add(
+                Method::GET,
+                "/*rest".to_string(),
+                Handler::new(
+                    |req, resp, params, app, state| {
+                        Box::pin(template(req, resp, params, app, state))
+                    },
+                    None,
+                ),
+            );
+
+            for (uri, want) in [
+                ("/users/new", "/users/new"),
+                ("/users/1", "/users/:id"),
+                ("/users/new/edit", "/users/:id/edit"),
+                ("/users/1/edit", "/users/:id/edit"),
+                ("/other", "/*rest"),
+            ] {
+                let req = Request::builder().uri(uri).body(Body::default()).unwrap();
+                let mut response = router.dispatch(req, App::new()).await.unwrap();
+                let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+                assert_eq!(body, want.as_bytes(), "{} with order {:?}", uri, order);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_malformed_paths() {
+        use super::{Route, Router};
+        use crate::{app::App, handler::Handler, path::MatchOptions, Error, NoState};
+        use http::{Method, Request, StatusCode};
+        use hyper::Body;
+
+        let handler = || {
+            Handler::new(
+                |req, resp, _, _, state| Box::pin(async { Ok((req, resp, state)) }),
+                None,
+            )
+        };
+
+        // a route handed a path that doesn't fit it, as when dispatched directly.
+        let route: Route<(), NoState> =
+            Route::new(Method::GET, "/users/:id{[0-9]+}".to_string(), handler());
+        for provided in ["/users", "/users/erik", "/users/1/posts", "/posts/1"] {
+            match route
+                .dispatch(
+                    provided.to_string(),
+                    Request::default(),
+                    App::new(),
+                    NoState {},
+                    MatchOptions::default(),
+                )
+                .await
+            {
+                Err(Error::StatusCode(status, _)) => {
+                    assert_eq!(status, StatusCode::NOT_FOUND, "{}", provided)
+                }
+                _ => panic!("{}: expected a status", provided),
+            }
+        }
+
+        let mut router: Router<(), NoState> = Router::new();
+        router.add(Method::GET, "/users/:id{[0-9]+}".to_string(), handler());
+        router.add(Method::GET, "/files/*path".to_string(), handler());
+
+        for (uri, expected) in [
+            ("/users/erik", StatusCode::NOT_FOUND),
+            ("/users/1/extra", StatusCode::NOT_FOUND),
+            ("/users", StatusCode::NOT_FOUND),
+            ("//users", StatusCode::NOT_FOUND),
+            ("/users/%zz", StatusCode::BAD_REQUEST),
+            ("/files/%zz", StatusCode::BAD_REQUEST),
+            ("/files/%C3%28", StatusCode::BAD_REQUEST),
+        ] {
+            let req = Request::builder().uri(uri).body(Body::default()).unwrap();
+            match router.dispatch(req, App::new()).await {
+                Err(Error::StatusCode(status, _)) => assert_eq!(status, expected, "{}", uri),
+                other => panic!("{}: unexpected {:?}", uri, other.map(|r| r.status())),
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid route path `users/:id`: path must begin with `/`")]
+    fn test_router_rejects_relative_paths() {
+        use super::Router;
+        use crate::{handler::Handler, NoState};
+        use http::Method;
+
+        let mut router: Router<(), NoState> = Router::new();
+        router.add(
+            Method::GET,
+            "users/:id".to_string(),
+            Handler::new(
+                |req, resp, _, _, state| Box::pin(async { Ok((req, resp, state)) }),
+                None,
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_router_method_index() {
+        use super::{Methods, Router};
+        use crate::{app::RouteOptions, handler::Handler, HTTPResult, NoState, Params};
+        use http::{header, Method, Request, Response, StatusCode};
+        use hyper::Body;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        async fn method(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: crate::app::App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = req.method().to_string();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        let handler = || {
+            Handler::new(
+                |req, resp, params, app, state| Box::pin(method(req, resp, params, app, state)),
+                None,
+            )
+        };
+
+        // counts how often the POST route is consulted.
+        let consulted = Arc::new(AtomicUsize::new(0));
+        let counter = consulted.clone();
+
+        let mut router = Router::new();
+        router.add_with_options(
+            Methods::from(Method::POST),
+            "/items".to_string(),
+            handler(),
+            RouteOptions {
+                guard: Some(Arc::new(move |_: &Request<Body>| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    true
+                })),
+                ..Default::default()
+            },
+        );
+        router.add(Method::GET, "/items".to_string(), handler());
+        router.add(Methods::Any, "/any".to_string(), handler());
+        router.add(
+            Methods::set(&[Method::PUT, Method::PATCH]),
+            "/items/:id".to_string(),
+            handler(),
+        );
+
+        let dispatch = |method: Method, uri: &str| {
+            router.dispatch(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::default())
+                    .unwrap(),
+                crate::app::App::new(),
+            )
+        };
+
+        for (method, uri) in [
+            (Method::GET, "/items"),
+            (Method::DELETE, "/any"),
+            (Method::PUT, "/items/1"),
+            (Method::PATCH, "/items/1"),
+        ] {
+            let mut response = dispatch(method.clone(), uri).await.unwrap();
+            let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+            assert_eq!(body, method.as_str().as_bytes());
+        }
+        assert_eq!(consulted.load(Ordering::SeqCst), 0);
+
+        let mut response = dispatch(Method::POST, "/items").await.unwrap();
+        let body = hyper::body::to_bytes(response.body_mut()).await.unwrap();
+        assert_eq!(body, "POST".as_bytes());
+        assert_eq!(consulted.load(Ordering::SeqCst), 1);
+
+        // other methods are only consulted once nothing serves the request.
+        let response = dispatch(Method::DELETE, "/items").await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers().get(header::ALLOW).unwrap(),
+            "POST, GET, HEAD"
+        );
+        assert_eq!(consulted.load(Ordering::SeqCst), 2);
+    }
 }
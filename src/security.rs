@@ -0,0 +1,605 @@
+use std::sync::Arc;
+
+use http::{header, HeaderName, HeaderValue};
+
+use crate::{handler::Handler, Error, TransientState};
+
+/// The nonce a [CspNonce] handler generated for a request. See [crate::csp_nonce].
+#[derive(Debug, Clone)]
+pub(crate) struct Nonce(pub(crate) String);
+
+/// SecurityHeaders adds a set of security headers to every response, and is given to
+/// [crate::app::App::wrap_after] so that it also sees the responses of errors and of requests no
+/// route matched:
+///
+/// ```ignore
+///     app.wrap_after(SecurityHeaders::new().handler());
+/// ```
+///
+/// By default these are sent:
+///
+/// - `Strict-Transport-Security: max-age=31536000; includeSubDomains`
+/// - `X-Content-Type-Options: nosniff`
+/// - `X-Frame-Options: DENY`
+/// - `Referrer-Policy: strict-origin-when-cross-origin`
+/// - `Content-Security-Policy: frame-ancestors 'none'`
+///
+/// Each can be given another value, or turned off with None. A header a handler has already set is
+/// left alone, so routes can loosen the policy for themselves, unless [SecurityHeaders::force] is
+/// used. The `Content-Security-Policy` header is the exception: a policy already set, such as by
+/// [CspNonce], is merged with by adding the directives it lacks, so that a single header is sent.
+/// Invalid header values panic when given, as they are fixed when the App is built.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<(HeaderName, Option<HeaderValue>)>,
+    force: bool,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            headers: vec![
+                (
+                    header::STRICT_TRANSPORT_SECURITY,
+                    Some(HeaderValue::from_static(
+                        "max-age=31536000; includeSubDomains",
+                    )),
+                ),
+                (
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    Some(HeaderValue::from_static("nosniff")),
+                ),
+                (
+                    header::X_FRAME_OPTIONS,
+                    Some(HeaderValue::from_static("DENY")),
+                ),
+                (
+                    header::REFERRER_POLICY,
+                    Some(HeaderValue::from_static("strict-origin-when-cross-origin")),
+                ),
+                (
+                    header::CONTENT_SECURITY_POLICY,
+                    Some(HeaderValue::from_static("frame-ancestors 'none'")),
+                ),
+            ],
+            force: false,
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// The default set of headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the `Strict-Transport-Security` header, which tells browsers to only use HTTPS for the
+    /// site. Sites also served over plain HTTP may want to turn it off.
+    pub fn strict_transport_security(self, value: Option<&str>) -> Self {
+        self.set(header::STRICT_TRANSPORT_SECURITY, value)
+    }
+
+    /// Send `X-Content-Type-Options: nosniff`, which stops browsers guessing content types, or not.
+    pub fn content_type_options(self, nosniff: bool) -> Self {
+        self.set(header::X_CONTENT_TYPE_OPTIONS, nosniff.then_some("nosniff"))
+    }
+
+    /// Set the `X-Frame-Options` header, such as `SAMEORIGIN` to allow framing by the site itself.
+    /// Browsers honouring `frame-ancestors` in the content security policy ignore it.
+    pub fn frame_options(self, value: Option<&str>) -> Self {
+        self.set(header::X_FRAME_OPTIONS, value)
+    }
+
+    /// Set the `Referrer-Policy` header.
+    pub fn referrer_policy(self, value: Option<&str>) -> Self {
+        self.set(header::REFERRER_POLICY, value)
+    }
+
+    /// Set the `Content-Security-Policy` header. The policy replaces the default one entirely, so
+    /// include `frame-ancestors` in it to keep refusing to be framed.
+    pub fn content_security_policy(self, value: Option<&str>) -> Self {
+        self.set(header::CONTENT_SECURITY_POLICY, value)
+    }
+
+    /// Replace the headers even when a handler has already set them. Directives of a content
+    /// security policy already set are replaced by those of the same name.
+    pub fn force(mut self) -> Self {
+        self.force = true;
+        self
+    }
+
+    /// Build the handler adding the headers.
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let headers = Arc::new(self);
+
+        Handler::from_fn(move |req, mut resp, _params, _app, state| {
+            if let Some(resp) = &mut resp {
+                headers.apply(resp.headers_mut());
+            }
+
+            async move { Ok((req, resp, state)) }
+        })
+        .named("security_headers")
+    }
+
+    fn apply(&self, headers: &mut http::HeaderMap) {
+        for (name, value) in &self.headers {
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let existing = match *name == header::CONTENT_SECURITY_POLICY {
+                true => headers
+                    .get(name)
+                    .and_then(|existing| existing.to_str().ok()),
+                false => None,
+            };
+
+            if let Some(existing) = existing {
+                let mut policy = Policy::parse(existing);
+                policy.merge(
+                    &Policy::parse(value.to_str().unwrap_or_default()),
+                    self.force,
+                );
+                if let Ok(merged) = HeaderValue::from_str(&policy.to_string()) {
+                    headers.insert(name, merged);
+                }
+            } else if self.force || !headers.contains_key(name) {
+                headers.insert(name, value.clone());
+            }
+        }
+    }
+
+    fn set(mut self, name: HeaderName, value: Option<&str>) -> Self {
+        let value = value.map(|value| match HeaderValue::from_str(value) {
+            Ok(value) => value,
+            Err(_) => panic!("invalid value for {} header: {:?}", name, value),
+        });
+
+        for header in &mut self.headers {
+            if header.0 == name {
+                header.1 = value;
+                break;
+            }
+        }
+
+        self
+    }
+}
+
+/// CspNonce generates a random nonce for each request, for allowing the inline scripts of a page
+/// under a strict `Content-Security-Policy`. Handlers embed the nonce, available through
+/// [crate::csp_nonce], in the tags of the scripts they render, and the handler adds it to the
+/// policy of the response:
+///
+/// ```ignore
+///     app.wrap(CspNonce::new().handler());
+///     app.wrap_after(SecurityHeaders::new().handler());
+///
+///     // in a handler:
+///     let nonce = csp_nonce(&req).unwrap();
+///     let page = format!("<script nonce=\"{}\">start()</script>", nonce);
+/// ```
+///
+/// The nonce is added to the `script-src` directive unless others are given with
+/// [CspNonce::directives]. A directive the policy lacks is added with the sources of its
+/// `default-src`, so that those stay allowed, and a response without a policy is given one of the
+/// directives alone. It composes with [SecurityHeaders], which merges its policy into the one with
+/// the nonce rather than sending a second header.
+///
+/// Nonces are 128 bits drawn from the operating system's random source for each request. Should
+/// it fail, the request is answered with a 500 Internal Server Error.
+#[derive(Debug, Clone)]
+pub struct CspNonce {
+    directives: Vec<String>,
+}
+
+impl Default for CspNonce {
+    fn default() -> Self {
+        Self {
+            directives: vec!["script-src".to_string()],
+        }
+    }
+}
+
+impl CspNonce {
+    /// Add nonces to `script-src`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add nonces to these directives, such as `script-src` and `style-src`, rather than
+    /// `script-src` alone. Panics if there are none, or if any is not a directive name.
+    pub fn directives(mut self, directives: &[&str]) -> Self {
+        if directives.is_empty() {
+            panic!("CSP nonces must be added to at least one directive");
+        }
+
+        for directive in directives {
+            if directive.is_empty()
+                || !directive
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            {
+                panic!("invalid CSP directive name `{}`", directive);
+            }
+        }
+
+        self.directives = directives
+            .iter()
+            .map(|directive| directive.to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Build the handler generating nonces, which leads the chains that render with them; usually
+    /// given to [crate::app::App::wrap].
+    pub fn handler<S, T>(self) -> Handler<S, T>
+    where
+        S: Send + 'static,
+        T: TransientState + 'static + Clone + Send,
+    {
+        let csp = Arc::new(self);
+
+        Handler::around(
+            Arc::new(move |mut req, resp, params, app, state, next| {
+                let csp = csp.clone();
+                let mut bytes = [0u8; 16];
+                let drawn = getrandom::getrandom(&mut bytes);
+                let nonce = bytes
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect::<String>();
+                req.extensions_mut().insert(Nonce(nonce.clone()));
+
+                Box::pin(async move {
+                    if let Err(e) = drawn {
+                        return Err(Error::InternalServerError(format!(
+                            "could not draw a CSP nonce: {}",
+                            e
+                        )));
+                    }
+
+                    let (req, mut resp, state) = match next {
+                        Some(next) => next.perform(req, resp, params, app, state).await?,
+                        None => (req, resp, state),
+                    };
+
+                    if let Some(resp) = &mut resp {
+                        let mut policy = resp
+                            .headers()
+                            .get(header::CONTENT_SECURITY_POLICY)
+                            .and_then(|policy| policy.to_str().ok())
+                            .map(Policy::parse)
+                            .unwrap_or_default();
+                        policy.add_nonce(&csp.directives, &nonce);
+                        resp.headers_mut().insert(
+                            header::CONTENT_SECURITY_POLICY,
+                            HeaderValue::from_str(&policy.to_string())?,
+                        );
+                    }
+
+                    Ok((req, resp, state))
+                })
+            }),
+            None,
+        )
+        .named("csp_nonce")
+    }
+}
+
+// A content security policy, as its directives in order, each with its lowercased name and its
+// sources.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Policy(Vec<(String, String)>);
+
+impl Policy {
+    // parse the directives of the policy; of those with the same name, the first is kept, as it is
+    // the one browsers enforce.
+    fn parse(policy: &str) -> Self {
+        let mut directives: Vec<(String, String)> = Vec::new();
+
+        for directive in policy.split(';') {
+            let directive = directive.trim();
+            let (name, sources) = directive
+                .split_once(|c: char| c.is_ascii_whitespace())
+                .unwrap_or((directive, ""));
+            let name = name.to_ascii_lowercase();
+
+            if !name.is_empty() && directives.iter().all(|(existing, _)| *existing != name) {
+                directives.push((name, sources.trim().to_string()));
+            }
+        }
+
+        Self(directives)
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut String> {
+        self.0
+            .iter_mut()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, sources)| sources)
+    }
+
+    // add the directives of `other` this policy lacks, and replace those it has if `force` is set.
+    fn merge(&mut self, other: &Policy, force: bool) {
+        for (name, sources) in &other.0 {
+            match self.get_mut(name) {
+                Some(existing) if force => *existing = sources.clone(),
+                Some(_) => {}
+                None => self.0.push((name.clone(), sources.clone())),
+            }
+        }
+    }
+
+    // allow the nonce in each directive, adding those the policy lacks with the sources of its
+    // default-src.
+    fn add_nonce(&mut self, directives: &[String], nonce: &str) {
+        let source = format!("'nonce-{}'", nonce);
+        let default = self
+            .get_mut("default-src")
+            .map(|sources| sources.clone())
+            .unwrap_or_default();
+
+        for directive in directives {
+            match self.get_mut(directive) {
+                // 'none' may not be combined with other sources.
+                Some(sources) if sources.eq_ignore_ascii_case("'none'") => {
+                    *sources = source.clone()
+                }
+                Some(sources) if sources.is_empty() => *sources = source.clone(),
+                Some(sources) => *sources = format!("{} {}", sources, source),
+                None if default.is_empty() || default.eq_ignore_ascii_case("'none'") => {
+                    self.0.push((directive.clone(), source.clone()))
+                }
+                None => self
+                    .0
+                    .push((directive.clone(), format!("{} {}", default, source))),
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let directives = self
+            .0
+            .iter()
+            .map(|(name, sources)| match sources.is_empty() {
+                true => name.clone(),
+                false => format!("{} {}", name, sources),
+            })
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", directives.join("; "))
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_security_headers() {
+        use super::SecurityHeaders;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, Error, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+
+        async fn framed(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let res = Response::builder()
+                .header("x-frame-options", "SAMEORIGIN")
+                .body(Body::from("framed"))
+                .unwrap();
+            Ok((req, Some(res), state))
+        }
+
+        async fn fail(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            _state: NoState,
+        ) -> HTTPResult<NoState> {
+            Err(Error::new_status(StatusCode::CONFLICT, "conflict"))
+        }
+
+        let app = |headers: SecurityHeaders| {
+            let mut app = App::new();
+            app.get("/framed", compose_handler!(framed));
+            app.get("/fail", compose_handler!(fail));
+            app.wrap_after(headers.handler());
+            TestApp::new(app)
+        };
+
+        let defaults = app(SecurityHeaders::new());
+        for path in ["/fail", "/missing"] {
+            let res = defaults.get(path).await;
+            let headers = res.headers();
+            assert_eq!(
+                headers.get("strict-transport-security").unwrap(),
+                "max-age=31536000; includeSubDomains"
+            );
+            assert_eq!(headers.get("x-content-type-options").unwrap(), "nosniff");
+            assert_eq!(headers.get("x-frame-options").unwrap(), "DENY");
+            assert_eq!(
+                headers.get("referrer-policy").unwrap(),
+                "strict-origin-when-cross-origin"
+            );
+            assert_eq!(
+                headers.get("content-security-policy").unwrap(),
+                "frame-ancestors 'none'"
+            );
+        }
+
+        // headers set by the handler are kept.
+        let res = defaults.get("/framed").await;
+        assert_eq!(res.headers().get("x-frame-options").unwrap(), "SAMEORIGIN");
+
+        let custom = app(SecurityHeaders::new()
+            .strict_transport_security(None)
+            .content_type_options(false)
+            .content_security_policy(Some("default-src 'self'"))
+            .force());
+        let res = custom.get("/framed").await;
+        let headers = res.headers();
+        assert!(headers.get("strict-transport-security").is_none());
+        assert!(headers.get("x-content-type-options").is_none());
+        assert_eq!(headers.get("x-frame-options").unwrap(), "DENY");
+        assert_eq!(
+            headers.get("content-security-policy").unwrap(),
+            "default-src 'self'"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid value for referrer-policy header")]
+    fn test_security_headers_rejects_invalid_values() {
+        super::SecurityHeaders::new().referrer_policy(Some("no-referrer\n"));
+    }
+
+    #[test]
+    fn test_policy() {
+        use super::Policy;
+
+        let mut policy = Policy::parse(
+            "default-src 'self' cdn.example;  SCRIPT-SRC ; img-src *; img-src 'none'",
+        );
+        assert_eq!(
+            policy.to_string(),
+            "default-src 'self' cdn.example; script-src; img-src *"
+        );
+
+        policy.merge(
+            &Policy::parse("img-src 'self'; frame-ancestors 'none'"),
+            false,
+        );
+        assert_eq!(
+            policy.to_string(),
+            "default-src 'self' cdn.example; script-src; img-src *; frame-ancestors 'none'"
+        );
+        policy.merge(&Policy::parse("img-src 'self'"), true);
+        assert_eq!(
+            policy.to_string(),
+            "default-src 'self' cdn.example; script-src; img-src 'self'; frame-ancestors 'none'"
+        );
+
+        let directives = ["script-src", "style-src", "font-src"].map(String::from);
+        let mut policy =
+            Policy::parse("default-src 'self'; script-src 'strict-dynamic'; font-src 'none'");
+        policy.add_nonce(&directives, "abc");
+        assert_eq!(
+            policy.to_string(),
+            "default-src 'self'; script-src 'strict-dynamic' 'nonce-abc'; font-src 'nonce-abc'; \
+             style-src 'self' 'nonce-abc'"
+        );
+
+        let mut policy = Policy::default();
+        policy.add_nonce(&directives[..1], "abc");
+        assert_eq!(policy.to_string(), "script-src 'nonce-abc'");
+    }
+
+    #[tokio::test]
+    async fn test_csp_nonce() {
+        use super::{CspNonce, SecurityHeaders};
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, csp_nonce, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn page(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = csp_nonce(&req).unwrap().to_string();
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn own_policy(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let res = Response::builder()
+                .header("content-security-policy", "default-src 'self'")
+                .body(Body::from(csp_nonce(&req).unwrap().to_string()))
+                .unwrap();
+            Ok((req, Some(res), state))
+        }
+
+        let mut app = App::new();
+        app.get("/", compose_handler!(page));
+        app.get("/own", compose_handler!(own_policy));
+        app.wrap(
+            CspNonce::new()
+                .directives(&["script-src", "style-src"])
+                .handler(),
+        );
+        app.wrap_after(SecurityHeaders::new().handler());
+        let app = TestApp::new(app);
+
+        let nonce = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let mut res = app.get(path).await;
+                let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+                let nonce = String::from_utf8(body.to_vec()).unwrap();
+                let policies: Vec<String> = res
+                    .headers()
+                    .get_all("content-security-policy")
+                    .iter()
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .collect();
+                (nonce, policies)
+            }
+        };
+
+        // the nonce is fresh for each request, and merged with the default policy in one header.
+        let (first, policies) = nonce("/").await;
+        assert_eq!(first.len(), 32);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            policies,
+            vec![format!(
+                "script-src 'nonce-{0}'; style-src 'nonce-{0}'; frame-ancestors 'none'",
+                first
+            )]
+        );
+        let (second, _) = nonce("/").await;
+        assert_ne!(first, second);
+
+        let (nonce, policies) = nonce("/own").await;
+        assert_eq!(
+            policies,
+            vec![format!(
+                "default-src 'self'; script-src 'self' 'nonce-{0}'; style-src 'self' 'nonce-{0}'; \
+                 frame-ancestors 'none'",
+                nonce
+            )]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid CSP directive name `script src`")]
+    fn test_csp_nonce_rejects_invalid_directives() {
+        super::CspNonce::new().directives(&["script src"]);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    future::{poll_fn, Future},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use http::{Request, Response};
+use hyper::Body;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{app::App, handler::Handler, Error, Params, PinBox, TransientState};
+
+type Stash<T> = Arc<Mutex<Option<Result<(Request<Body>, bool, T), Error>>>>;
+
+/// ChainError is the error type yielded by [Chain] when the remainder of the ratpack chain fails.
+/// The original [crate::Error] is restored once the tower layer hands control back, so statuses
+/// survive the round trip.
+#[derive(Debug)]
+pub struct ChainError(String);
+
+impl std::fmt::Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+/// Chain is the inner [tower_service::Service] handed to a tower [tower_layer::Layer] by
+/// [layer]. Calling it resumes the remaining ratpack chain with the request the layer provides.
+pub struct Chain<S: Send + 'static, T: TransientState + 'static> {
+    next: Option<Handler<S, T>>,
+    response: Arc<Mutex<Option<Response<Body>>>>,
+    params: Params,
+    app: App<S, T>,
+    state: T,
+    stash: Stash<T>,
+}
+
+impl<S: Send + 'static, T: TransientState + 'static> Clone for Chain<S, T> {
+    fn clone(&self) -> Self {
+        Self {
+            next: self.next.clone(),
+            response: self.response.clone(),
+            params: self.params.clone(),
+            app: self.app.share(),
+            state: self.state.clone(),
+            stash: self.stash.clone(),
+        }
+    }
+}
+
+impl<S: Send + 'static, T: TransientState + 'static> Service<Request<Body>> for Chain<S, T> {
+    type Response = Response<Body>;
+    type Error = ChainError;
+    type Future = PinBox<dyn Future<Output = Result<Response<Body>, ChainError>> + Send>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let next = self.next.clone();
+        let response = self.response.lock().unwrap().take();
+        let params = self.params.clone();
+        let app = self.app.share();
+        let state = self.state.clone();
+        let stash = self.stash.clone();
+
+        Box::pin(async move {
+            let result = match next {
+                Some(next) => next.perform(req, response, params, app, state).await,
+                None => Ok((req, response, state)),
+            };
+
+            match result {
+                Ok((req, response, state)) => {
+                    let has_response = response.is_some();
+                    stash
+                        .lock()
+                        .unwrap()
+                        .replace(Ok((req, has_response, state)));
+                    Ok(response.unwrap_or_default())
+                }
+                Err(e) => {
+                    let msg = format!("{:?}", e);
+                    stash.lock().unwrap().replace(Err(e));
+                    Err(ChainError(msg))
+                }
+            }
+        })
+    }
+}
+
+/// Wrap the `next` chain in a tower [tower_layer::Layer]. The returned [crate::handler::Handler]
+/// hands the request to the layer's service, whose inner service resumes `next`; the tower
+/// middleware therefore surrounds the rest of the ratpack chain.
+///
+/// If the layer answers on its own (e.g. a timeout), the chain continues with a copy of the
+/// original request's head and the state as it was when the layer was entered. Errors from the
+/// layer's service are converted into a [crate::Error::InternalServerError], except when they
+/// originate from the ratpack chain itself, in which case the original error is returned.
+///
+/// ```ignore
+///     app.get(
+///         "/slow",
+///         ratpack::tower::layer(TimeoutLayer::new(Duration::from_secs(1)), compose_handler!(slow)),
+///     );
+/// ```
+pub fn layer<L, S, T>(layer: L, next: Handler<S, T>) -> Handler<S, T>
+where
+    L: Layer<Chain<S, T>> + Send + Sync + 'static,
+    L::Service: Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+    <L::Service as Service<Request<Body>>>::Error: std::fmt::Display,
+    <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    S: Send + 'static,
+    T: TransientState + 'static,
+{
+    let layer = Arc::new(layer);
+
+    Handler::around(
+        Arc::new(move |req, response, params, app, state: T, next| {
+            let head = Request::builder()
+                .method(req.method().clone())
+                .uri(req.uri().clone())
+                .version(req.version());
+            let head = req
+                .headers()
+                .iter()
+                .fold(head, |head, (k, v)| head.header(k, v));
+
+            let stash: Stash<T> = Arc::new(Mutex::new(None));
+            let initial_state = state.clone();
+
+            let mut svc = layer.layer(Chain {
+                next,
+                response: Arc::new(Mutex::new(response)),
+                params,
+                app,
+                state,
+                stash: stash.clone(),
+            });
+
+            Box::pin(async move {
+                poll_fn(|cx| svc.poll_ready(cx))
+                    .await
+                    .map_err(|e| Error::new(e.to_string()))?;
+                let res = svc.call(req).await;
+                let stashed = stash.lock().unwrap().take();
+
+                match (res, stashed) {
+                    (Ok(response), Some(Ok((req, has_response, state)))) => {
+                        Ok((req, has_response.then_some(response), state))
+                    }
+                    (Ok(response), _) => {
+                        Ok((head.body(Body::empty())?, Some(response), initial_state))
+                    }
+                    (Err(_), Some(Err(e))) => Err(e),
+                    (Err(e), _) => Err(Error::new(e.to_string())),
+                }
+            })
+        }),
+        Some(next),
+    )
+    .named("tower::layer")
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_tower_timeout_layer() {
+        use std::time::Duration;
+
+        use http::{Request, Response, StatusCode};
+        use hyper::Body;
+        use tower_http::timeout::TimeoutLayer;
+
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, Error, HTTPResult, Params, TransientState,
+        };
+
+        #[derive(Clone)]
+        struct Stages(Vec<&'static str>);
+
+        impl TransientState for Stages {
+            fn initial() -> Self {
+                Self(Vec::new())
+            }
+        }
+
+        async fn slow(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Stages>,
+            mut state: Stages,
+        ) -> HTTPResult<Stages> {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            state.0.push("slow");
+            Ok((req, Some(Response::new(Body::from("slow"))), state))
+        }
+
+        async fn fast(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Stages>,
+            mut state: Stages,
+        ) -> HTTPResult<Stages> {
+            state.0.push("fast");
+            Ok((req, None, state))
+        }
+
+        async fn report(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Stages>,
+            state: Stages,
+        ) -> HTTPResult<Stages> {
+            Ok((
+                req,
+                Some(Response::new(Body::from(state.0.join(",")))),
+                state,
+            ))
+        }
+
+        async fn fail(
+            _req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), Stages>,
+            _state: Stages,
+        ) -> HTTPResult<Stages> {
+            Err(Error::new_status(
+                StatusCode::IM_A_TEAPOT,
+                "short and stout",
+            ))
+        }
+
+        let mut app = App::new();
+        app.get(
+            "/slow",
+            super::layer(
+                TimeoutLayer::new(Duration::from_millis(20)),
+                compose_handler!(slow),
+            ),
+        );
+        app.get(
+            "/fast",
+            super::layer(
+                TimeoutLayer::new(Duration::from_millis(200)),
+                compose_handler!(fast, report),
+            ),
+        );
+        app.get(
+            "/fail",
+            super::layer(
+                TimeoutLayer::new(Duration::from_millis(200)),
+                compose_handler!(fail),
+            ),
+        );
+
+        let app = TestApp::new(app);
+
+        let res = app.get("/slow").await;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let mut res = app.get("/fast").await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "fast".as_bytes());
+
+        let mut res = app.get("/fail").await;
+        assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+        let body = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+        assert_eq!(body, "short and stout".as_bytes());
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use http::{header, Request};
+use hyper::Body;
+
+use crate::{handler::Handler, TransientState};
+
+// the increment of the SplitMix64 generator.
+pub(crate) const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The variant of a [Weighted] handler chosen for a request. See [crate::variant].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Variant(pub(crate) usize);
+
+#[derive(Debug, Clone)]
+enum Sticky {
+    Header(header::HeaderName),
+    Cookie(String),
+}
+
+/// Weighted picks one of several handler chains for each request, in proportion to their weights.
+/// This is useful for trying out a new implementation on a share of traffic:
+///
+/// ```ignore
+///     app.get(
+///         "/search",
+///         Weighted::new(&[(90, compose_handler!(search)), (10, compose_handler!(search_v2))])
+///             .sticky_cookie("session")
+///             .handler(),
+///     );
+/// ```
+///
+/// [crate::app::App::get_weighted] covers the common case. The position of the chosen chain in
+/// the list is available to its handlers through [crate::variant], so that it can be logged.
+///
+/// Chains are chosen with a pseudo-random generator, seeded from the clock unless
+/// [Weighted::seed] is used. With [Weighted::sticky_header] or [Weighted::sticky_cookie], requests
+/// carrying the header or cookie are instead assigned by a hash of its value, so that a given user
+/// consistently sees the same variant; requests without it are assigned at random.
+#[derive(Clone)]
+pub struct Weighted<S: Send, T: TransientState + 'static> {
+    variants: Vec<(u32, Handler<S, T>)>,
+    seed: Option<u64>,
+    sticky: Option<Sticky>,
+}
+
+impl<S, T> Weighted<S, T>
+where
+    S: Send + 'static,
+    T: TransientState + 'static + Clone + Send,
+{
+    /// Choose between the chains in proportion to their weights. Panics if the weights add up to
+    /// zero, as no chain could be chosen.
+    pub fn new(variants: &[(u32, Handler<S, T>)]) -> Self {
+        if variants
+            .iter()
+            .map(|(weight, _)| *weight as u64)
+            .sum::<u64>()
+            == 0
+        {
+            panic!("weighted route must have a variant with a weight above zero");
+        }
+
+        Self {
+            variants: variants.to_vec(),
+            seed: None,
+            sticky: None,
+        }
+    }
+
+    /// Seed the generator choosing between chains, so that the sequence of choices is repeatable.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Assign requests by the value of the named header, such as one carrying a user ID. The name
+    /// must be lowercase.
+    pub fn sticky_header(mut self, name: &'static str) -> Self {
+        self.sticky = Some(Sticky::Header(header::HeaderName::from_static(name)));
+        self
+    }
+
+    /// Assign requests by the value of the named cookie, such as a session cookie.
+    pub fn sticky_cookie(mut self, name: &str) -> Self {
+        self.sticky = Some(Sticky::Cookie(name.to_string()));
+        self
+    }
+
+    /// Build the handler choosing between the chains. Any chain appended to it is performed after
+    /// the chosen one.
+    pub fn handler(self) -> Handler<S, T> {
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        let rng = Arc::new(AtomicU64::new(seed));
+        let weighted = Arc::new(self);
+
+        Handler::around(
+            Arc::new(move |mut req, resp, params, app, state, next| {
+                let weighted = weighted.clone();
+                let roll = match weighted.sticky_key(&req) {
+                    Some(key) => fnv1a(key.as_bytes()),
+                    None => split_mix(rng.fetch_add(GAMMA, Ordering::Relaxed)),
+                };
+                let variant = weighted.choose(roll);
+                req.extensions_mut().insert(Variant(variant));
+
+                Box::pin(async move {
+                    let (req, resp, state) = weighted.variants[variant]
+                        .1
+                        .perform(req, resp, params.clone(), app.share(), state)
+                        .await?;
+
+                    match next {
+                        Some(next) => next.perform(req, resp, params, app, state).await,
+                        None => Ok((req, resp, state)),
+                    }
+                })
+            }),
+            None,
+        )
+        .named("weighted")
+    }
+
+    // the value requests are assigned by, if the request has one.
+    fn sticky_key(&self, req: &Request<Body>) -> Option<String> {
+        match self.sticky.as_ref()? {
+            Sticky::Header(name) => req
+                .headers()
+                .get(name)?
+                .to_str()
+                .ok()
+                .map(ToString::to_string),
+            Sticky::Cookie(name) => crate::cookies::get(req).get(name).map(ToString::to_string),
+        }
+    }
+
+    // the position of the variant `roll` falls in, when laid out by weight.
+    fn choose(&self, roll: u64) -> usize {
+        let total: u64 = self.variants.iter().map(|(weight, _)| *weight as u64).sum();
+        let mut roll = roll % total;
+
+        for (i, (weight, _)) in self.variants.iter().enumerate() {
+            if roll < *weight as u64 {
+                return i;
+            }
+            roll -= *weight as u64;
+        }
+
+        unreachable!("roll is below the total weight")
+    }
+}
+
+// the output of the SplitMix64 generator for the given state.
+pub(crate) fn split_mix(state: u64) -> u64 {
+    let mut z = state.wrapping_add(GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// FNV-1a, which unlike the standard library's hasher is stable across releases, so that users
+// keep their variant across upgrades.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_weighted() {
+        use super::Weighted;
+        use crate::{
+            app::{App, TestApp},
+            compose_handler, variant, HTTPResult, NoState, Params,
+        };
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn old(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("old {}", variant(&req).unwrap());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn new(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            let body = format!("new {}", variant(&req).unwrap());
+            Ok((req, Some(Response::new(Body::from(body))), state))
+        }
+
+        async fn body(app: &TestApp<(), NoState>, req: Request<Body>) -> String {
+            let mut res = app.dispatch(req).await;
+            let bytes = hyper::body::to_bytes(res.body_mut()).await.unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        }
+
+        let variants = [(90, compose_handler!(old)), (10, compose_handler!(new))];
+        let app = |weighted: Weighted<(), NoState>| {
+            let mut app = App::new();
+            app.get("/search", weighted.handler());
+            TestApp::new(app)
+        };
+        let get = |header: Option<(&str, &str)>| {
+            let mut req = Request::builder().uri("/search");
+            if let Some((name, value)) = header {
+                req = req.header(name, value);
+            }
+            req.body(Body::empty()).unwrap()
+        };
+
+        // the same seed makes the same choices, in about the given proportions.
+        let first = app(Weighted::new(&variants).seed(7));
+        let second = app(Weighted::new(&variants).seed(7));
+        let mut new_count = 0;
+        for _ in 0..1000 {
+            let chosen = body(&first, get(None)).await;
+            assert_eq!(chosen, body(&second, get(None)).await);
+            match chosen.as_str() {
+                "old 0" => {}
+                "new 1" => new_count += 1,
+                other => panic!("unexpected variant {}", other),
+            }
+        }
+        assert!((50..150).contains(&new_count), "{}", new_count);
+
+        let sticky = app(Weighted::new(&variants).sticky_header("x-user"));
+        for user in ["alice", "bob", "carol"] {
+            let chosen = body(&sticky, get(Some(("x-user", user)))).await;
+            for _ in 0..10 {
+                assert_eq!(body(&sticky, get(Some(("x-user", user)))).await, chosen);
+            }
+        }
+
+        let sticky = app(Weighted::new(&variants).sticky_cookie("session"));
+        let chosen = body(&sticky, get(Some(("cookie", "theme=dark; session=abc")))).await;
+        for _ in 0..10 {
+            assert_eq!(
+                body(&sticky, get(Some(("cookie", "session=abc")))).await,
+                chosen
+            );
+        }
+
+        // a variant without weight is never chosen.
+        let only_new = app(Weighted::new(&[
+            (0, compose_handler!(old)),
+            (1, compose_handler!(new)),
+        ]));
+        for _ in 0..10 {
+            assert_eq!(body(&only_new, get(None)).await, "new 1");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "weighted route must have a variant with a weight above zero")]
+    fn test_weighted_rejects_zero_weights() {
+        use crate::{compose_handler, HTTPResult, NoState, Params};
+        use http::{Request, Response};
+        use hyper::Body;
+
+        async fn old(
+            req: Request<Body>,
+            _resp: Option<Response<Body>>,
+            _params: Params,
+            _app: crate::app::App<(), NoState>,
+            state: NoState,
+        ) -> HTTPResult<NoState> {
+            Ok((req, None, state))
+        }
+
+        super::Weighted::new(&[(0, compose_handler!(old))]);
+    }
+}